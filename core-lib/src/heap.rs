@@ -0,0 +1,241 @@
+//! First-fit heap allocator with an address-ordered free list
+//!
+//! Block sizes and addresses are multiples of [`GRANULARITY`], so a free block
+//! can always be split without leaving fragments too small to be tracked.
+
+use core::{alloc::Layout, mem::size_of, ptr::NonNull};
+
+/// Allocation granularity. Every free block has to fit a [`FreeBlock`] header
+pub const GRANULARITY: usize = 16;
+
+struct FreeBlock {
+    size: usize,
+    next: Option<NonNull<FreeBlock>>,
+}
+
+const _: () = assert!(size_of::<FreeBlock>() <= GRANULARITY);
+
+pub struct Heap {
+    head: Option<NonNull<FreeBlock>>,
+    total: usize,
+    free: usize,
+}
+
+// SAFETY: heap exclusively owns memory regions added to it
+unsafe impl Send for Heap {}
+
+impl Heap {
+    pub const fn new() -> Heap {
+        Heap {
+            head: None,
+            total: 0,
+            free: 0,
+        }
+    }
+
+    /// Makes given memory region available for allocation
+    ///
+    /// # Safety
+    /// Region must be valid for reads and writes, unused and not overlapping
+    /// with any region added previously
+    pub unsafe fn add_region(&mut self, start: usize, size: usize) {
+        let aligned_start = align_up(start, GRANULARITY);
+        let end = (start + size) & !(GRANULARITY - 1);
+        if end <= aligned_start {
+            return;
+        }
+
+        self.total += end - aligned_start;
+        self.insert(aligned_start, end - aligned_start);
+    }
+
+    /// Total number of bytes managed by the heap
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Number of bytes available for allocation
+    pub fn free(&self) -> usize {
+        self.free
+    }
+
+    pub fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let size = Self::block_size(layout);
+        let align = layout.align().max(GRANULARITY);
+
+        let mut previous: Option<NonNull<FreeBlock>> = None;
+        let mut current = self.head;
+        while let Some(block_ptr) = current {
+            // SAFETY: all blocks in the list are valid free blocks
+            let block = unsafe { block_ptr.as_ptr().read() };
+            let block_start = block_ptr.as_ptr() as usize;
+            let block_end = block_start + block.size;
+            let start = align_up(block_start, align);
+            let end = start + size;
+
+            if end <= block_end {
+                // unlink the block, then return unused parts at its front and back
+                self.set_next(previous, block.next);
+                self.free -= block.size;
+                if end < block_end {
+                    unsafe { self.insert(end, block_end - end) };
+                }
+                if start > block_start {
+                    unsafe { self.insert(block_start, start - block_start) };
+                }
+                return NonNull::new(start as *mut u8);
+            }
+
+            previous = current;
+            current = block.next;
+        }
+        None
+    }
+
+    /// Returns previously allocated memory to the heap
+    ///
+    /// # Safety
+    /// Pointer must have been returned by `allocate` of this heap with the same layout
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.insert(ptr.as_ptr() as usize, Self::block_size(layout));
+    }
+
+    fn block_size(layout: Layout) -> usize {
+        align_up(layout.size().max(1), GRANULARITY)
+    }
+
+    /// Inserts a free block in address order, merging it with adjacent blocks
+    unsafe fn insert(&mut self, start: usize, size: usize) {
+        self.free += size;
+
+        let mut previous: Option<NonNull<FreeBlock>> = None;
+        let mut current = self.head;
+        while let Some(block_ptr) = current {
+            if block_ptr.as_ptr() as usize > start {
+                break;
+            }
+            previous = current;
+            current = block_ptr.as_ref().next;
+        }
+
+        let mut block = NonNull::new_unchecked(start as *mut FreeBlock);
+        block.as_ptr().write(FreeBlock {
+            size,
+            next: current,
+        });
+        self.set_next(previous, Some(block));
+
+        if let Some(next) = current {
+            if start + size == next.as_ptr() as usize {
+                let next = next.as_ptr().read();
+                block.as_mut().size += next.size;
+                block.as_mut().next = next.next;
+            }
+        }
+        if let Some(mut previous) = previous {
+            if previous.as_ptr() as usize + previous.as_ref().size == start {
+                let block = block.as_ptr().read();
+                previous.as_mut().size += block.size;
+                previous.as_mut().next = block.next;
+            }
+        }
+    }
+
+    fn set_next(&mut self, block: Option<NonNull<FreeBlock>>, next: Option<NonNull<FreeBlock>>) {
+        match block {
+            // SAFETY: all blocks in the list are valid free blocks
+            Some(mut block) => unsafe { block.as_mut().next = next },
+            None => self.head = next,
+        }
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn align_up(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::alloc::Layout;
+    use std::{boxed::Box, vec::Vec};
+
+    use super::Heap;
+
+    #[repr(align(4096))]
+    struct Arena([u8; 0x4000]);
+
+    fn heap_with_arena() -> (Heap, Box<Arena>) {
+        let arena = Box::new(Arena([0; 0x4000]));
+        let mut heap = Heap::new();
+        unsafe { heap.add_region(arena.0.as_ptr() as usize, arena.0.len()) };
+        (heap, arena)
+    }
+
+    #[test]
+    fn test_allocate_and_free() {
+        let (mut heap, _arena) = heap_with_arena();
+        assert_eq!(heap.free(), 0x4000);
+
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let a = heap.allocate(layout).unwrap();
+        let b = heap.allocate(layout).unwrap();
+        assert_ne!(a, b);
+        assert_eq!(heap.free(), 0x4000 - 2 * 112);
+
+        unsafe {
+            heap.deallocate(a, layout);
+            heap.deallocate(b, layout);
+        }
+        assert_eq!(heap.free(), 0x4000);
+        // free blocks were merged back into one
+        let whole = Layout::from_size_align(0x4000, 16).unwrap();
+        assert!(heap.allocate(whole).is_some());
+    }
+
+    #[test]
+    fn test_alignment() {
+        let (mut heap, arena) = heap_with_arena();
+        let small = Layout::from_size_align(16, 16).unwrap();
+        let page = Layout::from_size_align(4096, 4096).unwrap();
+
+        let first = heap.allocate(small).unwrap();
+        let aligned = heap.allocate(page).unwrap();
+        assert_eq!(aligned.as_ptr() as usize % 4096, 0);
+        assert_eq!(aligned.as_ptr() as usize, arena.0.as_ptr() as usize + 4096);
+
+        // the gap between allocations is still usable
+        let second = heap.allocate(small).unwrap();
+        assert!((second.as_ptr() as usize) < aligned.as_ptr() as usize);
+
+        unsafe {
+            heap.deallocate(first, small);
+            heap.deallocate(second, small);
+            heap.deallocate(aligned, page);
+        }
+        assert_eq!(heap.free(), heap.total());
+    }
+
+    #[test]
+    fn test_exhaustion() {
+        let (mut heap, _arena) = heap_with_arena();
+        let layout = Layout::from_size_align(1024, 16).unwrap();
+        let blocks: Vec<_> = (0..16).map(|_| heap.allocate(layout).unwrap()).collect();
+        assert!(heap.allocate(layout).is_none());
+
+        for block in blocks.iter().step_by(2) {
+            unsafe { heap.deallocate(*block, layout) };
+        }
+        assert!(heap
+            .allocate(Layout::from_size_align(2048, 16).unwrap())
+            .is_none());
+        assert!(heap.allocate(layout).is_some());
+    }
+}
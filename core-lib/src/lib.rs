@@ -2,4 +2,7 @@
 
 //! A platform-independent, testable library with facilities for no_std development
 
+pub mod heap;
 pub mod sync;
+pub mod syscall;
+pub mod time;
@@ -0,0 +1,793 @@
+//! System call ABI shared by the kernel and user programs
+//!
+//! Arguments are passed in `a0`-`a5` and the call number in `a7`, following RISC-V Linux.
+//! Numbers are the same as Linux ones, so they stay stable as calls are added. `a0` holds
+//! the result, which is a negated [`Errno`] on failure.
+
+/// Controls a device, e.g. a terminal with `TC*` requests: `ioctl(fd, request, argument) -> 0`
+pub const IOCTL: usize = 29;
+/// Opens a file: `openat(dirfd, path, flags, mode) -> fd`
+pub const OPENAT: usize = 56;
+/// Closes a file descriptor: `close(fd) -> 0`
+pub const CLOSE: usize = 57;
+/// Creates a pipe, storing its read and write descriptors as two `i32`s:
+/// `pipe2(fds, flags) -> 0`
+pub const PIPE2: usize = 59;
+/// Reads [`Dirent`] records of a directory: `getdents64(fd, buffer, length) -> read`
+pub const GETDENTS64: usize = 61;
+/// Moves the offset of a file: `lseek(fd, offset, whence) -> offset`
+pub const LSEEK: usize = 62;
+/// Reads bytes from a file descriptor: `read(fd, buffer, length) -> read`
+pub const READ: usize = 63;
+/// Writes bytes to a file descriptor: `write(fd, buffer, length) -> written`
+pub const WRITE: usize = 64;
+/// Gets a [`Stat`] of a path: `newfstatat(dirfd, path, stat, flags) -> 0`
+pub const NEWFSTATAT: usize = 79;
+/// Gets a [`Stat`] of a file descriptor: `fstat(fd, stat) -> 0`
+pub const FSTAT: usize = 80;
+/// Writes modified data of all filesystems to disks: `sync() -> 0`
+pub const SYNC: usize = 81;
+/// Ends the calling process: `exit(code) -> !`
+pub const EXIT: usize = 93;
+/// Blocks for a time given by a [`Timespec`]: `nanosleep(request, remaining) -> 0`
+pub const NANOSLEEP: usize = 101;
+/// Lets other tasks run: `sched_yield() -> 0`
+pub const SCHED_YIELD: usize = 124;
+/// Sends a signal to a process, only checking that it exists for signal 0: `kill(pid, signal)
+/// -> 0`
+pub const KILL: usize = 129;
+/// Installs a [`SigAction`] for a signal, storing the previous one if `old` is not null:
+/// `rt_sigaction(signal, action, old, sigsetsize) -> 0`
+pub const RT_SIGACTION: usize = 134;
+/// Changes the mask of blocked signals with `SIG_BLOCK`, `SIG_UNBLOCK` or `SIG_SETMASK`,
+/// storing the previous one if `old` is not null: `rt_sigprocmask(how, set, old, sigsetsize)
+/// -> 0`
+pub const RT_SIGPROCMASK: usize = 135;
+/// Returns from a signal handler, restoring registers saved when it was entered. Called by
+/// the restorer of a [`SigAction`]: `rt_sigreturn() -> !`
+pub const RT_SIGRETURN: usize = 139;
+/// Returns id of the calling process: `getpid() -> pid`
+pub const GETPID: usize = 172;
+/// Creates a socket, only `AF_INET` ones of type `SOCK_STREAM` or `SOCK_DGRAM` being
+/// supported: `socket(domain, type, protocol) -> fd`
+pub const SOCKET: usize = 198;
+/// Gives a socket a local [`SockAddrIn`], port 0 picking a free one:
+/// `bind(fd, address, length) -> 0`
+pub const BIND: usize = 200;
+/// Makes a stream socket accept connections, up to `backlog` of which wait to be accepted:
+/// `listen(fd, backlog) -> 0`
+pub const LISTEN: usize = 201;
+/// Blocks until a connection to a listening socket is established, returning a socket for it
+/// and storing the address of the peer if `address` is not null:
+/// `accept(fd, address, length) -> fd`
+pub const ACCEPT: usize = 202;
+/// Connects a stream socket, blocking until the connection is established, or sets the peer
+/// a datagram socket sends to by default and only receives from:
+/// `connect(fd, address, length) -> 0`
+pub const CONNECT: usize = 203;
+/// Stores the local [`SockAddrIn`] of a socket: `getsockname(fd, address, length) -> 0`,
+/// where `length` points to the size of the buffer
+pub const GETSOCKNAME: usize = 204;
+/// Sends a datagram, to the connected peer if `address` is null. `flags` have to be 0:
+/// `sendto(fd, buffer, length, flags, address, address_length) -> sent`
+pub const SENDTO: usize = 206;
+/// Receives a datagram, storing its sender if `address` is not null. `flags` have to be 0 and
+/// bytes past `length` are discarded: `recvfrom(fd, buffer, length, flags, address, address_length) -> received`
+pub const RECVFROM: usize = 207;
+/// Unmaps pages of a range: `munmap(address, length) -> 0`
+pub const MUNMAP: usize = 215;
+/// Creates a child process with a copy-on-write copy of the address space, in which the call
+/// returns 0. Only fork-like use is supported, with an exit signal in `flags` and no stack:
+/// `clone(flags, stack, parent_tid, tls, child_tid) -> child pid`
+pub const CLONE: usize = 220;
+/// Replaces the program of the calling process, passing it null-terminated arguments:
+/// `execve(path, argv, envp) -> argc`
+pub const EXECVE: usize = 221;
+/// Maps anonymous memory or a file: `mmap(address, length, protection, flags, fd, offset) ->
+/// address`
+pub const MMAP: usize = 222;
+/// Changes protection of mapped pages: `mprotect(address, length, protection) -> 0`
+pub const MPROTECT: usize = 226;
+/// Waits for a child, any one for pid -1, to exit, storing its status:
+/// `wait4(pid, status, options, rusage) -> pid`
+pub const WAIT4: usize = 260;
+
+// calls specific to losgatos, numbered above all Linux ones
+
+/// Creates an IPC endpoint, returning a handle with all rights: `endpoint_create() -> handle`
+pub const ENDPOINT_CREATE: usize = 500;
+/// Makes another handle to the same object with some of the rights of the original one:
+/// `handle_duplicate(handle, rights) -> handle`
+pub const HANDLE_DUPLICATE: usize = 501;
+/// Closes a handle: `handle_close(handle) -> 0`
+pub const HANDLE_CLOSE: usize = 502;
+/// Sends an [`IpcMessage`] to an endpoint and blocks until it is replied to:
+/// `ipc_send(handle, message, reply) -> 0`
+pub const IPC_SEND: usize = 503;
+/// Blocks until a message is sent to an endpoint, returning a handle to reply with:
+/// `ipc_receive(handle, message) -> reply handle`
+pub const IPC_RECEIVE: usize = 504;
+/// Replies to a received message, closing the reply handle: `ipc_reply(handle, message) -> 0`
+pub const IPC_REPLY: usize = 505;
+/// Creates a zeroed memory object of at least `size` bytes, which can be shared by passing
+/// its handle: `memory_create(size) -> handle`
+pub const MEMORY_CREATE: usize = 506;
+/// Maps pages of a memory object, shared with all other mappings of it:
+/// `memory_map(handle, length, protection, offset) -> address`
+pub const MEMORY_MAP: usize = 507;
+/// Sends `SIGALRM` after a number of seconds, cancelling the alarm set before if it is 0.
+/// Missing from the RISC-V Linux table: `alarm(seconds) -> seconds left of the previous one`
+pub const ALARM: usize = 508;
+
+pub const PROT_READ: usize = 0x1;
+pub const PROT_WRITE: usize = 0x2;
+pub const PROT_EXEC: usize = 0x4;
+
+/// Makes `wait4` return 0 instead of blocking if no child has exited
+pub const WNOHANG: usize = 1;
+
+/// Status reported by `wait4` for a process which exited with a code
+pub fn exited_status(code: i32) -> i32 {
+    (code & 0xff) << 8
+}
+
+/// Status reported by `wait4` for a process terminated by a signal
+pub fn signaled_status(signal: usize, core_dumped: bool) -> i32 {
+    (signal & 0x7f) as i32 | if core_dumped { 0x80 } else { 0 }
+}
+
+/// Exit code of a process which exited normally, given its `wait4` status
+pub fn exit_code(status: i32) -> Option<i32> {
+    (status & 0x7f == 0).then_some((status >> 8) & 0xff)
+}
+
+/// Signal which terminated a process, given its `wait4` status
+pub fn term_signal(status: i32) -> Option<usize> {
+    let signal = (status & 0x7f) as usize;
+    (signal != 0).then_some(signal)
+}
+
+/// Whether a process terminated by a signal had its state dumped, given its `wait4` status
+pub fn core_dumped(status: i32) -> bool {
+    term_signal(status).is_some() && status & 0x80 != 0
+}
+
+// signals, with their default actions
+/// Hangup of the terminal, terminates
+pub const SIGHUP: usize = 1;
+/// Interrupt character typed on the terminal, terminates
+pub const SIGINT: usize = 2;
+/// Quit request, terminates with a core dump
+pub const SIGQUIT: usize = 3;
+/// Illegal instruction, terminates with a core dump
+pub const SIGILL: usize = 4;
+/// Breakpoint, terminates with a core dump
+pub const SIGTRAP: usize = 5;
+/// Abort request, terminates with a core dump
+pub const SIGABRT: usize = 6;
+/// Access to memory which cannot be provided, e.g. past the end of a mapped file, or
+/// a misaligned one. Terminates with a core dump
+pub const SIGBUS: usize = 7;
+/// Arithmetic error, terminates with a core dump
+pub const SIGFPE: usize = 8;
+/// Terminates, cannot be caught, ignored or blocked
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+/// Access to unmapped memory or against its protection, terminates with a core dump
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+/// Write to a pipe without readers, terminates
+pub const SIGPIPE: usize = 13;
+/// Alarm set with `alarm` went off, terminates
+pub const SIGALRM: usize = 14;
+/// Termination request, terminates
+pub const SIGTERM: usize = 15;
+/// Child exited, ignored by default. The only signal `clone` accepts to be sent on exit
+pub const SIGCHLD: usize = 17;
+/// Continues a stopped process, ignored otherwise
+pub const SIGCONT: usize = 18;
+/// Stops the process, cannot be caught, ignored or blocked
+pub const SIGSTOP: usize = 19;
+/// Stop request from the terminal, stops the process
+pub const SIGTSTP: usize = 20;
+/// Number of signals, which are numbered from 1
+pub const NSIG: usize = 64;
+
+/// Restores the default action of a signal
+pub const SIG_DFL: usize = 0;
+/// Ignores a signal
+pub const SIG_IGN: usize = 1;
+
+// ways of changing the signal mask with `rt_sigprocmask`
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+// flags of signal actions
+/// Restorer of the action is valid. Handlers are only entered if it is set
+pub const SA_RESTORER: usize = 0x0400_0000;
+/// Does not block the signal while its handler runs
+pub const SA_NODEFER: usize = 0x4000_0000;
+/// Restores the default action once the handler is entered
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+/// Set of signals, as passed to `rt_sigprocmask` and in a [`SigAction`]
+pub type SigSet = u64;
+
+/// Set with a single signal
+pub const fn sigmask(signal: usize) -> SigSet {
+    1 << (signal - 1)
+}
+
+/// Action taken on a signal, as passed to `rt_sigaction`
+///
+/// A handler is called with the signal number in `a0` and returns to `restorer`, which has to
+/// make `rt_sigreturn`. Signals in `mask` are blocked while it runs.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SigAction {
+    /// Handler address, `SIG_DFL` or `SIG_IGN`
+    pub handler: usize,
+    /// `SA_*` flags
+    pub flags: usize,
+    pub restorer: usize,
+    pub mask: SigSet,
+}
+
+pub const AF_INET: usize = 2;
+pub const SOCK_STREAM: usize = 1;
+pub const SOCK_DGRAM: usize = 2;
+/// Protocol of stream sockets, which may also be given as 0
+pub const IPPROTO_TCP: usize = 6;
+/// Protocol of datagram sockets, which may also be given as 0
+pub const IPPROTO_UDP: usize = 17;
+
+/// IPv4 socket address, as passed to `bind`, `connect`, `accept`, `sendto` and `recvfrom`.
+/// The port and address are in network byte order
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SockAddrIn {
+    /// Always `AF_INET`
+    pub family: u16,
+    pub port: [u8; 2],
+    pub address: [u8; 4],
+    pub zero: [u8; 8],
+}
+
+impl SockAddrIn {
+    pub fn new(address: [u8; 4], port: u16) -> SockAddrIn {
+        SockAddrIn {
+            family: AF_INET as u16,
+            port: port.to_be_bytes(),
+            address,
+            zero: [0; 8],
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        u16::from_be_bytes(self.port)
+    }
+}
+
+pub const MAP_SHARED: usize = 0x01;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+/// `dirfd` standing for the working directory, which is always the root
+pub const AT_FDCWD: usize = -100isize as usize;
+/// Makes `newfstatat` describe a symlink instead of its target
+pub const AT_SYMLINK_NOFOLLOW: usize = 0x100;
+
+pub const O_RDONLY: usize = 0;
+pub const O_WRONLY: usize = 0o1;
+pub const O_RDWR: usize = 0o2;
+pub const O_CREAT: usize = 0o100;
+pub const O_EXCL: usize = 0o200;
+pub const O_TRUNC: usize = 0o1000;
+pub const O_APPEND: usize = 0o2000;
+pub const O_DIRECTORY: usize = 0o200000;
+pub const O_NOFOLLOW: usize = 0o400000;
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+/// File type bits of a mode
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFIFO: u32 = 0o010000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+pub const S_IFSOCK: u32 = 0o140000;
+
+pub const DT_UNKNOWN: u8 = 0;
+pub const DT_FIFO: u8 = 1;
+pub const DT_CHR: u8 = 2;
+pub const DT_DIR: u8 = 4;
+pub const DT_REG: u8 = 8;
+pub const DT_LNK: u8 = 10;
+pub const DT_SOCK: u8 = 12;
+
+/// File metadata, as returned by `fstat` and `newfstatat`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stat {
+    pub inode: u64,
+    /// File type and permission bits
+    pub mode: u32,
+    pub links: u32,
+    pub size: u64,
+}
+
+/// Time interval, as passed to `nanosleep`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timespec {
+    pub seconds: i64,
+    pub nanoseconds: i64,
+}
+
+// rights of handles
+/// Sending messages to an endpoint, or replying with a reply handle
+pub const RIGHT_SEND: u32 = 1 << 0;
+/// Receiving messages from an endpoint
+pub const RIGHT_RECEIVE: u32 = 1 << 1;
+/// Passing the handle in a message
+pub const RIGHT_TRANSFER: u32 = 1 << 2;
+/// Making more handles with `handle_duplicate`
+pub const RIGHT_DUPLICATE: u32 = 1 << 3;
+/// Mapping a memory object readable or executable
+pub const RIGHT_READ: u32 = 1 << 4;
+/// Mapping a memory object writeable
+pub const RIGHT_WRITE: u32 = 1 << 5;
+
+/// Most bytes of data in an IPC message
+pub const IPC_MAX_DATA: usize = 256;
+/// Most handles moved with an IPC message
+pub const IPC_MAX_HANDLES: usize = 4;
+
+/// Message passed with `ipc_send`, `ipc_receive` and `ipc_reply`
+///
+/// Handles listed in a sent message are moved out of the sender's handle table. Received
+/// messages list their numbers in the receiver's one.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpcMessage {
+    pub length: u32,
+    pub handle_count: u32,
+    pub handles: [u32; IPC_MAX_HANDLES],
+    pub data: [u8; IPC_MAX_DATA],
+}
+
+impl IpcMessage {
+    /// Creates a message without handles, failing if the data is too long
+    pub fn new(data: &[u8]) -> Option<IpcMessage> {
+        let mut message = IpcMessage::default();
+        message.data.get_mut(..data.len())?.copy_from_slice(data);
+        message.length = data.len() as u32;
+        Some(message)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data[..(self.length as usize).min(IPC_MAX_DATA)]
+    }
+
+    pub fn handles(&self) -> &[u32] {
+        &self.handles[..(self.handle_count as usize).min(IPC_MAX_HANDLES)]
+    }
+}
+
+impl Default for IpcMessage {
+    fn default() -> Self {
+        IpcMessage {
+            length: 0,
+            handle_count: 0,
+            handles: [0; IPC_MAX_HANDLES],
+            data: [0; IPC_MAX_DATA],
+        }
+    }
+}
+
+/// Gets the [`Termios`] of a terminal
+pub const TCGETS: usize = 0x5401;
+/// Sets the [`Termios`] of a terminal
+pub const TCSETS: usize = 0x5402;
+
+// input modes
+/// Translates carriage returns to newlines
+pub const ICRNL: u32 = 0o400;
+// output modes
+/// Processes output, as set by other output modes
+pub const OPOST: u32 = 0o1;
+/// Translates newlines to carriage return and newline pairs
+pub const ONLCR: u32 = 0o4;
+// local modes
+/// Makes interrupt, quit and suspend characters send `SIGINT`, `SIGQUIT` and `SIGTSTP`
+pub const ISIG: u32 = 0o1;
+/// Canonical mode, in which input is edited and read by lines
+pub const ICANON: u32 = 0o2;
+/// Echoes input characters
+pub const ECHO: u32 = 0o10;
+/// Echoes erasing characters by erasing the previous character from the screen
+pub const ECHOE: u32 = 0o20;
+
+// indices of control characters
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VSUSP: usize = 10;
+pub const VWERASE: usize = 14;
+
+pub const NCCS: usize = 19;
+
+/// Terminal settings, as passed with `TCGETS` and `TCSETS`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Termios {
+    pub input_flags: u32,
+    pub output_flags: u32,
+    pub control_flags: u32,
+    pub local_flags: u32,
+    pub line: u8,
+    /// Control characters, indexed with `V*` constants
+    pub control_characters: [u8; NCCS],
+}
+
+impl Termios {
+    /// Settings of a terminal in canonical mode with echo, with usual control characters
+    pub fn canonical() -> Termios {
+        let mut control_characters = [0; NCCS];
+        control_characters[VINTR] = 0x03;
+        control_characters[VQUIT] = 0x1c;
+        control_characters[VERASE] = 0x7f;
+        control_characters[VKILL] = 0x15;
+        control_characters[VEOF] = 0x04;
+        control_characters[VSUSP] = 0x1a;
+        control_characters[VWERASE] = 0x17;
+        Termios {
+            input_flags: ICRNL,
+            output_flags: OPOST | ONLCR,
+            control_flags: 0,
+            local_flags: ISIG | ICANON | ECHO | ECHOE,
+            line: 0,
+            control_characters,
+        }
+    }
+
+    /// Switches to raw mode, in which bytes are passed through as they are
+    pub fn make_raw(&mut self) {
+        self.input_flags &= !ICRNL;
+        self.output_flags &= !OPOST;
+        self.local_flags &= !(ISIG | ICANON | ECHO | ECHOE);
+    }
+}
+
+/// Reason for a system call failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Errno {
+    /// Operation not permitted
+    EPERM = 1,
+    /// No such file or directory
+    ENOENT = 2,
+    /// No such process
+    ESRCH = 3,
+    /// Interrupted system call
+    EINTR = 4,
+    /// I/O error
+    EIO = 5,
+    /// Argument list too long
+    E2BIG = 7,
+    /// Exec format error
+    ENOEXEC = 8,
+    /// Bad file descriptor
+    EBADF = 9,
+    /// No child processes
+    ECHILD = 10,
+    /// Out of memory
+    ENOMEM = 12,
+    /// Permission denied
+    EACCES = 13,
+    /// Bad address
+    EFAULT = 14,
+    /// Device or resource busy
+    EBUSY = 16,
+    /// File exists
+    EEXIST = 17,
+    /// No such device
+    ENODEV = 19,
+    /// Not a directory
+    ENOTDIR = 20,
+    /// Is a directory
+    EISDIR = 21,
+    /// Invalid argument
+    EINVAL = 22,
+    /// Too many open files
+    EMFILE = 24,
+    /// Not a terminal
+    ENOTTY = 25,
+    /// File too large
+    EFBIG = 27,
+    /// No space left on device
+    ENOSPC = 28,
+    /// Illegal seek
+    ESPIPE = 29,
+    /// Read-only file system
+    EROFS = 30,
+    /// Broken pipe
+    EPIPE = 32,
+    /// File name too long
+    ENAMETOOLONG = 36,
+    /// Function not implemented
+    ENOSYS = 38,
+    /// Directory not empty
+    ENOTEMPTY = 39,
+    /// Too many levels of symbolic links
+    ELOOP = 40,
+    /// Socket operation on non-socket
+    ENOTSOCK = 88,
+    /// Destination address required
+    EDESTADDRREQ = 89,
+    /// Message too long
+    EMSGSIZE = 90,
+    /// Protocol not supported
+    EPROTONOSUPPORT = 93,
+    /// Operation not supported on transport endpoint
+    EOPNOTSUPP = 95,
+    /// Address family not supported by protocol
+    EAFNOSUPPORT = 97,
+    /// Address already in use
+    EADDRINUSE = 98,
+    /// Cannot assign requested address
+    EADDRNOTAVAIL = 99,
+    /// Network is unreachable
+    ENETUNREACH = 101,
+    /// Connection reset by peer
+    ECONNRESET = 104,
+    /// Transport endpoint is already connected
+    EISCONN = 106,
+    /// Transport endpoint is not connected
+    ENOTCONN = 107,
+    /// Connection timed out
+    ETIMEDOUT = 110,
+    /// Connection refused
+    ECONNREFUSED = 111,
+}
+
+impl Errno {
+    const ALL: [Errno; 43] = [
+        Errno::EPERM,
+        Errno::ENOENT,
+        Errno::ESRCH,
+        Errno::EINTR,
+        Errno::EIO,
+        Errno::E2BIG,
+        Errno::ENOEXEC,
+        Errno::EBADF,
+        Errno::ECHILD,
+        Errno::ENOMEM,
+        Errno::EACCES,
+        Errno::EFAULT,
+        Errno::EBUSY,
+        Errno::EEXIST,
+        Errno::ENODEV,
+        Errno::ENOTDIR,
+        Errno::EISDIR,
+        Errno::EINVAL,
+        Errno::EMFILE,
+        Errno::ENOTTY,
+        Errno::EFBIG,
+        Errno::ENOSPC,
+        Errno::ESPIPE,
+        Errno::EROFS,
+        Errno::EPIPE,
+        Errno::ENAMETOOLONG,
+        Errno::ENOSYS,
+        Errno::ENOTEMPTY,
+        Errno::ELOOP,
+        Errno::ENOTSOCK,
+        Errno::EDESTADDRREQ,
+        Errno::EMSGSIZE,
+        Errno::EPROTONOSUPPORT,
+        Errno::EOPNOTSUPP,
+        Errno::EAFNOSUPPORT,
+        Errno::EADDRINUSE,
+        Errno::EADDRNOTAVAIL,
+        Errno::ENETUNREACH,
+        Errno::ECONNRESET,
+        Errno::EISCONN,
+        Errno::ENOTCONN,
+        Errno::ETIMEDOUT,
+        Errno::ECONNREFUSED,
+    ];
+
+    pub fn from_code(code: usize) -> Option<Errno> {
+        Self::ALL.into_iter().find(|errno| *errno as usize == code)
+    }
+}
+
+/// Largest error code, results above `-MAX_ERRNO` are errors
+const MAX_ERRNO: usize = 4095;
+
+/// Encodes a result of a system call as a value of `a0`
+pub fn encode_result(result: Result<usize, Errno>) -> usize {
+    match result {
+        Ok(value) => value,
+        Err(errno) => (errno as usize).wrapping_neg(),
+    }
+}
+
+/// Decodes a value of `a0` returned by a system call
+pub fn decode_result(value: usize) -> Result<usize, Errno> {
+    if value > MAX_ERRNO.wrapping_neg() {
+        let code = value.wrapping_neg();
+        Err(Errno::from_code(code).unwrap_or(Errno::EINVAL))
+    } else {
+        Ok(value)
+    }
+}
+
+/// Directory entry, as stored by `getdents64`: a header of `inode: u64`, `offset: i64`,
+/// `length: u16` and `file_type: u8`, followed by a null-terminated name. Records are
+/// aligned to 8 bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dirent<'a> {
+    pub inode: u64,
+    /// Directory offset of the next entry
+    pub offset: i64,
+    pub file_type: u8,
+    pub name: &'a str,
+}
+
+const DIRENT_HEADER_SIZE: usize = 19;
+
+impl<'a> Dirent<'a> {
+    /// Size of the record storing the entry
+    pub fn record_length(&self) -> usize {
+        (DIRENT_HEADER_SIZE + self.name.len() + 1).next_multiple_of(8)
+    }
+
+    /// Stores the record at the beginning of a buffer, returning its length,
+    /// or `None` if the buffer is too small
+    pub fn encode(&self, buffer: &mut [u8]) -> Option<usize> {
+        let length = self.record_length();
+        let record = buffer.get_mut(..length)?;
+        record.fill(0);
+        record[0..8].copy_from_slice(&self.inode.to_ne_bytes());
+        record[8..16].copy_from_slice(&self.offset.to_ne_bytes());
+        record[16..18].copy_from_slice(&(length as u16).to_ne_bytes());
+        record[18] = self.file_type;
+        record[DIRENT_HEADER_SIZE..DIRENT_HEADER_SIZE + self.name.len()]
+            .copy_from_slice(self.name.as_bytes());
+        Some(length)
+    }
+
+    /// Iterates over records stored in a buffer, stopping at the first malformed one
+    pub fn decode_all(buffer: &'a [u8]) -> impl Iterator<Item = Dirent<'a>> {
+        let mut rest = buffer;
+        core::iter::from_fn(move || {
+            let header = rest.get(..DIRENT_HEADER_SIZE)?;
+            let length = u16::from_ne_bytes(header[16..18].try_into().unwrap()) as usize;
+            let record = rest.get(DIRENT_HEADER_SIZE..length)?;
+            let name_length = record.iter().position(|byte| *byte == 0)?;
+            let dirent = Dirent {
+                inode: u64::from_ne_bytes(header[0..8].try_into().unwrap()),
+                offset: i64::from_ne_bytes(header[8..16].try_into().unwrap()),
+                file_type: header[18],
+                name: core::str::from_utf8(&record[..name_length]).ok()?,
+            };
+            rest = &rest[length..];
+            Some(dirent)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        core_dumped, decode_result, encode_result, exit_code, exited_status, sigmask,
+        signaled_status, term_signal, Dirent, Errno, IpcMessage, SockAddrIn, DT_DIR, DT_REG,
+        IPC_MAX_DATA, NSIG, SIGINT, SIGKILL, SIGSEGV,
+    };
+
+    #[test]
+    fn test_result_roundtrip() {
+        for result in [
+            Ok(0),
+            Ok(42),
+            Ok(usize::MAX / 2),
+            Err(Errno::EFAULT),
+            Err(Errno::ENOSYS),
+        ] {
+            assert_eq!(decode_result(encode_result(result)), result);
+        }
+    }
+
+    #[test]
+    fn test_errors_are_negative() {
+        assert_eq!(encode_result(Err(Errno::EINVAL)) as isize, -22);
+        assert_eq!(decode_result(-9isize as usize), Err(Errno::EBADF));
+    }
+
+    #[test]
+    fn test_exit_status() {
+        assert_eq!(exited_status(3), 0x300);
+        assert_eq!(exit_code(exited_status(3)), Some(3));
+        // codes are truncated to a byte, like on Linux
+        assert_eq!(exit_code(exited_status(-1)), Some(255));
+        // killed by signal 9
+        assert_eq!(exit_code(9), None);
+        assert_eq!(term_signal(exited_status(3)), None);
+    }
+
+    #[test]
+    fn test_signaled_status() {
+        let killed = signaled_status(SIGKILL, false);
+        assert_eq!(term_signal(killed), Some(SIGKILL));
+        assert_eq!(exit_code(killed), None);
+        assert!(!core_dumped(killed));
+        assert!(core_dumped(signaled_status(SIGSEGV, true)));
+        // exit code 128 has the core dump bit set, but is no signal
+        assert!(!core_dumped(exited_status(128)));
+    }
+
+    #[test]
+    fn test_sigmask() {
+        assert_eq!(sigmask(1), 1);
+        assert_eq!(sigmask(SIGINT), 0b10);
+        assert_eq!(sigmask(NSIG), 1 << 63);
+    }
+
+    #[test]
+    fn test_sockaddr_in() {
+        let address = SockAddrIn::new([10, 0, 2, 15], 0x1234);
+        assert_eq!(core::mem::size_of::<SockAddrIn>(), 16);
+        assert_eq!(address.family, 2);
+        assert_eq!(address.port, [0x12, 0x34]);
+        assert_eq!(address.port(), 0x1234);
+    }
+
+    #[test]
+    fn test_dirent_roundtrip() {
+        let dirents = [
+            Dirent {
+                inode: 1,
+                offset: 1,
+                file_type: DT_DIR,
+                name: ".",
+            },
+            Dirent {
+                inode: 7,
+                offset: 2,
+                file_type: DT_REG,
+                name: "a-longer-file-name",
+            },
+        ];
+        let mut buffer = [0u8; 128];
+        let mut length = 0;
+        for dirent in &dirents {
+            length += dirent.encode(&mut buffer[length..]).unwrap();
+        }
+        assert_eq!(length % 8, 0);
+        assert!(Dirent::decode_all(&buffer[..length]).eq(dirents));
+        assert_eq!(dirents[1].encode(&mut buffer[..16]), None);
+    }
+
+    #[test]
+    fn test_ipc_message_data() {
+        let message = IpcMessage::new(b"ping").unwrap();
+        assert_eq!(message.data(), b"ping");
+        assert!(message.handles().is_empty());
+        assert_eq!(IpcMessage::new(&[0; IPC_MAX_DATA + 1]), None);
+
+        // lengths coming from user programs are not trusted
+        let mut message = IpcMessage::new(b"ping").unwrap();
+        message.length = u32::MAX;
+        message.handle_count = 100;
+        assert_eq!(message.data().len(), IPC_MAX_DATA);
+        assert_eq!(message.handles().len(), 4);
+    }
+}
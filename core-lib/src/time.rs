@@ -0,0 +1,93 @@
+//! Calendar calculations
+
+use core::fmt::Display;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A point in time broken down into calendar fields (proleptic Gregorian calendar, UTC)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u64,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub nanosecond: u32,
+}
+
+impl DateTime {
+    /// Converts a number of seconds and nanoseconds since the UNIX epoch into calendar fields
+    pub fn from_unix(seconds: u64, nanosecond: u32) -> DateTime {
+        let days = seconds / SECONDS_PER_DAY;
+        let seconds_of_day = seconds % SECONDS_PER_DAY;
+
+        // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = days + 719468;
+        let era = z / 146097;
+        let day_of_era = z - era * 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        DateTime {
+            year,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds_of_day / 3600) as u8,
+            minute: (seconds_of_day / 60 % 60) as u8,
+            second: (seconds_of_day % 60) as u8,
+            nanosecond,
+        }
+    }
+}
+
+impl Display for DateTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:06}",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            self.nanosecond / 1000
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::DateTime;
+    use std::format;
+
+    #[test]
+    fn test_epoch() {
+        let date = DateTime::from_unix(0, 0);
+        assert_eq!((date.year, date.month, date.day), (1970, 1, 1));
+        assert_eq!((date.hour, date.minute, date.second), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_leap_day() {
+        // 2024-02-29 13:37:42 UTC
+        let date = DateTime::from_unix(1709213862, 500_000_000);
+        assert_eq!((date.year, date.month, date.day), (2024, 2, 29));
+        assert_eq!((date.hour, date.minute, date.second), (13, 37, 42));
+        assert_eq!(date.nanosecond, 500_000_000);
+    }
+
+    #[test]
+    fn test_display() {
+        let date = DateTime::from_unix(951782400, 1_234_000);
+        assert_eq!(format!("{}", date), "2000-02-29 00:00:00.001234");
+    }
+}
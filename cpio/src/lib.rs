@@ -0,0 +1,293 @@
+#![no_std]
+
+//! Reader of cpio archives in the "new ASCII" (newc) format, used for initial ramdisks
+//!
+//! Every entry is a 110-byte header of hexadecimal fields, followed by a null-terminated
+//! name and the file data, both padded to 4 bytes. The archive ends with a `TRAILER!!!` entry.
+
+use core::str;
+
+use snafu::Snafu;
+
+const HEADER_SIZE: usize = 110;
+const MAGIC: &[u8] = b"070701";
+/// Magic of archives with checksums, which are not verified
+const MAGIC_CRC: &[u8] = b"070702";
+const TRAILER: &str = "TRAILER!!!";
+const FIELD_SIZE: usize = 8;
+const MODE_FIELD: usize = 1;
+const FILE_SIZE_FIELD: usize = 6;
+const NAME_SIZE_FIELD: usize = 11;
+
+const MODE_TYPE_MASK: u32 = 0o170000;
+const MODE_REGULAR: u32 = 0o100000;
+const MODE_DIRECTORY: u32 = 0o040000;
+const MODE_SYMLINK: u32 = 0o120000;
+
+#[derive(Debug, Snafu, PartialEq, Eq)]
+pub enum CpioError {
+    #[snafu(display("Invaild entry magic at offset {offset}"))]
+    InvaildMagic { offset: usize },
+    #[snafu(display("Invaild header field at offset {offset}"))]
+    InvaildField { offset: usize },
+    #[snafu(display("Invaild entry name at offset {offset}"))]
+    InvaildName { offset: usize },
+    #[snafu(display("Archive truncated at offset {offset}"))]
+    Truncated { offset: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    Other,
+}
+
+/// A cpio newc archive kept in memory
+#[derive(Clone, Copy)]
+pub struct Archive<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Archive<'a> {
+    pub fn new(bytes: &'a [u8]) -> Archive<'a> {
+        Archive { bytes }
+    }
+
+    /// Iterates over entries of the archive, stopping at the trailer or the first error
+    pub fn entries(&self) -> Entries<'a> {
+        Entries {
+            bytes: self.bytes,
+            offset: 0,
+            finished: false,
+        }
+    }
+
+    /// Finds an entry by its path, with or without leading `/` or `./`
+    pub fn find(&self, path: &str) -> Result<Option<Entry<'a>>, CpioError> {
+        let path = normalize(path);
+        for entry in self.entries() {
+            let entry = entry?;
+            if entry.path() == path {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A file, directory or other node stored in an archive
+#[derive(Debug, Clone, Copy)]
+pub struct Entry<'a> {
+    name: &'a str,
+    mode: u32,
+    data: &'a [u8],
+}
+
+impl<'a> Entry<'a> {
+    /// Name as stored in the archive
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Name relative to the archive root, without leading `/` or `./`. Empty for the root
+    pub fn path(&self) -> &'a str {
+        normalize(self.name)
+    }
+
+    /// Unix mode, including the file type bits
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    /// Permission bits of the mode
+    pub fn permissions(&self) -> u32 {
+        self.mode & !MODE_TYPE_MASK
+    }
+
+    pub fn file_type(&self) -> FileType {
+        match self.mode & MODE_TYPE_MASK {
+            MODE_REGULAR => FileType::Regular,
+            MODE_DIRECTORY => FileType::Directory,
+            MODE_SYMLINK => FileType::Symlink,
+            _ => FileType::Other,
+        }
+    }
+
+    /// Contents of a file, or the target of a symlink
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+pub struct Entries<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    finished: bool,
+}
+
+impl<'a> Entries<'a> {
+    fn read_entry(&mut self) -> Result<Option<Entry<'a>>, CpioError> {
+        let offset = self.offset;
+        let header = self.slice(offset, HEADER_SIZE)?;
+        if &header[..MAGIC.len()] != MAGIC && &header[..MAGIC.len()] != MAGIC_CRC {
+            return Err(CpioError::InvaildMagic { offset });
+        }
+        let field = |index: usize| {
+            let start = MAGIC.len() + index * FIELD_SIZE;
+            str::from_utf8(&header[start..start + FIELD_SIZE])
+                .ok()
+                .and_then(|field| u32::from_str_radix(field, 16).ok())
+                .ok_or(CpioError::InvaildField {
+                    offset: offset + start,
+                })
+        };
+        let mode = field(MODE_FIELD)?;
+        let file_size = field(FILE_SIZE_FIELD)? as usize;
+        let name_size = field(NAME_SIZE_FIELD)? as usize;
+
+        let name_offset = offset + HEADER_SIZE;
+        let name = self
+            .slice(name_offset, name_size)?
+            .strip_suffix(&[0])
+            .and_then(|name| str::from_utf8(name).ok())
+            .ok_or(CpioError::InvaildName {
+                offset: name_offset,
+            })?;
+        let data_offset = align(name_offset + name_size);
+        let data = self.slice(data_offset, file_size)?;
+        self.offset = align(data_offset + file_size);
+
+        if name == TRAILER {
+            return Ok(None);
+        }
+        Ok(Some(Entry { name, mode, data }))
+    }
+
+    fn slice(&self, offset: usize, length: usize) -> Result<&'a [u8], CpioError> {
+        offset
+            .checked_add(length)
+            .and_then(|end| self.bytes.get(offset..end))
+            .ok_or(CpioError::Truncated { offset })
+    }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<Entry<'a>, CpioError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let entry = self.read_entry().transpose();
+        if !matches!(entry, Some(Ok(_))) {
+            self.finished = true;
+        }
+        entry
+    }
+}
+
+fn align(offset: usize) -> usize {
+    offset.next_multiple_of(4)
+}
+
+fn normalize(path: &str) -> &str {
+    let mut path = path;
+    loop {
+        if let Some(rest) = path.strip_prefix("./") {
+            path = rest;
+        } else if let Some(rest) = path.strip_prefix('/') {
+            path = rest;
+        } else if path == "." {
+            return "";
+        } else {
+            return path;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::{format, vec::Vec};
+
+    use crate::{Archive, CpioError, FileType};
+
+    fn push_entry(archive: &mut Vec<u8>, name: &str, mode: u32, data: &[u8]) {
+        let header = format!(
+            "070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+            0,
+            mode,
+            0,
+            0,
+            1,
+            0,
+            data.len(),
+            0,
+            0,
+            0,
+            0,
+            name.len() + 1,
+            0
+        );
+        archive.extend_from_slice(header.as_bytes());
+        archive.extend_from_slice(name.as_bytes());
+        archive.push(0);
+        archive.resize(archive.len().next_multiple_of(4), 0);
+        archive.extend_from_slice(data);
+        archive.resize(archive.len().next_multiple_of(4), 0);
+    }
+
+    fn sample() -> Vec<u8> {
+        let mut archive = Vec::new();
+        push_entry(&mut archive, ".", 0o040755, &[]);
+        push_entry(&mut archive, "./init", 0o100755, b"\x7fELF");
+        push_entry(&mut archive, "./etc", 0o040755, &[]);
+        push_entry(&mut archive, "./etc/motd", 0o100644, b"hello\n");
+        push_entry(&mut archive, "TRAILER!!!", 0, &[]);
+        archive
+    }
+
+    #[test]
+    fn test_entries() {
+        let bytes = sample();
+        let archive = Archive::new(&bytes);
+        let entries: Vec<_> = archive.entries().map(Result::unwrap).collect();
+        let paths: Vec<_> = entries.iter().map(|entry| entry.path()).collect();
+        assert_eq!(paths, ["", "init", "etc", "etc/motd"]);
+        assert_eq!(entries[2].file_type(), FileType::Directory);
+        assert_eq!(entries[3].permissions(), 0o644);
+        assert_eq!(entries[3].data(), b"hello\n");
+    }
+
+    #[test]
+    fn test_find() {
+        let bytes = sample();
+        let archive = Archive::new(&bytes);
+        let init = archive.find("/init").unwrap().unwrap();
+        assert_eq!(init.file_type(), FileType::Regular);
+        assert_eq!(init.data(), b"\x7fELF");
+        assert!(archive.find("/missing").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_invaild_archives() {
+        let bytes = sample();
+        let truncated = Archive::new(&bytes[..bytes.len() - 20]);
+        assert!(matches!(
+            truncated.entries().last(),
+            Some(Err(CpioError::Truncated { .. }))
+        ));
+
+        let mut corrupted = bytes.clone();
+        corrupted[0] = b'1';
+        let mut entries = Archive::new(&corrupted).entries();
+        assert_eq!(
+            entries.next().unwrap().unwrap_err(),
+            CpioError::InvaildMagic { offset: 0 }
+        );
+        assert!(entries.next().is_none());
+    }
+}
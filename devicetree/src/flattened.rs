@@ -2,7 +2,10 @@
 
 use core::{ffi::CStr, slice};
 
-use crate::{node::CellSizes, DeviceTreeError};
+use crate::{
+    node::{node_end, CellSizes, FDT_BEGIN_NODE, FDT_END_NODE, FDT_PROP},
+    DeviceTreeError,
+};
 
 use super::node::NodeRef;
 
@@ -49,7 +52,7 @@ impl<'dt> FlattenedDeviceTree<'dt> {
         }
     }
     /// Returns a reference to a device tree's root node
-    pub fn root(&self) -> Result<NodeRef, DeviceTreeError> {
+    pub fn root(&self) -> Result<NodeRef<'_>, DeviceTreeError> {
         const FDT_END: FdtCell = 0x00000009;
         debug_assert_eq!(self.structure[self.structure.len() - 1].to_be(), FDT_END);
         NodeRef::from_slice(
@@ -59,6 +62,29 @@ impl<'dt> FlattenedDeviceTree<'dt> {
         )
     }
 
+    /// Returns an iterator over all nodes of a device tree, in depth-first order
+    pub fn nodes(&self) -> impl Iterator<Item = NodeRef<'_>> {
+        AllNodesIterator {
+            fdt: self,
+            i: 0,
+            depth: 0,
+            cell_sizes: [CellSizes::default(); MAX_DEPTH + 1],
+        }
+    }
+
+    /// Returns all nodes with given string listed in their `compatible` property
+    pub fn find_compatible<'a>(
+        &'a self,
+        compatible: &'a str,
+    ) -> impl Iterator<Item = NodeRef<'a>> + 'a {
+        self.nodes().filter(move |n| n.is_compatible(compatible))
+    }
+
+    /// Finds a node referenced by given phandle
+    pub fn find_by_phandle(&self, phandle: u32) -> Option<NodeRef<'_>> {
+        self.nodes().find(|n| n.phandle() == Some(phandle))
+    }
+
     pub(super) fn string(&self, offset: usize) -> Result<&str, DeviceTreeError> {
         CStr::from_bytes_until_nul(&self.strings[offset..])
             .map_err(|_source| DeviceTreeError::CStringConversionFail)
@@ -73,12 +99,78 @@ impl<'dt> FlattenedDeviceTree<'dt> {
         self.header
     }
 
+    /// Returns `(address, size)` entries of the memory reservation block
+    pub fn memory_reservations(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let header_ptr = self.header as *const FdtHeader;
+        // SAFETY: reservation block is a part of a device tree, terminated by an empty entry
+        let entries: *const [u64; 2] =
+            unsafe { header_ptr.byte_offset(self.header.off_mem_rsvmap() as isize) }.cast();
+        (0..)
+            .map(move |i| unsafe { entries.add(i).read_unaligned() })
+            .map(|[address, size]| (u64::from_be(address) as usize, u64::from_be(size) as usize))
+            .take_while(|(address, size)| *address != 0 || *size != 0)
+    }
+
     unsafe fn offset_and_size_to_slice<'a, A, T>(ptr: *const A, offset: u32, size: u32) -> &'a [T] {
         let offset_ptr = ptr.byte_offset(offset as isize) as *const T;
         slice::from_raw_parts(offset_ptr, (size as usize) / size_of::<T>())
     }
 }
 
+/// Deepest nesting of nodes returned by [`FlattenedDeviceTree::nodes`]. Nodes nested deeper
+/// are skipped together with their subtrees, as cell sizes of their ancestors are not kept
+const MAX_DEPTH: usize = 16;
+
+struct AllNodesIterator<'dt> {
+    fdt: &'dt FlattenedDeviceTree<'dt>,
+    i: usize,
+    /// Number of ancestors of the next node, always up to [`MAX_DEPTH`]
+    depth: usize,
+    /// Cell sizes declared by each of currently visited node's ancestors
+    cell_sizes: [CellSizes; MAX_DEPTH + 1],
+}
+
+impl<'dt> Iterator for AllNodesIterator<'dt> {
+    type Item = NodeRef<'dt>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let structure = self.fdt.structure;
+        while self.i < structure.len() {
+            match structure[self.i].to_be() {
+                FDT_BEGIN_NODE => {
+                    let start = self.i;
+                    let end = node_end(structure, start)?;
+                    if self.depth == MAX_DEPTH {
+                        self.i = end + 1;
+                        continue;
+                    }
+                    let node = NodeRef::from_slice(
+                        self.fdt,
+                        &structure[start..end],
+                        self.cell_sizes[self.depth],
+                    )
+                    .ok()?;
+
+                    let (_, name_words_len) = NodeRef::read_name(&structure[start + 1..]).ok()?;
+                    self.i = start + 1 + name_words_len;
+                    self.depth += 1;
+                    self.cell_sizes[self.depth] = node.child_cell_sizes();
+                    return Some(node);
+                }
+                FDT_PROP => {
+                    self.i += 3 + (structure[self.i + 1].to_be() as usize).div_ceil(4);
+                }
+                FDT_END_NODE => {
+                    self.depth = self.depth.saturating_sub(1);
+                    self.i += 1;
+                }
+                _ => self.i += 1,
+            }
+        }
+        None
+    }
+}
+
 /// Device tree header, as defined in a devicetree standard
 #[repr(C)]
 #[derive(Debug)]
@@ -108,6 +200,16 @@ impl FdtHeader {
         self.magic() == Self::MAGIC_NUMBER
     }
 
+    /// Total size of the device tree blob, in bytes (in host byte order)
+    pub fn totalsize(&self) -> u32 {
+        self.totalsize.to_be()
+    }
+
+    /// Returns offset to the memory reservation block (in host byte order)
+    pub fn off_mem_rsvmap(&self) -> u32 {
+        self.off_mem_rsvmap.to_be()
+    }
+
     /// Returns offset to a struct data (in host byte order)
     pub fn off_dt_struct(&self) -> u32 {
         self.off_dt_struct.to_be()
@@ -128,3 +230,220 @@ impl FdtHeader {
         self.size_dt_strings.to_be()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::{FdtHeader, FlattenedDeviceTree, MAX_DEPTH};
+    use crate::node::{FDT_BEGIN_NODE, FDT_END_NODE, FDT_PROP};
+
+    const FDT_END: u32 = 0x00000009;
+
+    /// Builds a device tree blob, laid out as by `dtc`
+    #[derive(Default)]
+    struct Builder {
+        /// Cells of the structure block, in big-endian byte order
+        structure: Vec<u32>,
+        strings: Vec<u8>,
+    }
+
+    impl Builder {
+        fn begin(&mut self, name: &str) -> &mut Self {
+            self.structure.push(FDT_BEGIN_NODE.to_be());
+            self.push_bytes(name.as_bytes(), true);
+            self
+        }
+
+        fn end(&mut self) -> &mut Self {
+            self.structure.push(FDT_END_NODE.to_be());
+            self
+        }
+
+        fn cells(&mut self, name: &str, cells: &[u32]) -> &mut Self {
+            let bytes: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+            self.property(name, &bytes)
+        }
+
+        fn string(&mut self, name: &str, value: &str) -> &mut Self {
+            let mut bytes = value.as_bytes().to_vec();
+            bytes.push(0);
+            self.property(name, &bytes)
+        }
+
+        fn property(&mut self, name: &str, value: &[u8]) -> &mut Self {
+            let name_offset = self.strings.len();
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+            self.structure.push(FDT_PROP.to_be());
+            self.structure.push((value.len() as u32).to_be());
+            self.structure.push((name_offset as u32).to_be());
+            self.push_bytes(value, false);
+            self
+        }
+
+        /// Appends bytes to the structure block, padded with zeros to whole cells
+        fn push_bytes(&mut self, bytes: &[u8], null_terminated: bool) {
+            let mut bytes = bytes.to_vec();
+            if null_terminated {
+                bytes.push(0);
+            }
+            bytes.resize(bytes.len().next_multiple_of(4), 0);
+            let cells = bytes.chunks_exact(4);
+            self.structure
+                .extend(cells.map(|c| u32::from_ne_bytes(c.try_into().unwrap())));
+        }
+
+        /// Returns the blob as cells, so that it is aligned like the header
+        fn build(&mut self) -> Vec<u32> {
+            self.structure.push(FDT_END.to_be());
+            let header_cells = size_of::<FdtHeader>() / 4;
+            // an empty memory reservation block
+            let reservation_cells = 4;
+            let struct_offset = (header_cells + reservation_cells) * 4;
+            let struct_size = self.structure.len() * 4;
+            let strings_offset = struct_offset + struct_size;
+            let total_size = strings_offset + self.strings.len();
+
+            let header = [
+                0xd00dfeed,
+                total_size as u32,
+                struct_offset as u32,
+                strings_offset as u32,
+                (header_cells * 4) as u32,
+                17,
+                16,
+                0,
+                self.strings.len() as u32,
+                struct_size as u32,
+            ];
+            let mut blob: Vec<u32> = header.iter().map(|field: &u32| field.to_be()).collect();
+            blob.extend([0; 4]);
+            blob.extend(&self.structure);
+            let mut strings = self.strings.clone();
+            strings.resize(strings.len().next_multiple_of(4), 0);
+            blob.extend(
+                strings
+                    .chunks_exact(4)
+                    .map(|c| u32::from_ne_bytes(c.try_into().unwrap())),
+            );
+            blob
+        }
+    }
+
+    fn parse(blob: &[u32]) -> FlattenedDeviceTree<'_> {
+        unsafe { FlattenedDeviceTree::from_ptr(blob.as_ptr().cast()) }.unwrap()
+    }
+
+    fn example() -> Vec<u32> {
+        Builder::default()
+            .begin("")
+            .cells("#address-cells", &[2])
+            .cells("#size-cells", &[2])
+            .begin("soc")
+            .string("compatible", "simple-bus")
+            .cells("#address-cells", &[1])
+            .cells("#size-cells", &[1])
+            .begin("uart@1000")
+            .string("compatible", "ns16550a")
+            .cells("reg", &[0x1000, 0x100])
+            .end()
+            .begin("plic@2000")
+            .string("compatible", "riscv,plic0")
+            .cells("reg", &[0x2000, 0x10, 0x3000, 0x20])
+            .cells("phandle", &[5])
+            .end()
+            .end()
+            .begin("memory@80000000")
+            .cells("reg", &[0x0, 0x8000_0000, 0x0, 0x100_0000])
+            .end()
+            .end()
+            .build()
+    }
+
+    #[test]
+    fn test_nodes() {
+        let blob = example();
+        let fdt = parse(&blob);
+        let mut names = fdt.nodes().map(|node| node.full_name());
+        assert_eq!(names.next(), Some(""));
+        assert_eq!(names.next(), Some("soc"));
+        assert_eq!(names.next(), Some("uart@1000"));
+        assert_eq!(names.next(), Some("plic@2000"));
+        assert_eq!(names.next(), Some("memory@80000000"));
+        assert_eq!(names.next(), None);
+
+        let root = fdt.root().unwrap();
+        let soc = root.child("soc").unwrap();
+        assert_eq!(soc.children().count(), 2);
+        assert_eq!(soc.child("uart").unwrap().location(), Some(0x1000));
+    }
+
+    #[test]
+    fn test_cell_sizes() {
+        let blob = example();
+        let fdt = parse(&blob);
+        let root = fdt.root().unwrap();
+        assert_eq!((root.address_cells(), root.size_cells()), (2, 2));
+
+        let soc = root.child("soc").unwrap();
+        assert_eq!((soc.address_cells(), soc.size_cells()), (1, 1));
+        assert_eq!(soc.parent_address_cells(), 2);
+
+        // regs are read with cell sizes of the parent, also when visiting all nodes
+        for node in [soc.child("plic").unwrap(), fdt.nodes().nth(3).unwrap()] {
+            let regs: Vec<_> = node.regs().collect();
+            assert_eq!(regs, [(0x2000, 0x10), (0x3000, 0x20)]);
+        }
+        let memory = fdt.nodes().last().unwrap();
+        assert_eq!(
+            memory.regs().collect::<Vec<_>>(),
+            [(0x8000_0000, 0x100_0000)]
+        );
+    }
+
+    #[test]
+    fn test_find() {
+        let blob = example();
+        let fdt = parse(&blob);
+        let uart = fdt.find_compatible("ns16550a").next().unwrap();
+        assert_eq!(uart.full_name(), "uart@1000");
+        assert_eq!(fdt.find_compatible("simple-bus").count(), 1);
+        assert_eq!(fdt.find_compatible("virtio,mmio").count(), 0);
+
+        let plic = fdt.find_by_phandle(5).unwrap();
+        assert_eq!(plic.full_name(), "plic@2000");
+        assert_eq!(plic.phandle(), Some(5));
+        assert!(fdt.find_by_phandle(6).is_none());
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let mut builder = Builder::default();
+        builder
+            .begin("")
+            .cells("#address-cells", &[1])
+            .cells("#size-cells", &[1]);
+        for _ in 0..MAX_DEPTH + 4 {
+            builder.begin("node");
+        }
+        for _ in 0..MAX_DEPTH + 4 {
+            builder.end();
+        }
+        let blob = builder
+            .begin("device@100")
+            .cells("reg", &[0x100, 0x10])
+            .end()
+            .end()
+            .build();
+        let fdt = parse(&blob);
+
+        // the root and nodes up to the deepest nesting, then the device
+        assert_eq!(fdt.nodes().count(), MAX_DEPTH + 1);
+        let device = fdt.nodes().last().unwrap();
+        assert_eq!(device.full_name(), "device@100");
+        assert_eq!(device.regs().collect::<Vec<_>>(), [(0x100, 0x10)]);
+    }
+}
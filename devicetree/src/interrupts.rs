@@ -0,0 +1,149 @@
+//! Interrupt mapping properties
+
+use super::{node::NodeRef, value::DeviceTreeValue};
+
+/// Maximal number of cells of an interrupt specifier or an unit address
+pub const MAX_SPECIFIER_CELLS: usize = 8;
+
+/// A list of cells identifying an interrupt or an unit address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Specifier {
+    cells: [u32; MAX_SPECIFIER_CELLS],
+    len: usize,
+}
+
+impl Specifier {
+    pub fn from_cells(cells: &[u32]) -> Specifier {
+        let mut specifier = Specifier {
+            cells: [0; MAX_SPECIFIER_CELLS],
+            len: cells.len().min(MAX_SPECIFIER_CELLS),
+        };
+        specifier.cells[..specifier.len].copy_from_slice(&cells[..specifier.len]);
+        specifier
+    }
+
+    fn read(cells: &mut impl Iterator<Item = u32>, len: usize) -> Option<Specifier> {
+        if len > MAX_SPECIFIER_CELLS {
+            return None;
+        }
+        let mut specifier = Specifier {
+            cells: [0; MAX_SPECIFIER_CELLS],
+            len,
+        };
+        for cell in specifier.cells.iter_mut().take(len) {
+            *cell = cells.next()?;
+        }
+        Some(specifier)
+    }
+
+    pub fn cells(&self) -> &[u32] {
+        &self.cells[..self.len]
+    }
+
+    /// Returns a copy of the specifier with given mask applied to each cell
+    pub fn masked(&self, mask: &Specifier) -> Specifier {
+        let mut masked = *self;
+        for (cell, mask) in masked.cells.iter_mut().zip(mask.cells()) {
+            *cell &= mask;
+        }
+        masked
+    }
+}
+
+/// Single entry of an `interrupt-map` property
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptMapEntry {
+    pub child_address: Specifier,
+    pub child_interrupt: Specifier,
+    /// Phandle of the interrupt parent
+    pub parent: u32,
+    pub parent_address: Specifier,
+    pub parent_interrupt: Specifier,
+}
+
+impl<'dt> NodeRef<'dt> {
+    /// Phandle of node's interrupt parent, if it is set explicitly
+    pub fn interrupt_parent(&self) -> Option<u32> {
+        self.property("interrupt-parent").and_then(|v| v.u32().ok())
+    }
+
+    /// Value of node's `#interrupt-cells`
+    pub fn interrupt_cells(&self) -> Option<usize> {
+        self.property("#interrupt-cells")
+            .and_then(|v| v.u32().ok())
+            .map(|v| v as usize)
+    }
+
+    /// Returns `(interrupt parent phandle, interrupt specifier)` entries of node's
+    /// `interrupts-extended` property
+    pub fn interrupts_extended(&self) -> impl Iterator<Item = (u32, Specifier)> + 'dt {
+        let fdt = self.tree();
+        let mut cells = self.property("interrupts-extended").map(|v| v.cells());
+        core::iter::from_fn(move || {
+            let cells = cells.as_mut()?;
+            let parent = cells.next()?;
+            let interrupt_cells = fdt.find_by_phandle(parent)?.interrupt_cells()?;
+            Some((parent, Specifier::read(cells, interrupt_cells)?))
+        })
+    }
+
+    /// Returns entries of node's `interrupt-map` property
+    pub fn interrupt_map(&self) -> impl Iterator<Item = InterruptMapEntry> + 'dt {
+        let fdt = self.tree();
+        let child_address_cells = self.address_cells() as usize;
+        let child_interrupt_cells = self.interrupt_cells().unwrap_or(1);
+        let mut cells = self
+            .property("interrupt-map")
+            .map(|v: DeviceTreeValue<'dt>| v.cells());
+        // consecutive entries usually refer to the same parent
+        let mut last_parent: Option<(u32, usize, usize)> = None;
+
+        core::iter::from_fn(move || {
+            let cells = cells.as_mut()?;
+            let child_address = Specifier::read(cells, child_address_cells)?;
+            let child_interrupt = Specifier::read(cells, child_interrupt_cells)?;
+            let parent = cells.next()?;
+
+            let (address_cells, interrupt_cells) = match last_parent {
+                Some((phandle, address_cells, interrupt_cells)) if phandle == parent => {
+                    (address_cells, interrupt_cells)
+                }
+                _ => {
+                    let parent_node = fdt.find_by_phandle(parent)?;
+                    let address_cells = parent_node
+                        .property("#address-cells")
+                        .and_then(|v| v.u32().ok())
+                        .unwrap_or(0) as usize;
+                    let interrupt_cells = parent_node.interrupt_cells().unwrap_or(1);
+                    last_parent = Some((parent, address_cells, interrupt_cells));
+                    (address_cells, interrupt_cells)
+                }
+            };
+
+            Some(InterruptMapEntry {
+                child_address,
+                child_interrupt,
+                parent,
+                parent_address: Specifier::read(cells, address_cells)?,
+                parent_interrupt: Specifier::read(cells, interrupt_cells)?,
+            })
+        })
+    }
+
+    /// Returns `interrupt-map-mask` as masks for child unit address and interrupt specifier,
+    /// with cells not covered by the property set to all ones
+    pub fn interrupt_map_mask(&self) -> (Specifier, Specifier) {
+        let address_cells = (self.address_cells() as usize).min(MAX_SPECIFIER_CELLS);
+        let interrupt_cells = self.interrupt_cells().unwrap_or(1).min(MAX_SPECIFIER_CELLS);
+        let mut cells = self
+            .property("interrupt-map-mask")
+            .into_iter()
+            .flat_map(|v| v.cells())
+            .chain(core::iter::repeat(u32::MAX));
+
+        let address_mask = Specifier::read(&mut cells, address_cells);
+        let interrupt_mask = Specifier::read(&mut cells, interrupt_cells);
+        // reading from an infinite iterator of cells cannot fail
+        (address_mask.unwrap(), interrupt_mask.unwrap())
+    }
+}
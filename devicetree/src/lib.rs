@@ -6,12 +6,14 @@
 
 mod error;
 mod flattened;
+mod interrupts;
 mod iter;
 mod node;
 mod value;
 
 pub use error::DeviceTreeError;
 pub use flattened::{FdtHeader, FlattenedDeviceTree};
+pub use interrupts::{InterruptMapEntry, Specifier, MAX_SPECIFIER_CELLS};
 pub use iter::NodeIterExt;
 pub use node::NodeRef;
 pub use value::DeviceTreeValue;
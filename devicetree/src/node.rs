@@ -8,17 +8,22 @@ use super::{
     value::DeviceTreeValue,
 };
 
-const FDT_BEGIN_NODE: FdtCell = 0x00000001;
-const FDT_END_NODE: FdtCell = 0x00000002;
-const FDT_PROP: FdtCell = 0x00000003;
+pub(crate) const FDT_BEGIN_NODE: FdtCell = 0x00000001;
+pub(crate) const FDT_END_NODE: FdtCell = 0x00000002;
+pub(crate) const FDT_PROP: FdtCell = 0x00000003;
 
 /// Reference to a single node inside of a device tree
+#[derive(Clone, Copy)]
 pub struct NodeRef<'dt> {
     fdt: &'dt FlattenedDeviceTree<'dt>,
+    full_name: &'dt str,
     name: &'dt str,
     location: Option<usize>,
     data: &'dt [FdtCell],
+    /// Cell sizes of the parent node, used to interpret this node's properties
     cell_sizes: CellSizes,
+    /// Cell sizes declared by this node, used to interpret its children properties
+    child_cell_sizes: CellSizes,
 }
 
 impl<'dt> NodeRef<'dt> {
@@ -29,70 +34,141 @@ impl<'dt> NodeRef<'dt> {
     ) -> Result<NodeRef<'dt>, DeviceTreeError> {
         debug_assert_eq!(slice[0].to_be(), FDT_BEGIN_NODE);
 
-        let bytes: &[u8] = unsafe { slice[1..].align_to().1 };
-        let name_cstr = CStr::from_bytes_until_nul(bytes)
-            .map_err(|_| DeviceTreeError::CStringConversionFail)?;
-        let name_bytes_len = name_cstr.to_bytes().len();
-        let name_words_len = name_bytes_len / 4 + 1;
-
-        let name_str = name_cstr
-            .to_str()
-            .map_err(|source| DeviceTreeError::InvaildUTF8 { source })?;
-        let mut name_it = name_str.split('@');
+        let (full_name, name_words_len) = Self::read_name(&slice[1..])?;
+        let mut name_it = full_name.split('@');
         let name = name_it.next().unwrap_or("");
-        let location = name_it.next().and_then(|s| str::parse(s).ok());
+        let location = name_it
+            .next()
+            .and_then(|s| usize::from_str_radix(s, 16).ok());
 
         let data = &slice[name_words_len + 1..slice.len()];
 
         let mut node_ref = NodeRef {
             fdt,
+            full_name,
             name,
             data,
             location,
             cell_sizes,
+            child_cell_sizes: cell_sizes,
         };
-        node_ref.cell_sizes = CellSizes::for_node(&node_ref, &node_ref.cell_sizes)?;
+        node_ref.child_cell_sizes = CellSizes::for_node(&node_ref, &cell_sizes)?;
 
         Ok(node_ref)
     }
 
+    /// Reads a node name following `FDT_BEGIN_NODE` token, returning it with its length in cells
+    pub(super) fn read_name(cells: &[FdtCell]) -> Result<(&str, usize), DeviceTreeError> {
+        let bytes: &[u8] = unsafe { cells.align_to().1 };
+        let name_cstr = CStr::from_bytes_until_nul(bytes)
+            .map_err(|_| DeviceTreeError::CStringConversionFail)?;
+        let name_words_len = name_cstr.to_bytes().len() / 4 + 1;
+
+        let name_str = name_cstr
+            .to_str()
+            .map_err(|source| DeviceTreeError::InvaildUTF8 { source })?;
+        Ok((name_str, name_words_len))
+    }
+
     /// Retrieves node's name
-    pub fn name(&self) -> &str {
+    pub fn name(&self) -> &'dt str {
         self.name
     }
 
-    /// Retrieves node's location, if specified {
+    /// Retrieves node's name together with its unit address (`name@address`)
+    pub fn full_name(&self) -> &'dt str {
+        self.full_name
+    }
+
+    /// Retrieves node's location, if specified
     pub fn location(&self) -> Option<usize> {
         self.location
     }
 
+    /// Returns device tree this node belongs to
+    pub fn tree(&self) -> &'dt FlattenedDeviceTree<'dt> {
+        self.fdt
+    }
+
     /// If node has given property, returns it
-    pub fn property(&self, name: &str) -> Option<DeviceTreeValue> {
+    pub fn property(&self, name: &str) -> Option<DeviceTreeValue<'dt>> {
         self.properties().find(|(n, _)| *n == name).map(|(_, v)| v)
     }
 
     /// Returns an iterator returning a name-value pairs of node's properties
-    pub fn properties(&'dt self) -> impl Iterator<Item = (&'dt str, DeviceTreeValue)> {
-        PropertiesIterator { node: self, i: 0 }
+    pub fn properties(&self) -> impl Iterator<Item = (&'dt str, DeviceTreeValue<'dt>)> + 'dt {
+        PropertiesIterator { node: *self, i: 0 }
     }
 
     /// If node has child with given name, return it
-    pub fn child(&self, name: &str) -> Option<NodeRef> {
+    pub fn child(&self, name: &str) -> Option<NodeRef<'dt>> {
         self.children().find(|ch| ch.name() == name)
     }
 
     /// Returns an iterator iterating throguh all children nodes
-    pub fn children(&'dt self) -> impl Iterator<Item = NodeRef<'dt>> {
+    pub fn children(&self) -> impl Iterator<Item = NodeRef<'dt>> + 'dt {
         let mut i = 0;
         while i < self.data.len() {
             i += match self.data[i].to_be() {
                 FDT_BEGIN_NODE => break,
-                FDT_PROP => 3 + (3 + self.data[i + 1].to_be() as usize) / 4,
+                FDT_PROP => 3 + (self.data[i + 1].to_be() as usize).div_ceil(4),
                 _ => 1,
             };
         }
 
-        NodesIterator { node: self, i }
+        NodesIterator { node: *self, i }
+    }
+
+    /// Returns strings listed in node's `compatible` property
+    pub fn compatible(&self) -> impl Iterator<Item = &'dt str> {
+        self.property("compatible")
+            .into_iter()
+            .flat_map(|value| value.strings())
+    }
+
+    /// Checks whether node's `compatible` property lists given string
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible().any(|c| c == compatible)
+    }
+
+    /// Checks node's `status` property. Nodes without status are considered enabled
+    pub fn is_enabled(&self) -> bool {
+        match self.property("status").map(|s| s.string()) {
+            None => true,
+            Some(Ok(status)) => status == "okay" || status == "ok",
+            Some(Err(_)) => false,
+        }
+    }
+
+    /// Returns node's phandle, if it can be referenced by other nodes
+    pub fn phandle(&self) -> Option<u32> {
+        self.property("phandle")
+            .or_else(|| self.property("linux,phandle"))
+            .and_then(|v| v.u32().ok())
+    }
+
+    /// Returns `(address, size)` entries of node's `reg` property
+    pub fn regs(&self) -> impl Iterator<Item = (usize, usize)> + 'dt {
+        self.property("reg").into_iter().flat_map(|v| v.regs())
+    }
+
+    /// Value of node's `#address-cells`, used by its children
+    pub fn address_cells(&self) -> u32 {
+        self.child_cell_sizes.address
+    }
+
+    /// Value of node's `#size-cells`, used by its children
+    pub fn size_cells(&self) -> u32 {
+        self.child_cell_sizes.size
+    }
+
+    /// Value of `#address-cells` of node's parent, used to interpret node's own properties
+    pub fn parent_address_cells(&self) -> u32 {
+        self.cell_sizes.address
+    }
+
+    pub(crate) fn child_cell_sizes(&self) -> CellSizes {
+        self.child_cell_sizes
     }
 
     fn cells(&self, name: &str, default: u32) -> Result<u32, DeviceTreeError> {
@@ -106,7 +182,7 @@ impl<'dt> NodeRef<'dt> {
 }
 
 struct PropertiesIterator<'dt> {
-    node: &'dt NodeRef<'dt>,
+    node: NodeRef<'dt>,
     i: usize,
 }
 
@@ -114,24 +190,27 @@ impl<'dt> Iterator for PropertiesIterator<'dt> {
     type Item = (&'dt str, DeviceTreeValue<'dt>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        while self.i < self.node.data.len() {
-            if self.node.data[self.i].to_be() == FDT_PROP {
-                if self.i + 2 >= self.node.data.len() {
+        let data = self.node.data;
+        while self.i < data.len() {
+            if data[self.i].to_be() == FDT_PROP {
+                if self.i + 2 >= data.len() {
                     break;
                 }
-                let name_offset = self.node.data[self.i + 2].to_be() as usize;
+                let name_offset = data[self.i + 2].to_be() as usize;
                 let name = self.node.fdt.string(name_offset).expect("Invaild name");
 
-                let len = self.node.data[self.i + 1].to_be() as usize;
-                let fdt_cell_len = len / 4;
-                let value = &self.node.data[self.i + 3..self.i + 3 + fdt_cell_len];
+                let len = data[self.i + 1].to_be() as usize;
+                let fdt_cell_len = len.div_ceil(4);
+                let cells = &data[self.i + 3..self.i + 3 + fdt_cell_len];
+                // SAFETY: u32 can be safely reinterpreted as 4 bytes
+                let bytes: &[u8] = unsafe { cells.align_to().1 };
 
                 self.i += 3 + fdt_cell_len;
                 return Some((
                     name,
-                    DeviceTreeValue::wrap_cells(value, &self.node.cell_sizes),
+                    DeviceTreeValue::wrap_bytes(&bytes[..len], self.node.cell_sizes),
                 ));
-            } else if self.node.data[self.i].to_be() == FDT_BEGIN_NODE {
+            } else if data[self.i].to_be() == FDT_BEGIN_NODE {
                 break;
             } else {
                 self.i += 1;
@@ -142,7 +221,7 @@ impl<'dt> Iterator for PropertiesIterator<'dt> {
 }
 
 struct NodesIterator<'dt> {
-    node: &'dt NodeRef<'dt>,
+    node: NodeRef<'dt>,
     i: usize,
 }
 
@@ -150,42 +229,46 @@ impl<'dt> Iterator for NodesIterator<'dt> {
     type Item = NodeRef<'dt>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut stack = 0;
-        if self.i >= self.node.data.len() {
+        let data = self.node.data;
+        while self.i < data.len() && data[self.i].to_be() != FDT_BEGIN_NODE {
+            self.i += 1;
+        }
+        if self.i >= data.len() {
             return None;
         }
 
-        let mut start = self.i;
-        while self.i < self.node.data.len() {
-            match self.node.data[self.i].to_be() {
-                FDT_BEGIN_NODE => {
-                    if stack == 0 {
-                        start = self.i;
-                    }
-                    self.i += 1;
-                    stack += 1;
-                }
-                FDT_PROP => {
-                    self.i += 3 + (3 + self.node.data[self.i + 1].to_be() as usize) / 4;
-                }
-                FDT_END_NODE => {
-                    stack -= 1;
-                    self.i += 1;
-                    if stack == 0 {
-                        return NodeRef::from_slice(
-                            self.node.fdt,
-                            &self.node.data[start..self.i],
-                            self.node.cell_sizes,
-                        )
-                        .ok();
-                    }
-                }
-                _ => self.i += 1,
-            };
-        }
+        let start = self.i;
+        let end = node_end(data, start)?;
+        self.i = end + 1;
+        NodeRef::from_slice(self.node.fdt, &data[start..end], self.node.child_cell_sizes).ok()
+    }
+}
 
-        None
+/// Given a position of `FDT_BEGIN_NODE` token, finds a position of matching `FDT_END_NODE`
+pub(crate) fn node_end(data: &[FdtCell], start: usize) -> Option<usize> {
+    let mut stack = 0;
+    let mut i = start;
+    while i < data.len() {
+        match data[i].to_be() {
+            FDT_BEGIN_NODE => {
+                stack += 1;
+                i += 1;
+            }
+            FDT_PROP => {
+                i += 3 + (data[i + 1].to_be() as usize).div_ceil(4);
+            }
+            FDT_END_NODE => {
+                stack -= 1;
+                if stack == 0 {
+                    return Some(i);
+                }
+                i += 1;
+            }
+            _ => i += 1,
+        };
     }
+
+    None
 }
 
 #[derive(Debug, Clone, Copy)]
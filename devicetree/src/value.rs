@@ -4,24 +4,65 @@ use crate::node::CellSizes;
 
 use super::{error::DeviceTreeError, flattened::FdtCell};
 
-pub struct DeviceTreeValue<'dt>(&'dt [FdtCell], &'dt CellSizes);
+const CELL_SIZE: usize = size_of::<FdtCell>();
+
+#[derive(Clone, Copy)]
+pub struct DeviceTreeValue<'dt>(&'dt [u8], CellSizes);
 
 impl<'dt> DeviceTreeValue<'dt> {
-    pub(crate) fn wrap_cells(
-        cells: &'dt [FdtCell],
-        cell_sizes: &'dt CellSizes,
-    ) -> DeviceTreeValue<'dt> {
-        DeviceTreeValue(cells, cell_sizes)
+    pub(crate) fn wrap_bytes(bytes: &'dt [u8], cell_sizes: CellSizes) -> DeviceTreeValue<'dt> {
+        DeviceTreeValue(bytes, cell_sizes)
+    }
+
+    /// Raw bytes of the property, exactly as long as declared in the device tree
+    pub fn bytes(&self) -> &'dt [u8] {
+        self.0
     }
 
     pub fn u32(&self) -> Result<u32, DeviceTreeError> {
-        self.try_into().map(u32::to_be)
+        self.try_into()
     }
 
-    pub fn string(&self) -> Result<&str, DeviceTreeError> {
+    /// Reads a value encoded as two cells (`<u64>` in devicetree source)
+    pub fn u64(&self) -> Result<u64, DeviceTreeError> {
+        self.expect_size(2)?;
+        Ok(self.read_from_cells(0, 2) as u64)
+    }
+
+    /// Reads a value encoded as either one or two cells
+    pub fn usize(&self) -> Result<usize, DeviceTreeError> {
+        match self.cell_count() {
+            1 => self.u32().map(|v| v as usize),
+            _ => self.u64().map(|v| v as usize),
+        }
+    }
+
+    pub fn string(&self) -> Result<&'dt str, DeviceTreeError> {
         self.try_into()
     }
 
+    /// Interprets value as a `<stringlist>` - a list of null-terminated strings
+    pub fn strings(&self) -> impl Iterator<Item = &'dt str> {
+        let bytes = self.0.strip_suffix(&[0]).unwrap_or(self.0);
+        let is_empty = bytes.is_empty();
+        bytes
+            .split(|b| *b == 0)
+            .filter_map(|s| str::from_utf8(s).ok())
+            .filter(move |_| !is_empty)
+    }
+
+    /// Number of whole cells contained in this value
+    pub fn cell_count(&self) -> usize {
+        self.0.len() / CELL_SIZE
+    }
+
+    /// Iterates through the value's cells, in host byte order
+    pub fn cells(&self) -> impl Iterator<Item = u32> + 'dt {
+        self.0
+            .chunks_exact(CELL_SIZE)
+            .map(|c| u32::from_be_bytes(c.try_into().unwrap()))
+    }
+
     pub fn reg(&self) -> Result<(usize, usize), DeviceTreeError> {
         let address_cells = self.1.address() as usize;
         let size_cells = self.1.size() as usize;
@@ -33,11 +74,27 @@ impl<'dt> DeviceTreeValue<'dt> {
         Ok((address, size))
     }
 
+    /// Interprets value as a `reg` property with any number of `(address, size)` entries
+    pub fn regs(&self) -> impl Iterator<Item = (usize, usize)> + 'dt {
+        let value = *self;
+        let address_cells = self.1.address() as usize;
+        let size_cells = self.1.size() as usize;
+        let entry_cells = (address_cells + size_cells).max(1);
+
+        (0..self.cell_count() / entry_cells).map(move |i| {
+            let start = i * entry_cells;
+            (
+                value.read_from_cells(start, address_cells),
+                value.read_from_cells(start + address_cells, size_cells),
+            )
+        })
+    }
+
     fn expect_size(&self, expected: usize) -> Result<(), DeviceTreeError> {
-        if self.0.len() != expected {
+        if self.0.len() != expected * CELL_SIZE {
             Err(DeviceTreeError::InvaildPropertySize {
                 expected,
-                actual: self.0.len(),
+                actual: self.0.len() / CELL_SIZE,
             })
         } else {
             Ok(())
@@ -46,37 +103,27 @@ impl<'dt> DeviceTreeValue<'dt> {
 
     fn read_from_cells(&self, start: usize, cells: usize) -> usize {
         let mut result = 0;
-        for i in start..start + cells {
-            result += self.0[i].to_be() as usize;
-            if i != start + cells - 1 {
-                result <<= 32;
-            }
+        for cell in self.cells().skip(start).take(cells) {
+            result = (result << 32) | cell as usize;
         }
         result
     }
 }
 
-impl<'a: 'dt, 'dt> TryFrom<&'a DeviceTreeValue<'dt>> for u32 {
+impl<'a, 'dt> TryFrom<&'a DeviceTreeValue<'dt>> for u32 {
     type Error = DeviceTreeError;
 
-    fn try_from(DeviceTreeValue(value, _): &'a DeviceTreeValue<'dt>) -> Result<Self, Self::Error> {
-        if value.len() != 1 {
-            Err(DeviceTreeError::InvaildPropertySize {
-                expected: 1,
-                actual: value.len(),
-            })
-        } else {
-            Ok(value[0])
-        }
+    fn try_from(value: &'a DeviceTreeValue<'dt>) -> Result<Self, Self::Error> {
+        value.expect_size(1)?;
+        Ok(u32::from_be_bytes(value.0.try_into().unwrap()))
     }
 }
 
-impl<'a: 'dt, 'dt> TryFrom<&'a DeviceTreeValue<'dt>> for &'dt str {
+impl<'a, 'dt> TryFrom<&'a DeviceTreeValue<'dt>> for &'dt str {
     type Error = DeviceTreeError;
 
     fn try_from(DeviceTreeValue(value, _): &'a DeviceTreeValue<'dt>) -> Result<Self, Self::Error> {
-        // SAFETY: 32 is divisible by 8
-        let (_, bytes, _) = unsafe { value.align_to::<u8>() };
+        let bytes = value.strip_suffix(&[0]).unwrap_or(value);
         str::from_utf8(bytes).map_err(|e| DeviceTreeError::InvaildUTF8 { source: e })
     }
 }
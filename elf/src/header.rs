@@ -12,20 +12,22 @@ pub struct Header<'a> {
     class: Class,
     endianess: Endianness,
     header_data: &'a [u8],
+    /// Whole file, which offsets in the header are relative to
     data: &'a [u8],
 }
 
 impl<'a> Header<'a> {
+    /// Reads the header following the identification section of a whole ELF file
     pub fn from_bytes(data: &'a [u8], identification: &Identification) -> ElfResult<Header<'a>> {
         let size = Self::size(identification.class()?);
-        if data.len() < size {
+        if data.len() < EI_NIDENT + size {
             return Err(crate::ElfError::InvaildSize {
-                minimal: size,
+                minimal: EI_NIDENT + size,
                 actual: data.len(),
             });
         }
 
-        let data_slice = &data[..size];
+        let data_slice = &data[EI_NIDENT..EI_NIDENT + size];
         Ok(Header {
             class: identification.class()?,
             endianess: identification.endiannes()?,
@@ -75,7 +77,7 @@ impl<'a> Header<'a> {
         let e_phnum = self.offset(16, 3);
 
         HeaderLocations {
-            offset: self.addr(e_phoff) - EI_NIDENT,
+            offset: self.addr(e_phoff),
             size: self.word(e_phentsize),
             num: self.word(e_phnum),
         }
@@ -3,7 +3,8 @@
 use core::fmt::Display;
 
 use header::Header;
-use identification::{Identification, EI_NIDENT};
+use identification::Identification;
+use machine::Machine;
 use segment::Segment;
 
 pub mod endiannes;
@@ -26,13 +27,22 @@ impl<'a> Elf<'a> {
             return Err(ElfError::InvaildIdentification);
         }
 
-        let header = Header::from_bytes(&bytes[EI_NIDENT..], &identification)?;
+        let header = Header::from_bytes(bytes, &identification)?;
         Ok(Elf {
             identification,
             header,
         })
     }
 
+    /// Virtual address execution starts at, if the file has one
+    pub fn entrypoint(&self) -> Option<usize> {
+        self.header.entrypoint()
+    }
+
+    pub fn machine(&self) -> Machine {
+        self.header.machine()
+    }
+
     pub fn segments(&'a self) -> impl Iterator<Item = Segment<'a>> {
         self.header.segments()
     }
@@ -4,6 +4,8 @@ use core::fmt::Display;
 pub struct Machine(u16);
 
 impl Machine {
+    pub const RISCV: Machine = Machine(0xf3);
+
     pub fn from_id(id: u16) -> Machine {
         Machine(id)
     }
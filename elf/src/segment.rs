@@ -47,7 +47,7 @@ impl<'a> Segment<'a> {
     }
 
     pub fn memory_size(&self) -> usize {
-        self.read_usize(0x14, 0x20)
+        self.read_usize(0x14, 0x28)
     }
 
     pub fn executable(&self) -> bool {
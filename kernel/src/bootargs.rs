@@ -0,0 +1,29 @@
+//! Kernel command line, passed in `/chosen/bootargs`
+//!
+//! Consists of whitespace-separated options, either flags or `name=value` pairs
+
+use devicetree::FlattenedDeviceTree;
+
+/// Returns value of a `name=value` option, if it is present
+pub fn option<'a>(dt: &'a FlattenedDeviceTree, name: &str) -> Option<&'a str> {
+    options(dt)
+        .filter_map(|option| option.split_once('='))
+        .find(|(option_name, _)| *option_name == name)
+        .map(|(_, value)| value)
+}
+
+/// Returns whether a flag option is present
+pub fn flag(dt: &FlattenedDeviceTree, name: &str) -> bool {
+    options(dt).any(|option| option == name)
+}
+
+fn options<'a>(dt: &'a FlattenedDeviceTree) -> impl Iterator<Item = &'a str> {
+    let bootargs = dt
+        .root()
+        .ok()
+        .and_then(|root| root.child("chosen"))
+        .and_then(|chosen| chosen.property("bootargs"))
+        .and_then(|bootargs| bootargs.string().ok())
+        .unwrap_or("");
+    bootargs.split_ascii_whitespace()
+}
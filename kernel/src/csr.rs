@@ -56,4 +56,5 @@ csr!(stvec);
 csr!(sip);
 csr!(sie);
 csr!(scause);
-csr!(sscratch);
+csr!(satp);
+csr!(stval);
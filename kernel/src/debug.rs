@@ -1,7 +1,7 @@
 use core::fmt;
 use core_lib::sync::AtomicMutex;
 
-use crate::sbi;
+use crate::{sbi, traps::without_interrupts};
 
 pub struct DebugOutput {
     mutex: AtomicMutex<()>,
@@ -15,14 +15,23 @@ impl DebugOutput {
     }
 }
 
+impl DebugOutput {
+    pub fn write_bytes(&self, bytes: &[u8]) {
+        // an interrupt handler writing output while the lock is held would deadlock
+        without_interrupts(|| {
+            let _lock = self.mutex.lock();
+            for byte in bytes {
+                unsafe {
+                    sbi::debug_console::write_byte(*byte).unwrap();
+                };
+            }
+        });
+    }
+}
+
 impl<'a> fmt::Write for &'a DebugOutput {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        let _ = self.mutex.lock();
-        for byte in s.as_bytes() {
-            unsafe {
-                sbi::debug_console::write_byte(*byte).unwrap();
-            };
-        }
+        self.write_bytes(s.as_bytes());
         Ok(())
     }
 }
@@ -33,8 +42,17 @@ macro_rules! kdebug {
         {
             use crate::Supervisor;
             use core::fmt::Write;
-            let mut debug_output = Supervisor::global().debug_output();
-            writeln!(debug_output, "[{}:{}] {}", file!(), line!(), format_args!($($arg)*)).unwrap()
+            let supervisor = Supervisor::global();
+            let mut debug_output = supervisor.debug_output();
+            writeln!(
+                debug_output,
+                "[{}] [{}:{}] {}",
+                supervisor.clock().timestamp(),
+                file!(),
+                line!(),
+                format_args!($($arg)*)
+            )
+            .unwrap()
         }
     };
 }
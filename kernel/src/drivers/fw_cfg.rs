@@ -0,0 +1,212 @@
+//! QEMU firmware configuration interface (`qemu,fw-cfg-mmio`)
+//!
+//! Gives access to files passed to QEMU with `-fw_cfg name=opt/...,file=...`.
+//! Described in <https://www.qemu.org/docs/master/specs/fw_cfg.html>
+
+use core::str;
+
+use alloc::sync::Arc;
+use core_lib::sync::AtomicMutex;
+use devicetree::NodeRef;
+use snafu::OptionExt;
+
+use crate::kdebug;
+
+use super::{
+    mmio::{io_fence, MmioRegion},
+    registry::{DeviceInstance, Driver, MissingRegSnafu, NotRespondingSnafu, ProbeError},
+};
+
+pub static DRIVER: Driver = Driver {
+    name: "fw-cfg",
+    compatible: &["qemu,fw-cfg-mmio"],
+    probe,
+};
+
+// registers, big-endian
+const DATA: usize = 0x00;
+const SELECTOR: usize = 0x08;
+const DMA_ADDRESS: usize = 0x10;
+
+// items
+const SIGNATURE: u16 = 0x0000;
+const FEATURES: u16 = 0x0001;
+const FILE_DIRECTORY: u16 = 0x0019;
+
+const SIGNATURE_VALUE: &[u8; 4] = b"QEMU";
+const FEATURE_DMA: u32 = 1 << 1;
+
+const DMA_CONTROL_ERROR: u32 = 1 << 0;
+const DMA_CONTROL_READ: u32 = 1 << 1;
+const DMA_CONTROL_SKIP: u32 = 1 << 2;
+const DMA_CONTROL_SELECT: u32 = 1 << 3;
+
+const FILE_NAME_LENGTH: usize = 56;
+const FILE_ENTRY_SIZE: usize = 64;
+
+#[derive(Debug)]
+pub struct DmaError;
+
+/// Control structure of a DMA transfer, read and updated by the device
+#[repr(C, align(8))]
+struct DmaAccess {
+    control: u32,
+    length: u32,
+    address: u64,
+}
+
+pub struct FwCfg {
+    registers: MmioRegion,
+    has_dma: bool,
+    /// Serializes accesses, as selector and data offset are shared by all users
+    lock: AtomicMutex<()>,
+}
+
+/// Checks device's signature and available features
+fn probe(node: &NodeRef) -> Result<DeviceInstance, ProbeError> {
+    let reg = node.regs().next().context(MissingRegSnafu)?;
+
+    let mut fw_cfg = FwCfg {
+        // SAFETY: region comes from the device's node
+        registers: unsafe { MmioRegion::from_reg(reg) },
+        has_dma: false,
+        lock: AtomicMutex::new(()),
+    };
+
+    let mut signature = [0u8; 4];
+    fw_cfg.read_pio(SIGNATURE, 0, &mut signature);
+    if &signature != SIGNATURE_VALUE {
+        return NotRespondingSnafu.fail();
+    }
+
+    let mut features = [0u8; 4];
+    fw_cfg.read_pio(FEATURES, 0, &mut features);
+    fw_cfg.has_dma = u32::from_le_bytes(features) & FEATURE_DMA != 0;
+
+    Ok(Arc::new(fw_cfg))
+}
+
+impl FwCfg {
+    pub fn has_dma(&self) -> bool {
+        self.has_dma
+    }
+
+    /// Iterates through the file directory
+    pub fn files(&self) -> impl Iterator<Item = FwCfgFile> + '_ {
+        let mut count = [0u8; 4];
+        self.read(FILE_DIRECTORY, 0, &mut count);
+        let count = u32::from_be_bytes(count) as usize;
+
+        (0..count).map(|i| {
+            let mut entry = [0u8; FILE_ENTRY_SIZE];
+            self.read(
+                FILE_DIRECTORY,
+                size_of::<u32>() + i * FILE_ENTRY_SIZE,
+                &mut entry,
+            );
+            FwCfgFile::from_entry(&entry)
+        })
+    }
+
+    /// Reads file contents starting at given offset, returning number of bytes read
+    pub fn read_file(&self, file: &FwCfgFile, offset: usize, buffer: &mut [u8]) -> usize {
+        let size = file.size().saturating_sub(offset).min(buffer.len());
+        self.read(file.select, offset, &mut buffer[..size]);
+        size
+    }
+
+    /// Reads an item, using DMA if available, or the data register if a transfer fails
+    fn read(&self, key: u16, offset: usize, buffer: &mut [u8]) {
+        if self.has_dma {
+            match self.read_dma(key, offset, buffer) {
+                Ok(()) => return,
+                Err(DmaError) => kdebug!(
+                    "fw_cfg DMA transfer failed, reading item {:#x} by bytes",
+                    key
+                ),
+            }
+        }
+        self.read_pio(key, offset, buffer);
+    }
+
+    /// Reads an item byte by byte through the data register
+    fn read_pio(&self, key: u16, offset: usize, buffer: &mut [u8]) {
+        let _guard = self.lock.lock();
+        self.registers.write(SELECTOR, key.to_be());
+        for _ in 0..offset {
+            self.registers.read::<u8>(DATA);
+        }
+        for byte in buffer.iter_mut() {
+            *byte = self.registers.read(DATA);
+        }
+    }
+
+    fn read_dma(&self, key: u16, offset: usize, buffer: &mut [u8]) -> Result<(), DmaError> {
+        let _guard = self.lock.lock();
+        let select = ((key as u32) << 16) | DMA_CONTROL_SELECT;
+        self.transfer(select | DMA_CONTROL_SKIP, offset, 0)?;
+        self.transfer(DMA_CONTROL_READ, buffer.len(), buffer.as_mut_ptr() as usize)
+    }
+
+    fn transfer(&self, control: u32, length: usize, address: usize) -> Result<(), DmaError> {
+        let mut access = DmaAccess {
+            control: control.to_be(),
+            length: (length as u32).to_be(),
+            address: (address as u64).to_be(),
+        };
+        let access_ptr = &raw mut access;
+
+        io_fence();
+        // writing lower half of the address starts the transfer
+        let access_address = access_ptr as usize as u64;
+        self.registers
+            .write(DMA_ADDRESS, ((access_address >> 32) as u32).to_be());
+        self.registers
+            .write(DMA_ADDRESS + 4, (access_address as u32).to_be());
+
+        loop {
+            // SAFETY: pointer points to a local variable updated by the device
+            let control =
+                u32::from_be(unsafe { (&raw const (*access_ptr).control).read_volatile() });
+            if control & DMA_CONTROL_ERROR != 0 {
+                return Err(DmaError);
+            }
+            if control == 0 {
+                break;
+            }
+        }
+        io_fence();
+        Ok(())
+    }
+}
+
+/// Entry of the fw_cfg file directory
+#[derive(Clone)]
+pub struct FwCfgFile {
+    size: u32,
+    select: u16,
+    name: [u8; FILE_NAME_LENGTH],
+}
+
+impl FwCfgFile {
+    fn from_entry(entry: &[u8; FILE_ENTRY_SIZE]) -> FwCfgFile {
+        FwCfgFile {
+            size: u32::from_be_bytes(entry[0..4].try_into().unwrap()),
+            select: u16::from_be_bytes(entry[4..6].try_into().unwrap()),
+            name: entry[8..].try_into().unwrap(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        let length = self
+            .name
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(FILE_NAME_LENGTH);
+        str::from_utf8(&self.name[..length]).unwrap_or("")
+    }
+
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+}
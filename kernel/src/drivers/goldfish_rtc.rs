@@ -0,0 +1,53 @@
+//! Goldfish real-time clock, as emulated by QEMU
+//!
+//! Described in <https://android.googlesource.com/platform/external/qemu/+/master/docs/GOLDFISH-VIRTUAL-HARDWARE.TXT>
+
+use core::time::Duration;
+
+use alloc::sync::Arc;
+use devicetree::NodeRef;
+use snafu::OptionExt;
+
+use crate::time::SystemTime;
+
+use super::{
+    mmio::MmioRegion,
+    registry::{DeviceInstance, Driver, MissingRegSnafu, ProbeError},
+};
+
+pub static DRIVER: Driver = Driver {
+    name: "goldfish-rtc",
+    compatible: &["google,goldfish-rtc"],
+    probe,
+};
+
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+
+pub struct GoldfishRtc {
+    registers: MmioRegion,
+}
+
+fn probe(node: &NodeRef) -> Result<DeviceInstance, ProbeError> {
+    let reg = node.regs().next().context(MissingRegSnafu)?;
+
+    Ok(Arc::new(GoldfishRtc {
+        // SAFETY: region comes from the device's node
+        registers: unsafe { MmioRegion::from_reg(reg) },
+    }))
+}
+
+impl GoldfishRtc {
+    /// Reads current time, as nanoseconds since the UNIX epoch
+    pub fn read_nanos(&self) -> u64 {
+        // reading low half latches high half until it is read
+        let low: u32 = self.registers.read(TIME_LOW);
+        let high: u32 = self.registers.read(TIME_HIGH);
+        ((high as u64) << 32) | low as u64
+    }
+
+    /// Reads current wall-clock time
+    pub fn read(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_nanos(self.read_nanos())
+    }
+}
@@ -0,0 +1,51 @@
+//! Access to memory-mapped device registers
+
+use core::ptr::{read_volatile, write_volatile};
+
+/// A region of memory-mapped device registers
+#[derive(Debug, Clone, Copy)]
+pub struct MmioRegion {
+    base: usize,
+    size: usize,
+}
+
+impl MmioRegion {
+    /// Creates a region from a devicetree `reg` entry
+    ///
+    /// # Safety
+    /// Region must describe registers of a device, which are not accessed by other means
+    pub unsafe fn from_reg((base, size): (usize, usize)) -> MmioRegion {
+        MmioRegion { base, size }
+    }
+
+    /// Reads a register at given offset from the region's base
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        let ptr = self.register::<T>(offset);
+        // SAFETY: register is located inside of a device region
+        unsafe { read_volatile(ptr) }
+    }
+
+    /// Writes a register at given offset from the region's base
+    pub fn write<T: Copy>(&self, offset: usize, value: T) {
+        let ptr = self.register::<T>(offset) as *mut T;
+        // SAFETY: register is located inside of a device region
+        unsafe { write_volatile(ptr, value) }
+    }
+
+    fn register<T>(&self, offset: usize) -> *const T {
+        assert!(
+            offset + size_of::<T>() <= self.size,
+            "MMIO access at 0x{:x} outside of device region",
+            offset
+        );
+        (self.base + offset) as *const T
+    }
+}
+
+/// Orders preceding memory accesses before following device accesses and vice versa.
+/// Required around DMA, when a device reads or writes buffers in main memory
+#[inline]
+pub fn io_fence() {
+    // SAFETY: fence has no side effects besides ordering
+    unsafe { core::arch::asm!("fence iorw, iorw") };
+}
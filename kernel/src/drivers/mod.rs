@@ -0,0 +1,28 @@
+//! Device drivers for platform devices found in a devicetree
+//!
+//! Each driver lists `compatible` strings of nodes it handles and a probe function creating
+//! a device instance. Bound devices are kept in a [`DeviceRegistry`].
+
+pub mod fw_cfg;
+pub mod goldfish_rtc;
+pub mod mmio;
+pub mod pci;
+pub mod plic;
+pub mod registry;
+pub mod syscon;
+pub mod uart;
+pub mod virtio;
+
+pub use registry::{DeviceRegistry, Driver};
+
+/// All drivers built into the kernel
+static DRIVERS: &[&Driver] = &[
+    &plic::DRIVER,
+    &goldfish_rtc::DRIVER,
+    &syscon::POWEROFF_DRIVER,
+    &syscon::REBOOT_DRIVER,
+    &pci::ecam::DRIVER,
+    &fw_cfg::DRIVER,
+    &virtio::DRIVER,
+    &uart::DRIVER,
+];
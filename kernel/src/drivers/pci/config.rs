@@ -0,0 +1,42 @@
+//! PCI configuration space registers
+
+pub const VENDOR_ID: usize = 0x00;
+pub const DEVICE_ID: usize = 0x02;
+pub const COMMAND: usize = 0x04;
+pub const CLASS_REVISION: usize = 0x08;
+pub const HEADER_TYPE: usize = 0x0e;
+pub const BAR0: usize = 0x10;
+pub const INTERRUPT_LINE: usize = 0x3c;
+pub const INTERRUPT_PIN: usize = 0x3d;
+
+// type 1 (PCI-to-PCI bridge) header
+pub const PRIMARY_BUS: usize = 0x18;
+pub const SECONDARY_BUS: usize = 0x19;
+pub const SUBORDINATE_BUS: usize = 0x1a;
+pub const IO_BASE: usize = 0x1c;
+pub const IO_LIMIT: usize = 0x1d;
+pub const MEMORY_BASE: usize = 0x20;
+pub const MEMORY_LIMIT: usize = 0x22;
+pub const PREFETCHABLE_MEMORY_BASE: usize = 0x24;
+pub const PREFETCHABLE_MEMORY_LIMIT: usize = 0x26;
+pub const PREFETCHABLE_BASE_UPPER: usize = 0x28;
+pub const PREFETCHABLE_LIMIT_UPPER: usize = 0x2c;
+pub const IO_BASE_UPPER: usize = 0x30;
+pub const IO_LIMIT_UPPER: usize = 0x32;
+
+pub const COMMAND_IO_SPACE: u16 = 1 << 0;
+pub const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+pub const HEADER_TYPE_MASK: u8 = 0x7f;
+pub const HEADER_TYPE_MULTIFUNCTION: u8 = 0x80;
+pub const HEADER_TYPE_DEVICE: u8 = 0x0;
+pub const HEADER_TYPE_BRIDGE: u8 = 0x1;
+
+pub const BAR_IO: u32 = 0x1;
+pub const BAR_MEMORY_TYPE_MASK: u32 = 0x6;
+pub const BAR_MEMORY_TYPE_64: u32 = 0x4;
+pub const BAR_MEMORY_PREFETCHABLE: u32 = 0x8;
+
+pub const BAR_COUNT_DEVICE: usize = 6;
+pub const BAR_COUNT_BRIDGE: usize = 2;
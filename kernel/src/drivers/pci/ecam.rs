@@ -0,0 +1,638 @@
+//! Generic PCI host bridge with ECAM configuration space (`pci-host-ecam-generic`)
+//!
+//! Firmware does not configure the bus, so the kernel enumerates it, assigning bus numbers
+//! to bridges, BARs from host bridge's `ranges` and legacy INTx lines from its `interrupt-map`
+
+use alloc::sync::Arc;
+use devicetree::{NodeRef, Specifier};
+use snafu::OptionExt;
+
+use crate::{
+    drivers::{
+        mmio::MmioRegion,
+        registry::{DeviceInstance, Driver, InvaildPropertySnafu, MissingRegSnafu, ProbeError},
+    },
+    kdebug,
+};
+
+use super::{config, Bar, PciAddress, PciSpace};
+
+pub static DRIVER: Driver = Driver {
+    name: "pci-host-ecam",
+    compatible: &["pci-host-ecam-generic"],
+    probe,
+};
+
+const DEVICES_PER_BUS: u8 = 32;
+const FUNCTIONS_PER_DEVICE: u8 = 8;
+const ECAM_BUS_SIZE: usize = 1 << 20;
+
+const BRIDGE_MEMORY_ALIGNMENT: u64 = 0x10_0000;
+const BRIDGE_IO_ALIGNMENT: u64 = 0x1000;
+/// First I/O port handed out, as some devices treat address 0 as unassigned
+const IO_ALLOCATION_START: u64 = 0x1000;
+
+const INTERRUPT_LINE_NONE: u8 = 0xff;
+
+/// Region of host's address space forwarded to the PCI bus
+#[derive(Debug, Clone, Copy)]
+struct PciWindow {
+    space: PciSpace,
+    bus_address: u64,
+    cpu_address: usize,
+    size: u64,
+}
+
+impl PciWindow {
+    fn from_ranges_entry(entry: &[u32], parent_address_cells: usize) -> Option<PciWindow> {
+        let space = match (entry[0] >> 24) & 0x3 {
+            0x1 => PciSpace::Io,
+            0x2 => PciSpace::Memory32,
+            0x3 => PciSpace::Memory64,
+            _ => return None,
+        };
+
+        Some(PciWindow {
+            space,
+            bus_address: combine_cells(&entry[1..3]),
+            cpu_address: combine_cells(&entry[3..3 + parent_address_cells]) as usize,
+            size: combine_cells(&entry[3 + parent_address_cells..]),
+        })
+    }
+
+    fn translate(&self, bus_address: u64) -> Option<usize> {
+        if bus_address >= self.bus_address && bus_address - self.bus_address < self.size {
+            Some(self.cpu_address + (bus_address - self.bus_address) as usize)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct PciHostBridge {
+    config: MmioRegion,
+    bus_start: u8,
+    bus_end: u8,
+    /// Last bus number assigned during enumeration
+    last_bus: u8,
+    windows: [Option<PciWindow>; 3],
+}
+
+/// Enumerates the bus of a host bridge
+fn probe(node: &NodeRef) -> Result<DeviceInstance, ProbeError> {
+    let mut bridge = PciHostBridge::from_node(node)?;
+    bridge.enumerate(node);
+    bridge.log_functions();
+    Ok(Arc::new(bridge))
+}
+
+impl PciHostBridge {
+    fn from_node(node: &NodeRef) -> Result<PciHostBridge, ProbeError> {
+        let reg = node.regs().next().context(MissingRegSnafu)?;
+        let (bus_start, bus_end) = match node.property("bus-range") {
+            Some(range) => {
+                let mut cells = range.cells();
+                cells
+                    .next()
+                    .zip(cells.next())
+                    .map(|(start, end)| (start as u8, end as u8))
+                    .context(InvaildPropertySnafu { name: "bus-range" })?
+            }
+            None => (0, ((reg.1 / ECAM_BUS_SIZE).clamp(1, 256) - 1) as u8),
+        };
+
+        let mut windows = [None; 3];
+        let parent_address_cells = node.parent_address_cells() as usize;
+        let entry_cells = 3 + parent_address_cells + node.size_cells() as usize;
+        let mut cells = node
+            .property("ranges")
+            .context(InvaildPropertySnafu { name: "ranges" })?
+            .cells();
+        let mut entry = [0u32; 16];
+        'entries: loop {
+            for cell in entry.iter_mut().take(entry_cells) {
+                match cells.next() {
+                    Some(value) => *cell = value,
+                    None => break 'entries,
+                }
+            }
+            if let Some(window) =
+                PciWindow::from_ranges_entry(&entry[..entry_cells], parent_address_cells)
+            {
+                windows[window.space as usize].get_or_insert(window);
+            }
+        }
+
+        Ok(PciHostBridge {
+            // SAFETY: region comes from the host bridge node
+            config: unsafe { MmioRegion::from_reg(reg) },
+            bus_start,
+            bus_end,
+            last_bus: bus_start,
+            windows,
+        })
+    }
+
+    /// Iterates through all functions present on the bus
+    pub fn functions(&self) -> impl Iterator<Item = PciFunction<'_>> {
+        (self.bus_start..=self.last_bus)
+            .flat_map(|bus| (0..DEVICES_PER_BUS).map(move |device| (bus, device)))
+            .flat_map(move |(bus, device)| {
+                let first = self.function(PciAddress {
+                    bus,
+                    device,
+                    function: 0,
+                });
+                let functions = match (first.exists(), first.is_multifunction()) {
+                    (false, _) => 0,
+                    (true, false) => 1,
+                    (true, true) => FUNCTIONS_PER_DEVICE,
+                };
+                (0..functions)
+                    .map(move |function| {
+                        self.function(PciAddress {
+                            bus,
+                            device,
+                            function,
+                        })
+                    })
+                    .filter(|f| f.exists())
+            })
+    }
+
+    fn log_functions(&self) {
+        for function in self.functions() {
+            let address = function.address();
+            let (class, subclass, interface) = function.class();
+            kdebug!(
+                "PCI {} [{:04x}:{:04x}] class {:02x}.{:02x}.{:02x}",
+                address,
+                function.vendor_id(),
+                function.device_id(),
+                class,
+                subclass,
+                interface
+            );
+            for (index, bar) in function.bars().iter().enumerate() {
+                if let Some(bar) = bar {
+                    kdebug!("PCI {}   BAR{}: {}", address, index, bar);
+                }
+            }
+            if let Some(irq) = function.interrupt() {
+                kdebug!(
+                    "PCI {}   INT{} -> irq {}",
+                    address,
+                    (b'A' + function.interrupt_pin() - 1) as char,
+                    irq
+                );
+            }
+        }
+    }
+
+    fn function(&self, address: PciAddress) -> PciFunction<'_> {
+        PciFunction {
+            bridge: self,
+            address,
+        }
+    }
+
+    fn enumerate(&mut self, node: &NodeRef) {
+        let mut allocators = [None; 3];
+        for (allocator, window) in allocators.iter_mut().zip(self.windows.iter()) {
+            *allocator = window.map(|window| {
+                let next = match window.space {
+                    PciSpace::Io => window.bus_address.max(IO_ALLOCATION_START),
+                    _ => window.bus_address,
+                };
+                WindowAllocator { window, next }
+            });
+        }
+
+        let mut enumeration = Enumeration {
+            bridge: self,
+            node,
+            allocators,
+            next_bus: self.bus_start as u16 + 1,
+        };
+        enumeration.scan_bus(self.bus_start, None);
+        self.last_bus = (enumeration.next_bus - 1) as u8;
+    }
+
+    fn translate(&self, space: PciSpace, bus_address: u64) -> Option<usize> {
+        self.windows
+            .iter()
+            .flatten()
+            .filter(|w| (w.space == PciSpace::Io) == (space == PciSpace::Io))
+            .find_map(|w| w.translate(bus_address))
+    }
+
+    fn config_offset(&self, address: PciAddress, register: usize) -> usize {
+        ((address.bus - self.bus_start) as usize * ECAM_BUS_SIZE)
+            | ((address.device as usize) << 15)
+            | ((address.function as usize) << 12)
+            | register
+    }
+}
+
+/// A single function of a device on the PCI bus
+#[derive(Clone, Copy)]
+pub struct PciFunction<'a> {
+    bridge: &'a PciHostBridge,
+    address: PciAddress,
+}
+
+impl<'a> PciFunction<'a> {
+    pub fn address(&self) -> PciAddress {
+        self.address
+    }
+
+    /// Reads a configuration space register
+    pub fn read<T: Copy>(&self, register: usize) -> T {
+        self.bridge
+            .config
+            .read(self.bridge.config_offset(self.address, register))
+    }
+
+    /// Writes a configuration space register
+    pub fn write<T: Copy>(&self, register: usize, value: T) {
+        self.bridge
+            .config
+            .write(self.bridge.config_offset(self.address, register), value)
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.read(config::VENDOR_ID)
+    }
+
+    pub fn device_id(&self) -> u16 {
+        self.read(config::DEVICE_ID)
+    }
+
+    /// Returns class code, subclass and programming interface
+    pub fn class(&self) -> (u8, u8, u8) {
+        let class: u32 = self.read(config::CLASS_REVISION);
+        ((class >> 24) as u8, (class >> 16) as u8, (class >> 8) as u8)
+    }
+
+    pub fn header_type(&self) -> u8 {
+        self.read::<u8>(config::HEADER_TYPE) & config::HEADER_TYPE_MASK
+    }
+
+    /// Legacy interrupt pin used by the function (1 for INTA# to 4 for INTD#), 0 if none
+    pub fn interrupt_pin(&self) -> u8 {
+        self.read(config::INTERRUPT_PIN)
+    }
+
+    /// Interrupt number of the host's interrupt controller assigned to the function
+    pub fn interrupt(&self) -> Option<u32> {
+        let line: u8 = self.read(config::INTERRUPT_LINE);
+        if self.interrupt_pin() == 0 || line == INTERRUPT_LINE_NONE {
+            None
+        } else {
+            Some(line as u32)
+        }
+    }
+
+    /// Returns all implemented BARs, indexed by their register number
+    pub fn bars(&self) -> [Option<Bar>; config::BAR_COUNT_DEVICE] {
+        let mut bars = [None; config::BAR_COUNT_DEVICE];
+        let mut index = 0;
+        while index < self.bar_count() {
+            bars[index] = self.bar(index);
+            index += match bars[index] {
+                Some(Bar {
+                    space: PciSpace::Memory64,
+                    ..
+                }) => 2,
+                _ => 1,
+            };
+        }
+        bars
+    }
+
+    fn bar(&self, index: usize) -> Option<Bar> {
+        let (space, prefetchable, size) = self.size_bar(index)?;
+        let register = config::BAR0 + index * 4;
+        let low: u32 = self.read(register);
+        let bus_address = match space {
+            PciSpace::Io => (low & !0x3) as u64,
+            PciSpace::Memory32 => (low & !0xf) as u64,
+            PciSpace::Memory64 => {
+                let high: u32 = self.read(register + 4);
+                ((high as u64) << 32) | (low & !0xf) as u64
+            }
+        };
+
+        Some(Bar {
+            space,
+            prefetchable,
+            bus_address,
+            cpu_address: self.bridge.translate(space, bus_address),
+            size,
+        })
+    }
+
+    /// Determines BAR's type and size by writing all ones to it
+    fn size_bar(&self, index: usize) -> Option<(PciSpace, bool, u64)> {
+        let register = config::BAR0 + index * 4;
+        let command: u16 = self.read(config::COMMAND);
+        self.write(
+            config::COMMAND,
+            command & !(config::COMMAND_IO_SPACE | config::COMMAND_MEMORY_SPACE),
+        );
+
+        let original: u32 = self.read(register);
+        self.write(register, u32::MAX);
+        let low: u32 = self.read(register);
+        self.write(register, original);
+
+        let result = if low == 0 {
+            None
+        } else if original & config::BAR_IO != 0 {
+            let mask = (low & !0x3) | 0xffff_0000;
+            Some((PciSpace::Io, false, (!mask).wrapping_add(1) as u64))
+        } else {
+            let prefetchable = original & config::BAR_MEMORY_PREFETCHABLE != 0;
+            if original & config::BAR_MEMORY_TYPE_MASK == config::BAR_MEMORY_TYPE_64 {
+                let original_high: u32 = self.read(register + 4);
+                self.write(register + 4, u32::MAX);
+                let high: u32 = self.read(register + 4);
+                self.write(register + 4, original_high);
+
+                let mask = ((high as u64) << 32) | (low & !0xf) as u64;
+                Some((PciSpace::Memory64, prefetchable, (!mask).wrapping_add(1)))
+            } else {
+                let mask = 0xffff_ffff_0000_0000 | (low & !0xf) as u64;
+                Some((PciSpace::Memory32, prefetchable, (!mask).wrapping_add(1)))
+            }
+        };
+
+        self.write(config::COMMAND, command);
+        result
+    }
+
+    fn write_bar(&self, index: usize, space: PciSpace, bus_address: u64) {
+        let register = config::BAR0 + index * 4;
+        let original: u32 = self.read(register);
+        let flags = match space {
+            PciSpace::Io => original & 0x3,
+            _ => original & 0xf,
+        };
+        self.write(register, bus_address as u32 | flags);
+        if space == PciSpace::Memory64 {
+            self.write(register + 4, (bus_address >> 32) as u32);
+        }
+    }
+
+    fn bar_count(&self) -> usize {
+        match self.header_type() {
+            config::HEADER_TYPE_DEVICE => config::BAR_COUNT_DEVICE,
+            config::HEADER_TYPE_BRIDGE => config::BAR_COUNT_BRIDGE,
+            _ => 0,
+        }
+    }
+
+    fn exists(&self) -> bool {
+        self.vendor_id() != 0xffff
+    }
+
+    fn is_multifunction(&self) -> bool {
+        self.read::<u8>(config::HEADER_TYPE) & config::HEADER_TYPE_MULTIFUNCTION != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct WindowAllocator {
+    window: PciWindow,
+    next: u64,
+}
+
+impl WindowAllocator {
+    /// Allocates a naturally aligned region of given size, returning its bus address
+    fn allocate(&mut self, size: u64) -> Option<u64> {
+        let start = align_up(self.next, size);
+        let end = start.checked_add(size)?;
+        if end > self.window.bus_address + self.window.size {
+            None
+        } else {
+            self.next = end;
+            Some(start)
+        }
+    }
+
+    fn align(&mut self, alignment: u64) -> u64 {
+        self.next = align_up(self.next, alignment);
+        self.next
+    }
+}
+
+/// State of the bus enumeration
+struct Enumeration<'a, 'dt> {
+    bridge: &'a PciHostBridge,
+    node: &'a NodeRef<'dt>,
+    allocators: [Option<WindowAllocator>; 3],
+    next_bus: u16,
+}
+
+/// Path from a function to the root bus, used to swizzle interrupt pins:
+/// a root bus bridge behind which the function is and a sum of device numbers of other bridges
+type BridgePath = Option<(PciAddress, u8)>;
+
+impl<'a, 'dt> Enumeration<'a, 'dt> {
+    fn scan_bus(&mut self, bus: u8, path: BridgePath) {
+        for device in 0..DEVICES_PER_BUS {
+            let first = self.bridge.function(PciAddress {
+                bus,
+                device,
+                function: 0,
+            });
+            if !first.exists() {
+                continue;
+            }
+
+            let functions = if first.is_multifunction() {
+                FUNCTIONS_PER_DEVICE
+            } else {
+                1
+            };
+            for function in 0..functions {
+                let function = self.bridge.function(PciAddress {
+                    bus,
+                    device,
+                    function,
+                });
+                if function.exists() {
+                    self.setup_function(function, path);
+                }
+            }
+        }
+    }
+
+    fn setup_function(&mut self, function: PciFunction<'a>, path: BridgePath) {
+        let address = function.address();
+        let mut command: u16 = function.read(config::COMMAND);
+        command &=
+            !(config::COMMAND_IO_SPACE | config::COMMAND_MEMORY_SPACE | config::COMMAND_BUS_MASTER);
+        function.write(config::COMMAND, command);
+
+        let mut index = 0;
+        while index < function.bar_count() {
+            let Some((space, _, size)) = function.size_bar(index) else {
+                index += 1;
+                continue;
+            };
+
+            match self.allocate(space, size, path.is_none()) {
+                Some(bus_address) => {
+                    function.write_bar(index, space, bus_address);
+                    command |= match space {
+                        PciSpace::Io => config::COMMAND_IO_SPACE,
+                        _ => config::COMMAND_MEMORY_SPACE,
+                    };
+                }
+                None => kdebug!(
+                    "PCI {} BAR{}: cannot allocate 0x{:x} bytes",
+                    address,
+                    index,
+                    size
+                ),
+            }
+            index += if space == PciSpace::Memory64 { 2 } else { 1 };
+        }
+
+        let pin = function.interrupt_pin();
+        if pin != 0 {
+            let (root_address, root_pin) = match path {
+                None => (address, pin),
+                Some((root, offset)) => (root, swizzle(pin, address.device.wrapping_add(offset))),
+            };
+            let line = match route_interrupt(self.node, root_address, root_pin) {
+                Some(irq) if irq < INTERRUPT_LINE_NONE as u32 => irq as u8,
+                _ => INTERRUPT_LINE_NONE,
+            };
+            function.write(config::INTERRUPT_LINE, line);
+        }
+
+        if function.header_type() == config::HEADER_TYPE_BRIDGE {
+            self.setup_bridge(function, path);
+            command |= config::COMMAND_IO_SPACE | config::COMMAND_MEMORY_SPACE;
+        }
+        function.write(config::COMMAND, command | config::COMMAND_BUS_MASTER);
+    }
+
+    fn setup_bridge(&mut self, function: PciFunction<'a>, path: BridgePath) {
+        let address = function.address();
+        let secondary = self.next_bus;
+        if secondary > self.bridge.bus_end as u16 {
+            kdebug!("PCI {} no bus number left for a bridge", address);
+            return;
+        }
+        self.next_bus += 1;
+
+        function.write(config::PRIMARY_BUS, address.bus);
+        function.write(config::SECONDARY_BUS, secondary as u8);
+        function.write(config::SUBORDINATE_BUS, self.bridge.bus_end);
+
+        let memory_start = self.align(PciSpace::Memory32, BRIDGE_MEMORY_ALIGNMENT);
+        let io_start = self.align(PciSpace::Io, BRIDGE_IO_ALIGNMENT);
+
+        let child_path = match path {
+            None => Some((address, 0)),
+            Some((root, offset)) => Some((root, offset.wrapping_add(address.device))),
+        };
+        self.scan_bus(secondary as u8, child_path);
+
+        let subordinate = (self.next_bus - 1) as u8;
+        function.write(config::SUBORDINATE_BUS, subordinate);
+        let memory_end = self.align(PciSpace::Memory32, BRIDGE_MEMORY_ALIGNMENT);
+        let io_end = self.align(PciSpace::Io, BRIDGE_IO_ALIGNMENT);
+
+        match (memory_start, memory_end) {
+            (Some(start), Some(end)) if end > start => {
+                function.write(config::MEMORY_BASE, (start >> 16) as u16 & 0xfff0);
+                function.write(config::MEMORY_LIMIT, ((end - 1) >> 16) as u16 & 0xfff0);
+            }
+            _ => {
+                function.write(config::MEMORY_BASE, 0xfff0u16);
+                function.write(config::MEMORY_LIMIT, 0x0u16);
+            }
+        }
+        match (io_start, io_end) {
+            (Some(start), Some(end)) if end > start => {
+                function.write(config::IO_BASE, (start >> 8) as u8 & 0xf0);
+                function.write(config::IO_LIMIT, ((end - 1) >> 8) as u8 & 0xf0);
+                function.write(config::IO_BASE_UPPER, (start >> 16) as u16);
+                function.write(config::IO_LIMIT_UPPER, ((end - 1) >> 16) as u16);
+            }
+            _ => {
+                function.write(config::IO_BASE, 0xf0u8);
+                function.write(config::IO_LIMIT, 0x0u8);
+            }
+        }
+        // all memory behind bridges is allocated from the non-prefetchable window
+        function.write(config::PREFETCHABLE_MEMORY_BASE, 0xfff0u16);
+        function.write(config::PREFETCHABLE_MEMORY_LIMIT, 0x0u16);
+        function.write(config::PREFETCHABLE_BASE_UPPER, 0x0u32);
+        function.write(config::PREFETCHABLE_LIMIT_UPPER, 0x0u32);
+
+        kdebug!(
+            "PCI {} bridge to buses {:02x}-{:02x}",
+            address,
+            secondary,
+            subordinate
+        );
+    }
+
+    fn allocate(&mut self, space: PciSpace, size: u64, on_root_bus: bool) -> Option<u64> {
+        let mut allocate_from = |space: PciSpace| {
+            self.allocators[space as usize]
+                .as_mut()
+                .and_then(|a| a.allocate(size))
+        };
+
+        match space {
+            PciSpace::Io => allocate_from(PciSpace::Io),
+            PciSpace::Memory32 => allocate_from(PciSpace::Memory32),
+            // 64-bit window is not forwarded by bridges
+            PciSpace::Memory64 if on_root_bus => {
+                allocate_from(PciSpace::Memory32).or_else(|| allocate_from(PciSpace::Memory64))
+            }
+            PciSpace::Memory64 => allocate_from(PciSpace::Memory32),
+        }
+    }
+
+    fn align(&mut self, space: PciSpace, alignment: u64) -> Option<u64> {
+        self.allocators[space as usize]
+            .as_mut()
+            .map(|a| a.align(alignment))
+    }
+}
+
+/// Maps an interrupt pin of a device behind a bridge to a pin of the bridge
+fn swizzle(pin: u8, device: u8) -> u8 {
+    (pin - 1 + device % 4) % 4 + 1
+}
+
+/// Finds an interrupt number of a root bus device pin using host bridge's `interrupt-map`
+fn route_interrupt(node: &NodeRef, address: PciAddress, pin: u8) -> Option<u32> {
+    let (address_mask, pin_mask) = node.interrupt_map_mask();
+    let address = Specifier::from_cells(&[address.devicetree_cell(), 0, 0]).masked(&address_mask);
+    let pin = Specifier::from_cells(&[pin as u32]).masked(&pin_mask);
+
+    node.interrupt_map()
+        .find(|entry| {
+            entry.child_address.masked(&address_mask) == address
+                && entry.child_interrupt.masked(&pin_mask) == pin
+        })
+        .and_then(|entry| entry.parent_interrupt.cells().first().copied())
+}
+
+fn combine_cells(cells: &[u32]) -> u64 {
+    cells
+        .iter()
+        .fold(0, |result, cell| (result << 32) | *cell as u64)
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    value.div_ceil(alignment) * alignment
+}
@@ -0,0 +1,70 @@
+//! PCI bus support
+
+pub mod config;
+pub mod ecam;
+
+use core::fmt::Display;
+
+/// Location of a function on a PCI bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    /// Encodes address as a `phys.hi` cell of a devicetree PCI address, with space code set to 0
+    fn devicetree_cell(&self) -> u32 {
+        ((self.bus as u32) << 16) | ((self.device as u32) << 11) | ((self.function as u32) << 8)
+    }
+}
+
+impl Display for PciAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:02x}:{:02x}.{:x}",
+            self.bus, self.device, self.function
+        )
+    }
+}
+
+/// Address space of a BAR or a host bridge window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PciSpace {
+    Io,
+    Memory32,
+    Memory64,
+}
+
+/// An assigned base address register
+#[derive(Debug, Clone, Copy)]
+pub struct Bar {
+    pub space: PciSpace,
+    pub prefetchable: bool,
+    /// Address as seen on the PCI bus, programmed into the register
+    pub bus_address: u64,
+    /// Address as seen by the CPU, translated through host bridge's `ranges`
+    pub cpu_address: Option<usize>,
+    pub size: u64,
+}
+
+impl Display for Bar {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let space = match self.space {
+            PciSpace::Io => "io",
+            PciSpace::Memory32 => "mem32",
+            PciSpace::Memory64 => "mem64",
+        };
+        write!(f, "{} 0x{:x}", space, self.bus_address)?;
+        if let Some(cpu_address) = self.cpu_address {
+            write!(f, " (cpu 0x{:x})", cpu_address)?;
+        }
+        write!(f, ", size 0x{:x}", self.size)?;
+        if self.prefetchable {
+            write!(f, ", prefetchable")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+//! RISC-V platform-level interrupt controller
+//!
+//! Described in <https://github.com/riscv/riscv-plic-spec>
+
+use alloc::{sync::Arc, vec::Vec};
+use core_lib::sync::AtomicMutex;
+use devicetree::{FlattenedDeviceTree, NodeRef};
+use snafu::OptionExt;
+
+use crate::traps::without_interrupts;
+
+use super::{
+    mmio::MmioRegion,
+    registry::{DeviceInstance, Driver, InvaildPropertySnafu, MissingRegSnafu, ProbeError},
+};
+
+pub static DRIVER: Driver = Driver {
+    name: "plic",
+    compatible: &["sifive,plic-1.0.0", "riscv,plic0"],
+    probe,
+};
+
+const PRIORITY_BASE: usize = 0x00_0000;
+const ENABLE_BASE: usize = 0x00_2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_BASE: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+
+// context registers
+const THRESHOLD: usize = 0x0;
+const CLAIM_COMPLETE: usize = 0x4;
+
+/// Cause of a supervisor external interrupt, identifying S-mode contexts in `interrupts-extended`
+const SUPERVISOR_EXTERNAL_INTERRUPT: u32 = 9;
+
+/// Device handling interrupts of a source routed through the PLIC
+pub trait InterruptHandler: Send + Sync {
+    /// Called on a hart which claimed the interrupt, with interrupts disabled
+    fn handle_interrupt(&self);
+}
+
+pub struct Plic {
+    registers: MmioRegion,
+    /// Number of interrupt sources, numbered from 1
+    sources: u32,
+    /// Supervisor-mode contexts, as `(hart id, context)` pairs
+    contexts: Vec<(usize, usize)>,
+    handlers: AtomicMutex<Vec<(u32, Arc<dyn InterruptHandler>)>>,
+}
+
+/// Masks all interrupt sources, leaving the controller ready to enable them one by one
+fn probe(node: &NodeRef) -> Result<DeviceInstance, ProbeError> {
+    let reg = node.regs().next().context(MissingRegSnafu)?;
+    let sources = node
+        .property("riscv,ndev")
+        .and_then(|v| v.u32().ok())
+        .context(InvaildPropertySnafu { name: "riscv,ndev" })?;
+
+    let contexts = node
+        .interrupts_extended()
+        .enumerate()
+        .filter(|(_, (_, interrupt))| interrupt.cells() == [SUPERVISOR_EXTERNAL_INTERRUPT])
+        .filter_map(|(context, (intc, _))| Some((hart_id(node.tree(), intc)?, context)))
+        .collect();
+
+    let plic = Plic {
+        // SAFETY: region comes from the device's node
+        registers: unsafe { MmioRegion::from_reg(reg) },
+        sources,
+        contexts,
+        handlers: AtomicMutex::new(Vec::new()),
+    };
+    for irq in 1..=plic.sources {
+        plic.registers
+            .write(PRIORITY_BASE + irq as usize * size_of::<u32>(), 0u32);
+    }
+    for &(_, context) in &plic.contexts {
+        for word in 0..=plic.sources as usize / 32 {
+            plic.registers.write(
+                ENABLE_BASE + context * ENABLE_STRIDE + word * size_of::<u32>(),
+                0u32,
+            );
+        }
+        plic.write_context(context, THRESHOLD, 0);
+    }
+
+    Ok(Arc::new(plic))
+}
+
+/// Finds id of a hart owning given local interrupt controller
+fn hart_id(dt: &FlattenedDeviceTree, intc_phandle: u32) -> Option<usize> {
+    dt.root()
+        .ok()?
+        .child("cpus")?
+        .children()
+        .find(|cpu| {
+            cpu.child("interrupt-controller")
+                .is_some_and(|intc| intc.phandle() == Some(intc_phandle))
+        })?
+        .property("reg")?
+        .usize()
+        .ok()
+}
+
+impl Plic {
+    /// Claims highest-priority pending interrupt for a hart, returning its source number
+    pub fn claim(&self, hart_id: usize) -> Option<u32> {
+        let context = self.context(hart_id)?;
+        let irq: u32 = self
+            .registers
+            .read(CONTEXT_BASE + context * CONTEXT_STRIDE + CLAIM_COMPLETE);
+        (irq != 0).then_some(irq)
+    }
+
+    /// Enables an interrupt source on all harts, passing its interrupts to `handler`
+    pub fn register(&self, irq: u32, handler: Arc<dyn InterruptHandler>) {
+        assert!(
+            (1..=self.sources).contains(&irq),
+            "Invaild interrupt source {}",
+            irq
+        );
+        // the handler may be looked up by an interrupt right after it is enabled
+        without_interrupts(|| self.handlers.lock().push((irq, handler)));
+        self.registers
+            .write(PRIORITY_BASE + irq as usize * size_of::<u32>(), 1u32);
+        let (word, bit) = (irq as usize / 32, irq % 32);
+        for &(_, context) in &self.contexts {
+            let register = ENABLE_BASE + context * ENABLE_STRIDE + word * size_of::<u32>();
+            let enabled: u32 = self.registers.read(register);
+            self.registers.write(register, enabled | 1 << bit);
+        }
+    }
+
+    /// Runs the handler registered for a claimed interrupt, returning false if there is none
+    pub fn dispatch(&self, irq: u32) -> bool {
+        let handler = self
+            .handlers
+            .lock()
+            .iter()
+            .find(|(source, _)| *source == irq)
+            .map(|(_, handler)| handler.clone());
+        match handler {
+            Some(handler) => {
+                handler.handle_interrupt();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Signals that a claimed interrupt has been handled
+    pub fn complete(&self, hart_id: usize, irq: u32) {
+        if let Some(context) = self.context(hart_id) {
+            self.write_context(context, CLAIM_COMPLETE, irq);
+        }
+    }
+
+    fn context(&self, hart_id: usize) -> Option<usize> {
+        self.contexts
+            .iter()
+            .find(|(hart, _)| *hart == hart_id)
+            .map(|(_, context)| *context)
+    }
+
+    fn write_context(&self, context: usize, register: usize, value: u32) {
+        self.registers
+            .write(CONTEXT_BASE + context * CONTEXT_STRIDE + register, value);
+    }
+}
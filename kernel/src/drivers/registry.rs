@@ -0,0 +1,163 @@
+//! Matching devicetree nodes with drivers and keeping track of bound devices
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+
+use core_lib::sync::AtomicMutex;
+use devicetree::{FlattenedDeviceTree, NodeRef};
+use snafu::Snafu;
+
+use crate::{kdebug, traps::without_interrupts};
+
+use super::DRIVERS;
+
+/// Driver-specific state of a bound device
+pub type DeviceInstance = Arc<dyn Any + Send + Sync>;
+
+/// Reason for a driver refusing to bind to a device
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum ProbeError {
+    #[snafu(display("Node does not have a reg property"))]
+    MissingReg,
+    #[snafu(display("Missing or invaild property {name}"))]
+    InvaildProperty { name: &'static str },
+    #[snafu(display("Device does not respond as expected"))]
+    NotResponding,
+    /// Node describes an empty slot, which is not worth reporting
+    #[snafu(display("No device present"))]
+    NoDevice,
+    #[snafu(display("Unsupported device type {id}"))]
+    UnsupportedDevice { id: u32 },
+}
+
+pub struct Driver {
+    pub name: &'static str,
+    /// Values of `compatible` property of nodes handled by the driver
+    pub compatible: &'static [&'static str],
+    pub probe: fn(&NodeRef) -> Result<DeviceInstance, ProbeError>,
+}
+
+impl Driver {
+    /// Finds a driver for a node, preferring ones matching more specific `compatible` entries
+    fn for_node(node: &NodeRef) -> Option<&'static Driver> {
+        node.compatible().find_map(|compatible| {
+            DRIVERS
+                .iter()
+                .copied()
+                .find(|driver| driver.compatible.contains(&compatible))
+        })
+    }
+}
+
+struct BoundDevice {
+    driver: &'static Driver,
+    node_name: String,
+    instance: DeviceInstance,
+}
+
+/// Devices successfully probed by their drivers
+pub struct DeviceRegistry {
+    devices: AtomicMutex<Vec<BoundDevice>>,
+}
+
+impl DeviceRegistry {
+    pub const fn new() -> DeviceRegistry {
+        DeviceRegistry {
+            devices: AtomicMutex::new(Vec::new()),
+        }
+    }
+
+    /// Probes drivers for all enabled nodes of the devicetree
+    ///
+    /// Interrupt controllers go first. Other nodes are probed only after nodes they refer to
+    /// (interrupt parents, syscon regmaps) have been, unless dependencies are circular.
+    pub fn probe_all(&self, dt: &FlattenedDeviceTree) {
+        let mut pending: Vec<(NodeRef, &'static Driver)> = dt
+            .nodes()
+            .filter(|node| node.is_enabled())
+            .filter_map(|node| Driver::for_node(&node).map(|driver| (node, driver)))
+            .collect();
+        pending.sort_by_key(|(node, _)| node.property("interrupt-controller").is_none());
+
+        while !pending.is_empty() {
+            let ready = pending.iter().position(|(node, _)| {
+                let dependencies = dependencies(node);
+                !pending.iter().any(|(other, _)| {
+                    other
+                        .phandle()
+                        .is_some_and(|phandle| dependencies.contains(&phandle))
+                })
+            });
+            let index = ready.unwrap_or_else(|| {
+                kdebug!("Circular dependency between devices, probing in devicetree order");
+                0
+            });
+
+            let (node, driver) = pending.remove(index);
+            self.probe(&node, driver);
+        }
+    }
+
+    fn probe(&self, node: &NodeRef, driver: &'static Driver) {
+        match (driver.probe)(node) {
+            Ok(instance) => {
+                let device = BoundDevice {
+                    driver,
+                    node_name: String::from(node.full_name()),
+                    instance,
+                };
+                without_interrupts(|| self.devices.lock().push(device));
+            }
+            Err(ProbeError::NoDevice) => {}
+            Err(error) => kdebug!(
+                "{}: {} driver failed to probe: {}",
+                node.full_name(),
+                driver.name,
+                error
+            ),
+        }
+    }
+
+    /// Returns first bound device with given driver type
+    pub fn find<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        without_interrupts(|| {
+            self.devices
+                .lock()
+                .iter()
+                .find_map(|device| device.instance.clone().downcast::<T>().ok())
+        })
+    }
+
+    /// Returns all bound devices with given driver type, in the order they were probed
+    pub fn find_all<T: Any + Send + Sync>(&self) -> Vec<Arc<T>> {
+        without_interrupts(|| {
+            self.devices
+                .lock()
+                .iter()
+                .filter_map(|device| device.instance.clone().downcast::<T>().ok())
+                .collect()
+        })
+    }
+
+    /// Lists all bound devices
+    pub fn log_devices(&self) {
+        kdebug!("Bound devices:");
+        without_interrupts(|| {
+            for device in self.devices.lock().iter() {
+                kdebug!("  {} ({})", device.node_name, device.driver.name);
+            }
+        })
+    }
+}
+
+/// Phandles of nodes which have to be probed before the node
+fn dependencies(node: &NodeRef) -> Vec<u32> {
+    let mut phandles: Vec<u32> = ["interrupt-parent", "regmap"]
+        .into_iter()
+        .filter_map(|name| node.property(name).and_then(|v| v.u32().ok()))
+        .collect();
+    phandles.extend(node.interrupts_extended().map(|(parent, _)| parent));
+    phandles.extend(node.interrupt_map().map(|entry| entry.parent));
+    phandles
+}
@@ -0,0 +1,93 @@
+//! Poweroff and reboot performed by writing to a system controller register
+//!
+//! Follows `syscon-poweroff` and `syscon-reboot` devicetree bindings, as used by Linux
+
+use alloc::sync::Arc;
+use devicetree::NodeRef;
+use snafu::OptionExt;
+
+use super::{
+    mmio::MmioRegion,
+    registry::{Driver, InvaildPropertySnafu, MissingRegSnafu, ProbeError},
+};
+
+pub static POWEROFF_DRIVER: Driver = Driver {
+    name: "syscon-poweroff",
+    compatible: &["syscon-poweroff"],
+    probe: |node| Ok(Arc::new(SysconPoweroff(SysconWrite::from_node(node)?))),
+};
+
+pub static REBOOT_DRIVER: Driver = Driver {
+    name: "syscon-reboot",
+    compatible: &["syscon-reboot"],
+    probe: |node| Ok(Arc::new(SysconReboot(SysconWrite::from_node(node)?))),
+};
+
+pub struct SysconPoweroff(SysconWrite);
+
+impl SysconPoweroff {
+    /// Powers the machine off. Returns only if poweroff did not succeed
+    pub fn poweroff(&self) {
+        self.0.perform();
+    }
+}
+
+pub struct SysconReboot(SysconWrite);
+
+impl SysconReboot {
+    /// Reboots the machine. Returns only if reboot did not succeed
+    pub fn reboot(&self) {
+        self.0.perform();
+    }
+}
+
+/// A masked write of a value to a syscon register
+struct SysconWrite {
+    registers: MmioRegion,
+    offset: usize,
+    value: u32,
+    mask: u32,
+}
+
+impl SysconWrite {
+    fn from_node(node: &NodeRef) -> Result<SysconWrite, ProbeError> {
+        let syscon = node
+            .property("regmap")
+            .and_then(|v| v.u32().ok())
+            .and_then(|regmap| node.tree().find_by_phandle(regmap))
+            .context(InvaildPropertySnafu { name: "regmap" })?;
+        let reg = syscon.regs().next().context(MissingRegSnafu)?;
+
+        let offset = node
+            .property("offset")
+            .and_then(|v| v.u32().ok())
+            .context(InvaildPropertySnafu { name: "offset" })? as usize;
+        let value = node.property("value").and_then(|v| v.u32().ok());
+        let mask = node.property("mask").and_then(|v| v.u32().ok());
+        // if value is missing, mask is written as a value to the whole register (old binding)
+        let (value, mask) = match (value, mask) {
+            (Some(value), Some(mask)) => (value, mask),
+            (Some(value), None) => (value, u32::MAX),
+            (None, Some(mask)) => (mask, u32::MAX),
+            (None, None) => return InvaildPropertySnafu { name: "value" }.fail(),
+        };
+
+        Ok(SysconWrite {
+            // SAFETY: region comes from the syscon node
+            registers: unsafe { MmioRegion::from_reg(reg) },
+            offset,
+            value,
+            mask,
+        })
+    }
+
+    fn perform(&self) {
+        let value = if self.mask == u32::MAX {
+            self.value
+        } else {
+            let current: u32 = self.registers.read(self.offset);
+            (current & !self.mask) | (self.value & self.mask)
+        };
+        self.registers.write(self.offset, value);
+    }
+}
@@ -0,0 +1,91 @@
+//! NS16550A-compatible UART, the serial console of QEMU's `virt` machine
+//!
+//! The firmware has already set up the line, so only the FIFOs and the receive interrupt
+//! are configured. Output is polled, input is taken from the receive FIFO when the
+//! interrupt fires.
+
+use alloc::sync::Arc;
+use devicetree::NodeRef;
+use snafu::OptionExt;
+
+use super::{
+    mmio::MmioRegion,
+    registry::{DeviceInstance, Driver, MissingRegSnafu, ProbeError},
+};
+
+pub static DRIVER: Driver = Driver {
+    name: "ns16550",
+    compatible: &["ns16550a", "ns16550"],
+    probe,
+};
+
+// registers, spaced by 1 << reg-shift bytes
+const DATA: usize = 0;
+const INTERRUPT_ENABLE: usize = 1;
+const FIFO_CONTROL: usize = 2;
+const MODEM_CONTROL: usize = 4;
+const LINE_STATUS: usize = 5;
+
+const RECEIVE_INTERRUPT: u8 = 0x01;
+/// Enables and clears both FIFOs
+const FIFO_ENABLE: u8 = 0x07;
+/// DTR, RTS and OUT2, which gates the interrupt line
+const MODEM_READY: u8 = 0x0b;
+const DATA_READY: u8 = 0x01;
+const TRANSMIT_EMPTY: u8 = 0x20;
+
+pub struct Uart {
+    registers: MmioRegion,
+    shift: usize,
+    /// Interrupt source of the PLIC
+    irq: Option<u32>,
+}
+
+fn probe(node: &NodeRef) -> Result<DeviceInstance, ProbeError> {
+    let reg = node.regs().next().context(MissingRegSnafu)?;
+    let shift = node
+        .property("reg-shift")
+        .and_then(|v| v.u32().ok())
+        .unwrap_or(0) as usize;
+    let uart = Uart {
+        // SAFETY: region comes from the device's node
+        registers: unsafe { MmioRegion::from_reg(reg) },
+        shift,
+        irq: node.property("interrupts").and_then(|v| v.u32().ok()),
+    };
+    uart.write_register(FIFO_CONTROL, FIFO_ENABLE);
+    uart.write_register(MODEM_CONTROL, MODEM_READY);
+    Ok(Arc::new(uart))
+}
+
+impl Uart {
+    pub fn irq(&self) -> Option<u32> {
+        self.irq
+    }
+
+    /// Makes the UART raise its interrupt when received data is available
+    pub fn enable_receive_interrupt(&self) {
+        self.write_register(INTERRUPT_ENABLE, RECEIVE_INTERRUPT);
+    }
+
+    /// Takes a received byte, if there is one
+    pub fn read_byte(&self) -> Option<u8> {
+        (self.read_register(LINE_STATUS) & DATA_READY != 0).then(|| self.read_register(DATA))
+    }
+
+    /// Sends a byte, waiting until the transmitter can take it
+    pub fn write_byte(&self, byte: u8) {
+        while self.read_register(LINE_STATUS) & TRANSMIT_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        self.write_register(DATA, byte);
+    }
+
+    fn read_register(&self, register: usize) -> u8 {
+        self.registers.read(register << self.shift)
+    }
+
+    fn write_register(&self, register: usize, value: u8) {
+        self.registers.write(register << self.shift, value)
+    }
+}
@@ -0,0 +1,177 @@
+//! Virtio block device, a disk image passed to QEMU with
+//! `-drive file=...,if=none,format=raw,id=... -device virtio-blk-device,drive=...`
+//!
+//! Requests are made one at a time and polled for completion.
+
+use alloc::{boxed::Box, sync::Arc};
+use core::ptr::addr_of_mut;
+
+use core_lib::sync::AtomicMutex;
+use vfs::{block::check_range, BlockDevice, VfsError, VfsResult};
+
+use crate::drivers::registry::{DeviceInstance, ProbeError};
+
+use super::{
+    queue::{Buffer, Virtqueue},
+    Transport,
+};
+
+// features
+const FEATURE_READ_ONLY: u64 = 1 << 5;
+const FEATURE_FLUSH: u64 = 1 << 9;
+
+// configuration
+const CAPACITY: usize = 0x00;
+
+// request types
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+const REQUEST_FLUSH: u32 = 4;
+
+const STATUS_OK: u8 = 0;
+
+/// Size of sectors addressed by requests, regardless of the device's block size
+const SECTOR_SIZE: usize = 512;
+/// Largest transfer made with a single request
+const MAX_TRANSFER: usize = 64 * 1024;
+
+/// Request header and status, which are read and written by the device
+#[repr(C)]
+struct Request {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+    status: u8,
+}
+
+struct Queue {
+    queue: Virtqueue,
+    request: Box<Request>,
+}
+
+pub struct VirtioBlock {
+    transport: Transport,
+    /// Number of sectors
+    capacity: u64,
+    read_only: bool,
+    flush: bool,
+    queue: AtomicMutex<Queue>,
+}
+
+pub(super) fn probe(transport: Transport) -> Result<DeviceInstance, ProbeError> {
+    let features = transport.initialize(FEATURE_READ_ONLY | FEATURE_FLUSH)?;
+    let queue = Virtqueue::new();
+    transport.set_queue(0, &queue)?;
+    transport.driver_ok();
+
+    // the 64-bit field may not be read at once
+    let capacity = transport.config::<u32>(CAPACITY) as u64
+        | (transport.config::<u32>(CAPACITY + 4) as u64) << 32;
+    Ok(Arc::new(VirtioBlock {
+        transport,
+        capacity,
+        read_only: features & FEATURE_READ_ONLY != 0,
+        flush: features & FEATURE_FLUSH != 0,
+        queue: AtomicMutex::new(Queue {
+            queue,
+            request: Box::new(Request {
+                kind: 0,
+                reserved: 0,
+                sector: 0,
+                status: 0,
+            }),
+        }),
+    }))
+}
+
+impl VirtioBlock {
+    /// Makes a request with an optional data buffer, which the device writes to for reads
+    fn request(&self, kind: u32, sector: u64, data: Option<(*mut u8, usize)>) -> VfsResult<()> {
+        let mut queue = self.queue.lock();
+        let Queue { queue, request } = &mut *queue;
+        **request = Request {
+            kind,
+            reserved: 0,
+            sector,
+            status: u8::MAX,
+        };
+
+        let request_address = &**request as *const Request as usize;
+        let header = Buffer {
+            address: request_address,
+            length: 16,
+            writeable: false,
+        };
+        let status = Buffer {
+            address: addr_of_mut!(request.status) as usize,
+            length: 1,
+            writeable: true,
+        };
+        let notify = || self.transport.notify(0);
+        // SAFETY: request and data buffers are borrowed until the request completes, and
+        // kernel memory is identity-mapped
+        unsafe {
+            match data {
+                Some((address, length)) => {
+                    let data = Buffer {
+                        address: address as usize,
+                        length,
+                        writeable: kind == REQUEST_IN,
+                    };
+                    queue.submit(&[header, data, status], notify)
+                }
+                None => queue.submit(&[header, status], notify),
+            }
+        };
+        self.transport.acknowledge_interrupts();
+
+        if request.status != STATUS_OK {
+            return Err(VfsError::Io);
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for VirtioBlock {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.capacity
+    }
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn read_blocks(&self, block: u64, buffer: &mut [u8]) -> VfsResult<()> {
+        check_range(self, block, buffer.len())?;
+        for (index, chunk) in buffer.chunks_mut(MAX_TRANSFER).enumerate() {
+            let sector = block + (index * MAX_TRANSFER / SECTOR_SIZE) as u64;
+            self.request(REQUEST_IN, sector, Some((chunk.as_mut_ptr(), chunk.len())))?;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, block: u64, data: &[u8]) -> VfsResult<()> {
+        if self.read_only {
+            return Err(VfsError::ReadOnly);
+        }
+        check_range(self, block, data.len())?;
+        for (index, chunk) in data.chunks(MAX_TRANSFER).enumerate() {
+            let sector = block + (index * MAX_TRANSFER / SECTOR_SIZE) as u64;
+            // the device only reads the buffer
+            let address = chunk.as_ptr() as *mut u8;
+            self.request(REQUEST_OUT, sector, Some((address, chunk.len())))?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> VfsResult<()> {
+        if !self.flush {
+            return Ok(());
+        }
+        self.request(REQUEST_FLUSH, 0, None)
+    }
+}
@@ -0,0 +1,210 @@
+//! Virtio devices over the MMIO transport (`virtio,mmio`), as found on QEMU's virt machine
+//!
+//! Described in <https://docs.oasis-open.org/virtio/virtio/v1.2/virtio-v1.2.html>. Both the
+//! legacy (version 1) and the modern (version 2) register layouts are handled. QEMU creates
+//! nodes for all transport slots, empty ones report device ID 0 and are skipped.
+
+pub mod block;
+pub mod net;
+mod queue;
+
+use devicetree::NodeRef;
+use snafu::OptionExt;
+
+use super::{
+    mmio::MmioRegion,
+    registry::{
+        DeviceInstance, Driver, MissingRegSnafu, NoDeviceSnafu, NotRespondingSnafu, ProbeError,
+        UnsupportedDeviceSnafu,
+    },
+};
+
+pub use queue::Virtqueue;
+
+pub static DRIVER: Driver = Driver {
+    name: "virtio-mmio",
+    compatible: &["virtio,mmio"],
+    probe,
+};
+
+// registers
+const MAGIC_VALUE: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const DEVICE_FEATURES: usize = 0x010;
+const DEVICE_FEATURES_SELECT: usize = 0x014;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SELECT: usize = 0x024;
+const GUEST_PAGE_SIZE: usize = 0x028;
+const QUEUE_SELECT: usize = 0x030;
+const QUEUE_SIZE_MAX: usize = 0x034;
+const QUEUE_SIZE: usize = 0x038;
+const QUEUE_ALIGN: usize = 0x03c;
+const QUEUE_PFN: usize = 0x040;
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+const QUEUE_DESCRIPTORS: usize = 0x080;
+const QUEUE_DRIVER: usize = 0x090;
+const QUEUE_DEVICE: usize = 0x0a0;
+const CONFIG: usize = 0x100;
+
+const MAGIC: u32 = 0x7472_6976;
+const LEGACY_VERSION: u32 = 1;
+const MODERN_VERSION: u32 = 2;
+/// Page size used to locate legacy queues
+const LEGACY_PAGE_SIZE: u32 = 4096;
+
+// device status bits
+const ACKNOWLEDGE: u32 = 1;
+const DRIVER_LOADED: u32 = 2;
+const DRIVER_OK: u32 = 4;
+const FEATURES_OK: u32 = 8;
+const FAILED: u32 = 128;
+
+/// Device follows the modern specification, required from modern devices
+const FEATURE_VERSION_1: u64 = 1 << 32;
+
+// device types
+const NET_DEVICE: u32 = 1;
+const BLOCK_DEVICE: u32 = 2;
+
+fn probe(node: &NodeRef) -> Result<DeviceInstance, ProbeError> {
+    let reg = node.regs().next().context(MissingRegSnafu)?;
+    // SAFETY: region comes from the device's node
+    let transport = Transport::new(unsafe { MmioRegion::from_reg(reg) })?;
+    match transport.device_id() {
+        0 => NoDeviceSnafu.fail(),
+        NET_DEVICE => {
+            let irq = node.property("interrupts").and_then(|v| v.u32().ok());
+            net::probe(transport, irq)
+        }
+        BLOCK_DEVICE => block::probe(transport),
+        id => UnsupportedDeviceSnafu { id }.fail(),
+    }
+}
+
+/// Registers of a single virtio device
+pub struct Transport {
+    registers: MmioRegion,
+    legacy: bool,
+}
+
+impl Transport {
+    fn new(registers: MmioRegion) -> Result<Transport, ProbeError> {
+        if registers.read::<u32>(MAGIC_VALUE) != MAGIC {
+            return NotRespondingSnafu.fail();
+        }
+        let legacy = match registers.read::<u32>(VERSION) {
+            LEGACY_VERSION => true,
+            MODERN_VERSION => false,
+            _ => return NotRespondingSnafu.fail(),
+        };
+        Ok(Transport { registers, legacy })
+    }
+
+    pub fn device_id(&self) -> u32 {
+        self.registers.read(DEVICE_ID)
+    }
+
+    /// Whether the device uses the legacy interface, which changes some device-specific
+    /// structures
+    pub fn is_legacy(&self) -> bool {
+        self.legacy
+    }
+
+    /// Resets the device and negotiates features, accepting those of `wanted` the device
+    /// offers. Returns the accepted features
+    pub fn initialize(&self, wanted: u64) -> Result<u64, ProbeError> {
+        self.registers.write::<u32>(STATUS, 0);
+        self.registers.write(STATUS, ACKNOWLEDGE);
+        self.registers.write(STATUS, ACKNOWLEDGE | DRIVER_LOADED);
+
+        let mut offered = 0;
+        for half in 0..if self.legacy { 1 } else { 2 } {
+            self.registers.write::<u32>(DEVICE_FEATURES_SELECT, half);
+            offered |= (self.registers.read::<u32>(DEVICE_FEATURES) as u64) << (32 * half);
+        }
+        let required = if self.legacy { 0 } else { FEATURE_VERSION_1 };
+        if offered & required != required {
+            self.fail();
+            return NotRespondingSnafu.fail();
+        }
+        let accepted = offered & (wanted | required);
+        for half in 0..if self.legacy { 1 } else { 2 } {
+            self.registers.write::<u32>(DRIVER_FEATURES_SELECT, half);
+            self.registers
+                .write(DRIVER_FEATURES, (accepted >> (32 * half)) as u32);
+        }
+
+        if self.legacy {
+            self.registers.write(GUEST_PAGE_SIZE, LEGACY_PAGE_SIZE);
+        } else {
+            self.registers
+                .write(STATUS, ACKNOWLEDGE | DRIVER_LOADED | FEATURES_OK);
+            if self.registers.read::<u32>(STATUS) & FEATURES_OK == 0 {
+                self.fail();
+                return NotRespondingSnafu.fail();
+            }
+        }
+        Ok(accepted)
+    }
+
+    /// Hands a queue over to the device
+    pub fn set_queue(&self, index: u32, queue: &Virtqueue) -> Result<(), ProbeError> {
+        self.registers.write(QUEUE_SELECT, index);
+        let size = queue.size() as u32;
+        if self.registers.read::<u32>(QUEUE_SIZE_MAX) < size {
+            self.fail();
+            return NotRespondingSnafu.fail();
+        }
+        self.registers.write(QUEUE_SIZE, size);
+        let [descriptors, driver, device] = queue.addresses();
+        if self.legacy {
+            self.registers.write(QUEUE_ALIGN, LEGACY_PAGE_SIZE);
+            self.registers
+                .write(QUEUE_PFN, (descriptors / LEGACY_PAGE_SIZE as u64) as u32);
+        } else {
+            for (register, address) in [
+                (QUEUE_DESCRIPTORS, descriptors),
+                (QUEUE_DRIVER, driver),
+                (QUEUE_DEVICE, device),
+            ] {
+                self.registers.write(register, address as u32);
+                self.registers.write(register + 4, (address >> 32) as u32);
+            }
+            self.registers.write::<u32>(QUEUE_READY, 1);
+        }
+        Ok(())
+    }
+
+    /// Finishes initialization, after which the device may be used
+    pub fn driver_ok(&self) {
+        let status: u32 = self.registers.read(STATUS);
+        self.registers.write(STATUS, status | DRIVER_OK);
+    }
+
+    /// Tells the device there are new buffers in a queue
+    pub fn notify(&self, index: u32) {
+        self.registers.write(QUEUE_NOTIFY, index);
+    }
+
+    /// Acknowledges all pending interrupts, which are ignored by drivers polling for
+    /// completed requests
+    pub fn acknowledge_interrupts(&self) {
+        let status: u32 = self.registers.read(INTERRUPT_STATUS);
+        self.registers.write(INTERRUPT_ACK, status);
+    }
+
+    /// Reads a field of the device-specific configuration
+    pub fn config<T: Copy>(&self, offset: usize) -> T {
+        self.registers.read(CONFIG + offset)
+    }
+
+    fn fail(&self) {
+        let status: u32 = self.registers.read(STATUS);
+        self.registers.write(STATUS, status | FAILED);
+    }
+}
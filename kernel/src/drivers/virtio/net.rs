@@ -0,0 +1,186 @@
+//! Virtio network device, a network card passed to QEMU with
+//! `-netdev user,id=... -device virtio-net-device,netdev=...`
+//!
+//! All receive buffers stay posted to the device, which raises an interrupt once it fills one
+//! with a frame. Frames are transmitted one at a time, polling for completion.
+
+use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+
+use core_lib::sync::AtomicMutex;
+use packet::{ethernet, MacAddress};
+use snafu::OptionExt;
+
+use crate::{
+    drivers::{
+        plic::InterruptHandler,
+        registry::{DeviceInstance, InvaildPropertySnafu, ProbeError},
+    },
+    net::NetworkDevice,
+    task::wait_queue::WaitQueue,
+};
+
+use super::{
+    queue::{Buffer, Virtqueue},
+    Transport,
+};
+
+// features
+const FEATURE_MAC: u64 = 1 << 5;
+
+// configuration
+const MAC: usize = 0x00;
+
+const RECEIVE_QUEUE: u32 = 0;
+const TRANSMIT_QUEUE: u32 = 1;
+
+/// Header preceding frames, which legacy devices send without the number of merged buffers
+const HEADER_LENGTH: usize = 12;
+const LEGACY_HEADER_LENGTH: usize = 10;
+const BUFFER_SIZE: usize = HEADER_LENGTH + ethernet::HEADER_LENGTH + ethernet::MTU;
+/// Locally administered address used if the device does not provide one
+const DEFAULT_MAC: MacAddress = MacAddress([0x02, 0x00, 0x00, 0x12, 0x34, 0x56]);
+
+/// Receive queue with the buffers posted to it, indexed by request ids
+struct ReceiveQueue {
+    queue: Virtqueue,
+    buffers: Vec<Option<Box<[u8]>>>,
+}
+
+struct TransmitQueue {
+    queue: Virtqueue,
+    /// Header of sent frames, which is all zeros as no offloads are used
+    header: Box<[u8; HEADER_LENGTH]>,
+}
+
+pub struct VirtioNet {
+    transport: Transport,
+    mac: MacAddress,
+    header_length: usize,
+    /// Interrupt source of the PLIC
+    irq: u32,
+    receive: AtomicMutex<ReceiveQueue>,
+    transmit: AtomicMutex<TransmitQueue>,
+    /// Tasks waiting for a frame to be received
+    received: WaitQueue,
+}
+
+pub(super) fn probe(transport: Transport, irq: Option<u32>) -> Result<DeviceInstance, ProbeError> {
+    let irq = irq.context(InvaildPropertySnafu { name: "interrupts" })?;
+    let features = transport.initialize(FEATURE_MAC)?;
+    let queue = Virtqueue::new();
+    let mut receive = ReceiveQueue {
+        buffers: (0..queue.size()).map(|_| None).collect(),
+        queue,
+    };
+    let transmit = TransmitQueue {
+        queue: Virtqueue::new(),
+        header: Box::new([0; HEADER_LENGTH]),
+    };
+    transport.set_queue(RECEIVE_QUEUE, &receive.queue)?;
+    transport.set_queue(TRANSMIT_QUEUE, &transmit.queue)?;
+    for _ in 0..receive.buffers.len() {
+        receive.post(vec![0; BUFFER_SIZE].into_boxed_slice());
+    }
+    transport.driver_ok();
+    transport.notify(RECEIVE_QUEUE);
+
+    let mac = if features & FEATURE_MAC != 0 {
+        MacAddress(core::array::from_fn(|i| transport.config::<u8>(MAC + i)))
+    } else {
+        DEFAULT_MAC
+    };
+    let header_length = if transport.is_legacy() {
+        LEGACY_HEADER_LENGTH
+    } else {
+        HEADER_LENGTH
+    };
+    Ok(Arc::new(VirtioNet {
+        transport,
+        mac,
+        header_length,
+        irq,
+        receive: AtomicMutex::new(receive),
+        transmit: AtomicMutex::new(transmit),
+        received: WaitQueue::new(),
+    }))
+}
+
+impl ReceiveQueue {
+    /// Makes a buffer available to the device, which has to be notified afterwards
+    fn post(&mut self, mut buffer: Box<[u8]>) {
+        let request = Buffer {
+            address: buffer.as_mut_ptr() as usize,
+            length: buffer.len(),
+            writeable: true,
+        };
+        // SAFETY: the buffer is kept until the request is taken back, and kernel memory is
+        // identity-mapped
+        let id = unsafe { self.queue.post(&[request]) }.expect("Receive queue is full");
+        self.buffers[id as usize] = Some(buffer);
+    }
+}
+
+impl VirtioNet {
+    pub fn irq(&self) -> u32 {
+        self.irq
+    }
+
+    /// Takes a frame out of a filled buffer, posting the buffer again
+    fn take_frame(&self) -> Option<Vec<u8>> {
+        let mut receive = self.receive.lock();
+        let (id, length) = receive.queue.take_used()?;
+        let buffer = receive.buffers[id as usize]
+            .take()
+            .expect("Device used a buffer which was not posted");
+        let frame = buffer
+            .get(self.header_length..length as usize)
+            .map(Vec::from)
+            .unwrap_or_default();
+        receive.post(buffer);
+        self.transport.notify(RECEIVE_QUEUE);
+        Some(frame)
+    }
+}
+
+impl NetworkDevice for VirtioNet {
+    fn mac_address(&self) -> MacAddress {
+        self.mac
+    }
+
+    fn transmit(&self, frame: &[u8]) {
+        let mut transmit = self.transmit.lock();
+        let TransmitQueue { queue, header } = &mut *transmit;
+        let header = Buffer {
+            address: header.as_ptr() as usize,
+            length: self.header_length,
+            writeable: false,
+        };
+        // the device only reads the frame
+        let frame = Buffer {
+            address: frame.as_ptr() as usize,
+            length: frame.len(),
+            writeable: false,
+        };
+        // SAFETY: header and frame are borrowed until the request completes, and kernel
+        // memory is identity-mapped
+        unsafe { queue.submit(&[header, frame], || self.transport.notify(TRANSMIT_QUEUE)) };
+    }
+
+    fn receive(&self) -> Vec<u8> {
+        loop {
+            self.received
+                .wait_until(|| self.receive.lock().queue.has_used());
+            match self.take_frame() {
+                Some(frame) if !frame.is_empty() => return frame,
+                _ => {}
+            }
+        }
+    }
+}
+
+impl InterruptHandler for VirtioNet {
+    fn handle_interrupt(&self) {
+        self.transport.acknowledge_interrupts();
+        self.received.wake_all();
+    }
+}
@@ -0,0 +1,194 @@
+//! Split virtqueues, through which buffers are passed to a device
+
+use alloc::boxed::Box;
+use core::ptr::{addr_of, addr_of_mut, read_volatile, write_volatile};
+
+use super::super::mmio::io_fence;
+
+/// Number of descriptors in a queue
+const SIZE: usize = 8;
+
+const DESCRIPTOR_NEXT: u16 = 1;
+const DESCRIPTOR_WRITE: u16 = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor {
+    address: u64,
+    length: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct Available {
+    flags: u16,
+    index: u16,
+    ring: [u16; SIZE],
+    used_event: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UsedElement {
+    id: u32,
+    length: u32,
+}
+
+/// Ring written by the device. Legacy devices expect it on the page following the others
+#[repr(C, align(4096))]
+struct Used {
+    flags: u16,
+    index: u16,
+    ring: [UsedElement; SIZE],
+    available_event: u16,
+}
+
+#[repr(C, align(4096))]
+struct Rings {
+    descriptors: [Descriptor; SIZE],
+    available: Available,
+    used: Used,
+}
+
+/// Buffer passed to a device
+pub struct Buffer {
+    pub address: usize,
+    pub length: usize,
+    /// Whether the device writes to the buffer rather than reads it
+    pub writeable: bool,
+}
+
+/// Queue of requests, which are either waited for one at a time with [`Virtqueue::submit`]
+/// or posted and later collected with [`Virtqueue::take_used`]
+pub struct Virtqueue {
+    /// Shared with the device, which accesses it by its physical address
+    rings: Box<Rings>,
+    last_used: u16,
+    /// First of the free descriptors, which are chained by their `next` fields
+    free: u16,
+    free_count: usize,
+}
+
+impl Virtqueue {
+    pub fn new() -> Virtqueue {
+        // SAFETY: rings consist of integers only, for which zero is a valid value
+        let mut rings: Box<Rings> = unsafe { Box::new_zeroed().assume_init() };
+        for (index, descriptor) in rings.descriptors.iter_mut().enumerate() {
+            descriptor.next = index as u16 + 1;
+        }
+        Virtqueue {
+            rings,
+            last_used: 0,
+            free: 0,
+            free_count: SIZE,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        SIZE
+    }
+
+    /// Physical addresses of the descriptor table, the available and the used ring
+    pub fn addresses(&self) -> [u64; 3] {
+        // kernel memory is identity-mapped
+        let rings = &*self.rings;
+        [
+            addr_of!(rings.descriptors) as u64,
+            addr_of!(rings.available) as u64,
+            addr_of!(rings.used) as u64,
+        ]
+    }
+
+    /// Passes a chain of buffers to the device, returning the length written by it once
+    /// it is done. `notify` should tell the device about the new request. No other requests
+    /// may be in flight
+    ///
+    /// # Safety
+    /// Buffers must be valid for the device to access until this function returns
+    pub unsafe fn submit(&mut self, buffers: &[Buffer], notify: impl FnOnce()) -> u32 {
+        // SAFETY: buffers are valid until the request is taken back below
+        unsafe { self.post(buffers) }.expect("Virtqueue used by another request");
+        notify();
+        loop {
+            if let Some((_, length)) = self.take_used() {
+                return length;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Makes a chain of buffers available to the device without waiting for it, returning
+    /// the id of the request or `None` if there are not enough free descriptors. The device
+    /// has to be notified afterwards
+    ///
+    /// # Safety
+    /// Buffers must be valid for the device to access until the request is returned by
+    /// [`Virtqueue::take_used`]
+    pub unsafe fn post(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        assert!(
+            !buffers.is_empty() && buffers.len() <= SIZE,
+            "Invaild number of buffers in a virtqueue request"
+        );
+        if buffers.len() > self.free_count {
+            return None;
+        }
+        let head = self.free;
+        let mut index = head;
+        for (position, buffer) in buffers.iter().enumerate() {
+            let descriptor = &mut self.rings.descriptors[index as usize];
+            descriptor.address = buffer.address as u64;
+            descriptor.length = buffer.length as u32;
+            descriptor.flags = if buffer.writeable {
+                DESCRIPTOR_WRITE
+            } else {
+                0
+            };
+            if position + 1 < buffers.len() {
+                descriptor.flags |= DESCRIPTOR_NEXT;
+            }
+            index = descriptor.next;
+        }
+        // the next field of the last descriptor keeps pointing into the free list
+        self.free = index;
+        self.free_count -= buffers.len();
+
+        let available = &mut self.rings.available;
+        let index = available.index;
+        available.ring[index as usize % SIZE] = head;
+        io_fence();
+        // SAFETY: the index is a field of the rings
+        unsafe { write_volatile(addr_of_mut!(available.index), index.wrapping_add(1)) };
+        io_fence();
+        Some(head)
+    }
+
+    /// Takes back the next request completed by the device, returning its id and the length
+    /// written by the device
+    pub fn take_used(&mut self) -> Option<(u16, u32)> {
+        if !self.has_used() {
+            return None;
+        }
+        io_fence();
+        let element = self.rings.used.ring[self.last_used as usize % SIZE];
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let head = element.id as u16;
+        let mut last = head;
+        let mut count = 1;
+        while self.rings.descriptors[last as usize].flags & DESCRIPTOR_NEXT != 0 {
+            last = self.rings.descriptors[last as usize].next;
+            count += 1;
+        }
+        self.rings.descriptors[last as usize].next = self.free;
+        self.free = head;
+        self.free_count += count;
+        Some((head, element.length))
+    }
+
+    /// Checks whether the device has completed a request not taken back yet
+    pub fn has_used(&self) -> bool {
+        // SAFETY: the index is a field of the rings
+        unsafe { read_volatile(addr_of!(self.rings.used.index)) != self.last_used }
+    }
+}
@@ -12,3 +12,8 @@ pub extern "C" fn entrypoint_rs(_hart_id: i32, devicetree_ptr: *const FdtHeader)
     unsafe { supervisor.set_global() };
     supervisor.launch(devicetree_ptr);
 }
+
+#[no_mangle]
+pub extern "C" fn secondary_entrypoint_rs(_hart_id: usize) -> ! {
+    Supervisor::global().launch_secondary()
+}
@@ -0,0 +1,38 @@
+//! Console device backed by the debug output, given to processes as standard streams
+
+use alloc::sync::Arc;
+
+use vfs::{File, FileType, Inode, Metadata, OpenFlags, VfsResult};
+
+use crate::Supervisor;
+
+pub struct DebugConsole;
+
+impl DebugConsole {
+    /// Opens the console for reading and writing
+    pub fn open() -> Arc<File> {
+        File::from_inode(Arc::new(DebugConsole), OpenFlags::READ_WRITE)
+    }
+}
+
+impl Inode for DebugConsole {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            inode: 0,
+            file_type: FileType::CharDevice,
+            permissions: 0o620,
+            size: 0,
+            links: 1,
+        }
+    }
+
+    /// There is no input yet, so reads always end immediately
+    fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> VfsResult<usize> {
+        Ok(0)
+    }
+
+    fn write_at(&self, _offset: u64, data: &[u8]) -> VfsResult<usize> {
+        Supervisor::global().debug_output().write_bytes(data);
+        Ok(data.len())
+    }
+}
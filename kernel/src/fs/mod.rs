@@ -0,0 +1,251 @@
+//! Kernel side of the filesystem tree, see the `vfs` crate for the VFS itself
+
+pub mod console;
+pub mod pipe;
+pub mod syscall;
+pub mod tty;
+
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+use core::time::Duration;
+
+use cpio::{Archive, FileType};
+use vfs::{
+    partition, BlockDevice, DevFs, Ext2, Fat32, Inode, OpenFlags, TmpFs, Vfs, VfsError, VfsResult,
+};
+
+use crate::{
+    drivers::{plic::Plic, uart::Uart, DeviceRegistry},
+    kdebug, task, Supervisor,
+};
+
+use console::DebugConsole;
+use tty::Tty;
+
+/// Directory under which volumes are mounted, as `disk0`, `disk1p2` and so on
+const DISKS_PATH: &str = "/mnt";
+/// Directory holding device nodes
+const DEVICES_PATH: &str = "/dev";
+/// Path of the terminal opened as standard streams of processes
+pub const CONSOLE_PATH: &str = "/dev/console";
+/// How often dirty blocks of the buffer cache are written to disks
+const WRITE_BACK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Whole disk or a partition of one, which may hold a filesystem
+pub struct Volume {
+    /// `diskN` for a disk, with `pM` appended for its partitions
+    pub name: String,
+    pub device: Arc<dyn BlockDevice>,
+}
+
+/// Splits disks into volumes, being their partitions or whole disks without a partition table
+pub fn volumes(disks: &[Arc<dyn BlockDevice>]) -> Vec<Volume> {
+    let mut volumes = Vec::new();
+    for (index, disk) in disks.iter().enumerate() {
+        let partitions = match partition::partitions(disk) {
+            Ok(partitions) => partitions,
+            Err(error) => {
+                kdebug!("Cannot read partition table of disk {}: {}", index, error);
+                Vec::new()
+            }
+        };
+        if partitions.is_empty() {
+            volumes.push(Volume {
+                name: format!("disk{}", index),
+                device: disk.clone(),
+            });
+        }
+        for partition in partitions {
+            kdebug!(
+                "Disk {} partition {}: {} {:?}, {} blocks from {}",
+                index,
+                partition.number(),
+                partition.partition_type(),
+                partition.name(),
+                partition.block_count(),
+                partition.start()
+            );
+            volumes.push(Volume {
+                name: format!("disk{}p{}", index, partition.number()),
+                device: partition,
+            });
+        }
+    }
+    volumes
+}
+
+/// Mounts the root filesystem, returning the index of the volume holding it
+///
+/// The first volume with an ext2 filesystem is used. Without one, the root is a tmpfs which
+/// may use half of the memory and is populated from the initrd.
+pub fn mount_root(
+    vfs: &Vfs,
+    volumes: &[Volume],
+    initrd: Option<Archive>,
+    memory: usize,
+) -> Option<usize> {
+    for (index, volume) in volumes.iter().enumerate() {
+        let filesystem = match Ext2::new(volume.device.clone()) {
+            Ok(filesystem) => filesystem,
+            Err(VfsError::InvaildArgument) => continue,
+            Err(error) => {
+                kdebug!("Cannot use ext2 filesystem of {}: {}", volume.name, error);
+                continue;
+            }
+        };
+        vfs.mount("/", filesystem).expect("Cannot mount ext2 at /");
+        kdebug!("Mounted ext2 filesystem of {} at /", volume.name);
+        if initrd.is_some() {
+            kdebug!("Root is read from a disk, initrd is not unpacked");
+        }
+        return Some(index);
+    }
+
+    vfs.mount("/", TmpFs::new(memory as u64 / 2) as Arc<_>)
+        .expect("Cannot mount tmpfs at /");
+    kdebug!("Mounted tmpfs at /");
+    if let Some(initrd) = initrd {
+        populate(vfs, initrd);
+    }
+    None
+}
+
+/// Mounts FAT32 filesystems found on volumes other than the root one under [`DISKS_PATH`]
+pub fn mount_disks(vfs: &Vfs, volumes: &[Volume], root_volume: Option<usize>) {
+    for (index, volume) in volumes.iter().enumerate() {
+        if Some(index) == root_volume {
+            continue;
+        }
+        let filesystem = match Fat32::new(volume.device.clone()) {
+            Ok(filesystem) => filesystem,
+            Err(error) => {
+                kdebug!(
+                    "{} ({} blocks) has no FAT32 filesystem: {}",
+                    volume.name,
+                    volume.device.block_count(),
+                    error
+                );
+                continue;
+            }
+        };
+        let path = format!("{}/{}", DISKS_PATH, volume.name);
+        let result = [DISKS_PATH, &path]
+            .into_iter()
+            .try_for_each(|directory| match vfs.create_dir(directory, 0o755) {
+                Ok(()) | Err(VfsError::AlreadyExists) => Ok(()),
+                Err(error) => Err(error),
+            })
+            .and_then(|()| vfs.mount(&path, filesystem));
+        match result {
+            Ok(()) => kdebug!("Mounted FAT32 filesystem of {} at {}", volume.name, path),
+            Err(error) => kdebug!("Cannot mount {} at {}: {}", volume.name, path, error),
+        }
+    }
+}
+
+/// Mounts a devfs at [`DEVICES_PATH`], with the terminal on the UART as `console`
+///
+/// Without a UART, or without a way to get its interrupts, the console only writes to the
+/// debug output and has no input.
+pub fn mount_devices(vfs: &Vfs, devices: &DeviceRegistry) {
+    let uart = devices.find::<Uart>();
+    let plic = devices.find::<Plic>();
+    let console: Arc<dyn Inode> = match (uart, plic) {
+        (Some(uart), Some(plic)) if uart.irq().is_some() => {
+            let tty = Tty::new(uart.clone());
+            plic.register(uart.irq().unwrap(), tty.clone());
+            tty
+        }
+        _ => {
+            kdebug!("No UART with an interrupt, console has no input");
+            Arc::new(DebugConsole)
+        }
+    };
+    let devfs = DevFs::new();
+    devfs
+        .add("console", console)
+        .expect("Cannot add console to devfs");
+
+    let result = match vfs.create_dir(DEVICES_PATH, 0o755) {
+        Ok(()) | Err(VfsError::AlreadyExists) => vfs.mount(DEVICES_PATH, devfs as Arc<_>),
+        Err(error) => Err(error),
+    };
+    match result {
+        Ok(()) => kdebug!("Mounted devfs at {}", DEVICES_PATH),
+        Err(error) => kdebug!("Cannot mount devfs at {}: {}", DEVICES_PATH, error),
+    }
+}
+
+/// Starts a kernel thread periodically writing dirty cached blocks to disks
+pub fn start_write_back() {
+    task::spawn("write-back", || loop {
+        task::sleep(WRITE_BACK_INTERVAL);
+        if let Err(error) = Supervisor::global().block_cache().write_back() {
+            kdebug!("Cannot write back cached blocks: {}", error);
+        }
+    });
+}
+
+/// Writes all modified data of filesystems and of the buffer cache to disks
+pub fn sync(vfs: &Vfs) -> VfsResult<()> {
+    vfs.sync()?;
+    Supervisor::global().block_cache().sync()
+}
+
+/// Copies all entries of an archive to the filesystem tree
+fn populate(vfs: &Vfs, archive: Archive) {
+    let mut files = 0;
+    for entry in archive.entries() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(error) => {
+                kdebug!("Cannot read initrd: {}", error);
+                break;
+            }
+        };
+        if entry.path().is_empty() {
+            continue;
+        }
+        let path = entry.path();
+        let result = match entry.file_type() {
+            FileType::Directory => vfs.create_dir(path, entry.permissions()),
+            FileType::Regular => write_file(vfs, path, entry.data(), entry.permissions()),
+            FileType::Symlink => core::str::from_utf8(entry.data())
+                .map_err(|_| VfsError::InvaildPath)
+                .and_then(|target| vfs.symlink(target, path)),
+            FileType::Other => {
+                kdebug!("Initrd entry {} has unsupported type, skipping", path);
+                continue;
+            }
+        };
+        match result {
+            Ok(()) => files += 1,
+            Err(error) => kdebug!("Cannot unpack {} from initrd: {}", path, error),
+        }
+    }
+    kdebug!("Unpacked {} initrd entries", files);
+}
+
+fn write_file(vfs: &Vfs, path: &str, data: &[u8], permissions: u32) -> VfsResult<()> {
+    let flags = OpenFlags::WRITE_ONLY | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+    let file = vfs.open(path, flags, permissions)?;
+    let mut written = 0;
+    while written < data.len() {
+        written += file.write(&data[written..])?;
+    }
+    Ok(())
+}
+
+/// Reads the whole contents of a file
+pub fn read_file(vfs: &Vfs, path: &str) -> VfsResult<Vec<u8>> {
+    let file = vfs.open(path, OpenFlags::empty(), 0)?;
+    let mut data = vec![0; file.metadata().size as usize];
+    let mut read = 0;
+    while read < data.len() {
+        match file.read(&mut data[read..])? {
+            0 => break,
+            length => read += length,
+        }
+    }
+    data.truncate(read);
+    Ok(data)
+}
@@ -0,0 +1,135 @@
+//! Anonymous pipes, passing bytes from a write end to a read end
+//!
+//! Readers block while a pipe is empty and get an end of file once all writers are gone.
+//! Writers block while it is full and fail with a broken pipe once all readers are gone.
+
+use alloc::{collections::VecDeque, sync::Arc};
+
+use core_lib::sync::AtomicMutex;
+use vfs::{File, FileType, Inode, Metadata, OpenFlags, VfsError, VfsResult};
+
+use crate::task::wait_queue::WaitQueue;
+
+/// Most bytes buffered in a pipe
+const CAPACITY: usize = 64 * 1024;
+
+struct Buffer {
+    data: VecDeque<u8>,
+    /// Whether the read end is still open
+    reader: bool,
+    /// Whether the write end is still open
+    writer: bool,
+}
+
+struct Pipe {
+    buffer: AtomicMutex<Buffer>,
+    /// Readers waiting for data or for the writer to go away
+    readable: WaitQueue,
+    /// Writers waiting for space or for the reader to go away
+    writeable: WaitQueue,
+}
+
+/// Creates a pipe, returning files of its read and write ends
+pub fn pipe() -> (Arc<File>, Arc<File>) {
+    let pipe = Arc::new(Pipe {
+        buffer: AtomicMutex::new(Buffer {
+            data: VecDeque::new(),
+            reader: true,
+            writer: true,
+        }),
+        readable: WaitQueue::new(),
+        writeable: WaitQueue::new(),
+    });
+    let reader = File::from_inode(Arc::new(PipeReader(pipe.clone())), OpenFlags::empty());
+    let writer = File::from_inode(Arc::new(PipeWriter(pipe)), OpenFlags::WRITE_ONLY);
+    (reader, writer)
+}
+
+fn metadata(permissions: u32) -> Metadata {
+    Metadata {
+        inode: 0,
+        file_type: FileType::Fifo,
+        permissions,
+        size: 0,
+        links: 1,
+    }
+}
+
+struct PipeReader(Arc<Pipe>);
+
+impl Inode for PipeReader {
+    fn metadata(&self) -> Metadata {
+        metadata(0o400)
+    }
+
+    /// Blocks until there is data or no writer, returning whatever is buffered
+    fn read_at(&self, _offset: u64, buffer: &mut [u8]) -> VfsResult<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        let pipe = &self.0;
+        pipe.readable.wait_until(|| {
+            let state = pipe.buffer.lock();
+            !state.data.is_empty() || !state.writer
+        });
+        let read = {
+            let mut state = pipe.buffer.lock();
+            let length = state.data.len().min(buffer.len());
+            for (target, byte) in buffer.iter_mut().zip(state.data.drain(..length)) {
+                *target = byte;
+            }
+            length
+        };
+        pipe.writeable.wake_all();
+        Ok(read)
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.0.buffer.lock().reader = false;
+        self.0.writeable.wake_all();
+    }
+}
+
+struct PipeWriter(Arc<Pipe>);
+
+impl Inode for PipeWriter {
+    fn metadata(&self) -> Metadata {
+        metadata(0o200)
+    }
+
+    /// Blocks until all data is buffered, unless the reader goes away first
+    fn write_at(&self, _offset: u64, data: &[u8]) -> VfsResult<usize> {
+        let pipe = &self.0;
+        let mut written = 0;
+        while written < data.len() {
+            pipe.writeable.wait_until(|| {
+                let state = pipe.buffer.lock();
+                state.data.len() < CAPACITY || !state.reader
+            });
+            {
+                let mut state = pipe.buffer.lock();
+                if !state.reader {
+                    // data written so far is reported, the next write fails
+                    return match written {
+                        0 => Err(VfsError::BrokenPipe),
+                        written => Ok(written),
+                    };
+                }
+                let length = (CAPACITY - state.data.len()).min(data.len() - written);
+                state.data.extend(&data[written..written + length]);
+                written += length;
+            }
+            pipe.readable.wake_all();
+        }
+        Ok(written)
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.buffer.lock().writer = false;
+        self.0.readable.wake_all();
+    }
+}
@@ -0,0 +1,239 @@
+//! File system calls, operating on the file table of the calling process
+
+use alloc::{string::String, sync::Arc, vec};
+
+use core_lib::syscall::{
+    Dirent, Errno, Stat, AT_FDCWD, AT_SYMLINK_NOFOLLOW, SEEK_CUR, SEEK_END, SEEK_SET, SIGPIPE,
+};
+use vfs::{File, FileType, Metadata, OpenFlags, SeekFrom, VfsError};
+
+use crate::{
+    fs::{self, pipe},
+    process::{syscall::Arguments, Process},
+    Supervisor,
+};
+
+/// Longest path accepted from user programs, including the null terminator
+const PATH_MAX: usize = 4096;
+/// Largest amount of data copied through a kernel buffer at once
+const IO_CHUNK: usize = 4096;
+
+pub fn openat(
+    process: &Process,
+    [dirfd, path, flags, mode, ..]: Arguments,
+) -> Result<usize, Errno> {
+    let path = read_path(process, dirfd, path)?;
+    let flags = OpenFlags::from_bits(flags).ok_or(Errno::EINVAL)?;
+    let file = Supervisor::global()
+        .vfs()
+        .open(&path, flags, mode as u32 & 0o7777)
+        .map_err(|error| error.errno())?;
+    process
+        .files()
+        .lock()
+        .insert(file)
+        .map_err(|error| error.errno())
+}
+
+pub fn close(process: &Process, [fd, ..]: Arguments) -> Result<usize, Errno> {
+    // the file is released after the table is unlocked, as closing it may take a while
+    let file = process
+        .files()
+        .lock()
+        .remove(fd)
+        .map_err(|error| error.errno())?;
+    drop(file);
+    Ok(0)
+}
+
+pub fn read(process: &Process, [fd, buffer, length, ..]: Arguments) -> Result<usize, Errno> {
+    let file = file(process, fd)?;
+    // reading pipes, terminals and sockets again would block with some data already read
+    let is_regular = file.metadata().file_type == FileType::Regular;
+    let mut chunk = vec![0; length.min(IO_CHUNK)];
+    let mut total = 0;
+    while total < length {
+        let wanted = chunk.len().min(length - total);
+        let copied = file
+            .read(&mut chunk[..wanted])
+            .map_err(|error| error.errno())
+            .and_then(|read| {
+                process.copy_to_user(buffer.wrapping_add(total), &chunk[..read])?;
+                Ok(read)
+            });
+        // data taken from the file cannot be put back, so it is reported as read
+        let read = match copied {
+            Ok(read) => read,
+            Err(_) if total > 0 => break,
+            Err(errno) => return Err(errno),
+        };
+        total += read;
+        if read < wanted || !is_regular {
+            break;
+        }
+    }
+    Ok(total)
+}
+
+pub fn write(process: &Process, [fd, buffer, length, ..]: Arguments) -> Result<usize, Errno> {
+    let file = file(process, fd)?;
+    let mut chunk = vec![0; length.min(IO_CHUNK)];
+    let mut total = 0;
+    while total < length {
+        let wanted = chunk.len().min(length - total);
+        process.copy_from_user(buffer.wrapping_add(total), &mut chunk[..wanted])?;
+        let written = file.write(&chunk[..wanted]).map_err(|error| {
+            if let VfsError::BrokenPipe = error {
+                process.send_signal(SIGPIPE);
+            }
+            error.errno()
+        })?;
+        total += written;
+        if written < wanted {
+            break;
+        }
+    }
+    Ok(total)
+}
+
+pub fn lseek(process: &Process, [fd, offset, whence, ..]: Arguments) -> Result<usize, Errno> {
+    let position = match whence {
+        SEEK_SET => SeekFrom::Start(offset as u64),
+        SEEK_CUR => SeekFrom::Current(offset as i64),
+        SEEK_END => SeekFrom::End(offset as i64),
+        _ => return Err(Errno::EINVAL),
+    };
+    let file = file(process, fd)?;
+    match file.seek(position) {
+        Ok(offset) => Ok(offset as usize),
+        Err(VfsError::Unsupported) => Err(Errno::ESPIPE),
+        Err(error) => Err(error.errno()),
+    }
+}
+
+pub fn fstat(process: &Process, [fd, stat, ..]: Arguments) -> Result<usize, Errno> {
+    let metadata = file(process, fd)?.metadata();
+    write_stat(process, stat, &metadata)?;
+    Ok(0)
+}
+
+pub fn newfstatat(
+    process: &Process,
+    [dirfd, path, stat, flags, ..]: Arguments,
+) -> Result<usize, Errno> {
+    if flags & !AT_SYMLINK_NOFOLLOW != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let path = read_path(process, dirfd, path)?;
+    let metadata = Supervisor::global()
+        .vfs()
+        .metadata(&path, flags & AT_SYMLINK_NOFOLLOW == 0)
+        .map_err(|error| error.errno())?;
+    write_stat(process, stat, &metadata)?;
+    Ok(0)
+}
+
+pub fn getdents64(process: &Process, [fd, buffer, length, ..]: Arguments) -> Result<usize, Errno> {
+    let file = file(process, fd)?;
+    let mut records = vec![0; length.min(IO_CHUNK)];
+    let mut used = 0;
+    let mut refused = false;
+    file.read_dir(|entry, offset| {
+        let dirent = Dirent {
+            inode: entry.inode,
+            offset: offset as i64,
+            file_type: entry.file_type.dirent_type(),
+            name: &entry.name,
+        };
+        match dirent.encode(&mut records[used..]) {
+            Some(length) => {
+                used += length;
+                true
+            }
+            None => {
+                refused = true;
+                false
+            }
+        }
+    })
+    .map_err(|error| error.errno())?;
+
+    if used == 0 && refused {
+        // not even a single entry fits
+        return Err(Errno::EINVAL);
+    }
+    process.copy_to_user(buffer, &records[..used])?;
+    Ok(used)
+}
+
+pub fn pipe2(process: &Process, [fds, flags, ..]: Arguments) -> Result<usize, Errno> {
+    if flags != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let (reader, writer) = pipe::pipe();
+    let (read_fd, write_fd) = {
+        let mut files = process.files().lock();
+        let read_fd = files.insert(reader).map_err(|error| error.errno())?;
+        match files.insert(writer) {
+            Ok(write_fd) => (read_fd, write_fd),
+            Err(error) => {
+                let _ = files.remove(read_fd);
+                return Err(error.errno());
+            }
+        }
+    };
+    let mut bytes = [0u8; 8];
+    bytes[..4].copy_from_slice(&(read_fd as i32).to_ne_bytes());
+    bytes[4..].copy_from_slice(&(write_fd as i32).to_ne_bytes());
+    if let Err(errno) = process.copy_to_user(fds, &bytes) {
+        let mut files = process.files().lock();
+        let _ = files.remove(read_fd);
+        let _ = files.remove(write_fd);
+        return Err(errno);
+    }
+    Ok(0)
+}
+
+pub fn ioctl(process: &Process, [fd, request, argument, ..]: Arguments) -> Result<usize, Errno> {
+    file(process, fd)?
+        .ioctl(request, argument)
+        .map_err(|error| error.errno())
+}
+
+pub fn sync(_: &Process, _: Arguments) -> Result<usize, Errno> {
+    fs::sync(Supervisor::global().vfs()).map_err(|error| error.errno())?;
+    Ok(0)
+}
+
+/// Open file of a descriptor
+pub fn file(process: &Process, fd: usize) -> Result<Arc<File>, Errno> {
+    process
+        .files()
+        .lock()
+        .get(fd)
+        .map_err(|error| error.errno())
+}
+
+/// Reads a path passed along with a directory descriptor, which has to stand for the root
+/// for relative paths
+pub fn read_path(process: &Process, dirfd: usize, address: usize) -> Result<String, Errno> {
+    let path = process.read_string(address, PATH_MAX)?;
+    if !path.starts_with('/') && dirfd != AT_FDCWD {
+        return Err(Errno::EINVAL);
+    }
+    Ok(path)
+}
+
+fn write_stat(process: &Process, address: usize, metadata: &Metadata) -> Result<(), Errno> {
+    let stat = Stat {
+        inode: metadata.inode,
+        mode: metadata.file_type.mode_bits() | metadata.permissions,
+        links: metadata.links,
+        size: metadata.size,
+    };
+    // SAFETY: Stat has no padding, so all of its bytes are initialized
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&stat as *const Stat as *const u8, size_of::<Stat>())
+    };
+    process.copy_to_user(address, bytes)
+}
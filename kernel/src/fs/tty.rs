@@ -0,0 +1,304 @@
+//! Terminal on top of the console UART, with a line discipline editing and echoing input
+//!
+//! In canonical mode input is collected into lines, which can be edited with erase, kill
+//! and word erase characters, and readers get at most a line at a time. An end-of-file
+//! character at the start of a line makes a read return 0. Interrupt, quit and suspend
+//! characters drop pending input, fail reads in progress and send a signal to the foreground
+//! process, which is the one that last read from the terminal. In raw mode bytes are passed
+//! as they come.
+
+use alloc::{
+    collections::VecDeque,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+
+use core_lib::{
+    sync::AtomicMutex,
+    syscall::{
+        sigmask, SigSet, Termios, ECHO, ECHOE, ICANON, ICRNL, ISIG, NSIG, ONLCR, OPOST, SIGINT,
+        SIGQUIT, SIGTSTP, TCGETS, TCSETS, VEOF, VERASE, VINTR, VKILL, VQUIT, VSUSP, VWERASE,
+    },
+};
+use vfs::{FileType, Inode, Metadata, VfsError, VfsResult};
+
+use crate::{
+    drivers::{plic::InterruptHandler, uart::Uart},
+    process::Process,
+    task::{self, wait_queue::WaitQueue},
+    traps::without_interrupts,
+};
+
+/// Most bytes of input kept, further ones are dropped until some are read
+const MAX_INPUT: usize = 4096;
+/// Erases the previous character on the screen
+const ERASE_ECHO: &[u8] = b"\x08 \x08";
+
+struct State {
+    termios: Termios,
+    /// Line being edited in canonical mode
+    line: Vec<u8>,
+    /// Input ready to be read. In canonical mode chunks are lines, an empty one standing for
+    /// an end of file
+    ready: VecDeque<Vec<u8>>,
+    /// Count of signal characters received
+    interrupts: u64,
+    /// Signals to be sent to the foreground process
+    raised: SigSet,
+    foreground: Weak<Process>,
+}
+
+impl State {
+    fn pending(&self) -> usize {
+        self.line.len() + self.ready.iter().map(|chunk| chunk.len()).sum::<usize>()
+    }
+}
+
+pub struct Tty {
+    uart: Arc<Uart>,
+    /// Locked with interrupts disabled, as input comes from the interrupt handler
+    state: AtomicMutex<State>,
+    /// Readers waiting for input
+    input: WaitQueue,
+    /// Keeps output of writers and echo from interleaving
+    output: AtomicMutex<()>,
+}
+
+impl Tty {
+    /// Creates a terminal in canonical mode. It receives input once registered as the
+    /// interrupt handler of the UART
+    pub fn new(uart: Arc<Uart>) -> Arc<Tty> {
+        uart.enable_receive_interrupt();
+        Arc::new(Tty {
+            uart,
+            state: AtomicMutex::new(State {
+                termios: Termios::canonical(),
+                line: Vec::new(),
+                ready: VecDeque::new(),
+                interrupts: 0,
+                raised: 0,
+                foreground: Weak::new(),
+            }),
+            input: WaitQueue::new(),
+            output: AtomicMutex::new(()),
+        })
+    }
+
+    /// Handles a received byte, returning whether readers should be woken
+    fn receive(&self, state: &mut State, mut byte: u8) -> bool {
+        let termios = state.termios;
+        let control = termios.control_characters;
+        let echo = termios.local_flags & ECHO != 0;
+        if byte == b'\r' && termios.input_flags & ICRNL != 0 {
+            byte = b'\n';
+        }
+        // unset control characters are 0
+        let is = |index: usize| control[index] != 0 && byte == control[index];
+
+        let signal = [(VINTR, SIGINT), (VQUIT, SIGQUIT), (VSUSP, SIGTSTP)]
+            .into_iter()
+            .find(|(index, _)| is(*index));
+        if let Some((_, signal)) = signal.filter(|_| termios.local_flags & ISIG != 0) {
+            state.line.clear();
+            state.ready.clear();
+            state.interrupts += 1;
+            state.raised |= sigmask(signal);
+            if echo {
+                // e.g. ^C
+                self.write_output(&termios, &[b'^', byte ^ 0x40, b'\n']);
+            }
+            return true;
+        }
+        if termios.local_flags & ICANON == 0 {
+            if state.pending() >= MAX_INPUT {
+                return false;
+            }
+            match state.ready.back_mut() {
+                Some(chunk) if !chunk.is_empty() => chunk.push(byte),
+                _ => state.ready.push_back(Vec::from([byte])),
+            }
+            if echo {
+                self.write_output(&termios, &[byte]);
+            }
+            return true;
+        }
+
+        let erased = if is(VERASE) || byte == b'\x08' {
+            state.line.pop().map_or(0, |_| 1)
+        } else if is(VKILL) {
+            core::mem::take(&mut state.line).len()
+        } else if is(VWERASE) {
+            let spaces = state.line.iter().rev().take_while(|b| **b == b' ').count();
+            let word = state.line[..state.line.len() - spaces]
+                .iter()
+                .rev()
+                .take_while(|b| **b != b' ')
+                .count();
+            state.line.truncate(state.line.len() - spaces - word);
+            spaces + word
+        } else if is(VEOF) {
+            let line = core::mem::take(&mut state.line);
+            state.ready.push_back(line);
+            return true;
+        } else {
+            // the newline is always accepted, so that a full line can still be read
+            if state.pending() >= MAX_INPUT - 1 && byte != b'\n' {
+                return false;
+            }
+            state.line.push(byte);
+            if echo {
+                self.write_output(&termios, &[byte]);
+            }
+            if byte != b'\n' {
+                return false;
+            }
+            let line = core::mem::take(&mut state.line);
+            state.ready.push_back(line);
+            return true;
+        };
+        if echo && termios.local_flags & ECHOE != 0 {
+            for _ in 0..erased {
+                self.write_output(&termios, ERASE_ECHO);
+            }
+        }
+        false
+    }
+
+    /// Writes bytes to the UART, translating newlines if the terminal is set to
+    fn write_output(&self, termios: &Termios, data: &[u8]) {
+        let translate = termios.output_flags & (OPOST | ONLCR) == OPOST | ONLCR;
+        without_interrupts(|| {
+            let _lock = self.output.lock();
+            for byte in data {
+                if *byte == b'\n' && translate {
+                    self.uart.write_byte(b'\r');
+                }
+                self.uart.write_byte(*byte);
+            }
+        });
+    }
+
+    fn set_termios(&self, termios: Termios) {
+        without_interrupts(|| {
+            let mut state = self.state.lock();
+            // a line being edited becomes readable in raw mode
+            if termios.local_flags & ICANON == 0 && !state.line.is_empty() {
+                let line = core::mem::take(&mut state.line);
+                state.ready.push_back(line);
+            }
+            state.termios = termios;
+        });
+        self.input.wake_all();
+    }
+}
+
+impl InterruptHandler for Tty {
+    fn handle_interrupt(&self) {
+        let mut wake = false;
+        let (raised, foreground) = {
+            let mut state = self.state.lock();
+            while let Some(byte) = self.uart.read_byte() {
+                wake |= self.receive(&mut state, byte);
+            }
+            (core::mem::take(&mut state.raised), state.foreground.clone())
+        };
+        if wake {
+            self.input.wake_all();
+        }
+        if let Some(process) = foreground.upgrade().filter(|_| raised != 0) {
+            for signal in (1..=NSIG).filter(|signal| raised & sigmask(*signal) != 0) {
+                process.send_signal(signal);
+            }
+        }
+    }
+}
+
+impl Inode for Tty {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            inode: 0,
+            file_type: FileType::CharDevice,
+            permissions: 0o620,
+            size: 0,
+            links: 1,
+        }
+    }
+
+    /// Blocks until there is input, returning at most a line in canonical mode
+    fn read_at(&self, _offset: u64, buffer: &mut [u8]) -> VfsResult<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        let foreground = task::current().process().map(Arc::downgrade);
+        let interrupts = without_interrupts(|| {
+            let mut state = self.state.lock();
+            if let Some(foreground) = foreground {
+                state.foreground = foreground;
+            }
+            state.interrupts
+        });
+        loop {
+            self.input.wait_until(|| {
+                let state = self.state.lock();
+                !state.ready.is_empty() || state.interrupts != interrupts
+            });
+            let result = without_interrupts(|| {
+                let mut state = self.state.lock();
+                if state.interrupts != interrupts {
+                    return Some(Err(VfsError::Interrupted));
+                }
+                let chunk = state.ready.front_mut()?;
+                let length = chunk.len().min(buffer.len());
+                buffer[..length].copy_from_slice(&chunk[..length]);
+                chunk.drain(..length);
+                if chunk.is_empty() {
+                    state.ready.pop_front();
+                }
+                Some(Ok(length))
+            });
+            // another reader could have taken the input first
+            if let Some(result) = result {
+                return result;
+            }
+        }
+    }
+
+    fn write_at(&self, _offset: u64, data: &[u8]) -> VfsResult<usize> {
+        let termios = without_interrupts(|| self.state.lock().termios);
+        self.write_output(&termios, data);
+        Ok(data.len())
+    }
+
+    fn ioctl(&self, request: usize, argument: usize) -> VfsResult<usize> {
+        let process = task::current()
+            .process()
+            .cloned()
+            .ok_or(VfsError::BadAddress)?;
+        match request {
+            TCGETS => {
+                let termios = without_interrupts(|| self.state.lock().termios);
+                // SAFETY: Termios has no padding, so all of its bytes are initialized
+                let bytes = unsafe {
+                    core::slice::from_raw_parts(
+                        &termios as *const Termios as *const u8,
+                        size_of::<Termios>(),
+                    )
+                };
+                process
+                    .copy_to_user(argument, bytes)
+                    .map_err(|_| VfsError::BadAddress)?;
+            }
+            TCSETS => {
+                let mut bytes = [0u8; size_of::<Termios>()];
+                process
+                    .copy_from_user(argument, &mut bytes)
+                    .map_err(|_| VfsError::BadAddress)?;
+                // SAFETY: any bytes make a valid Termios, which is read unaligned
+                let termios = unsafe { (bytes.as_ptr() as *const Termios).read_unaligned() };
+                self.set_termios(termios);
+            }
+            _ => return Err(VfsError::NotATerminal),
+        }
+        Ok(0)
+    }
+}
@@ -0,0 +1,57 @@
+//! Information about harts and starting secondary ones
+
+use core::arch::asm;
+
+use devicetree::FlattenedDeviceTree;
+
+use crate::{kdebug, sbi, task::KernelStack};
+
+/// Maximal number of harts supported, hart ids have to be lower than that
+pub const MAX_HARTS: usize = 8;
+
+extern "C" {
+    fn secondary_entrypoint();
+}
+
+/// Returns id of the current hart, stored in `tp` at entry
+pub fn current_id() -> usize {
+    let id: usize;
+    // SAFETY: tp is set at entry and never modified afterwards
+    unsafe { asm!("mv {}, tp", out(reg) id) };
+    id
+}
+
+/// Starts all enabled harts listed in the devicetree, other than the current one
+pub fn start_secondary_harts(dt: &FlattenedDeviceTree) {
+    let Some(cpus) = dt.root().ok().and_then(|root| root.child("cpus")) else {
+        return;
+    };
+    let hart_ids = cpus
+        .children()
+        .filter(|cpu| cpu.property("device_type").and_then(|v| v.string().ok()) == Some("cpu"))
+        .filter(|cpu| cpu.is_enabled())
+        .filter_map(|cpu| cpu.property("reg")?.usize().ok());
+
+    for hart_id in hart_ids.filter(|id| *id != current_id()) {
+        if hart_id >= MAX_HARTS {
+            kdebug!("Hart {} not supported, ignoring it", hart_id);
+            continue;
+        }
+
+        // used by the hart's idle task for as long as the machine runs
+        let stack = KernelStack::new();
+        let stack_top = stack.top();
+        core::mem::forget(stack);
+
+        match unsafe {
+            sbi::hart_state::hart_start(
+                hart_id,
+                secondary_entrypoint as *const () as usize,
+                stack_top,
+            )
+        } {
+            Ok(_) => kdebug!("Starting hart {}", hart_id),
+            Err(error) => kdebug!("Cannot start hart {}: {:?}", hart_id, error),
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//! Initial ramdisk, a cpio newc archive loaded by the bootloader
+//!
+//! Its location is passed in `linux,initrd-start` and `linux,initrd-end` properties of
+//! `/chosen`. The memory is reserved in the memory map, so the archive stays in place.
+
+use cpio::Archive;
+use devicetree::FlattenedDeviceTree;
+
+/// Returns the `(start, size)` region of the initrd, if there is one
+pub fn region(dt: &FlattenedDeviceTree) -> Option<(usize, usize)> {
+    let chosen = dt.root().ok()?.child("chosen")?;
+    let start = chosen.property("linux,initrd-start")?.usize().ok()?;
+    let end = chosen.property("linux,initrd-end")?.usize().ok()?;
+    (end > start).then_some((start, end - start))
+}
+
+/// Returns the initrd archive, if the bootloader passed one
+pub fn archive(dt: &FlattenedDeviceTree) -> Option<Archive<'static>> {
+    let (start, size) = region(dt)?;
+    // SAFETY: kernel is identity-mapped and the region is excluded from the memory map,
+    // so nothing ever writes to it
+    let bytes = unsafe { core::slice::from_raw_parts(start as *const u8, size) };
+    Some(Archive::new(bytes))
+}
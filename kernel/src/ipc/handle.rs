@@ -0,0 +1,198 @@
+//! Handles, through which processes refer to IPC objects with a set of rights
+
+use alloc::{sync::Arc, vec::Vec};
+
+use bitflags::bitflags;
+use core_lib::syscall::{
+    RIGHT_DUPLICATE, RIGHT_READ, RIGHT_RECEIVE, RIGHT_SEND, RIGHT_TRANSFER, RIGHT_WRITE,
+};
+
+use crate::memory::object::VmObject;
+
+use super::{Endpoint, IpcError, ReplyToken};
+
+/// Most handles open in a process at once
+const MAX_HANDLES: usize = 256;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Rights: u32 {
+        const SEND = RIGHT_SEND;
+        const RECEIVE = RIGHT_RECEIVE;
+        const TRANSFER = RIGHT_TRANSFER;
+        const DUPLICATE = RIGHT_DUPLICATE;
+        const READ = RIGHT_READ;
+        const WRITE = RIGHT_WRITE;
+    }
+}
+
+impl Rights {
+    /// Rights of a newly created endpoint
+    pub const ENDPOINT: Rights = Rights::SEND
+        .union(Rights::RECEIVE)
+        .union(Rights::TRANSFER)
+        .union(Rights::DUPLICATE);
+    /// Rights of a newly created memory object
+    pub const MEMORY: Rights = Rights::READ
+        .union(Rights::WRITE)
+        .union(Rights::TRANSFER)
+        .union(Rights::DUPLICATE);
+}
+
+/// Counts a handle as a receiver of an endpoint while it exists
+struct Receiver(Arc<Endpoint>);
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.0.remove_receiver();
+    }
+}
+
+enum Object {
+    Endpoint {
+        endpoint: Arc<Endpoint>,
+        /// Only kept for dropping
+        _receiver: Option<Receiver>,
+    },
+    Reply(ReplyToken),
+    Memory(Arc<VmObject>),
+}
+
+/// Object referred to by a handle, along with rights to it
+pub struct Capability {
+    object: Object,
+    rights: Rights,
+}
+
+impl Capability {
+    pub fn endpoint(endpoint: Arc<Endpoint>, rights: Rights) -> Capability {
+        let receiver = rights.contains(Rights::RECEIVE).then(|| {
+            endpoint.add_receiver();
+            Receiver(endpoint.clone())
+        });
+        Capability {
+            object: Object::Endpoint {
+                endpoint,
+                _receiver: receiver,
+            },
+            rights,
+        }
+    }
+
+    pub fn memory(object: Arc<VmObject>, rights: Rights) -> Capability {
+        Capability {
+            object: Object::Memory(object),
+            rights,
+        }
+    }
+
+    /// Capability to reply to a received message, which can be passed on but not duplicated
+    pub fn reply(token: ReplyToken) -> Capability {
+        Capability {
+            object: Object::Reply(token),
+            rights: Rights::SEND | Rights::TRANSFER,
+        }
+    }
+
+    pub fn rights(&self) -> Rights {
+        self.rights
+    }
+
+    /// Makes a capability to the same object with a subset of the rights
+    pub fn duplicate(&self, rights: Rights) -> Result<Capability, IpcError> {
+        if !self.rights.contains(Rights::DUPLICATE | rights) {
+            return Err(IpcError::MissingRights);
+        }
+        match &self.object {
+            Object::Endpoint { endpoint, .. } => Ok(Capability::endpoint(endpoint.clone(), rights)),
+            Object::Memory(object) => Ok(Capability::memory(object.clone(), rights)),
+            Object::Reply(_) => Err(IpcError::WrongObject),
+        }
+    }
+
+    /// Endpoint referred to, if the capability has the given right to it
+    pub fn endpoint_with(&self, right: Rights) -> Result<Arc<Endpoint>, IpcError> {
+        let Object::Endpoint { endpoint, .. } = &self.object else {
+            return Err(IpcError::WrongObject);
+        };
+        if !self.rights.contains(right) {
+            return Err(IpcError::MissingRights);
+        }
+        Ok(endpoint.clone())
+    }
+
+    /// Memory object referred to
+    pub fn memory_object(&self) -> Result<Arc<VmObject>, IpcError> {
+        match &self.object {
+            Object::Memory(object) => Ok(object.clone()),
+            _ => Err(IpcError::WrongObject),
+        }
+    }
+
+    pub fn is_reply(&self) -> bool {
+        matches!(self.object, Object::Reply(_))
+    }
+
+    pub fn into_reply(self) -> Result<ReplyToken, IpcError> {
+        match self.object {
+            Object::Reply(token) => Ok(token),
+            _ => Err(IpcError::WrongObject),
+        }
+    }
+}
+
+/// Handles of a process, numbered like file descriptors
+#[derive(Default)]
+pub struct HandleTable {
+    handles: Vec<Option<Capability>>,
+}
+
+impl HandleTable {
+    pub fn new() -> HandleTable {
+        HandleTable::default()
+    }
+
+    /// Adds a capability at the lowest free handle, which is returned
+    pub fn insert(&mut self, capability: Capability) -> Result<usize, IpcError> {
+        let handle = match self.handles.iter().position(Option::is_none) {
+            Some(handle) => handle,
+            None if self.handles.len() < MAX_HANDLES => {
+                self.handles.push(None);
+                self.handles.len() - 1
+            }
+            None => return Err(IpcError::TooManyHandles),
+        };
+        self.handles[handle] = Some(capability);
+        Ok(handle)
+    }
+
+    pub fn get(&self, handle: usize) -> Result<&Capability, IpcError> {
+        self.handles
+            .get(handle)
+            .and_then(Option::as_ref)
+            .ok_or(IpcError::InvaildHandle)
+    }
+
+    /// Closes a handle, returning the capability it referred to
+    pub fn remove(&mut self, handle: usize) -> Result<Capability, IpcError> {
+        let capability = self
+            .handles
+            .get_mut(handle)
+            .and_then(Option::take)
+            .ok_or(IpcError::InvaildHandle)?;
+        while self.handles.last().is_some_and(Option::is_none) {
+            self.handles.pop();
+        }
+        Ok(capability)
+    }
+
+    /// Number of handles which can still be inserted
+    pub fn free_slots(&self) -> usize {
+        let used = self
+            .handles
+            .iter()
+            .filter(|handle| handle.is_some())
+            .count();
+        MAX_HANDLES - used
+    }
+}
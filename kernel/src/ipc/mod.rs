@@ -0,0 +1,173 @@
+//! Synchronous message passing through endpoints
+//!
+//! A client sends a message to an endpoint and blocks until a server, having received it,
+//! replies. Servers block while there is nothing to receive. Messages carry a few bytes of
+//! data and handles, which are moved between handle tables of the processes, so that access
+//! to endpoints can be given away.
+
+pub mod handle;
+pub mod syscall;
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+
+use core_lib::{sync::AtomicMutex, syscall::Errno};
+use snafu::Snafu;
+
+use crate::task::wait_queue::WaitQueue;
+
+use handle::Capability;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Snafu)]
+pub enum IpcError {
+    #[snafu(display("Invaild handle"))]
+    InvaildHandle,
+    #[snafu(display("Handle refers to another kind of object"))]
+    WrongObject,
+    #[snafu(display("Handle lacks rights needed for the operation"))]
+    MissingRights,
+    #[snafu(display("No one can receive from the endpoint or reply anymore"))]
+    PeerClosed,
+    #[snafu(display("Too many handles"))]
+    TooManyHandles,
+    #[snafu(display("Invaild message"))]
+    InvaildMessage,
+}
+
+impl IpcError {
+    /// Error number reported to user programs
+    pub fn errno(&self) -> Errno {
+        match self {
+            IpcError::InvaildHandle => Errno::EBADF,
+            IpcError::WrongObject => Errno::EINVAL,
+            IpcError::MissingRights => Errno::EACCES,
+            IpcError::PeerClosed => Errno::EPIPE,
+            IpcError::TooManyHandles => Errno::EMFILE,
+            IpcError::InvaildMessage => Errno::EINVAL,
+        }
+    }
+}
+
+pub struct Message {
+    pub data: Vec<u8>,
+    pub handles: Vec<Capability>,
+}
+
+/// A sent message, from being queued until it is replied to
+struct Transaction {
+    /// Taken by the receiver
+    request: AtomicMutex<Option<Message>>,
+    reply: AtomicMutex<Option<Result<Message, IpcError>>>,
+    replied: WaitQueue,
+}
+
+impl Transaction {
+    /// Completes the transaction, unless it already is
+    fn complete(&self, result: Result<Message, IpcError>) {
+        {
+            let mut reply = self.reply.lock();
+            if reply.is_some() {
+                return;
+            }
+            *reply = Some(result);
+        }
+        self.replied.wake_all();
+    }
+}
+
+/// Obligation of a receiver to reply to a message. A sender gets an error if it is dropped
+/// without replying
+pub struct ReplyToken(Arc<Transaction>);
+
+impl ReplyToken {
+    pub fn reply(self, message: Message) {
+        self.0.complete(Ok(message));
+    }
+}
+
+impl Drop for ReplyToken {
+    fn drop(&mut self) {
+        self.0.complete(Err(IpcError::PeerClosed));
+    }
+}
+
+struct EndpointState {
+    /// Messages waiting for a receiver
+    pending: VecDeque<Arc<Transaction>>,
+    /// Number of handles with the right to receive, without which messages are refused
+    receivers: usize,
+}
+
+pub struct Endpoint {
+    state: AtomicMutex<EndpointState>,
+    /// Receivers waiting for messages
+    receivable: WaitQueue,
+}
+
+impl Endpoint {
+    pub fn new() -> Arc<Endpoint> {
+        Arc::new(Endpoint {
+            state: AtomicMutex::new(EndpointState {
+                pending: VecDeque::new(),
+                receivers: 0,
+            }),
+            receivable: WaitQueue::new(),
+        })
+    }
+
+    /// Sends a message and waits for the reply. Handles of a message which is not received
+    /// are closed
+    pub fn send(&self, message: Message) -> Result<Message, IpcError> {
+        let transaction = Arc::new(Transaction {
+            request: AtomicMutex::new(Some(message)),
+            reply: AtomicMutex::new(None),
+            replied: WaitQueue::new(),
+        });
+        {
+            let mut state = self.state.lock();
+            if state.receivers == 0 {
+                return Err(IpcError::PeerClosed);
+            }
+            state.pending.push_back(transaction.clone());
+        }
+        self.receivable.wake_all();
+
+        transaction
+            .replied
+            .wait_until(|| transaction.reply.lock().is_some());
+        let reply = transaction.reply.lock().take();
+        reply.unwrap()
+    }
+
+    /// Waits for a message, which has to be replied to with the returned token
+    pub fn receive(&self) -> (Message, ReplyToken) {
+        loop {
+            self.receivable
+                .wait_until(|| !self.state.lock().pending.is_empty());
+            let transaction = self.state.lock().pending.pop_front();
+            // another receiver could have taken the message first
+            if let Some(transaction) = transaction {
+                let message = transaction.request.lock().take().unwrap();
+                return (message, ReplyToken(transaction));
+            }
+        }
+    }
+
+    fn add_receiver(&self) {
+        self.state.lock().receivers += 1;
+    }
+
+    /// Drops a receiving handle, failing waiting messages if it was the last one
+    fn remove_receiver(&self) {
+        let orphaned = {
+            let mut state = self.state.lock();
+            state.receivers -= 1;
+            if state.receivers > 0 {
+                return;
+            }
+            core::mem::take(&mut state.pending)
+        };
+        for transaction in orphaned {
+            transaction.complete(Err(IpcError::PeerClosed));
+        }
+    }
+}
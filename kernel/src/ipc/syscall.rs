@@ -0,0 +1,231 @@
+//! System calls creating and closing handles and passing messages through endpoints
+
+use alloc::vec::Vec;
+
+use core_lib::syscall::{Errno, IpcMessage, IPC_MAX_DATA, IPC_MAX_HANDLES};
+
+use crate::{
+    memory::{mapping::Mapping, object::VmObject, page::PAGE_SIZE, paging::PageFlags},
+    process::{
+        syscall::{protection_flags, Arguments},
+        Process,
+    },
+};
+
+use super::{
+    handle::{Capability, HandleTable, Rights},
+    Endpoint, IpcError, Message,
+};
+
+pub fn endpoint_create(process: &Process, _: Arguments) -> Result<usize, Errno> {
+    let capability = Capability::endpoint(Endpoint::new(), Rights::ENDPOINT);
+    process
+        .handles()
+        .lock()
+        .insert(capability)
+        .map_err(|error| error.errno())
+}
+
+pub fn handle_duplicate(
+    process: &Process,
+    [handle, rights, ..]: Arguments,
+) -> Result<usize, Errno> {
+    let rights = u32::try_from(rights)
+        .ok()
+        .and_then(Rights::from_bits)
+        .ok_or(Errno::EINVAL)?;
+    let mut handles = process.handles().lock();
+    let duplicate = handles
+        .get(handle)
+        .and_then(|capability| capability.duplicate(rights))
+        .map_err(|error| error.errno())?;
+    handles.insert(duplicate).map_err(|error| error.errno())
+}
+
+pub fn handle_close(process: &Process, [handle, ..]: Arguments) -> Result<usize, Errno> {
+    let capability = process
+        .handles()
+        .lock()
+        .remove(handle)
+        .map_err(|error| error.errno())?;
+    // closing a reply handle or the last receiving one wakes senders
+    drop(capability);
+    Ok(0)
+}
+
+pub fn ipc_send(
+    process: &Process,
+    [handle, message, reply, ..]: Arguments,
+) -> Result<usize, Errno> {
+    let request = read_message(process, message)?;
+    let (endpoint, request) = {
+        let mut handles = process.handles().lock();
+        let endpoint = handles
+            .get(handle)
+            .and_then(|capability| capability.endpoint_with(Rights::SEND))
+            .map_err(|error| error.errno())?;
+        let message = take_message(&mut handles, &request, None).map_err(|error| error.errno())?;
+        (endpoint, message)
+    };
+    let response = endpoint.send(request).map_err(|error| error.errno())?;
+    write_message(process, reply, response)?;
+    Ok(0)
+}
+
+pub fn ipc_receive(process: &Process, [handle, message, ..]: Arguments) -> Result<usize, Errno> {
+    let endpoint = {
+        let handles = process.handles().lock();
+        // the message is not taken if its handles could not be stored
+        if handles.free_slots() < IPC_MAX_HANDLES + 1 {
+            return Err(Errno::EMFILE);
+        }
+        handles
+            .get(handle)
+            .and_then(|capability| capability.endpoint_with(Rights::RECEIVE))
+            .map_err(|error| error.errno())?
+    };
+    let (request, token) = endpoint.receive();
+    // dropping the token on failure tells the sender that the message was lost
+    let reply_handle = process
+        .handles()
+        .lock()
+        .insert(Capability::reply(token))
+        .map_err(|error| error.errno())?;
+    if let Err(errno) = write_message(process, message, request) {
+        let _ = process.handles().lock().remove(reply_handle);
+        return Err(errno);
+    }
+    Ok(reply_handle)
+}
+
+pub fn ipc_reply(process: &Process, [handle, message, ..]: Arguments) -> Result<usize, Errno> {
+    let response = read_message(process, message)?;
+    let (token, response) = {
+        let mut handles = process.handles().lock();
+        if !handles
+            .get(handle)
+            .map_err(|error| error.errno())?
+            .is_reply()
+        {
+            return Err(IpcError::WrongObject.errno());
+        }
+        let response =
+            take_message(&mut handles, &response, Some(handle)).map_err(|error| error.errno())?;
+        let token = handles.remove(handle).and_then(Capability::into_reply);
+        (token.map_err(|error| error.errno())?, response)
+    };
+    token.reply(response);
+    Ok(0)
+}
+
+pub fn memory_create(process: &Process, [size, ..]: Arguments) -> Result<usize, Errno> {
+    if size == 0 || size.checked_next_multiple_of(PAGE_SIZE).is_none() {
+        return Err(Errno::EINVAL);
+    }
+    let capability = Capability::memory(VmObject::anonymous(size), Rights::MEMORY);
+    process
+        .handles()
+        .lock()
+        .insert(capability)
+        .map_err(|error| error.errno())
+}
+
+/// Maps a memory object shared, allowing the permissions given by rights of the handle
+pub fn memory_map(
+    process: &Process,
+    [handle, length, protection, offset, ..]: Arguments,
+) -> Result<usize, Errno> {
+    let protection = protection_flags(protection)?;
+    let (object, rights) = {
+        let handles = process.handles().lock();
+        let capability = handles.get(handle).map_err(|error| error.errno())?;
+        let object = capability.memory_object().map_err(|error| error.errno())?;
+        (object, capability.rights())
+    };
+    let end = offset.checked_add(length).ok_or(Errno::EINVAL)?;
+    if length == 0 || !offset.is_multiple_of(PAGE_SIZE) || end > object.size() {
+        return Err(Errno::EINVAL);
+    }
+    let mut maximum = PageFlags::empty();
+    if rights.contains(Rights::READ) {
+        maximum |= PageFlags::READABLE | PageFlags::EXECUTABLE;
+    }
+    if rights.contains(Rights::WRITE) {
+        maximum |= PageFlags::WRITEABLE;
+    }
+    let mapping = Mapping::object(object, offset, true, protection, maximum)
+        .map_err(|_| IpcError::MissingRights.errno())?;
+    process.map(length, mapping)
+}
+
+fn read_message(process: &Process, address: usize) -> Result<IpcMessage, Errno> {
+    let mut bytes = [0u8; size_of::<IpcMessage>()];
+    process.copy_from_user(address, &mut bytes)?;
+    // SAFETY: any bytes make a valid IpcMessage, which is read unaligned
+    let message = unsafe { (bytes.as_ptr() as *const IpcMessage).read_unaligned() };
+    if message.length as usize > IPC_MAX_DATA || message.handle_count as usize > IPC_MAX_HANDLES {
+        return Err(IpcError::InvaildMessage.errno());
+    }
+    Ok(message)
+}
+
+/// Moves handles listed in a message out of the table, leaving it untouched if any of them
+/// cannot be transferred. The `keep` handle, used for the operation itself, cannot be sent
+fn take_message(
+    handles: &mut HandleTable,
+    message: &IpcMessage,
+    keep: Option<usize>,
+) -> Result<Message, IpcError> {
+    let numbers: Vec<usize> = message.handles().iter().map(|h| *h as usize).collect();
+    for (i, handle) in numbers.iter().enumerate() {
+        if numbers[..i].contains(handle) || keep == Some(*handle) {
+            return Err(IpcError::InvaildMessage);
+        }
+        if !handles.get(*handle)?.rights().contains(Rights::TRANSFER) {
+            return Err(IpcError::MissingRights);
+        }
+    }
+    Ok(Message {
+        data: Vec::from(message.data()),
+        handles: numbers
+            .into_iter()
+            .map(|handle| handles.remove(handle).unwrap())
+            .collect(),
+    })
+}
+
+/// Stores handles of a received message in the table and copies the message to the process
+fn write_message(process: &Process, address: usize, message: Message) -> Result<(), Errno> {
+    let mut received = IpcMessage::new(&message.data).ok_or(Errno::EINVAL)?;
+    let mut handles = process.handles().lock();
+    let mut inserted = Vec::new();
+    for capability in message.handles {
+        match handles.insert(capability) {
+            Ok(handle) => inserted.push(handle),
+            Err(error) => {
+                for handle in inserted {
+                    let _ = handles.remove(handle);
+                }
+                return Err(error.errno());
+            }
+        }
+    }
+    received.handle_count = inserted.len() as u32;
+    for (target, handle) in received.handles.iter_mut().zip(&inserted) {
+        *target = *handle as u32;
+    }
+    // SAFETY: IpcMessage has no padding, so all of its bytes are initialized
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            &received as *const IpcMessage as *const u8,
+            size_of::<IpcMessage>(),
+        )
+    };
+    if let Err(errno) = process.copy_to_user(address, bytes) {
+        for handle in inserted {
+            let _ = handles.remove(handle);
+        }
+        return Err(errno);
+    }
+    Ok(())
+}
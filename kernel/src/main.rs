@@ -1,31 +1,85 @@
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
+mod bootargs;
 mod csr;
 mod debug;
+mod drivers;
 mod entry;
+mod fs;
+mod hart;
+mod initrd;
+mod ipc;
 mod memory;
+mod net;
+mod power;
+mod process;
 mod sbi;
+mod task;
+mod time;
 mod traps;
 
+use alloc::{sync::Arc, vec::Vec};
 use core::panic::PanicInfo;
-use core::{fmt::Write, ptr::addr_of};
-use csr::Csr;
+use core::{
+    fmt::Write,
+    hint::spin_loop,
+    ptr::null_mut,
+    sync::atomic::{AtomicPtr, Ordering},
+    time::Duration,
+};
 use debug::DebugOutput;
 use devicetree::{FdtHeader, FlattenedDeviceTree};
-use memory::map::MemoryMap;
+use drivers::{
+    fw_cfg::FwCfg, goldfish_rtc::GoldfishRtc, virtio::block::VirtioBlock, DeviceRegistry,
+};
+use ipc::{
+    handle::{Capability, Rights},
+    Endpoint, Message,
+};
+use memory::{heap, map::MemoryMap};
+use net::Network;
+use power::PowerControl;
+use task::{
+    mutex::Mutex,
+    policy::{Class, Policy},
+    scheduler,
+};
+use time::Clock;
 use traps::{
     disable_interrupts, enable_interrupts, initialize_interrupts, wfi, InterruptCode, InterruptMask,
 };
+use vfs::{BlockDevice, BufferCache, Vfs};
+
+static GLOBAL: AtomicPtr<Supervisor> = AtomicPtr::new(null_mut());
+
+/// Executable of the first user process, unless overridden by the `init` boot argument
+const INIT_PATH: &str = "/init";
+/// Part of the memory used by the buffer cache
+const BLOCK_CACHE_SHARE: usize = 32;
 
 struct Supervisor {
     debug_output: DebugOutput,
+    clock: Clock,
+    power: PowerControl,
+    devices: DeviceRegistry,
+    vfs: Vfs,
+    block_cache: BufferCache,
+    network: Network,
 }
 
 impl Supervisor {
     pub fn new() -> Supervisor {
         Supervisor {
             debug_output: DebugOutput::new(),
+            clock: Clock::new(),
+            power: PowerControl::new(),
+            devices: DeviceRegistry::new(),
+            vfs: Vfs::new(),
+            block_cache: BufferCache::new(),
+            network: Network::new(),
         }
     }
 
@@ -35,8 +89,10 @@ impl Supervisor {
         unsafe {
             initialize_interrupts();
             enable_interrupts();
-            let mask: InterruptMask = InterruptCode::Timer.into();
-            mask.enable();
+            for code in [InterruptCode::Timer, InterruptCode::Software] {
+                let mask: InterruptMask = code.into();
+                mask.enable();
+            }
         }
         kdebug!("Initialized interrupts");
 
@@ -45,24 +101,258 @@ impl Supervisor {
             FlattenedDeviceTree::from_ptr(devicetree_ptr).expect("Empty devicetree pointer")
         };
 
+        self.clock.initialize_from_devicetree(&fdt);
+
         kdebug!("Building memory map");
         let memory_map = MemoryMap::build_from_devicetree(&fdt);
+        let memory = heap::initialize(&memory_map);
+
+        kdebug!("Probing devices");
+        self.devices.probe_all(&fdt);
+        self.devices.log_devices();
+        unsafe {
+            let mask: InterruptMask = InterruptCode::External.into();
+            mask.enable();
+        }
+
+        if let Some(rtc) = self.devices.find::<GoldfishRtc>() {
+            self.clock.set_system_time(rtc.read(), self.clock.now());
+            kdebug!("Wall clock set from goldfish RTC");
+        } else {
+            kdebug!("No RTC found, wall clock unavailable");
+        }
+
+        self.power.initialize(&self.devices);
+
+        self.block_cache
+            .set_capacity(memory / BLOCK_CACHE_SHARE)
+            .expect("Cannot size empty buffer cache");
+        let disks: Vec<Arc<dyn BlockDevice>> = self
+            .devices
+            .find_all::<VirtioBlock>()
+            .into_iter()
+            .map(|disk| Supervisor::global().block_cache().attach(disk) as Arc<_>)
+            .collect();
+        let volumes = fs::volumes(&disks);
+        let root_volume = fs::mount_root(&self.vfs, &volumes, initrd::archive(&fdt), memory);
+        fs::mount_disks(&self.vfs, &volumes, root_volume);
+        fs::mount_devices(&self.vfs, &self.devices);
 
-        wfi()
+        if let Some(fw_cfg) = self.devices.find::<FwCfg>() {
+            Self::log_fw_cfg_files(&fw_cfg);
+        }
+
+        if let Some(slice) = bootargs::option(&fdt, "timeslice").and_then(|v| v.parse().ok()) {
+            scheduler::set_time_slice(Duration::from_millis(slice));
+        }
+        kdebug!("Scheduler time slice: {:?}", scheduler::time_slice());
+        scheduler::initialize_boot_hart();
+        fs::start_write_back();
+        net::initialize(&self.devices, &fdt);
+        hart::start_secondary_harts(&fdt);
+        if bootargs::flag(&fdt, "selftest") {
+            Self::check_threads();
+            Self::check_scheduling_classes();
+            Self::check_ipc();
+        }
+        self.run_init(bootargs::option(&fdt, "init").unwrap_or(INIT_PATH));
+
+        if let Err(error) = fs::sync(&self.vfs) {
+            kdebug!("Cannot write back filesystems: {}", error);
+        }
+        if bootargs::flag(&fdt, "reboot") {
+            kdebug!("Nothing left to do, rebooting");
+            self.power.reboot()
+        }
+        kdebug!("Nothing left to do, powering off");
+        self.power.poweroff()
     }
 
-    pub unsafe fn set_global(&self) {
-        let ptr_addr = addr_of!(*self) as usize;
+    /// Entry of secondary harts, which only run tasks
+    pub fn launch_secondary(&self) -> ! {
         unsafe {
-            csr::sscratch::write(ptr_addr);
+            initialize_interrupts();
+            enable_interrupts();
+            for code in [
+                InterruptCode::Timer,
+                InterruptCode::Software,
+                InterruptCode::External,
+            ] {
+                let mask: InterruptMask = code.into();
+                mask.enable();
+            }
+        }
+        kdebug!("Hart {} online", hart::current_id());
+        scheduler::run_secondary_hart()
+    }
+
+    /// Runs a few kernel threads, which may get picked up by any hart, and waits for them
+    fn check_threads() {
+        let workers: Vec<_> = (0..4)
+            .map(|i| {
+                task::spawn("worker", move || {
+                    task::sleep(Duration::from_millis(10 * i as u64));
+                    kdebug!("Worker {} running on hart {}", i, hart::current_id());
+                    // keep busy for a few time slices, either being preempted or yielding
+                    let clock = Supervisor::global().clock();
+                    let start = clock.now();
+                    while clock.now() - start < 3 * scheduler::time_slice() {
+                        if i % 2 == 0 {
+                            spin_loop();
+                        } else {
+                            task::yield_now();
+                        }
+                    }
+                    i
+                })
+            })
+            .collect();
+        for worker in workers {
+            let code = worker.join();
+            kdebug!("{} exited with {}", worker, code);
+        }
+    }
+
+    /// Sends a message carrying a handle to a server thread, which replies with the data
+    /// uppercased
+    fn check_ipc() {
+        let endpoint = Endpoint::new();
+        let receiver = Capability::endpoint(endpoint.clone(), Rights::RECEIVE);
+        let server = task::spawn("ipc-server", move || {
+            let endpoint = receiver.endpoint_with(Rights::RECEIVE).unwrap();
+            let (request, token) = endpoint.receive();
+            let rights: Vec<_> = request.handles.iter().map(|h| h.rights()).collect();
+            kdebug!("IPC server got handles with rights {:?}", rights);
+            token.reply(Message {
+                data: request.data.to_ascii_uppercase(),
+                handles: Vec::new(),
+            });
+            0
+        });
+        let request = Message {
+            data: Vec::from(b"ping"),
+            handles: Vec::from([Capability::endpoint(endpoint.clone(), Rights::SEND)]),
+        };
+        match endpoint.send(request) {
+            Ok(reply) => kdebug!("IPC reply: {:?}", core::str::from_utf8(&reply.data)),
+            Err(error) => kdebug!("IPC send failed: {}", error),
         }
+        server.join();
+    }
+
+    /// Runs a priority inversion scenario and a periodic deadline task, then logs statistics of
+    /// all scheduling classes
+    fn check_scheduling_classes() {
+        static SHARED: Mutex<u32> = Mutex::new(0);
+        let busy = |duration: Duration| {
+            let clock = Supervisor::global().clock();
+            let start = clock.now();
+            while clock.now() - start < duration {
+                spin_loop();
+            }
+        };
+
+        let holder = task::spawn("holder", move || {
+            let mut shared = SHARED.lock();
+            busy(3 * scheduler::time_slice());
+            *shared += 1;
+            0
+        });
+        let waiter = task::spawn("waiter", move || {
+            task::sleep(scheduler::time_slice());
+            let start = Supervisor::global().clock().now();
+            *SHARED.lock() += 1;
+            let waited = Supervisor::global().clock().now() - start;
+            kdebug!("Real-time waiter got the mutex after {:?}", waited);
+            0
+        });
+        if let Err(error) = waiter.set_policy(Policy::RealTime(50)) {
+            kdebug!("Cannot make {} real-time: {}", waiter, error);
+        }
+
+        let period = Duration::from_millis(10);
+        let periodic = task::spawn("periodic", move || {
+            kdebug!(
+                "{} running with {:?}",
+                task::current(),
+                task::current().policy()
+            );
+            for _ in 0..10 {
+                busy(period / 5);
+                task::wait_next_period();
+            }
+            0
+        });
+        let policy = Policy::Deadline {
+            runtime: period / 4,
+            period,
+        };
+        if let Err(error) = periodic.set_policy(policy) {
+            kdebug!("Cannot make {} a deadline task: {}", periodic, error);
+        }
+        let greedy = Policy::Deadline {
+            runtime: period,
+            period,
+        };
+        // asked for a task of the check, which is refused anyway, rather than the boot task
+        if let Err(error) = holder.set_policy(greedy) {
+            kdebug!("Deadline task using a whole hart refused: {}", error);
+        }
+
+        for task in [holder, waiter, periodic] {
+            task.join();
+        }
+        for class in Class::ALL {
+            kdebug!("{:?} class: {}", class, scheduler::statistics(class));
+        }
+    }
+
+    /// Runs the init program from the root filesystem and waits for it to exit
+    fn run_init(&self, path: &str) {
+        let image = match fs::read_file(&self.vfs, path) {
+            Ok(image) => image,
+            Err(error) => {
+                kdebug!("Cannot read {}, not starting init: {}", path, error);
+                return;
+            }
+        };
+
+        match process::spawn("init", &image, &["init"]) {
+            Ok(init) => {
+                let code = init.join();
+                kdebug!("init exited with {}", code);
+            }
+            Err(error) => kdebug!("Cannot load init: {}", error),
+        }
+    }
+
+    /// Lists user-provided fw_cfg files, showing beginning of their contents
+    fn log_fw_cfg_files(fw_cfg: &FwCfg) {
+        kdebug!("fw_cfg files (DMA: {}):", fw_cfg.has_dma());
+        for file in fw_cfg.files().filter(|f| f.name().starts_with("opt/")) {
+            let mut preview = [0u8; 32];
+            let length = fw_cfg.read_file(&file, 0, &mut preview);
+            kdebug!(
+                "  {} ({} bytes): {:?}",
+                file.name(),
+                file.size(),
+                core::str::from_utf8(&preview[..length]).unwrap_or("<binary>")
+            );
+        }
+    }
+
+    pub unsafe fn set_global(&self) {
+        GLOBAL.store(
+            self as *const Supervisor as *mut Supervisor,
+            Ordering::Release,
+        );
     }
 
     pub fn global() -> &'static Self {
         // SAFETY:
-        // sstatus is initialized in assembly code to 0 - in this case we panic
-        // otherwise, it is assumed that it is set by `set_global` function, to a Supervisor value
-        let supervisor_ptr = unsafe { csr::sscratch::read() } as *const Supervisor;
+        // pointer is null until `set_global` is called - in this case we panic
+        // otherwise, it points to a Supervisor value which lives until the machine stops
+        let supervisor_ptr = GLOBAL.load(Ordering::Acquire);
 
         unsafe { supervisor_ptr.as_ref() }.expect("uninitialized global state")
     }
@@ -70,6 +360,26 @@ impl Supervisor {
     pub fn debug_output(&self) -> &DebugOutput {
         &self.debug_output
     }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    pub fn devices(&self) -> &DeviceRegistry {
+        &self.devices
+    }
+
+    pub fn vfs(&self) -> &Vfs {
+        &self.vfs
+    }
+
+    pub fn block_cache(&self) -> &BufferCache {
+        &self.block_cache
+    }
+
+    pub fn network(&self) -> &Network {
+        &self.network
+    }
 }
 
 #[panic_handler]
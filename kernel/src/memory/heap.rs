@@ -0,0 +1,45 @@
+//! Kernel heap, backing `alloc` collections with all free physical memory
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::{null_mut, NonNull},
+};
+
+use core_lib::{heap::Heap, sync::AtomicMutex};
+
+use crate::{kdebug, traps::without_interrupts};
+
+use super::map::MemoryMap;
+
+#[global_allocator]
+static HEAP: KernelHeap = KernelHeap(AtomicMutex::new(Heap::new()));
+
+/// Heap shared by all harts. Interrupts are disabled while it is locked,
+/// so that it can be used by interrupt handlers
+struct KernelHeap(AtomicMutex<Heap>);
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_interrupts(|| self.0.lock().allocate(layout)).map_or(null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            without_interrupts(|| unsafe { self.0.lock().deallocate(ptr, layout) });
+        }
+    }
+}
+
+/// Makes free regions of the memory map available for allocation, returning their total size
+pub fn initialize(memory_map: &MemoryMap) -> usize {
+    let total = without_interrupts(|| {
+        let mut heap = HEAP.0.lock();
+        for region in memory_map.free_regions() {
+            // SAFETY: memory map excludes memory used by the kernel and the firmware
+            unsafe { heap.add_region(region.start().as_usize(), region.size()) };
+        }
+        heap.total()
+    });
+    kdebug!("Heap initialized with {} KiB", total / 1024);
+    total
+}
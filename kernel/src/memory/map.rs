@@ -1,12 +1,17 @@
-use core::ptr::addr_of;
+use devicetree::{FlattenedDeviceTree, NodeIterExt};
 
-use devicetree::{FlattenedDeviceTree, NodeIterExt, NodeRef};
-
-use crate::kdebug;
+use crate::{initrd, kdebug};
 
 use super::types::{PhysicalAddr, PhysicalAddrRange};
 
-pub struct MemoryMap;
+/// Maximal number of distinct free memory regions tracked
+const MAX_REGIONS: usize = 32;
+
+/// Physical memory available for use by the kernel
+pub struct MemoryMap {
+    /// Free regions, as `(start, size)` pairs
+    free: [Option<(usize, usize)>; MAX_REGIONS],
+}
 
 extern "C" {
     static mut _start: u8;
@@ -14,6 +19,8 @@ extern "C" {
 }
 
 impl MemoryMap {
+    /// Finds memory described by the devicetree, excluding kernel image, the devicetree itself,
+    /// the initrd and regions reserved by the firmware
     pub fn build_from_devicetree(dt: &FlattenedDeviceTree) -> MemoryMap {
         let kernel_area = unsafe {
             PhysicalAddrRange::from_start_end(
@@ -23,19 +30,82 @@ impl MemoryMap {
         };
         kdebug!("Kernel static area: {:?}", kernel_area);
 
+        let mut map = MemoryMap {
+            free: [None; MAX_REGIONS],
+        };
+
         let root = dt.root().expect("Cannot read device tree root");
         let memory_nodes = root.children().named("memory");
         for memory_node in memory_nodes {
-            let memory_area: PhysicalAddrRange = PhysicalAddrRange::from_reg(
-                memory_node
-                    .property("reg")
-                    .expect("Memory device tree node does not have reg")
-                    .reg()
-                    .expect("Invaild memory reg property type"),
+            for reg in memory_node.regs() {
+                kdebug!("Memory area: {:?}", PhysicalAddrRange::from_reg(reg));
+                map.add(reg);
+            }
+        }
+
+        map.reserve((kernel_area.start().as_usize(), kernel_area.size()));
+        map.reserve((
+            dt.header() as *const _ as usize,
+            dt.header().totalsize() as usize,
+        ));
+        for reservation in dt.memory_reservations() {
+            kdebug!(
+                "Reserved memory: {:?}",
+                PhysicalAddrRange::from_reg(reservation)
             );
-            kdebug!("Memory area: {:?}", memory_area);
+            map.reserve(reservation);
+        }
+        if let Some(initrd) = initrd::region(dt) {
+            kdebug!("Initrd: {:?}", PhysicalAddrRange::from_reg(initrd));
+            map.reserve(initrd);
+        }
+        if let Some(reserved_memory) = root.child("reserved-memory") {
+            for reg in reserved_memory.children().flat_map(|node| node.regs()) {
+                kdebug!("Reserved memory: {:?}", PhysicalAddrRange::from_reg(reg));
+                map.reserve(reg);
+            }
         }
 
-        MemoryMap
+        for region in map.free_regions() {
+            kdebug!("Free memory: {:?}", region);
+        }
+        map
+    }
+
+    pub fn free_regions(&self) -> impl Iterator<Item = PhysicalAddrRange> + '_ {
+        self.free
+            .iter()
+            .flatten()
+            .map(|region| PhysicalAddrRange::from_reg(*region))
+    }
+
+    fn add(&mut self, (start, size): (usize, usize)) {
+        match self.free.iter_mut().find(|region| region.is_none()) {
+            Some(slot) if size > 0 => *slot = Some((start, size)),
+            Some(_) => {}
+            None => kdebug!("Too many memory regions, ignoring 0x{:x}", start),
+        }
+    }
+
+    /// Removes a region from free memory, splitting free regions it overlaps with
+    fn reserve(&mut self, (reserved_start, reserved_size): (usize, usize)) {
+        let reserved_end = reserved_start.saturating_add(reserved_size);
+        for index in 0..MAX_REGIONS {
+            let Some((start, size)) = self.free[index] else {
+                continue;
+            };
+            let end = start + size;
+            if reserved_end <= start || reserved_start >= end {
+                continue;
+            }
+
+            self.free[index] = None;
+            if start < reserved_start {
+                self.add((start, reserved_start - start));
+            }
+            if reserved_end < end {
+                self.add((reserved_end, end - reserved_end));
+            }
+        }
     }
 }
@@ -0,0 +1,265 @@
+//! Mappings of memory in the user part of address spaces, with pages filled in on faults
+//!
+//! A mapping shows either private zeroed memory or pages of a memory object. Shared mappings
+//! use pages of the object itself, so writes are seen by everything mapping it. Private ones
+//! get copies of them when they are first written.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::ops::Range;
+
+use core_lib::syscall::Errno;
+use snafu::Snafu;
+use vfs::VfsError;
+
+use crate::kdebug;
+
+use super::{
+    object::VmObject,
+    page::{Page, PAGE_SIZE},
+    paging::{AddressSpace, PageFlags},
+};
+
+/// Reason for a page fault not being resolved
+#[derive(Debug, Snafu)]
+pub enum FaultError {
+    #[snafu(display("Address is not mapped"))]
+    Unmapped,
+    #[snafu(display("Access is not permitted by the mapping"))]
+    AccessDenied,
+    #[snafu(display("Page is past the end of the mapped object"))]
+    BeyondEnd,
+    #[snafu(display("Cannot read the mapped file: {source}"))]
+    Io { source: VfsError },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Snafu)]
+pub enum MappingError {
+    #[snafu(display("Range is not mapped"))]
+    NotMapped,
+    #[snafu(display("Protection is not allowed for the mapping"))]
+    NotPermitted,
+}
+
+impl MappingError {
+    /// Error number reported to user programs
+    pub fn errno(&self) -> Errno {
+        match self {
+            MappingError::NotMapped => Errno::ENOMEM,
+            MappingError::NotPermitted => Errno::EACCES,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Mapping {
+    /// End address, set once the mapping is placed
+    end: usize,
+    /// Object providing pages, private zeroed memory being used without one
+    object: Option<Arc<VmObject>>,
+    /// Offset of the first page in the object, in bytes
+    offset: usize,
+    shared: bool,
+    /// Permissions of the pages
+    protection: PageFlags,
+    /// Permissions which the mapping may be given
+    maximum: PageFlags,
+}
+
+impl Mapping {
+    /// Private zeroed memory
+    pub fn anonymous(protection: PageFlags) -> Mapping {
+        Mapping {
+            end: 0,
+            object: None,
+            offset: 0,
+            shared: false,
+            protection,
+            maximum: PageFlags::READABLE | PageFlags::WRITEABLE | PageFlags::EXECUTABLE,
+        }
+    }
+
+    /// Pages of an object starting at a page-aligned offset, failing if the protection
+    /// exceeds the maximum one
+    pub fn object(
+        object: Arc<VmObject>,
+        offset: usize,
+        shared: bool,
+        protection: PageFlags,
+        maximum: PageFlags,
+    ) -> Result<Mapping, MappingError> {
+        if !maximum.contains(protection) {
+            return Err(MappingError::NotPermitted);
+        }
+        Ok(Mapping {
+            end: 0,
+            object: Some(object),
+            offset,
+            shared,
+            protection,
+            maximum,
+        })
+    }
+
+    /// Index in the object of the page at an address, given the start of the mapping
+    fn index(&self, start: usize, address: usize) -> usize {
+        (self.offset + address - start) / PAGE_SIZE
+    }
+
+    fn note_protection(&self) {
+        if let Some(object) = &self.object {
+            if self.shared && self.protection.contains(PageFlags::WRITEABLE) {
+                object.mark_written();
+            }
+        }
+    }
+}
+
+impl AddressSpace {
+    /// Places a mapping of `length` bytes in a free part of a range, returning its address
+    pub fn map(
+        &mut self,
+        within: Range<usize>,
+        length: usize,
+        mut mapping: Mapping,
+    ) -> Option<usize> {
+        let length = length.checked_next_multiple_of(PAGE_SIZE)?;
+        let start = self.free_range(within, length)?;
+        mapping.end = start + length;
+        mapping.note_protection();
+        self.mappings.insert(start, mapping);
+        Some(start)
+    }
+
+    /// Removes mappings from a page-aligned range, writing back pages of shared file mappings
+    pub fn unmap(&mut self, start: usize, length: usize) {
+        let end = start.saturating_add(length.next_multiple_of(PAGE_SIZE));
+        self.split(start);
+        self.split(end);
+        let starts: Vec<usize> = self.mappings.range(start..end).map(|(s, _)| *s).collect();
+        for start in starts {
+            let mapping = self.mappings.remove(&start).unwrap();
+            for page in (start..mapping.end).step_by(PAGE_SIZE) {
+                self.unmap_page(page);
+            }
+            if let Some(object) = mapping.object.as_ref().filter(|_| mapping.shared) {
+                let indices = mapping.index(start, start)..mapping.index(start, mapping.end);
+                if let Err(error) = object.write_back(indices) {
+                    kdebug!("Cannot write back mapped file: {}", error);
+                }
+            }
+        }
+    }
+
+    /// Removes all mappings, e.g. when a process exits
+    pub fn unmap_all(&mut self) {
+        while let Some((start, end)) = self.mappings.first_key_value().map(|(s, m)| (*s, m.end)) {
+            self.unmap(start, end - start);
+        }
+    }
+
+    /// Changes permissions of pages in a page-aligned range, which has to be fully mapped
+    pub fn protect(
+        &mut self,
+        start: usize,
+        length: usize,
+        protection: PageFlags,
+    ) -> Result<(), MappingError> {
+        let end = start
+            .checked_add(length.next_multiple_of(PAGE_SIZE))
+            .ok_or(MappingError::NotMapped)?;
+        // checked before anything changes
+        let mut covered = start;
+        for (mapping_start, mapping) in self.mappings.range(..end) {
+            if mapping.end <= covered {
+                continue;
+            }
+            if *mapping_start > covered {
+                return Err(MappingError::NotMapped);
+            }
+            if !mapping.maximum.contains(protection) {
+                return Err(MappingError::NotPermitted);
+            }
+            covered = mapping.end;
+        }
+        if covered < end {
+            return Err(MappingError::NotMapped);
+        }
+
+        self.split(start);
+        self.split(end);
+        let starts: Vec<usize> = self.mappings.range(start..end).map(|(s, _)| *s).collect();
+        for start in starts {
+            let mapping = self.mappings.get_mut(&start).unwrap();
+            mapping.protection = protection;
+            mapping.note_protection();
+            let mapping_end = mapping.end;
+            for page in (start..mapping_end).step_by(PAGE_SIZE) {
+                self.protect_page(page, protection);
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the page at an address accessible with given permissions, if a mapping allows it
+    pub fn handle_fault(&mut self, address: usize, access: PageFlags) -> Result<(), FaultError> {
+        let page = address - address % PAGE_SIZE;
+        if access.contains(PageFlags::WRITEABLE) && self.copy_on_write(page) {
+            return Ok(());
+        }
+        let (start, mapping) = self
+            .mappings
+            .range(..=address)
+            .next_back()
+            .filter(|(_, mapping)| address < mapping.end)
+            .ok_or(FaultError::Unmapped)?;
+        if !mapping.protection.contains(access) {
+            return Err(FaultError::AccessDenied);
+        }
+        let (protection, shared) = (mapping.protection, mapping.shared);
+        // a page made inaccessible by mprotect is still there
+        if self.frame(page).is_some() {
+            self.protect_page(page, protection);
+            return Ok(());
+        }
+        // private mappings copy pages of objects when they are first written
+        let frame = match &mapping.object {
+            None => Arc::new(Page::new()),
+            Some(object) => object.page(mapping.index(*start, page))?,
+        };
+        self.map_frame(page, frame, protection, shared);
+        // written right away, so the copy is made now rather than by another fault
+        if access.contains(PageFlags::WRITEABLE) {
+            self.copy_on_write(page);
+        }
+        Ok(())
+    }
+
+    /// Splits the mapping containing a page-aligned address in two, so that one starts there
+    fn split(&mut self, at: usize) {
+        let Some((start, mapping)) = self.mappings.range_mut(..at).next_back() else {
+            return;
+        };
+        if at >= mapping.end {
+            return;
+        }
+        let mut second = mapping.clone();
+        second.offset += at - start;
+        mapping.end = at;
+        self.mappings.insert(at, second);
+    }
+
+    /// Finds the lowest page-aligned address in a range with `length` free bytes after it
+    fn free_range(&self, within: Range<usize>, length: usize) -> Option<usize> {
+        let mut candidate = within.start;
+        for (start, mapping) in self.mappings.range(..within.end) {
+            if mapping.end <= candidate {
+                continue;
+            }
+            if *start >= candidate && start - candidate >= length {
+                break;
+            }
+            candidate = mapping.end;
+        }
+        (within.end.checked_sub(candidate)? >= length).then_some(candidate)
+    }
+}
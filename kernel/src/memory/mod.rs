@@ -1,2 +1,7 @@
+pub mod heap;
 pub mod map;
+pub mod mapping;
+pub mod object;
+pub mod page;
+pub mod paging;
 pub mod types;
@@ -0,0 +1,136 @@
+//! Memory objects, providing pages to mappings in address spaces
+//!
+//! Anonymous objects start zeroed, file objects read pages of a file on first use. A file
+//! has a single object for as long as it is mapped anywhere, so that all shared mappings of
+//! it see the same pages.
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use core_lib::sync::AtomicMutex;
+use vfs::{Dentry, VfsResult};
+
+use crate::task::mutex::Mutex;
+
+use super::{
+    mapping::FaultError,
+    page::{Page, PAGE_SIZE},
+};
+
+/// Objects of mapped files, by addresses of their dentries, which the objects keep alive
+static FILE_OBJECTS: AtomicMutex<BTreeMap<usize, Weak<VmObject>>> =
+    AtomicMutex::new(BTreeMap::new());
+
+enum Source {
+    Anonymous { size: usize },
+    File(Arc<Dentry>),
+}
+
+pub struct VmObject {
+    source: Source,
+    /// Pages used so far, by their indices. Locked while a page is read from a file
+    pages: Mutex<BTreeMap<usize, Arc<Page>>>,
+    /// Whether pages may have been modified through a shared mapping, so that they have to be
+    /// written back to the file
+    written: AtomicBool,
+}
+
+impl VmObject {
+    /// Creates a zeroed object of at least `size` bytes
+    pub fn anonymous(size: usize) -> Arc<VmObject> {
+        Arc::new(VmObject::new(Source::Anonymous {
+            size: size.next_multiple_of(PAGE_SIZE),
+        }))
+    }
+
+    /// Gets the object of a regular file, creating it if the file is not mapped yet
+    pub fn file(dentry: &Arc<Dentry>) -> Arc<VmObject> {
+        let key = Arc::as_ptr(dentry) as usize;
+        let mut objects = FILE_OBJECTS.lock();
+        if let Some(object) = objects.get(&key).and_then(Weak::upgrade) {
+            return object;
+        }
+        objects.retain(|_, object| object.strong_count() > 0);
+        let object = Arc::new(VmObject::new(Source::File(dentry.clone())));
+        objects.insert(key, Arc::downgrade(&object));
+        object
+    }
+
+    fn new(source: Source) -> VmObject {
+        VmObject {
+            source,
+            pages: Mutex::new(BTreeMap::new()),
+            written: AtomicBool::new(false),
+        }
+    }
+
+    /// Size in bytes, which for a file object changes along with the file
+    pub fn size(&self) -> usize {
+        match &self.source {
+            Source::Anonymous { size } => *size,
+            Source::File(dentry) => dentry.inode().metadata().size as usize,
+        }
+    }
+
+    /// Gets a page by its index, reading it from the file if needed. The part of the last
+    /// page of a file past its end is zeroed
+    pub fn page(&self, index: usize) -> Result<Arc<Page>, FaultError> {
+        if index >= self.size().div_ceil(PAGE_SIZE) {
+            return Err(FaultError::BeyondEnd);
+        }
+        let mut pages = self.pages.lock();
+        if let Some(page) = pages.get(&index) {
+            return Ok(page.clone());
+        }
+        let mut page = Page::new();
+        if let Source::File(dentry) = &self.source {
+            let buffer = page.bytes_mut();
+            let mut read = 0;
+            while read < PAGE_SIZE {
+                let offset = (index * PAGE_SIZE + read) as u64;
+                match dentry.inode().read_at(offset, &mut buffer[read..]) {
+                    Ok(0) => break,
+                    Ok(length) => read += length,
+                    Err(source) => return Err(FaultError::Io { source }),
+                }
+            }
+        }
+        let page = Arc::new(page);
+        pages.insert(index, page.clone());
+        Ok(page)
+    }
+
+    /// Notes that the object is mapped shared and writeable
+    pub fn mark_written(&self) {
+        self.written.store(true, Ordering::Relaxed);
+    }
+
+    /// Writes pages in a range of indices back to the file, if they could have been modified
+    pub fn write_back(&self, indices: Range<usize>) -> VfsResult<()> {
+        let Source::File(dentry) = &self.source else {
+            return Ok(());
+        };
+        if !self.written.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let size = self.size();
+        let pages = self.pages.lock();
+        for (index, page) in pages.range(indices) {
+            let offset = index * PAGE_SIZE;
+            if offset >= size {
+                break;
+            }
+            let length = PAGE_SIZE.min(size - offset);
+            dentry
+                .inode()
+                .write_at(offset as u64, &page.bytes()[..length])?;
+        }
+        Ok(())
+    }
+}
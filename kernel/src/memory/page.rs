@@ -0,0 +1,58 @@
+use core::{alloc::Layout, ptr::NonNull};
+
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error};
+
+pub const PAGE_SIZE: usize = 4096;
+
+/// A zeroed, page-aligned frame of physical memory, allocated from the heap.
+/// As the kernel is identity-mapped, its address is the physical one
+pub struct Page(NonNull<[u8; PAGE_SIZE]>);
+
+// SAFETY: page memory is exclusively owned
+unsafe impl Send for Page {}
+unsafe impl Sync for Page {}
+
+impl Page {
+    pub fn new() -> Page {
+        // SAFETY: layout has non-zero size
+        let frame = unsafe { alloc_zeroed(Self::layout()) };
+        match NonNull::new(frame) {
+            Some(frame) => Page(frame.cast()),
+            None => handle_alloc_error(Self::layout()),
+        }
+    }
+
+    /// Creates a page with contents of another one
+    pub fn copy_of(source: &Page) -> Page {
+        let page = Page::new();
+        // SAFETY: both pages are allocated, and a new one is not used by anything else
+        unsafe { core::ptr::copy_nonoverlapping(source.0.as_ptr(), page.0.as_ptr(), 1) };
+        page
+    }
+
+    pub fn address(&self) -> usize {
+        self.0.as_ptr() as usize
+    }
+
+    /// Contents of the page. Pages mapped in user memory can change while they are read
+    pub fn bytes(&self) -> &[u8; PAGE_SIZE] {
+        // SAFETY: page is allocated for as long as it exists
+        unsafe { self.0.as_ref() }
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8; PAGE_SIZE] {
+        // SAFETY: page is allocated for as long as it exists
+        unsafe { self.0.as_mut() }
+    }
+
+    fn layout() -> Layout {
+        Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap()
+    }
+}
+
+impl Drop for Page {
+    fn drop(&mut self) {
+        // SAFETY: page was allocated with the same layout
+        unsafe { dealloc(self.0.as_ptr().cast(), Self::layout()) };
+    }
+}
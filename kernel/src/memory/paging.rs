@@ -0,0 +1,405 @@
+//! Sv39 address spaces of user processes
+//!
+//! The kernel runs identity-mapped, so every address space maps addresses below [`USER_START`]
+//! to the same physical ones with gigapages accessible only from S-mode. This way the kernel
+//! keeps running after switching to any of them. User mappings live between [`USER_START`]
+//! and [`USER_END`].
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::arch::asm;
+
+use bitflags::bitflags;
+use snafu::Snafu;
+
+use crate::csr::{self, Csr};
+
+use super::{
+    mapping::Mapping,
+    page::{Page, PAGE_SIZE},
+};
+
+/// Lowest address available to user programs, which are linked to be loaded there
+pub const USER_START: usize = 0x20_0000_0000;
+/// End of the lower half of Sv39 address space
+pub const USER_END: usize = 0x40_0000_0000;
+
+const ENTRIES: usize = 512;
+const LEVELS: usize = 3;
+const GIGAPAGE_SIZE: usize = 1 << 30;
+const PAGE_SHIFT: usize = 12;
+const PPN_SHIFT: usize = 10;
+const SATP_MODE_SV39: usize = 8 << 60;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PageFlags: usize {
+        const VALID = 1 << 0;
+        const READABLE = 1 << 1;
+        const WRITEABLE = 1 << 2;
+        const EXECUTABLE = 1 << 3;
+        const USER = 1 << 4;
+        const GLOBAL = 1 << 5;
+        const ACCESSED = 1 << 6;
+        const DIRTY = 1 << 7;
+        /// Reserved for software, marks a page which becomes writeable once it is copied
+        const COPY_ON_WRITE = 1 << 8;
+    }
+}
+
+/// Flags giving access to a page
+const PERMISSIONS: PageFlags = PageFlags::READABLE
+    .union(PageFlags::WRITEABLE)
+    .union(PageFlags::EXECUTABLE);
+
+/// Page table entry
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+struct Entry(usize);
+
+impl Entry {
+    fn new(address: usize, flags: PageFlags) -> Entry {
+        Entry((address >> PAGE_SHIFT) << PPN_SHIFT | flags.bits())
+    }
+
+    fn address(&self) -> usize {
+        (self.0 >> PPN_SHIFT) << PAGE_SHIFT
+    }
+
+    fn flags(&self) -> PageFlags {
+        PageFlags::from_bits_truncate(self.0)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.flags().contains(PageFlags::VALID)
+    }
+}
+
+type Table = [Entry; ENTRIES];
+
+/// Page present in the user part of an address space
+#[derive(Clone)]
+struct UserPage {
+    frame: Arc<Page>,
+    /// Whether writes go to the frame even if other address spaces use it, as they do for
+    /// shared mappings
+    shared: bool,
+}
+
+impl UserPage {
+    /// Leaf entry mapping the page with given permissions, which is not writeable if the
+    /// frame has to be copied first
+    fn entry(&self, mut flags: PageFlags) -> Entry {
+        if flags.is_empty() {
+            return Entry(0);
+        }
+        if flags.contains(PageFlags::WRITEABLE)
+            && !self.shared
+            && Arc::strong_count(&self.frame) > 1
+        {
+            flags.remove(PageFlags::WRITEABLE);
+            flags.insert(PageFlags::COPY_ON_WRITE);
+        }
+        Entry::new(self.frame.address(), leaf_flags(flags))
+    }
+}
+
+/// Access to user memory which is not mapped with required permissions
+#[derive(Debug, Snafu)]
+#[snafu(display("Invaild user memory access at 0x{address:x}"))]
+pub struct UserFault {
+    address: usize,
+}
+
+pub struct AddressSpace {
+    root: Page,
+    /// Non-root page tables
+    tables: Vec<Page>,
+    /// Pages present in the user part, by their virtual addresses
+    pages: BTreeMap<usize, UserPage>,
+    /// Mappings filled in on page faults, by their start addresses
+    pub(super) mappings: BTreeMap<usize, Mapping>,
+}
+
+impl AddressSpace {
+    /// Creates an address space with only the kernel part mapped
+    pub fn new() -> AddressSpace {
+        let root = Page::new();
+        let kernel_flags = PageFlags::VALID
+            | PageFlags::READABLE
+            | PageFlags::WRITEABLE
+            | PageFlags::EXECUTABLE
+            | PageFlags::GLOBAL
+            | PageFlags::ACCESSED
+            | PageFlags::DIRTY;
+        // SAFETY: root page is owned by the address space
+        let entries = unsafe { table(root.address()) };
+        for (index, entry) in entries.iter_mut().enumerate() {
+            if index * GIGAPAGE_SIZE >= USER_START {
+                break;
+            }
+            *entry = Entry::new(index * GIGAPAGE_SIZE, kernel_flags);
+        }
+
+        AddressSpace {
+            root,
+            tables: Vec::new(),
+            pages: BTreeMap::new(),
+            mappings: BTreeMap::new(),
+        }
+    }
+
+    /// Value of `satp` register selecting the address space
+    pub fn satp(&self) -> usize {
+        SATP_MODE_SV39 | self.root.address() >> PAGE_SHIFT
+    }
+
+    /// Maps a new zeroed page at a page-aligned user address, or adds flags to the private
+    /// page already mapped there. Returns contents of the page
+    pub fn map_page(&mut self, address: usize, flags: PageFlags) -> &mut [u8; PAGE_SIZE] {
+        assert_user_page(address);
+        self.pages.entry(address).or_insert_with(|| UserPage {
+            frame: Arc::new(Page::new()),
+            shared: false,
+        });
+        let flags = self.permissions(address) | flags;
+        self.protect_page(address, flags);
+
+        let page = self.pages.get_mut(&address).unwrap();
+        Arc::get_mut(&mut page.frame)
+            .expect("Page shared with another address space mapped as a new one")
+            .bytes_mut()
+    }
+
+    /// Maps a frame at a page-aligned user address with given permissions, replacing the page
+    /// mapped there. Writes to a frame which is not shared go to a copy if other address
+    /// spaces use it too
+    pub fn map_frame(&mut self, address: usize, frame: Arc<Page>, flags: PageFlags, shared: bool) {
+        assert_user_page(address);
+        self.pages.insert(address, UserPage { frame, shared });
+        self.protect_page(address, flags);
+    }
+
+    /// Removes the page mapped at a user address, returning its frame
+    pub fn unmap_page(&mut self, address: usize) -> Option<Arc<Page>> {
+        let page = self.pages.remove(&address)?;
+        *self.leaf_entry(address) = Entry(0);
+        flush_page(address);
+        Some(page.frame)
+    }
+
+    /// Changes permissions of the page mapped at a user address. Without any, the page stays
+    /// in the address space but cannot be accessed
+    pub fn protect_page(&mut self, address: usize, flags: PageFlags) {
+        let Some(page) = self.pages.get(&address) else {
+            return;
+        };
+        let entry = page.entry(flags);
+        *self.leaf_entry(address) = entry;
+        flush_page(address);
+    }
+
+    /// Frame of the page present at a user address, even if it cannot be accessed
+    pub fn frame(&self, address: usize) -> Option<&Arc<Page>> {
+        self.pages.get(&address).map(|page| &page.frame)
+    }
+
+    /// Gives a private copy of a copy-on-write page at a user address to the address space,
+    /// returning whether the page was one
+    pub fn copy_on_write(&mut self, address: usize) -> bool {
+        if !self.pages.contains_key(&address) {
+            return false;
+        }
+        let flags = self.leaf_entry(address).flags();
+        if !flags.contains(PageFlags::COPY_ON_WRITE) {
+            return false;
+        }
+        let page = self.pages.get_mut(&address).unwrap();
+        // the last user of a frame takes it over
+        if Arc::strong_count(&page.frame) > 1 {
+            page.frame = Arc::new(Page::copy_of(&page.frame));
+        }
+        let flags = self.permissions(address);
+        self.protect_page(address, flags);
+        true
+    }
+
+    /// Creates a copy of the address space for a child process. Private pages become
+    /// copy-on-write in both address spaces, shared ones stay shared
+    pub fn fork(&mut self) -> AddressSpace {
+        let mut child = AddressSpace::new();
+        child.mappings = self.mappings.clone();
+        let addresses: Vec<usize> = self.pages.keys().copied().collect();
+        for address in addresses {
+            let flags = self.permissions(address);
+            let page = self.pages[&address].clone();
+            child.map_frame(address, page.frame, flags, page.shared);
+            // made copy-on-write, as the frame is used twice now
+            self.protect_page(address, flags);
+        }
+        child
+    }
+
+    /// Permissions of the page present at a user address, including writes to a
+    /// copy-on-write page
+    fn permissions(&mut self, address: usize) -> PageFlags {
+        if !self.pages.contains_key(&address) {
+            return PageFlags::empty();
+        }
+        let entry = *self.leaf_entry(address);
+        if !entry.is_valid() {
+            return PageFlags::empty();
+        }
+        let mut flags = entry.flags() & PERMISSIONS;
+        if entry.flags().contains(PageFlags::COPY_ON_WRITE) {
+            flags |= PageFlags::WRITEABLE;
+        }
+        flags
+    }
+
+    /// Physical address backing a user address, if it is mapped with all given flags
+    pub fn translate(&self, address: usize, flags: PageFlags) -> Option<usize> {
+        let mut table_address = self.root.address();
+        for level in (0..LEVELS).rev() {
+            // SAFETY: tables are owned by the address space
+            let entry = unsafe { table(table_address)[vpn(address, level)] };
+            if !entry.is_valid() {
+                return None;
+            }
+            if entry
+                .flags()
+                .intersects(PageFlags::READABLE | PageFlags::WRITEABLE | PageFlags::EXECUTABLE)
+            {
+                let offset = address % (PAGE_SIZE << (9 * level));
+                return entry
+                    .flags()
+                    .contains(flags | PageFlags::USER)
+                    .then_some(entry.address() + offset);
+            }
+            table_address = entry.address();
+        }
+        None
+    }
+
+    /// Copies user memory starting at `address` into a buffer
+    pub fn copy_from_user(&mut self, address: usize, buffer: &mut [u8]) -> Result<(), UserFault> {
+        self.for_each_chunk(
+            address,
+            buffer.len(),
+            PageFlags::READABLE,
+            |physical, offset, length| {
+                // SAFETY: chunk is in a readable user page owned by the address space
+                let source = unsafe { core::slice::from_raw_parts(physical as *const u8, length) };
+                buffer[offset..offset + length].copy_from_slice(source);
+            },
+        )
+    }
+
+    /// Copies data into user memory starting at `address`
+    pub fn copy_to_user(&mut self, address: usize, data: &[u8]) -> Result<(), UserFault> {
+        self.for_each_chunk(
+            address,
+            data.len(),
+            PageFlags::WRITEABLE,
+            |physical, offset, length| {
+                // SAFETY: chunk is in a writeable user page owned by the address space
+                let target =
+                    unsafe { core::slice::from_raw_parts_mut(physical as *mut u8, length) };
+                target.copy_from_slice(&data[offset..offset + length]);
+            },
+        )
+    }
+
+    /// Splits a user memory range on page boundaries, calling `f` with the physical address,
+    /// offset in the range and length of each part. Pages of mappings are faulted in, nothing
+    /// is accessed if any page of the range cannot be accessed with the flags
+    fn for_each_chunk(
+        &mut self,
+        address: usize,
+        length: usize,
+        flags: PageFlags,
+        mut f: impl FnMut(usize, usize, usize),
+    ) -> Result<(), UserFault> {
+        let end = address
+            .checked_add(length)
+            .filter(|end| *end <= USER_END)
+            .ok_or(UserFault { address })?;
+        let mut chunks = Vec::new();
+        let mut current = address;
+        while current < end {
+            let physical = match self.translate(current, flags) {
+                Some(physical) => physical,
+                None => {
+                    self.handle_fault(current, flags)
+                        .map_err(|_| UserFault { address: current })?;
+                    self.translate(current, flags)
+                        .ok_or(UserFault { address: current })?
+                }
+            };
+            let chunk_end = end.min((current / PAGE_SIZE + 1) * PAGE_SIZE);
+            chunks.push((physical, current - address, chunk_end - current));
+            current = chunk_end;
+        }
+        for (physical, offset, length) in chunks {
+            f(physical, offset, length);
+        }
+        Ok(())
+    }
+
+    /// Finds the last level entry for an address, creating missing page tables on the way
+    fn leaf_entry(&mut self, address: usize) -> &mut Entry {
+        let mut table_address = self.root.address();
+        for level in (1..LEVELS).rev() {
+            // SAFETY: tables are owned by the address space
+            let entry = unsafe { &mut table(table_address)[vpn(address, level)] };
+            if !entry.is_valid() {
+                let next = Page::new();
+                *entry = Entry::new(next.address(), PageFlags::VALID);
+                self.tables.push(next);
+            }
+            table_address = entry.address();
+        }
+        // SAFETY: tables are owned by the address space
+        unsafe { &mut table(table_address)[vpn(address, 0)] }
+    }
+}
+
+fn assert_user_page(address: usize) {
+    assert!(
+        (USER_START..USER_END).contains(&address) && address.is_multiple_of(PAGE_SIZE),
+        "Invaild user page address 0x{:x}",
+        address
+    );
+}
+
+/// Flags of a valid user leaf entry with given permissions
+fn leaf_flags(flags: PageFlags) -> PageFlags {
+    flags | PageFlags::VALID | PageFlags::USER | PageFlags::ACCESSED | PageFlags::DIRTY
+}
+
+/// Drops translations of a page cached by the current hart. Other harts flush everything
+/// when switching to an address space
+fn flush_page(address: usize) {
+    // SAFETY: flushing translations has no effects besides later page table walks
+    unsafe { asm!("sfence.vma {}, zero", in(reg) address) };
+}
+
+/// Index in a page table of given level
+fn vpn(address: usize, level: usize) -> usize {
+    (address >> (PAGE_SHIFT + 9 * level)) % ENTRIES
+}
+
+/// # Safety
+/// Address has to point to a page table which is not used through other references
+unsafe fn table<'a>(address: usize) -> &'a mut Table {
+    &mut *(address as *mut Table)
+}
+
+/// Switches the current hart to an address space given by a `satp` value,
+/// 0 being the bare identity mapping
+///
+/// # Safety
+/// Address space has to map the kernel, and live for as long as it is active
+pub unsafe fn activate(satp: usize) {
+    csr::satp::write(satp);
+    asm!("sfence.vma");
+}
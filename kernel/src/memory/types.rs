@@ -14,6 +14,10 @@ impl PhysicalAddr {
     pub unsafe fn from_ptr<T>(ptr: *const T) -> PhysicalAddr {
         PhysicalAddr(ptr as usize)
     }
+
+    pub fn as_usize(&self) -> usize {
+        self.0
+    }
 }
 
 #[derive(Copy, Clone, Hash, PartialEq, Eq)]
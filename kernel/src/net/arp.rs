@@ -0,0 +1,110 @@
+//! Cache of MAC addresses of hosts on the links, filled from ARP requests and replies
+//!
+//! Packets sent to a host whose address is not known yet wait in the cache while it is
+//! requested. Requests are repeated a few times before the packets are dropped.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::{net::Ipv4Addr, time::Duration};
+
+use packet::MacAddress;
+
+use crate::time::Instant;
+
+use super::Interface;
+
+/// How long a learned address is used before it has to be requested again
+const LIFETIME: Duration = Duration::from_secs(60);
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+/// Requests sent for an address before waiting packets are dropped
+const MAX_REQUESTS: u32 = 3;
+/// Most packets waiting for a single address, older ones being dropped
+const MAX_WAITING: usize = 8;
+
+struct Entry {
+    mac: MacAddress,
+    expires: Instant,
+}
+
+/// Address being requested, with packets waiting for it
+pub struct Pending {
+    pub interface: Arc<Interface>,
+    pub packets: Vec<Vec<u8>>,
+    requests: u32,
+    next_request: Instant,
+}
+
+pub struct ArpCache {
+    entries: BTreeMap<Ipv4Addr, Entry>,
+    pending: BTreeMap<Ipv4Addr, Pending>,
+}
+
+impl ArpCache {
+    pub const fn new() -> ArpCache {
+        ArpCache {
+            entries: BTreeMap::new(),
+            pending: BTreeMap::new(),
+        }
+    }
+
+    pub fn lookup(&self, address: Ipv4Addr, now: Instant) -> Option<MacAddress> {
+        self.entries
+            .get(&address)
+            .filter(|entry| entry.expires > now)
+            .map(|entry| entry.mac)
+    }
+
+    /// Whether the address is cached or being requested, in which case it is worth updating
+    pub fn knows(&self, address: Ipv4Addr) -> bool {
+        self.entries.contains_key(&address) || self.pending.contains_key(&address)
+    }
+
+    /// Stores an address, returning packets which were waiting for it
+    pub fn insert(&mut self, address: Ipv4Addr, mac: MacAddress, now: Instant) -> Option<Pending> {
+        let expires = now + LIFETIME;
+        self.entries.insert(address, Entry { mac, expires });
+        self.pending.remove(&address)
+    }
+
+    /// Keeps an IPv4 packet until the address of its next hop is known. Returns whether the
+    /// address has to be requested, which is the case for the first waiting packet
+    pub fn enqueue(
+        &mut self,
+        address: Ipv4Addr,
+        interface: &Arc<Interface>,
+        packet: Vec<u8>,
+        now: Instant,
+    ) -> bool {
+        let mut first = false;
+        let pending = self.pending.entry(address).or_insert_with(|| {
+            first = true;
+            Pending {
+                interface: interface.clone(),
+                packets: Vec::new(),
+                requests: 1,
+                next_request: now + REQUEST_INTERVAL,
+            }
+        });
+        if pending.packets.len() == MAX_WAITING {
+            pending.packets.remove(0);
+        }
+        pending.packets.push(packet);
+        first
+    }
+
+    /// Forgets expired addresses and gives up on ones requested too many times. Returns
+    /// addresses which have to be requested again
+    pub fn expire(&mut self, now: Instant) -> Vec<(Ipv4Addr, Arc<Interface>)> {
+        self.entries.retain(|_, entry| entry.expires > now);
+        self.pending
+            .retain(|_, pending| pending.requests < MAX_REQUESTS || pending.next_request > now);
+        self.pending
+            .iter_mut()
+            .filter(|(_, pending)| pending.next_request <= now)
+            .map(|(address, pending)| {
+                pending.requests += 1;
+                pending.next_request = now + REQUEST_INTERVAL;
+                (*address, pending.interface.clone())
+            })
+            .collect()
+    }
+}
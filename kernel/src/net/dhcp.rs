@@ -0,0 +1,332 @@
+//! DHCP client, leasing the address of an interface from a server (RFC 2131)
+//!
+//! The client broadcasts DISCOVER, requests the first address offered and configures the
+//! interface once the server acknowledges it. The lease is renewed with its server after half
+//! of its time and with any server after seven eighths of it. If it expires anyway, the
+//! interface loses its address and the client starts over. Timers are driven by the network
+//! timer.
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::{net::Ipv4Addr, time::Duration};
+
+use packet::{
+    dhcp::{CLIENT_PORT, SERVER_PORT},
+    ipv4::PROTOCOL_UDP,
+    DhcpMessage, DhcpMessageType, Ipv4Cidr, MacAddress, UdpDatagram,
+};
+
+use crate::{kdebug, time::Instant, Supervisor};
+
+use super::{Interface, Network};
+
+/// Wait for a reply before the first retransmission, doubled after each one
+const INITIAL_TIMEOUT: Duration = Duration::from_secs(4);
+const MAX_TIMEOUT: Duration = Duration::from_secs(64);
+/// Shortest wait between requests extending a lease
+const MIN_RENEW_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for offers
+    Selecting,
+    /// Waiting for the server to acknowledge the offered address
+    Requesting {
+        offered: Ipv4Addr,
+        server: Ipv4Addr,
+    },
+    Bound,
+    /// Asking the server of the lease to extend it
+    Renewing,
+    /// Asking any server to extend the lease
+    Rebinding,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Lease {
+    address: Ipv4Cidr,
+    gateway: Option<Ipv4Addr>,
+    server: Ipv4Addr,
+    /// Times of renewing, rebinding and losing the lease, which are `None` for infinite ones
+    renew_at: Option<Instant>,
+    rebind_at: Option<Instant>,
+    expires_at: Option<Instant>,
+}
+
+/// What the network stack does for the client, once it is unlocked
+enum Action {
+    /// Broadcasts a message, from the address of the lease if there is one
+    Broadcast(DhcpMessage),
+    /// Sends a message to the server of the lease
+    Send(DhcpMessage, Ipv4Addr),
+    Configure(Ipv4Cidr, Option<Ipv4Addr>),
+    Deconfigure,
+}
+
+pub struct DhcpClient {
+    mac: MacAddress,
+    state: State,
+    transaction: u32,
+    lease: Option<Lease>,
+    retransmit_at: Option<Instant>,
+    timeout: Duration,
+}
+
+impl DhcpClient {
+    fn new(mac: MacAddress) -> DhcpClient {
+        DhcpClient {
+            mac,
+            state: State::Selecting,
+            transaction: 0,
+            lease: None,
+            retransmit_at: None,
+            timeout: INITIAL_TIMEOUT,
+        }
+    }
+
+    /// Starts looking for a server with a new transaction, dropping the lease if there is one
+    fn start(&mut self, now: Instant) -> Vec<Action> {
+        let mut actions = Vec::new();
+        if self.lease.take().is_some() {
+            actions.push(Action::Deconfigure);
+        }
+        // differs between clients by the MAC address and between attempts by the clock
+        let [_, _, a, b, c, d] = self.mac.0;
+        let clock = now.since_boot().as_micros() as u32;
+        self.transaction = u32::from_be_bytes([a, b, c, d]) ^ clock.wrapping_mul(0x9e37_79b9);
+        self.state = State::Selecting;
+        self.timeout = INITIAL_TIMEOUT;
+        actions.push(self.transmit(now));
+        actions
+    }
+
+    /// Sends the message of the current state, waiting for a reply to it up to `timeout`
+    fn transmit(&mut self, now: Instant) -> Action {
+        let mut message = DhcpMessage::new(DhcpMessageType::Request, self.transaction, self.mac);
+        let action = match (self.state, self.lease) {
+            (State::Selecting, _) => {
+                message.kind = DhcpMessageType::Discover;
+                message.broadcast = true;
+                Action::Broadcast(message)
+            }
+            (State::Requesting { offered, server }, _) => {
+                message.broadcast = true;
+                message.requested_address = Some(offered);
+                message.server_identifier = Some(server);
+                Action::Broadcast(message)
+            }
+            (State::Renewing, Some(lease)) => {
+                message.client_address = lease.address.address;
+                Action::Send(message, lease.server)
+            }
+            (_, lease) => {
+                message.client_address = lease.map_or(Ipv4Addr::UNSPECIFIED, |l| l.address.address);
+                Action::Broadcast(message)
+            }
+        };
+        let timeout = match (self.state, self.lease) {
+            // half of the time left until the next step, as in RFC 2131, 4.4.5
+            (State::Renewing, Some(lease)) => renew_timeout(now, lease.rebind_at),
+            (State::Rebinding, Some(lease)) => renew_timeout(now, lease.expires_at),
+            _ => self.timeout,
+        };
+        self.retransmit_at = Some(now + timeout);
+        action
+    }
+
+    fn receive(&mut self, message: &DhcpMessage, now: Instant) -> Vec<Action> {
+        if message.transaction != self.transaction || message.client_mac != self.mac {
+            return Vec::new();
+        }
+        match (self.state, message.kind) {
+            (State::Selecting, DhcpMessageType::Offer) => {
+                let Some(server) = message.server_identifier else {
+                    return Vec::new();
+                };
+                if message.your_address.is_unspecified() {
+                    return Vec::new();
+                }
+                self.state = State::Requesting {
+                    offered: message.your_address,
+                    server,
+                };
+                self.timeout = INITIAL_TIMEOUT;
+                vec![self.transmit(now)]
+            }
+            (State::Requesting { .. } | State::Renewing | State::Rebinding, kind) => match kind {
+                DhcpMessageType::Ack => self.bind(message, now),
+                DhcpMessageType::Nak => {
+                    kdebug!("DHCP server refused the address, starting over");
+                    self.start(now)
+                }
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    /// Takes the lease acknowledged by a server
+    fn bind(&mut self, message: &DhcpMessage, now: Instant) -> Vec<Action> {
+        // without the mask of the subnet, other hosts are only reached through the gateway
+        let prefix = message
+            .subnet_mask
+            .map_or(32, |mask| u32::from(mask).leading_ones() as u8);
+        let server = match (self.state, message.server_identifier) {
+            (_, Some(server)) => server,
+            (State::Requesting { server, .. }, None) => server,
+            (_, None) => self
+                .lease
+                .map_or(Ipv4Addr::UNSPECIFIED, |lease| lease.server),
+        };
+        let at = |seconds: u32| Some(now + Duration::from_secs(seconds.into()));
+        let (renew_at, rebind_at, expires_at) = match message.lease_time {
+            None | Some(u32::MAX) => (None, None, None),
+            Some(time) => (
+                at(message.renewal_time.unwrap_or(time / 2)),
+                at(message.rebinding_time.unwrap_or(time / 8 * 7)),
+                at(time),
+            ),
+        };
+        let lease = Lease {
+            address: Ipv4Cidr {
+                address: message.your_address,
+                prefix,
+            },
+            gateway: message.router,
+            server,
+            renew_at,
+            rebind_at,
+            expires_at,
+        };
+
+        let changed = self.lease.is_none_or(|previous| {
+            previous.address != lease.address || previous.gateway != lease.gateway
+        });
+        self.lease = Some(lease);
+        self.state = State::Bound;
+        self.retransmit_at = None;
+        match changed {
+            true => vec![Action::Configure(lease.address, lease.gateway)],
+            false => Vec::new(),
+        }
+    }
+
+    fn tick(&mut self, now: Instant) -> Vec<Action> {
+        let reached = |at: Option<Instant>| at.is_some_and(|at| at <= now);
+        if let Some(lease) = self.lease {
+            if reached(lease.expires_at) {
+                kdebug!("DHCP lease of {} expired", lease.address);
+                return self.start(now);
+            }
+            let next = match self.state {
+                State::Bound | State::Renewing if reached(lease.rebind_at) => State::Rebinding,
+                State::Bound if reached(lease.renew_at) => State::Renewing,
+                state => state,
+            };
+            if next != self.state {
+                self.state = next;
+                return vec![self.transmit(now)];
+            }
+        }
+        if !reached(self.retransmit_at) {
+            return Vec::new();
+        }
+        if self.timeout >= MAX_TIMEOUT && matches!(self.state, State::Requesting { .. }) {
+            // the server does not answer anymore, another one may
+            return self.start(now);
+        }
+        self.timeout = (self.timeout * 2).min(MAX_TIMEOUT);
+        vec![self.transmit(now)]
+    }
+}
+
+/// Half of the time left until `until`, but not less than [`MIN_RENEW_TIMEOUT`]
+fn renew_timeout(now: Instant, until: Option<Instant>) -> Duration {
+    until.map_or(MIN_RENEW_TIMEOUT, |until| {
+        ((until - now) / 2).max(MIN_RENEW_TIMEOUT)
+    })
+}
+
+impl Network {
+    /// Leases an address for an interface, which is configured once a server assigns it
+    pub fn start_dhcp(&self, interface: &Arc<Interface>) {
+        let now = Supervisor::global().clock().now();
+        *interface.dhcp.lock() = Some(DhcpClient::new(interface.mac));
+        self.update_dhcp(interface, |client| client.start(now));
+    }
+
+    /// Passes a datagram sent to the DHCP client port to the client of an interface. Returns
+    /// false if the datagram is for another port or the interface has no client
+    pub(super) fn receive_dhcp(&self, interface: &Arc<Interface>, datagram: &UdpDatagram) -> bool {
+        if datagram.destination_port != CLIENT_PORT || interface.dhcp.lock().is_none() {
+            return false;
+        }
+        if datagram.source_port != SERVER_PORT {
+            return true;
+        }
+        match DhcpMessage::parse(datagram.payload) {
+            Ok(message) => {
+                let now = Supervisor::global().clock().now();
+                self.update_dhcp(interface, |client| client.receive(&message, now));
+            }
+            Err(error) => kdebug!("{}: dropped a DHCP message: {}", interface.name, error),
+        }
+        true
+    }
+
+    /// Retransmits DHCP messages and renews leases, called periodically
+    pub(super) fn tick_dhcp(&self) {
+        let now = Supervisor::global().clock().now();
+        let interfaces = self.interfaces.lock().clone();
+        for interface in &interfaces {
+            self.update_dhcp(interface, |client| client.tick(now));
+        }
+    }
+
+    /// Changes the client of an interface, if it has one, then does what it asked for
+    fn update_dhcp(
+        &self,
+        interface: &Arc<Interface>,
+        change: impl FnOnce(&mut DhcpClient) -> Vec<Action>,
+    ) {
+        let actions = {
+            let mut dhcp = interface.dhcp.lock();
+            let Some(client) = dhcp.as_mut() else {
+                return;
+            };
+            change(client)
+        };
+        for action in actions {
+            match action {
+                Action::Broadcast(message) => {
+                    let source = interface
+                        .address()
+                        .map_or(Ipv4Addr::UNSPECIFIED, |address| address.address);
+                    let datagram = UdpDatagram {
+                        source_port: CLIENT_PORT,
+                        destination_port: SERVER_PORT,
+                        payload: &message.to_bytes(),
+                    }
+                    .to_bytes(source, Ipv4Addr::BROADCAST);
+                    self.broadcast_ipv4(interface, source, PROTOCOL_UDP, &datagram);
+                }
+                Action::Send(message, server) => {
+                    let Some(source) = interface.address() else {
+                        continue;
+                    };
+                    let source = source.address;
+                    let datagram = UdpDatagram {
+                        source_port: CLIENT_PORT,
+                        destination_port: SERVER_PORT,
+                        payload: &message.to_bytes(),
+                    }
+                    .to_bytes(source, server);
+                    if let Err(error) = self.send_ipv4(source, server, PROTOCOL_UDP, &datagram) {
+                        kdebug!("{}: cannot renew the DHCP lease: {}", interface.name, error);
+                    }
+                }
+                Action::Configure(address, gateway) => self.configure(interface, address, gateway),
+                Action::Deconfigure => self.deconfigure(interface),
+            }
+        }
+    }
+}
@@ -0,0 +1,532 @@
+//! In-kernel IPv4 network stack
+//!
+//! Every network device becomes an interface, whose received frames are handled by a task of
+//! its own. ARP requests for our addresses are answered and ARP replies fill the cache. IPv4
+//! packets addressed to us go to ICMP, which answers echo requests, or to UDP and TCP
+//! sockets. Sent packets are routed by the routing table and wait in the ARP cache until the
+//! MAC address of their next hop is known.
+//!
+//! The first interface is configured at boot, statically by the `ip=` and `gw=` boot options
+//! or by DHCP.
+
+pub mod arp;
+pub mod dhcp;
+pub mod port;
+pub mod route;
+pub mod socket;
+pub mod syscall;
+pub mod tcp;
+pub mod udp;
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::{
+    net::Ipv4Addr,
+    sync::atomic::{AtomicU16, Ordering},
+    time::Duration,
+};
+
+use core_lib::{sync::AtomicMutex, syscall::Errno};
+use devicetree::FlattenedDeviceTree;
+use packet::{
+    ethernet::{self, ETHERTYPE_ARP, ETHERTYPE_IPV4},
+    icmp::{self, PORT_UNREACHABLE},
+    ipv4::{self, DEFAULT_TTL, PROTOCOL_ICMP, PROTOCOL_TCP, PROTOCOL_UDP},
+    ArpOperation, ArpPacket, EthernetFrame, IcmpPacket, Ipv4Cidr, Ipv4Packet, MacAddress,
+    PacketError, TcpSegment, UdpDatagram,
+};
+use snafu::Snafu;
+use vfs::VfsError;
+
+use crate::{
+    bootargs,
+    drivers::{plic::Plic, virtio::net::VirtioNet, DeviceRegistry},
+    kdebug, task, Supervisor,
+};
+
+use arp::ArpCache;
+use dhcp::DhcpClient;
+use port::Ports;
+use route::{Route, RoutingTable};
+use udp::UdpSocket;
+
+/// Interval of checking timeouts, like those of the ARP cache and TCP retransmissions
+const TIMER_INTERVAL: Duration = Duration::from_millis(100);
+/// Longest wait for a DHCP lease at boot, after which the interface is configured in the
+/// background
+const DHCP_BOOT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Network card sending and receiving Ethernet frames
+pub trait NetworkDevice: Send + Sync {
+    fn mac_address(&self) -> MacAddress;
+
+    /// Sends a frame, without the frame check sequence
+    fn transmit(&self, frame: &[u8]);
+
+    /// Blocks until a frame is received, returning it
+    fn receive(&self) -> Vec<u8>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Snafu)]
+pub enum NetError {
+    #[snafu(display("No route to the destination"))]
+    NoRoute,
+    #[snafu(display("Address is already in use"))]
+    AddressInUse,
+    #[snafu(display("Address is not assigned to any interface"))]
+    AddressNotAvailable,
+    #[snafu(display("Socket is already bound"))]
+    AlreadyBound,
+    #[snafu(display("Destination address is required"))]
+    DestinationRequired,
+    #[snafu(display("Message is too long"))]
+    MessageTooLong,
+    #[snafu(display("Operation is not supported by the socket"))]
+    Unsupported,
+    #[snafu(display("Socket is not connected"))]
+    NotConnected,
+    #[snafu(display("Socket is already connected"))]
+    AlreadyConnected,
+    #[snafu(display("Socket is not listening"))]
+    NotListening,
+    #[snafu(display("Connection refused"))]
+    ConnectionRefused,
+    #[snafu(display("Connection reset by peer"))]
+    ConnectionReset,
+    #[snafu(display("Connection timed out"))]
+    TimedOut,
+    #[snafu(display("Connection is closed for sending"))]
+    Shutdown,
+}
+
+impl NetError {
+    /// Error number reported to user programs
+    pub fn errno(&self) -> Errno {
+        match self {
+            NetError::NoRoute => Errno::ENETUNREACH,
+            NetError::AddressInUse => Errno::EADDRINUSE,
+            NetError::AddressNotAvailable => Errno::EADDRNOTAVAIL,
+            NetError::AlreadyBound => Errno::EINVAL,
+            NetError::DestinationRequired => Errno::EDESTADDRREQ,
+            NetError::MessageTooLong => Errno::EMSGSIZE,
+            NetError::Unsupported => Errno::EOPNOTSUPP,
+            NetError::NotConnected => Errno::ENOTCONN,
+            NetError::AlreadyConnected => Errno::EISCONN,
+            NetError::NotListening => Errno::EINVAL,
+            NetError::ConnectionRefused => Errno::ECONNREFUSED,
+            NetError::ConnectionReset => Errno::ECONNRESET,
+            NetError::TimedOut => Errno::ETIMEDOUT,
+            NetError::Shutdown => Errno::EPIPE,
+        }
+    }
+
+    /// Error of reading or writing a socket as a file
+    pub fn vfs_error(&self) -> VfsError {
+        match self {
+            NetError::NoRoute => VfsError::NetworkUnreachable,
+            NetError::DestinationRequired | NetError::NotConnected => VfsError::NotConnected,
+            NetError::ConnectionReset => VfsError::ConnectionReset,
+            NetError::TimedOut => VfsError::TimedOut,
+            NetError::Shutdown => VfsError::BrokenPipe,
+            _ => VfsError::InvaildArgument,
+        }
+    }
+}
+
+/// Network device with the address assigned to it
+pub struct Interface {
+    /// Name used in logs, e.g. `eth0`
+    name: String,
+    device: Arc<dyn NetworkDevice>,
+    mac: MacAddress,
+    address: AtomicMutex<Option<Ipv4Cidr>>,
+    /// Client leasing the address, unless it was configured statically
+    dhcp: AtomicMutex<Option<DhcpClient>>,
+}
+
+impl Interface {
+    pub fn address(&self) -> Option<Ipv4Cidr> {
+        *self.address.lock()
+    }
+
+    fn send_frame(&self, destination: MacAddress, ethertype: u16, payload: &[u8]) {
+        let frame = EthernetFrame {
+            destination,
+            source: self.mac,
+            ethertype,
+            payload,
+        };
+        self.device.transmit(&frame.to_bytes());
+    }
+
+    /// Whether a packet sent to an address is meant for the interface
+    fn accepts(&self, destination: Ipv4Addr) -> bool {
+        destination.is_broadcast()
+            || self.address().is_some_and(|address| {
+                destination == address.address || destination == address.broadcast()
+            })
+    }
+}
+
+pub struct Network {
+    interfaces: AtomicMutex<Vec<Arc<Interface>>>,
+    routes: AtomicMutex<RoutingTable>,
+    arp: AtomicMutex<ArpCache>,
+    udp: AtomicMutex<Ports<UdpSocket>>,
+    tcp: AtomicMutex<tcp::Table>,
+    /// Identification of the next sent IPv4 packet
+    identification: AtomicU16,
+}
+
+impl Network {
+    pub const fn new() -> Network {
+        Network {
+            interfaces: AtomicMutex::new(Vec::new()),
+            routes: AtomicMutex::new(RoutingTable::new()),
+            arp: AtomicMutex::new(ArpCache::new()),
+            udp: AtomicMutex::new(Ports::new()),
+            tcp: AtomicMutex::new(tcp::Table::new()),
+            identification: AtomicU16::new(0),
+        }
+    }
+
+    /// Adds an interface for a device, starting a task handling frames it receives
+    pub fn add_interface(&self, device: Arc<dyn NetworkDevice>) -> Arc<Interface> {
+        let interface = {
+            let mut interfaces = self.interfaces.lock();
+            let interface = Arc::new(Interface {
+                name: format!("eth{}", interfaces.len()),
+                mac: device.mac_address(),
+                device,
+                address: AtomicMutex::new(None),
+                dhcp: AtomicMutex::new(None),
+            });
+            interfaces.push(interface.clone());
+            interface
+        };
+        kdebug!("{}: MAC address {}", interface.name, interface.mac);
+
+        let receiver = interface.clone();
+        task::spawn(&interface.name, move || loop {
+            let frame = receiver.device.receive();
+            Supervisor::global().network().receive(&receiver, &frame);
+        });
+        interface
+    }
+
+    /// Assigns an address to an interface, replacing its routes with one to its subnet and
+    /// a default one through `gateway`
+    pub fn configure(
+        &self,
+        interface: &Arc<Interface>,
+        address: Ipv4Cidr,
+        gateway: Option<Ipv4Addr>,
+    ) {
+        *interface.address.lock() = Some(address);
+        {
+            let mut routes = self.routes.lock();
+            routes.remove_interface(interface);
+            routes.add(Route {
+                destination: address,
+                gateway: None,
+                interface: interface.clone(),
+            });
+            if let Some(gateway) = gateway {
+                routes.add(Route {
+                    destination: Ipv4Cidr {
+                        address: Ipv4Addr::UNSPECIFIED,
+                        prefix: 0,
+                    },
+                    gateway: Some(gateway),
+                    interface: interface.clone(),
+                });
+            }
+        }
+        match gateway {
+            Some(gateway) => kdebug!(
+                "{}: address {}, gateway {}",
+                interface.name,
+                address,
+                gateway
+            ),
+            None => kdebug!("{}: address {}", interface.name, address),
+        }
+    }
+
+    /// Removes the address of an interface along with its routes
+    pub fn deconfigure(&self, interface: &Arc<Interface>) {
+        *interface.address.lock() = None;
+        self.routes.lock().remove_interface(interface);
+        kdebug!("{}: address removed", interface.name);
+    }
+
+    /// Whether an address is assigned to one of the interfaces
+    pub fn is_local(&self, address: Ipv4Addr) -> bool {
+        self.interfaces
+            .lock()
+            .iter()
+            .any(|interface| interface.address().is_some_and(|a| a.address == address))
+    }
+
+    /// Address of the interface through which packets to a destination are sent
+    pub fn source_address(&self, destination: Ipv4Addr) -> Result<Ipv4Addr, NetError> {
+        let routes = self.routes.lock();
+        let route = routes.lookup(destination).ok_or(NetError::NoRoute)?;
+        route
+            .interface
+            .address()
+            .map(|address| address.address)
+            .ok_or(NetError::NoRoute)
+    }
+
+    /// Sends an IPv4 packet through the interface chosen by the routing table
+    pub fn send_ipv4(
+        &self,
+        source: Ipv4Addr,
+        destination: Ipv4Addr,
+        protocol: u8,
+        payload: &[u8],
+    ) -> Result<(), NetError> {
+        if ipv4::HEADER_LENGTH + payload.len() > ethernet::MTU {
+            return Err(NetError::MessageTooLong);
+        }
+        let (interface, next_hop) = {
+            let routes = self.routes.lock();
+            let route = routes.lookup(destination).ok_or(NetError::NoRoute)?;
+            (route.interface.clone(), route.next_hop(destination))
+        };
+        let packet = self.ipv4_packet(source, destination, protocol, payload);
+
+        let subnet = interface.address();
+        if destination.is_broadcast() || subnet.is_some_and(|s| destination == s.broadcast()) {
+            interface.send_frame(MacAddress::BROADCAST, ETHERTYPE_IPV4, &packet);
+            return Ok(());
+        }
+        let now = Supervisor::global().clock().now();
+        let mut arp = self.arp.lock();
+        match arp.lookup(next_hop, now) {
+            Some(mac) => {
+                drop(arp);
+                interface.send_frame(mac, ETHERTYPE_IPV4, &packet);
+            }
+            None => {
+                if arp.enqueue(next_hop, &interface, packet, now) {
+                    drop(arp);
+                    self.request(&interface, next_hop);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Broadcasts an IPv4 packet on the link of an interface, which needs no address for it
+    pub fn broadcast_ipv4(
+        &self,
+        interface: &Interface,
+        source: Ipv4Addr,
+        protocol: u8,
+        payload: &[u8],
+    ) {
+        let packet = self.ipv4_packet(source, Ipv4Addr::BROADCAST, protocol, payload);
+        interface.send_frame(MacAddress::BROADCAST, ETHERTYPE_IPV4, &packet);
+    }
+
+    fn ipv4_packet(
+        &self,
+        source: Ipv4Addr,
+        destination: Ipv4Addr,
+        protocol: u8,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        Ipv4Packet {
+            source,
+            destination,
+            protocol,
+            ttl: DEFAULT_TTL,
+            identification: self.identification.fetch_add(1, Ordering::Relaxed),
+            payload,
+        }
+        .to_bytes()
+    }
+
+    /// Asks hosts on the link of an interface for the MAC address of an IPv4 one
+    fn request(&self, interface: &Interface, address: Ipv4Addr) {
+        let Some(source) = interface.address() else {
+            return;
+        };
+        let request = ArpPacket::request(interface.mac, source.address, address);
+        interface.send_frame(MacAddress::BROADCAST, ETHERTYPE_ARP, &request.to_bytes());
+    }
+
+    /// Repeats ARP requests, forgets expired addresses and handles timeouts of TCP and DHCP,
+    /// called periodically
+    fn tick(&self) {
+        let now = Supervisor::global().clock().now();
+        let requests = self.arp.lock().expire(now);
+        for (address, interface) in requests {
+            self.request(&interface, address);
+        }
+        self.tick_tcp();
+        self.tick_dhcp();
+    }
+
+    /// Handles a frame received by an interface
+    fn receive(&self, interface: &Arc<Interface>, frame: &[u8]) {
+        let Ok(frame) = EthernetFrame::parse(frame) else {
+            return;
+        };
+        if frame.destination != interface.mac && !frame.destination.is_multicast() {
+            return;
+        }
+        let result = match frame.ethertype {
+            ETHERTYPE_ARP => self.receive_arp(interface, frame.payload),
+            ETHERTYPE_IPV4 => self.receive_ipv4(interface, frame.payload),
+            _ => Ok(()),
+        };
+        if let Err(error) = result {
+            kdebug!("{}: dropped a frame: {}", interface.name, error);
+        }
+    }
+
+    fn receive_arp(&self, interface: &Interface, payload: &[u8]) -> Result<(), PacketError> {
+        let packet = ArpPacket::parse(payload)?;
+        let Some(address) = interface.address() else {
+            return Ok(());
+        };
+        let for_us = packet.target_ip == address.address;
+
+        // senders are learned from requests for us, and updated if they are already known
+        let sender = packet.sender_ip;
+        let waiting = {
+            let mut arp = self.arp.lock();
+            if !sender.is_unspecified() && (for_us || arp.knows(sender)) {
+                let now = Supervisor::global().clock().now();
+                arp.insert(sender, packet.sender_mac, now)
+            } else {
+                None
+            }
+        };
+        if let Some(waiting) = waiting {
+            for waiting_packet in waiting.packets {
+                waiting
+                    .interface
+                    .send_frame(packet.sender_mac, ETHERTYPE_IPV4, &waiting_packet);
+            }
+        }
+
+        if for_us && packet.operation == ArpOperation::Request {
+            let reply = packet.reply(interface.mac);
+            interface.send_frame(packet.sender_mac, ETHERTYPE_ARP, &reply.to_bytes());
+        }
+        Ok(())
+    }
+
+    fn receive_ipv4(&self, interface: &Arc<Interface>, bytes: &[u8]) -> Result<(), PacketError> {
+        let packet = Ipv4Packet::parse(bytes)?;
+        if !interface.accepts(packet.destination) {
+            return Ok(());
+        }
+        // errors are not sent in response to broadcasts
+        let unicast = interface
+            .address()
+            .is_some_and(|address| packet.destination == address.address);
+        match packet.protocol {
+            PROTOCOL_ICMP => {
+                let message = IcmpPacket::parse(packet.payload)?;
+                if message.kind == icmp::ECHO_REQUEST && unicast {
+                    self.send_icmp(packet.destination, packet.source, &message.echo_reply());
+                }
+            }
+            PROTOCOL_UDP => {
+                let datagram =
+                    UdpDatagram::parse(packet.payload, packet.source, packet.destination)?;
+                let delivered =
+                    self.receive_dhcp(interface, &datagram) || self.receive_udp(&packet, &datagram);
+                if !delivered && unicast {
+                    let message = IcmpPacket::unreachable(PORT_UNREACHABLE, bytes);
+                    self.send_icmp(packet.destination, packet.source, &message);
+                }
+            }
+            PROTOCOL_TCP if unicast => {
+                let segment = TcpSegment::parse(packet.payload, packet.source, packet.destination)?;
+                self.receive_tcp(&packet, &segment);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn send_icmp(&self, source: Ipv4Addr, destination: Ipv4Addr, message: &IcmpPacket) {
+        let result = self.send_ipv4(source, destination, PROTOCOL_ICMP, &message.to_bytes());
+        if let Err(error) = result {
+            kdebug!("Cannot send ICMP message to {}: {}", destination, error);
+        }
+    }
+}
+
+/// Adds interfaces for network devices and starts the network timer. The first interface
+/// is configured by boot options, or by DHCP, whose lease is waited for a while
+pub fn initialize(devices: &DeviceRegistry, dt: &FlattenedDeviceTree) {
+    let cards = devices.find_all::<VirtioNet>();
+    if cards.is_empty() {
+        kdebug!("No network devices");
+        return;
+    }
+    let Some(plic) = devices.find::<Plic>() else {
+        kdebug!("No interrupt controller, network devices are not used");
+        return;
+    };
+    let network = Supervisor::global().network();
+    let interfaces: Vec<_> = cards
+        .into_iter()
+        .map(|card| {
+            plic.register(card.irq(), card.clone());
+            network.add_interface(card)
+        })
+        .collect();
+    task::spawn("net-timer", || loop {
+        task::sleep(TIMER_INTERVAL);
+        Supervisor::global().network().tick();
+    });
+
+    let interface = &interfaces[0];
+    if let Some((address, gateway)) = static_configuration(dt) {
+        network.configure(interface, address, gateway);
+        return;
+    }
+    network.start_dhcp(interface);
+    let clock = Supervisor::global().clock();
+    let start = clock.now();
+    while interface.address().is_none() {
+        if clock.now() - start >= DHCP_BOOT_TIMEOUT {
+            kdebug!(
+                "{}: no DHCP lease yet, waiting in the background",
+                interface.name
+            );
+            return;
+        }
+        task::sleep(TIMER_INTERVAL);
+    }
+}
+
+/// Address and gateway given by `ip=address/prefix` and `gw=address` boot options. Without
+/// `ip`, or with `ip=dhcp`, the address is leased by DHCP
+fn static_configuration(dt: &FlattenedDeviceTree) -> Option<(Ipv4Cidr, Option<Ipv4Addr>)> {
+    let address = match bootargs::option(dt, "ip")? {
+        "dhcp" => return None,
+        address => match address.parse() {
+            Ok(address) => address,
+            Err(error) => {
+                kdebug!("Ignoring address {}: {}, using DHCP", address, error);
+                return None;
+            }
+        },
+    };
+    let gateway = bootargs::option(dt, "gw").and_then(|gateway| match gateway.parse() {
+        Ok(gateway) => Some(gateway),
+        Err(error) => {
+            kdebug!("Ignoring gateway {}: {}", gateway, error);
+            None
+        }
+    });
+    Some((address, gateway))
+}
@@ -0,0 +1,75 @@
+//! Ports of a transport protocol, each held by one socket
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+};
+use core::ops::RangeInclusive;
+
+use super::NetError;
+
+/// Ports given to sockets which are not bound explicitly
+const EPHEMERAL_PORTS: RangeInclusive<u16> = 49152..=65535;
+
+/// Sockets by the ports they are bound to
+pub struct Ports<T> {
+    sockets: BTreeMap<u16, Weak<T>>,
+    next_ephemeral: u16,
+}
+
+impl<T> Ports<T> {
+    pub const fn new() -> Ports<T> {
+        Ports {
+            sockets: BTreeMap::new(),
+            next_ephemeral: *EPHEMERAL_PORTS.start(),
+        }
+    }
+
+    /// Binds a socket to a port, or to a free ephemeral one for port 0. Returns the port
+    pub fn bind(&mut self, port: u16, socket: &Arc<T>) -> Result<u16, NetError> {
+        let port = match port {
+            0 => self.free_ephemeral()?,
+            port if self.is_used(port) => return Err(NetError::AddressInUse),
+            port => port,
+        };
+        self.sockets.insert(port, Arc::downgrade(socket));
+        Ok(port)
+    }
+
+    /// Socket bound to a port
+    pub fn get(&self, port: u16) -> Option<Arc<T>> {
+        self.sockets.get(&port).and_then(Weak::upgrade)
+    }
+
+    /// Frees a port, if it is still bound to the socket
+    pub fn release(&mut self, port: u16, socket: *const T) {
+        if self
+            .sockets
+            .get(&port)
+            .is_some_and(|bound| bound.as_ptr() == socket)
+        {
+            self.sockets.remove(&port);
+        }
+    }
+
+    fn is_used(&self, port: u16) -> bool {
+        self.sockets
+            .get(&port)
+            .is_some_and(|socket| socket.strong_count() > 0)
+    }
+
+    fn free_ephemeral(&mut self) -> Result<u16, NetError> {
+        for _ in EPHEMERAL_PORTS {
+            let port = self.next_ephemeral;
+            self.next_ephemeral = if port == *EPHEMERAL_PORTS.end() {
+                *EPHEMERAL_PORTS.start()
+            } else {
+                port + 1
+            };
+            if !self.is_used(port) {
+                return Ok(port);
+            }
+        }
+        Err(NetError::AddressInUse)
+    }
+}
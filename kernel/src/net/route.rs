@@ -0,0 +1,58 @@
+//! Routing table, choosing the interface and next hop of sent packets by the longest prefix
+//! matching their destination
+
+use alloc::{sync::Arc, vec::Vec};
+use core::net::Ipv4Addr;
+
+use packet::Ipv4Cidr;
+
+use super::Interface;
+
+pub struct Route {
+    /// Subnet reached through the route, with its network address
+    pub destination: Ipv4Cidr,
+    /// Router packets are passed to, `None` for hosts on the link of the interface
+    pub gateway: Option<Ipv4Addr>,
+    pub interface: Arc<Interface>,
+}
+
+impl Route {
+    /// Host to which a packet for a destination is sent
+    pub fn next_hop(&self, destination: Ipv4Addr) -> Ipv4Addr {
+        self.gateway.unwrap_or(destination)
+    }
+}
+
+/// Routes sorted from the longest prefix
+pub struct RoutingTable {
+    routes: Vec<Route>,
+}
+
+impl RoutingTable {
+    pub const fn new() -> RoutingTable {
+        RoutingTable { routes: Vec::new() }
+    }
+
+    /// Adds a route, which is used after routes with the same prefix length added earlier
+    pub fn add(&mut self, mut route: Route) {
+        route.destination.address = route.destination.network();
+        let index = self
+            .routes
+            .iter()
+            .position(|other| other.destination.prefix < route.destination.prefix)
+            .unwrap_or(self.routes.len());
+        self.routes.insert(index, route);
+    }
+
+    /// Removes all routes through an interface
+    pub fn remove_interface(&mut self, interface: &Interface) {
+        self.routes
+            .retain(|route| !core::ptr::eq(&*route.interface, interface));
+    }
+
+    pub fn lookup(&self, destination: Ipv4Addr) -> Option<&Route> {
+        self.routes
+            .iter()
+            .find(|route| route.destination.contains(destination))
+    }
+}
@@ -0,0 +1,62 @@
+//! Sockets as open files, so that they can be read, written and closed like other ones
+
+use alloc::sync::Arc;
+use core::any::Any;
+
+use vfs::{File, FileType, Inode, Metadata, OpenFlags, VfsResult};
+
+use super::{tcp::TcpSocket, udp::UdpSocket};
+
+#[derive(Clone)]
+pub enum Socket {
+    Udp(Arc<UdpSocket>),
+    Tcp(Arc<TcpSocket>),
+}
+
+struct SocketInode(Socket);
+
+impl Inode for SocketInode {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            inode: 0,
+            file_type: FileType::Socket,
+            permissions: 0o777,
+            size: 0,
+            links: 1,
+        }
+    }
+
+    fn read_at(&self, _offset: u64, buffer: &mut [u8]) -> VfsResult<usize> {
+        match &self.0 {
+            Socket::Udp(socket) => Ok(socket.receive_from(buffer).0),
+            Socket::Tcp(socket) => socket.read(buffer).map_err(|error| error.vfs_error()),
+        }
+    }
+
+    fn write_at(&self, _offset: u64, data: &[u8]) -> VfsResult<usize> {
+        match &self.0 {
+            Socket::Udp(socket) => socket.send_to(data, None),
+            Socket::Tcp(socket) => socket.write(data),
+        }
+        .map_err(|error| error.vfs_error())
+    }
+
+    fn as_any(&self) -> Option<&dyn Any> {
+        Some(self)
+    }
+}
+
+/// Opens a socket as a readable and writeable file
+pub fn open(socket: Socket) -> Arc<File> {
+    File::from_inode(Arc::new(SocketInode(socket)), OpenFlags::READ_WRITE)
+}
+
+/// Socket of an open file, if it is one
+pub fn of_file(file: &File) -> Option<Socket> {
+    let inode = file
+        .dentry()
+        .inode()
+        .as_any()?
+        .downcast_ref::<SocketInode>()?;
+    Some(inode.0.clone())
+}
@@ -0,0 +1,195 @@
+//! Socket system calls, operating on sockets opened as files of the calling process
+
+use alloc::vec;
+use core::net::{Ipv4Addr, SocketAddrV4};
+
+use core_lib::syscall::{
+    Errno, SockAddrIn, AF_INET, IPPROTO_TCP, IPPROTO_UDP, SOCK_DGRAM, SOCK_STREAM,
+};
+
+use crate::{
+    fs::syscall as fs,
+    process::{syscall::Arguments, Process},
+};
+
+use super::{
+    socket::{self, Socket},
+    tcp::{connection::SEND_BUFFER, TcpSocket},
+    udp::{UdpSocket, MAX_PAYLOAD},
+    NetError,
+};
+
+pub fn socket(process: &Process, [domain, kind, protocol, ..]: Arguments) -> Result<usize, Errno> {
+    if domain != AF_INET {
+        return Err(Errno::EAFNOSUPPORT);
+    }
+    let socket = match (kind, protocol) {
+        (SOCK_DGRAM, 0 | IPPROTO_UDP) => Socket::Udp(UdpSocket::new()),
+        (SOCK_STREAM, 0 | IPPROTO_TCP) => Socket::Tcp(TcpSocket::new()),
+        _ => return Err(Errno::EPROTONOSUPPORT),
+    };
+    process
+        .files()
+        .lock()
+        .insert(socket::open(socket))
+        .map_err(|error| error.errno())
+}
+
+pub fn bind(process: &Process, [fd, address, length, ..]: Arguments) -> Result<usize, Errno> {
+    let address = read_address(process, address, length)?;
+    match socket_of(process, fd)? {
+        Socket::Udp(socket) => socket.bind(address),
+        Socket::Tcp(socket) => socket.bind(address),
+    }
+    .map_err(|error| error.errno())?;
+    Ok(0)
+}
+
+pub fn connect(process: &Process, [fd, address, length, ..]: Arguments) -> Result<usize, Errno> {
+    let address = read_address(process, address, length)?;
+    match socket_of(process, fd)? {
+        Socket::Udp(socket) => socket.connect(address),
+        Socket::Tcp(socket) => socket.connect(address),
+    }
+    .map_err(|error| error.errno())?;
+    Ok(0)
+}
+
+pub fn listen(process: &Process, [fd, backlog, ..]: Arguments) -> Result<usize, Errno> {
+    match socket_of(process, fd)? {
+        Socket::Udp(_) => Err(NetError::Unsupported),
+        Socket::Tcp(socket) => socket.listen(backlog),
+    }
+    .map_err(|error| error.errno())?;
+    Ok(0)
+}
+
+pub fn accept(process: &Process, [fd, address, length, ..]: Arguments) -> Result<usize, Errno> {
+    let Socket::Tcp(socket) = socket_of(process, fd)? else {
+        return Err(NetError::Unsupported.errno());
+    };
+    let (accepted, remote) = socket.accept().map_err(|error| error.errno())?;
+    let fd = process
+        .files()
+        .lock()
+        .insert(socket::open(Socket::Tcp(accepted)))
+        .map_err(|error| error.errno())?;
+    if address != 0 {
+        write_address(process, address, length, remote)?;
+    }
+    Ok(fd)
+}
+
+pub fn getsockname(
+    process: &Process,
+    [fd, address, length, ..]: Arguments,
+) -> Result<usize, Errno> {
+    let local = match socket_of(process, fd)? {
+        Socket::Udp(socket) => socket.local_address(),
+        Socket::Tcp(socket) => socket.local_address(),
+    };
+    write_address(process, address, length, local)?;
+    Ok(0)
+}
+
+pub fn sendto(
+    process: &Process,
+    [fd, buffer, length, flags, address, address_length]: Arguments,
+) -> Result<usize, Errno> {
+    if flags != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let socket = socket_of(process, fd)?;
+    let destination = match address {
+        0 => None,
+        address => Some(read_address(process, address, address_length)?),
+    };
+    // streams send a part of larger buffers, which is reported by the returned length
+    let length = match socket {
+        Socket::Udp(_) if length > MAX_PAYLOAD => return Err(Errno::EMSGSIZE),
+        Socket::Udp(_) => length,
+        Socket::Tcp(_) => length.min(SEND_BUFFER),
+    };
+    let mut data = vec![0; length];
+    process.copy_from_user(buffer, &mut data)?;
+    match socket {
+        Socket::Udp(socket) => socket.send_to(&data, destination),
+        // the address of a connected stream is ignored
+        Socket::Tcp(socket) => socket.write(&data),
+    }
+    .map_err(|error| error.errno())
+}
+
+pub fn recvfrom(
+    process: &Process,
+    [fd, buffer, length, flags, address, address_length]: Arguments,
+) -> Result<usize, Errno> {
+    if flags != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let socket = socket_of(process, fd)?;
+    let (received, source, data) = match socket {
+        Socket::Udp(socket) => {
+            let mut data = vec![0; length.min(MAX_PAYLOAD)];
+            let (received, source) = socket.receive_from(&mut data);
+            (received, source, data)
+        }
+        Socket::Tcp(socket) => {
+            let source = socket.remote_address().map_err(|error| error.errno())?;
+            let mut data = vec![0; length.min(SEND_BUFFER)];
+            let received = socket.read(&mut data).map_err(|error| error.errno())?;
+            (received, source, data)
+        }
+    };
+    process.copy_to_user(buffer, &data[..received])?;
+    if address != 0 {
+        write_address(process, address, address_length, source)?;
+    }
+    Ok(received)
+}
+
+fn socket_of(process: &Process, fd: usize) -> Result<Socket, Errno> {
+    let file = fs::file(process, fd)?;
+    socket::of_file(&file).ok_or(Errno::ENOTSOCK)
+}
+
+fn read_address(process: &Process, address: usize, length: usize) -> Result<SocketAddrV4, Errno> {
+    if length < size_of::<SockAddrIn>() {
+        return Err(Errno::EINVAL);
+    }
+    let mut bytes = [0u8; size_of::<SockAddrIn>()];
+    process.copy_from_user(address, &mut bytes)?;
+    // SAFETY: any bytes make a valid SockAddrIn, which is read unaligned
+    let address = unsafe { (bytes.as_ptr() as *const SockAddrIn).read_unaligned() };
+    if address.family as usize != AF_INET {
+        return Err(Errno::EAFNOSUPPORT);
+    }
+    Ok(SocketAddrV4::new(
+        Ipv4Addr::from(address.address),
+        address.port(),
+    ))
+}
+
+/// Stores an address in a buffer whose size is read from `length`, truncating it if needed.
+/// The full size of the address is stored back in `length`
+fn write_address(
+    process: &Process,
+    address: usize,
+    length: usize,
+    value: SocketAddrV4,
+) -> Result<(), Errno> {
+    let mut size = [0u8; size_of::<u32>()];
+    process.copy_from_user(length, &mut size)?;
+    let size = u32::from_ne_bytes(size) as usize;
+
+    let value = SockAddrIn::new(value.ip().octets(), value.port());
+    // SAFETY: SockAddrIn has no padding, so all of its bytes are initialized
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            &value as *const SockAddrIn as *const u8,
+            size_of::<SockAddrIn>(),
+        )
+    };
+    process.copy_to_user(address, &bytes[..size.min(bytes.len())])?;
+    process.copy_to_user(length, &(bytes.len() as u32).to_ne_bytes())
+}
@@ -0,0 +1,876 @@
+//! State of a single TCP connection: the handshake, sliding windows, retransmission with
+//! timeouts estimated as in RFC 6298 and Reno congestion control (RFC 5681)
+//!
+//! Segments which arrive out of order are dropped, the duplicate acknowledgments sent for
+//! them make the peer retransmit the missing data.
+
+use alloc::{
+    collections::VecDeque,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    cmp::{max, min},
+    mem,
+    net::SocketAddrV4,
+    time::Duration,
+};
+
+use core_lib::sync::AtomicMutex;
+use packet::{ethernet, ipv4, ipv4::PROTOCOL_TCP, tcp, TcpFlags, TcpSegment};
+
+use crate::{task::wait_queue::WaitQueue, time::Instant, Supervisor};
+
+use super::{super::NetError, Listener};
+
+/// Largest segment we can receive, announced to peers
+const LOCAL_MSS: usize = ethernet::MTU - ipv4::HEADER_LENGTH - tcp::HEADER_LENGTH;
+/// Segment size used when the peer does not announce one
+const DEFAULT_MSS: usize = 536;
+/// Size of the receive buffer, the largest window announced without window scaling
+const RECEIVE_BUFFER: usize = 65535;
+/// Size of the send buffer, writers block while it is full
+pub const SEND_BUFFER: usize = 65536;
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+const MIN_RTO: Duration = Duration::from_millis(200);
+const MAX_RTO: Duration = Duration::from_secs(60);
+/// Timeouts of a segment before the connection is dropped
+const MAX_RETRANSMISSIONS: u32 = 8;
+/// Timeouts of SYN before the connection attempt fails
+const MAX_SYN_RETRANSMISSIONS: u32 = 5;
+/// Time spent in TIME-WAIT, twice the maximum segment lifetime
+const TIME_WAIT: Duration = Duration::from_secs(60);
+/// Time a closed connection waits in FIN-WAIT-2 for the peer to close its side
+const FIN_WAIT_TIMEOUT: Duration = Duration::from_secs(60);
+/// Duplicate acknowledgments which trigger a fast retransmit
+const DUPLICATE_ACKS: u32 = 3;
+
+/// Whether sequence number `a` comes before `b`, modulo 2^32
+fn before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+/// Segment to be sent once the connection is unlocked
+struct Outgoing {
+    sequence: u32,
+    acknowledgment: u32,
+    flags: TcpFlags,
+    window: u16,
+    mss: Option<u16>,
+    payload: Vec<u8>,
+}
+
+/// Transmission control block, with variables named after those of RFC 9293
+struct Tcb {
+    state: State,
+    /// Reported to the user once the connection is closed abnormally
+    error: Option<NetError>,
+
+    initial_send: u32,
+    send_unacknowledged: u32,
+    send_next: u32,
+    /// Highest sequence number sent, ahead of `send_next` after a timeout rewinds it
+    send_max: u32,
+    send_window: usize,
+    /// Sequence and acknowledgment numbers of the segment which last updated the window
+    window_update: (u32, u32),
+    /// Data from `send_unacknowledged` on, part of which may have been sent
+    send_buffer: VecDeque<u8>,
+    /// Whether the user closed the connection, so a FIN follows the data
+    closing: bool,
+    /// Sequence number of our FIN, once it has been sent
+    fin_sequence: Option<u32>,
+    /// Largest segment the peer accepts
+    mss: usize,
+
+    receive_next: u32,
+    receive_buffer: VecDeque<u8>,
+    fin_received: bool,
+    ack_pending: bool,
+
+    congestion_window: usize,
+    slow_start_threshold: usize,
+    duplicate_acks: u32,
+
+    smoothed_rtt: Option<Duration>,
+    rtt_variance: Duration,
+    rto: Duration,
+    /// Sequence number whose acknowledgment measures the round-trip time, and when it was
+    /// sent. Cleared on retransmissions, which make the measurement ambiguous
+    rtt_sample: Option<(u32, Instant)>,
+    retransmit_at: Option<Instant>,
+    retransmissions: u32,
+    /// End of TIME-WAIT or FIN-WAIT-2
+    linger_until: Option<Instant>,
+
+    outgoing: Vec<Outgoing>,
+}
+
+impl Tcb {
+    fn new(state: State, initial_send: u32) -> Tcb {
+        Tcb {
+            state,
+            error: None,
+            initial_send,
+            send_unacknowledged: initial_send,
+            send_next: initial_send,
+            send_max: initial_send,
+            send_window: 0,
+            window_update: (0, 0),
+            send_buffer: VecDeque::new(),
+            closing: false,
+            fin_sequence: None,
+            mss: DEFAULT_MSS,
+            receive_next: 0,
+            receive_buffer: VecDeque::new(),
+            fin_received: false,
+            ack_pending: false,
+            congestion_window: DEFAULT_MSS,
+            slow_start_threshold: usize::MAX,
+            duplicate_acks: 0,
+            smoothed_rtt: None,
+            rtt_variance: Duration::ZERO,
+            rto: INITIAL_RTO,
+            rtt_sample: None,
+            retransmit_at: None,
+            retransmissions: 0,
+            linger_until: None,
+            outgoing: Vec::new(),
+        }
+    }
+
+    fn receive_window(&self) -> usize {
+        RECEIVE_BUFFER - self.receive_buffer.len()
+    }
+
+    fn is_synchronized(&self) -> bool {
+        !matches!(
+            self.state,
+            State::SynSent | State::SynReceived | State::Closed
+        )
+    }
+
+    fn emit(&mut self, sequence: u32, flags: TcpFlags, payload: Vec<u8>) {
+        let mss = flags.contains(TcpFlags::SYN).then_some(LOCAL_MSS as u16);
+        let acknowledgment = if flags.contains(TcpFlags::ACK) {
+            self.ack_pending = false;
+            self.receive_next
+        } else {
+            0
+        };
+        self.outgoing.push(Outgoing {
+            sequence,
+            acknowledgment,
+            flags,
+            window: self.receive_window() as u16,
+            mss,
+            payload,
+        });
+    }
+
+    /// Sends SYN, or SYN-ACK in response to the SYN of the peer
+    fn emit_syn(&mut self) {
+        let flags = match self.state {
+            State::SynSent => TcpFlags::SYN,
+            _ => TcpFlags::SYN | TcpFlags::ACK,
+        };
+        self.emit(self.initial_send, flags, Vec::new());
+    }
+
+    /// Sends data allowed by the windows, a FIN once all data is sent and an acknowledgment
+    /// if one is pending and no segment carried it
+    fn output(&mut self, now: Instant) {
+        if !self.is_synchronized() {
+            return;
+        }
+        let window = min(self.congestion_window, self.send_window);
+        loop {
+            let in_flight = self.send_next.wrapping_sub(self.send_unacknowledged) as usize;
+            let sent = min(in_flight, self.send_buffer.len());
+            let unsent = self.send_buffer.len() - sent;
+            let usable = window.saturating_sub(in_flight);
+            if unsent == 0 || usable == 0 {
+                break;
+            }
+            let length = min(min(self.mss, unsent), usable);
+            let payload = self
+                .send_buffer
+                .range(sent..sent + length)
+                .copied()
+                .collect();
+            let flags = if length == unsent {
+                TcpFlags::ACK | TcpFlags::PSH
+            } else {
+                TcpFlags::ACK
+            };
+            self.emit(self.send_next, flags, payload);
+            self.send_next = self.send_next.wrapping_add(length as u32);
+            self.sent_up_to(self.send_next, now);
+        }
+
+        let all_sent = self.send_next
+            == (self.send_unacknowledged).wrapping_add(self.send_buffer.len() as u32);
+        if self.closing && all_sent && !self.fin_sent() {
+            self.emit(self.send_next, TcpFlags::FIN | TcpFlags::ACK, Vec::new());
+            self.fin_sequence = Some(self.send_next);
+            self.send_next = self.send_next.wrapping_add(1);
+            self.sent_up_to(self.send_next, now);
+            self.state = match self.state {
+                State::Established => State::FinWait1,
+                State::CloseWait => State::LastAck,
+                state => state,
+            };
+        }
+
+        // waiting for an acknowledgment, or for the peer to open its window
+        let outstanding = self.send_next != self.send_unacknowledged;
+        let blocked = self.send_window == 0 && !self.send_buffer.is_empty();
+        if (outstanding || blocked) && self.retransmit_at.is_none() {
+            self.retransmit_at = Some(now + self.rto);
+        }
+        if self.ack_pending {
+            self.emit(self.send_next, TcpFlags::ACK, Vec::new());
+        }
+    }
+
+    /// Whether our FIN is among segments sent since the last timeout
+    fn fin_sent(&self) -> bool {
+        self.fin_sequence
+            .is_some_and(|fin| before(fin, self.send_next))
+    }
+
+    /// Records that sequence numbers up to `end` were sent, starting a round-trip time
+    /// measurement if none is running
+    fn sent_up_to(&mut self, end: u32, now: Instant) {
+        if before(self.send_max, end) {
+            self.send_max = end;
+            if self.rtt_sample.is_none() {
+                self.rtt_sample = Some((end, now));
+            }
+        }
+    }
+
+    fn measure_rtt(&mut self, rtt: Duration) {
+        match self.smoothed_rtt {
+            None => {
+                self.smoothed_rtt = Some(rtt);
+                self.rtt_variance = rtt / 2;
+            }
+            Some(smoothed) => {
+                let difference = smoothed.abs_diff(rtt);
+                self.rtt_variance = self.rtt_variance * 3 / 4 + difference / 4;
+                self.smoothed_rtt = Some(smoothed * 7 / 8 + rtt / 8);
+            }
+        }
+        let rto = self.smoothed_rtt.unwrap() + self.rtt_variance * 4;
+        self.rto = rto.clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// Closes the connection, with an error for the user unless it is `None`
+    fn terminate(&mut self, error: Option<NetError>) {
+        self.state = State::Closed;
+        self.error = self.error.or(error);
+        self.retransmit_at = None;
+        self.linger_until = None;
+    }
+
+    /// Resets the connection, telling the peer about it
+    fn abort(&mut self, error: NetError) {
+        if self.state != State::Closed && self.state != State::SynSent {
+            self.emit(self.send_next, TcpFlags::RST, Vec::new());
+        }
+        self.terminate(Some(error));
+    }
+
+    fn enter_time_wait(&mut self, now: Instant) {
+        self.state = State::TimeWait;
+        self.retransmit_at = None;
+        self.linger_until = Some(now + TIME_WAIT);
+    }
+
+    /// Closes our side of the connection, sending a FIN once the data is sent
+    fn close(&mut self, now: Instant) {
+        match self.state {
+            State::SynSent => self.terminate(None),
+            State::SynReceived | State::Established | State::CloseWait => {
+                self.closing = true;
+                self.output(now);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_timeout(&mut self, now: Instant) {
+        self.retransmit_at = None;
+        // probes of a zero window are not lost segments, the peer just has no room
+        let probing = self.send_window == 0 && self.is_synchronized();
+        if !probing {
+            let limit = match self.state {
+                State::SynSent | State::SynReceived => MAX_SYN_RETRANSMISSIONS,
+                _ => MAX_RETRANSMISSIONS,
+            };
+            if self.retransmissions >= limit {
+                self.abort(NetError::TimedOut);
+                return;
+            }
+            self.retransmissions += 1;
+            let in_flight = self.send_max.wrapping_sub(self.send_unacknowledged) as usize;
+            self.slow_start_threshold = max(in_flight / 2, 2 * self.mss);
+            self.congestion_window = self.mss;
+            self.duplicate_acks = 0;
+        }
+        self.rto = min(self.rto * 2, MAX_RTO);
+        self.rtt_sample = None;
+
+        if !self.is_synchronized() {
+            self.emit_syn();
+            self.retransmit_at = Some(now + self.rto);
+            return;
+        }
+        // go back to the first unacknowledged byte, sending one segment, or a byte of it
+        // beyond the closed window of the peer
+        self.send_next = self.send_unacknowledged;
+        let window = self.send_window;
+        if probing {
+            self.send_window = 1;
+        }
+        self.output(now);
+        self.send_window = window;
+    }
+
+    fn tick(&mut self, now: Instant) {
+        if self.retransmit_at.is_some_and(|at| at <= now) {
+            self.on_timeout(now);
+        }
+        if self.linger_until.is_some_and(|until| until <= now) {
+            self.terminate(None);
+        }
+    }
+
+    /// Handles a segment, returning whether it completed a handshake started by the peer
+    fn receive(&mut self, segment: &TcpSegment, now: Instant) -> bool {
+        match self.state {
+            State::Closed => return false,
+            State::SynSent => {
+                self.receive_syn_sent(segment, now);
+                return false;
+            }
+            _ => {}
+        }
+        let flags = segment.flags;
+        let length = segment.sequence_length();
+
+        if self.state == State::SynReceived
+            && flags.contains(TcpFlags::SYN)
+            && segment.sequence.wrapping_add(1) == self.receive_next
+        {
+            // the peer did not get our SYN-ACK
+            self.emit_syn();
+            return false;
+        }
+        if !self.acceptable(segment.sequence, length) {
+            if !flags.contains(TcpFlags::RST) {
+                self.ack_pending = true;
+                self.output(now);
+            }
+            return false;
+        }
+        if flags.contains(TcpFlags::RST) {
+            if segment.sequence == self.receive_next {
+                let error = match self.state {
+                    State::SynReceived | State::Closing | State::LastAck | State::TimeWait => None,
+                    _ => Some(NetError::ConnectionReset),
+                };
+                self.terminate(error);
+            } else {
+                // challenge acknowledgment of RFC 5961, a genuine peer resets again with the
+                // exact sequence number
+                self.ack_pending = true;
+                self.output(now);
+            }
+            return false;
+        }
+        if flags.contains(TcpFlags::SYN) {
+            self.ack_pending = true;
+            self.output(now);
+            return false;
+        }
+        if !flags.contains(TcpFlags::ACK) {
+            return false;
+        }
+
+        let mut established = false;
+        if self.state == State::SynReceived {
+            if segment.acknowledgment != self.send_max {
+                self.emit(segment.acknowledgment, TcpFlags::RST, Vec::new());
+                return false;
+            }
+            self.establish(segment, now);
+            established = true;
+        }
+        if !self.process_acknowledgment(segment, now) {
+            self.output(now);
+            return established;
+        }
+        if self.state != State::Closed {
+            self.process_data(segment, now);
+            self.output(now);
+        }
+        established
+    }
+
+    fn receive_syn_sent(&mut self, segment: &TcpSegment, now: Instant) {
+        let flags = segment.flags;
+        let acknowledgment = segment.acknowledgment;
+        let acknowledged = flags.contains(TcpFlags::ACK);
+        if acknowledged
+            && (!before(self.initial_send, acknowledgment) || before(self.send_max, acknowledgment))
+        {
+            if !flags.contains(TcpFlags::RST) {
+                self.emit(acknowledgment, TcpFlags::RST, Vec::new());
+            }
+            return;
+        }
+        if flags.contains(TcpFlags::RST) {
+            if acknowledged {
+                self.terminate(Some(NetError::ConnectionRefused));
+            }
+            return;
+        }
+        if !flags.contains(TcpFlags::SYN) {
+            return;
+        }
+        self.receive_next = segment.sequence.wrapping_add(1);
+        self.mss = segment.mss.map_or(DEFAULT_MSS, |mss| mss as usize);
+        if acknowledged {
+            self.establish(segment, now);
+            self.ack_pending = true;
+            self.output(now);
+        } else {
+            // simultaneous open
+            self.state = State::SynReceived;
+            self.send_window = segment.window as usize;
+            self.emit_syn();
+        }
+    }
+
+    /// Moves to ESTABLISHED once our SYN is acknowledged
+    fn establish(&mut self, segment: &TcpSegment, now: Instant) {
+        self.state = State::Established;
+        self.send_unacknowledged = segment.acknowledgment;
+        self.send_next = segment.acknowledgment;
+        self.send_window = segment.window as usize;
+        self.window_update = (segment.sequence, segment.acknowledgment);
+        if let Some((_, sent)) = self.rtt_sample.take() {
+            self.measure_rtt(now - sent);
+        }
+        // initial window of RFC 5681
+        self.congestion_window = min(4 * self.mss, max(2 * self.mss, 4380));
+        self.retransmissions = 0;
+        self.retransmit_at = None;
+    }
+
+    /// Whether a segment falls into the receive window (RFC 9293, 3.10.7.4)
+    fn acceptable(&self, sequence: u32, length: u32) -> bool {
+        let window = self.receive_window() as u32;
+        let next = self.receive_next;
+        let in_window =
+            |sequence: u32| !before(sequence, next) && before(sequence, next.wrapping_add(window));
+        match (length, window) {
+            (0, 0) => sequence == next,
+            (0, _) => in_window(sequence),
+            (_, 0) => false,
+            _ => in_window(sequence) || in_window(sequence.wrapping_add(length - 1)),
+        }
+    }
+
+    /// Handles the acknowledgment and window of a segment, returning false if it has to be
+    /// dropped
+    fn process_acknowledgment(&mut self, segment: &TcpSegment, now: Instant) -> bool {
+        let acknowledgment = segment.acknowledgment;
+        if before(self.send_max, acknowledgment) {
+            // acknowledges something not sent yet
+            self.ack_pending = true;
+            return false;
+        }
+        let window = segment.window as usize;
+        if before(self.send_unacknowledged, acknowledgment) {
+            self.acknowledge(acknowledgment, now);
+        } else if acknowledgment == self.send_unacknowledged
+            && segment.sequence_length() == 0
+            && window == self.send_window
+            && self.send_max != self.send_unacknowledged
+        {
+            self.duplicate_acknowledgment(now);
+        }
+
+        let (sequence, acknowledged) = self.window_update;
+        if before(sequence, segment.sequence)
+            || (sequence == segment.sequence && !before(acknowledgment, acknowledged))
+        {
+            self.send_window = window;
+            self.window_update = (segment.sequence, acknowledgment);
+        }
+        true
+    }
+
+    fn acknowledge(&mut self, acknowledgment: u32, now: Instant) {
+        let acknowledged = acknowledgment.wrapping_sub(self.send_unacknowledged) as usize;
+        let fin_acknowledged = self
+            .fin_sequence
+            .is_some_and(|fin| before(fin, acknowledgment));
+        let data = min(
+            acknowledged - usize::from(fin_acknowledged),
+            self.send_buffer.len(),
+        );
+        self.send_buffer.drain(..data);
+        self.send_unacknowledged = acknowledgment;
+        if before(self.send_next, acknowledgment) {
+            self.send_next = acknowledgment;
+        }
+
+        if let Some((sequence, sent)) = self.rtt_sample {
+            if !before(acknowledgment, sequence) {
+                self.rtt_sample = None;
+                self.measure_rtt(now - sent);
+            }
+        }
+        if self.duplicate_acks >= DUPLICATE_ACKS {
+            // end of fast recovery, the window inflated by duplicates deflates
+            self.congestion_window = self.slow_start_threshold;
+        } else if self.congestion_window < self.slow_start_threshold {
+            self.congestion_window += min(acknowledged, self.mss);
+        } else {
+            self.congestion_window += max(1, self.mss * self.mss / self.congestion_window);
+        }
+        self.duplicate_acks = 0;
+        self.retransmissions = 0;
+        self.retransmit_at = if self.send_unacknowledged == self.send_max {
+            None
+        } else {
+            Some(now + self.rto)
+        };
+
+        if fin_acknowledged {
+            match self.state {
+                State::FinWait1 => {
+                    self.state = State::FinWait2;
+                    self.linger_until = Some(now + FIN_WAIT_TIMEOUT);
+                }
+                State::Closing => self.enter_time_wait(now),
+                State::LastAck => self.terminate(None),
+                _ => {}
+            }
+        }
+    }
+
+    fn duplicate_acknowledgment(&mut self, now: Instant) {
+        self.duplicate_acks += 1;
+        if self.duplicate_acks == DUPLICATE_ACKS {
+            // fast retransmit of the segment the peer is missing
+            let in_flight = self.send_max.wrapping_sub(self.send_unacknowledged) as usize;
+            self.slow_start_threshold = max(in_flight / 2, 2 * self.mss);
+            self.rtt_sample = None;
+            let length = min(self.mss, self.send_buffer.len());
+            if length > 0 {
+                let payload = self.send_buffer.range(..length).copied().collect();
+                self.emit(self.send_unacknowledged, TcpFlags::ACK, payload);
+            } else if self.fin_sequence == Some(self.send_unacknowledged) {
+                let flags = TcpFlags::FIN | TcpFlags::ACK;
+                self.emit(self.send_unacknowledged, flags, Vec::new());
+            }
+            self.congestion_window = self.slow_start_threshold + 3 * self.mss;
+            self.retransmit_at = Some(now + self.rto);
+        } else if self.duplicate_acks > DUPLICATE_ACKS {
+            self.congestion_window += self.mss;
+        }
+    }
+
+    fn process_data(&mut self, segment: &TcpSegment, now: Instant) {
+        if !matches!(
+            self.state,
+            State::Established | State::FinWait1 | State::FinWait2
+        ) {
+            return;
+        }
+        if segment.sequence_length() == 0 {
+            return;
+        }
+        self.ack_pending = true;
+        if before(self.receive_next, segment.sequence) {
+            return;
+        }
+        let skipped = self.receive_next.wrapping_sub(segment.sequence) as usize;
+        let payload = segment.payload.get(skipped..).unwrap_or(&[]);
+        let accepted = min(payload.len(), self.receive_window());
+        self.receive_buffer.extend(&payload[..accepted]);
+        self.receive_next = self.receive_next.wrapping_add(accepted as u32);
+
+        if segment.flags.contains(TcpFlags::FIN) && accepted == payload.len() {
+            self.receive_next = self.receive_next.wrapping_add(1);
+            self.fin_received = true;
+            match self.state {
+                State::Established => self.state = State::CloseWait,
+                State::FinWait1 => self.state = State::Closing,
+                State::FinWait2 => self.enter_time_wait(now),
+                _ => {}
+            }
+        }
+    }
+
+    fn readable(&self) -> bool {
+        !self.receive_buffer.is_empty() || self.fin_received || self.state == State::Closed
+    }
+
+    /// Takes received data, returning `None` if there is none yet. Ok(0) stands for the end
+    /// of the stream
+    fn read(&mut self, buffer: &mut [u8]) -> Option<Result<usize, NetError>> {
+        if !self.receive_buffer.is_empty() {
+            let window = self.receive_window();
+            let length = min(buffer.len(), self.receive_buffer.len());
+            for (byte, received) in buffer.iter_mut().zip(self.receive_buffer.drain(..length)) {
+                *byte = received;
+            }
+            // announce the window once it can take a full segment again
+            if window < LOCAL_MSS && self.receive_window() >= LOCAL_MSS && self.is_synchronized() {
+                self.emit(self.send_next, TcpFlags::ACK, Vec::new());
+            }
+            return Some(Ok(length));
+        }
+        if let Some(error) = self.error {
+            return Some(Err(error));
+        }
+        (self.fin_received || self.state == State::Closed).then_some(Ok(0))
+    }
+
+    fn writable(&self) -> bool {
+        match self.state {
+            State::SynSent | State::SynReceived => self.error.is_some(),
+            State::Established | State::CloseWait => {
+                self.closing || self.send_buffer.len() < SEND_BUFFER
+            }
+            _ => true,
+        }
+    }
+
+    /// Queues as much data as fits into the send buffer, returning its length
+    fn write(&mut self, data: &[u8], now: Instant) -> Result<usize, NetError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        if self.closing || !matches!(self.state, State::Established | State::CloseWait) {
+            return Err(NetError::Shutdown);
+        }
+        let length = min(data.len(), SEND_BUFFER - self.send_buffer.len());
+        self.send_buffer.extend(&data[..length]);
+        self.output(now);
+        Ok(length)
+    }
+}
+
+/// Initial sequence number, driven by a clock ticking every 4 microseconds as in RFC 9293
+/// and offset by the ports so that connections opened at once differ
+fn initial_sequence(local: SocketAddrV4, remote: SocketAddrV4, now: Instant) -> u32 {
+    let clock = (now.since_boot().as_micros() / 4) as u32;
+    let ports = (local.port() as u32) << 16 | remote.port() as u32;
+    clock.wrapping_add(ports.wrapping_mul(0x9e37_79b9))
+}
+
+pub struct Connection {
+    local: SocketAddrV4,
+    remote: SocketAddrV4,
+    tcb: AtomicMutex<Tcb>,
+    /// Woken whenever the state or the buffers change
+    changed: WaitQueue,
+    /// Listener which accepts the connection once established, if the peer opened it
+    listener: Option<Weak<Listener>>,
+}
+
+impl Connection {
+    /// Starts opening a connection, sending SYN once [`Connection::flush`] is called
+    pub fn connect(local: SocketAddrV4, remote: SocketAddrV4) -> Arc<Connection> {
+        let now = Supervisor::global().clock().now();
+        let mut tcb = Tcb::new(State::SynSent, initial_sequence(local, remote, now));
+        tcb.emit_syn();
+        tcb.sent_up_to(tcb.initial_send.wrapping_add(1), now);
+        tcb.retransmit_at = Some(now + tcb.rto);
+        Arc::new(Connection {
+            local,
+            remote,
+            tcb: AtomicMutex::new(tcb),
+            changed: WaitQueue::new(),
+            listener: None,
+        })
+    }
+
+    /// Answers SYN of a peer connecting to a listener, sending SYN-ACK once
+    /// [`Connection::flush`] is called
+    pub fn accept(
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+        syn: &TcpSegment,
+        listener: Weak<Listener>,
+    ) -> Arc<Connection> {
+        let now = Supervisor::global().clock().now();
+        let mut tcb = Tcb::new(State::SynReceived, initial_sequence(local, remote, now));
+        tcb.receive_next = syn.sequence.wrapping_add(1);
+        tcb.send_window = syn.window as usize;
+        tcb.mss = syn.mss.map_or(DEFAULT_MSS, |mss| mss as usize);
+        tcb.emit_syn();
+        tcb.sent_up_to(tcb.initial_send.wrapping_add(1), now);
+        tcb.retransmit_at = Some(now + tcb.rto);
+        Arc::new(Connection {
+            local,
+            remote,
+            tcb: AtomicMutex::new(tcb),
+            changed: WaitQueue::new(),
+            listener: Some(listener),
+        })
+    }
+
+    pub fn local_address(&self) -> SocketAddrV4 {
+        self.local
+    }
+
+    pub fn remote_address(&self) -> SocketAddrV4 {
+        self.remote
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.tcb.lock().state == State::Closed
+    }
+
+    /// Changes the control block, then sends segments it queued and wakes waiting tasks
+    fn update<T>(&self, change: impl FnOnce(&mut Tcb, Instant) -> T) -> T {
+        let now = Supervisor::global().clock().now();
+        let (result, outgoing) = {
+            let mut tcb = self.tcb.lock();
+            let result = change(&mut tcb, now);
+            (result, mem::take(&mut tcb.outgoing))
+        };
+        let network = Supervisor::global().network();
+        for segment in outgoing {
+            let bytes = TcpSegment {
+                source_port: self.local.port(),
+                destination_port: self.remote.port(),
+                sequence: segment.sequence,
+                acknowledgment: segment.acknowledgment,
+                flags: segment.flags,
+                window: segment.window,
+                mss: segment.mss,
+                payload: &segment.payload,
+            }
+            .to_bytes(*self.local.ip(), *self.remote.ip());
+            // lost segments are retransmitted once they time out
+            let _ = network.send_ipv4(*self.local.ip(), *self.remote.ip(), PROTOCOL_TCP, &bytes);
+        }
+        self.changed.wake_all();
+        result
+    }
+
+    /// Sends segments queued when the connection was created
+    pub fn flush(&self) {
+        self.update(|_, _| ());
+    }
+
+    /// Handles a received segment, passing the connection to its listener once the peer
+    /// completes the handshake
+    pub fn receive(self: &Arc<Self>, segment: &TcpSegment) {
+        if !self.update(|tcb, now| tcb.receive(segment, now)) {
+            return;
+        }
+        match self.listener.as_ref().and_then(Weak::upgrade) {
+            Some(listener) => listener.push(self.clone()),
+            None => self.abort(NetError::ConnectionReset),
+        }
+    }
+
+    /// Handles timeouts, called periodically
+    pub fn tick(&self) {
+        self.update(|tcb, now| tcb.tick(now));
+    }
+
+    /// Blocks until the handshake started by [`Connection::connect`] is finished
+    pub fn wait_established(&self) -> Result<(), NetError> {
+        self.changed.wait_until(|| {
+            let tcb = self.tcb.lock();
+            tcb.state != State::SynSent && tcb.state != State::SynReceived
+        });
+        match self.tcb.lock().error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Blocks until data is received, copying as much of it as fits into the buffer. Returns
+    /// 0 once the peer closed its side
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize, NetError> {
+        loop {
+            self.changed.wait_until(|| self.tcb.lock().readable());
+            if let Some(result) = self.update(|tcb, _| tcb.read(buffer)) {
+                return result;
+            }
+        }
+    }
+
+    /// Blocks until all data is queued for sending, or the connection fails
+    pub fn write(&self, data: &[u8]) -> Result<usize, NetError> {
+        let mut written = 0;
+        while written < data.len() {
+            self.changed.wait_until(|| self.tcb.lock().writable());
+            match self.update(|tcb, now| tcb.write(&data[written..], now)) {
+                Ok(length) => written += length,
+                Err(_) if written > 0 => break,
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(written)
+    }
+
+    /// Closes the connection gracefully, the peer getting all data sent so far
+    pub fn close(&self) {
+        self.update(|tcb, now| tcb.close(now));
+    }
+
+    /// Resets the connection
+    pub fn abort(&self, error: NetError) {
+        self.update(|tcb, _| tcb.abort(error));
+    }
+}
+
+/// Resets a connection a segment belongs to, but which does not exist
+pub fn reset(local: SocketAddrV4, remote: SocketAddrV4, segment: &TcpSegment) {
+    let (sequence, acknowledgment, flags) = if segment.flags.contains(TcpFlags::ACK) {
+        (segment.acknowledgment, 0, TcpFlags::RST)
+    } else {
+        let end = segment.sequence.wrapping_add(segment.sequence_length());
+        (0, end, TcpFlags::RST | TcpFlags::ACK)
+    };
+    let bytes = TcpSegment {
+        source_port: local.port(),
+        destination_port: remote.port(),
+        sequence,
+        acknowledgment,
+        flags,
+        window: 0,
+        mss: None,
+        payload: &[],
+    }
+    .to_bytes(*local.ip(), *remote.ip());
+    let network = Supervisor::global().network();
+    let _ = network.send_ipv4(*local.ip(), *remote.ip(), PROTOCOL_TCP, &bytes);
+}
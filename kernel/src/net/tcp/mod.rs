@@ -0,0 +1,326 @@
+//! TCP sockets, which listen for connections or are connected to a peer
+//!
+//! Connections live in a table of the network stack, by their local and remote addresses,
+//! until they are closed on both sides. Sockets only hold them, so a connection closed by
+//! the user still delivers remaining data and waits in TIME-WAIT. Timers of all connections
+//! are driven by the network timer.
+
+pub mod connection;
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    mem,
+    net::{Ipv4Addr, SocketAddrV4},
+};
+
+use core_lib::sync::AtomicMutex;
+use packet::{Ipv4Packet, TcpFlags, TcpSegment};
+
+use crate::{task::wait_queue::WaitQueue, Supervisor};
+
+use super::{port::Ports, NetError, Network};
+
+use connection::Connection;
+
+/// Most connections waiting to be accepted by a listener, whatever backlog was asked for
+const MAX_BACKLOG: usize = 64;
+
+/// Connections and listening sockets of the network stack
+pub struct Table {
+    connections: BTreeMap<(SocketAddrV4, SocketAddrV4), Arc<Connection>>,
+    listeners: BTreeMap<u16, Weak<Listener>>,
+    ports: Ports<TcpSocket>,
+}
+
+impl Table {
+    pub const fn new() -> Table {
+        Table {
+            connections: BTreeMap::new(),
+            listeners: BTreeMap::new(),
+            ports: Ports::new(),
+        }
+    }
+}
+
+/// Queue of established connections of a listening socket
+pub struct Listener {
+    local: SocketAddrV4,
+    backlog: usize,
+    established: AtomicMutex<VecDeque<Arc<Connection>>>,
+    ready: WaitQueue,
+}
+
+impl Listener {
+    fn has_room(&self) -> bool {
+        self.established.lock().len() < self.backlog
+    }
+
+    fn push(&self, connection: Arc<Connection>) {
+        self.established.lock().push_back(connection);
+        self.ready.wake_all();
+    }
+
+    fn accept(&self) -> Arc<Connection> {
+        let mut connection = None;
+        self.ready.wait_until(|| {
+            connection = self.established.lock().pop_front();
+            connection.is_some()
+        });
+        connection.unwrap()
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let network = Supervisor::global().network();
+        {
+            let mut table = network.tcp.lock();
+            let this = self as *const Listener;
+            let port = self.local.port();
+            if table
+                .listeners
+                .get(&port)
+                .is_some_and(|listener| listener.as_ptr() == this)
+            {
+                table.listeners.remove(&port);
+            }
+        }
+        for connection in self.established.lock().drain(..) {
+            connection.abort(NetError::ConnectionReset);
+        }
+    }
+}
+
+enum State {
+    Idle,
+    Listening(Arc<Listener>),
+    Connected(Arc<Connection>),
+}
+
+struct Inner {
+    local: Option<SocketAddrV4>,
+    state: State,
+}
+
+pub struct TcpSocket {
+    inner: AtomicMutex<Inner>,
+}
+
+impl TcpSocket {
+    pub fn new() -> Arc<TcpSocket> {
+        Arc::new(TcpSocket {
+            inner: AtomicMutex::new(Inner {
+                local: None,
+                state: State::Idle,
+            }),
+        })
+    }
+
+    /// Local address, which is unspecified with port 0 until the socket is bound
+    pub fn local_address(&self) -> SocketAddrV4 {
+        self.inner
+            .lock()
+            .local
+            .unwrap_or(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))
+    }
+
+    /// Address of the peer of a connected socket
+    pub fn remote_address(&self) -> Result<SocketAddrV4, NetError> {
+        Ok(self.connection()?.remote_address())
+    }
+
+    /// Binds the socket to an address, which is either unspecified or that of an interface
+    pub fn bind(self: &Arc<Self>, address: SocketAddrV4) -> Result<(), NetError> {
+        let network = Supervisor::global().network();
+        if !address.ip().is_unspecified() && !network.is_local(*address.ip()) {
+            return Err(NetError::AddressNotAvailable);
+        }
+        let mut inner = self.inner.lock();
+        if inner.local.is_some() {
+            return Err(NetError::AlreadyBound);
+        }
+        let port = network.tcp.lock().ports.bind(address.port(), self)?;
+        inner.local = Some(SocketAddrV4::new(*address.ip(), port));
+        Ok(())
+    }
+
+    /// Binds the socket to an ephemeral port if it is not bound yet, returning its address
+    fn bind_ephemeral(self: &Arc<Self>) -> Result<SocketAddrV4, NetError> {
+        match self.bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)) {
+            Ok(()) | Err(NetError::AlreadyBound) => Ok(self.local_address()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Starts accepting connections to the local address
+    pub fn listen(self: &Arc<Self>, backlog: usize) -> Result<(), NetError> {
+        let local = self.bind_ephemeral()?;
+        let mut inner = self.inner.lock();
+        match inner.state {
+            State::Idle => {}
+            State::Listening(_) => return Ok(()),
+            State::Connected(_) => return Err(NetError::AlreadyConnected),
+        }
+        let listener = Arc::new(Listener {
+            local,
+            backlog: backlog.clamp(1, MAX_BACKLOG),
+            established: AtomicMutex::new(VecDeque::new()),
+            ready: WaitQueue::new(),
+        });
+        let network = Supervisor::global().network();
+        network
+            .tcp
+            .lock()
+            .listeners
+            .insert(local.port(), Arc::downgrade(&listener));
+        inner.state = State::Listening(listener);
+        Ok(())
+    }
+
+    /// Blocks until a connection is established, returning a socket for it and the address
+    /// of the peer
+    pub fn accept(&self) -> Result<(Arc<TcpSocket>, SocketAddrV4), NetError> {
+        let listener = match &self.inner.lock().state {
+            State::Listening(listener) => listener.clone(),
+            _ => return Err(NetError::NotListening),
+        };
+        let connection = listener.accept();
+        let remote = connection.remote_address();
+        let socket = Arc::new(TcpSocket {
+            inner: AtomicMutex::new(Inner {
+                local: Some(connection.local_address()),
+                state: State::Connected(connection),
+            }),
+        });
+        Ok((socket, remote))
+    }
+
+    /// Opens a connection to a peer, blocking until it is established
+    pub fn connect(self: &Arc<Self>, remote: SocketAddrV4) -> Result<(), NetError> {
+        let network = Supervisor::global().network();
+        let source = network.source_address(*remote.ip())?;
+        let bound = self.bind_ephemeral()?;
+        let connection = {
+            let mut inner = self.inner.lock();
+            match inner.state {
+                State::Idle => {}
+                State::Listening(_) => return Err(NetError::NotListening),
+                State::Connected(_) => return Err(NetError::AlreadyConnected),
+            }
+            let local = match *bound.ip() {
+                address if address.is_unspecified() => SocketAddrV4::new(source, bound.port()),
+                _ => bound,
+            };
+            let connection = Connection::connect(local, remote);
+            let mut table = network.tcp.lock();
+            if table.connections.contains_key(&(local, remote)) {
+                return Err(NetError::AddressInUse);
+            }
+            table
+                .connections
+                .insert((local, remote), connection.clone());
+            inner.local = Some(local);
+            inner.state = State::Connected(connection.clone());
+            connection
+        };
+        connection.flush();
+        let result = connection.wait_established();
+        if result.is_err() {
+            self.inner.lock().state = State::Idle;
+        }
+        result
+    }
+
+    fn connection(&self) -> Result<Arc<Connection>, NetError> {
+        match &self.inner.lock().state {
+            State::Connected(connection) => Ok(connection.clone()),
+            _ => Err(NetError::NotConnected),
+        }
+    }
+
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize, NetError> {
+        self.connection()?.read(buffer)
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<usize, NetError> {
+        self.connection()?.write(data)
+    }
+}
+
+impl Drop for TcpSocket {
+    fn drop(&mut self) {
+        let (local, state) = {
+            let mut inner = self.inner.lock();
+            (inner.local, mem::replace(&mut inner.state, State::Idle))
+        };
+        if let State::Connected(connection) = &state {
+            connection.close();
+        }
+        // a listener goes away along with the socket, unless a task still accepts from it
+        drop(state);
+        if let Some(local) = local {
+            let network = Supervisor::global().network();
+            network.tcp.lock().ports.release(local.port(), self);
+        }
+    }
+}
+
+impl Network {
+    /// Passes a segment sent to one of our addresses to its connection, opens a connection
+    /// for SYN sent to a listening socket, and resets connections which do not exist
+    pub(super) fn receive_tcp(&self, packet: &Ipv4Packet, segment: &TcpSegment) {
+        let local = SocketAddrV4::new(packet.destination, segment.destination_port);
+        let remote = SocketAddrV4::new(packet.source, segment.source_port);
+        let connection = self.tcp.lock().connections.get(&(local, remote)).cloned();
+        if let Some(connection) = connection {
+            connection.receive(segment);
+            return;
+        }
+
+        let flags = segment.flags;
+        if flags.contains(TcpFlags::SYN) && !flags.intersects(TcpFlags::ACK | TcpFlags::RST) {
+            let listener = self
+                .tcp
+                .lock()
+                .listeners
+                .get(&local.port())
+                .and_then(Weak::upgrade);
+            let listener = listener.filter(|listener| {
+                let address = *listener.local.ip();
+                address.is_unspecified() || address == *local.ip()
+            });
+            if let Some(listener) = listener {
+                // SYN is dropped when the backlog is full, the peer retries later
+                if listener.has_room() {
+                    let weak = Arc::downgrade(&listener);
+                    let connection = Connection::accept(local, remote, segment, weak);
+                    self.tcp
+                        .lock()
+                        .connections
+                        .insert((local, remote), connection.clone());
+                    connection.flush();
+                }
+                return;
+            }
+        }
+        if !flags.contains(TcpFlags::RST) {
+            connection::reset(local, remote, segment);
+        }
+    }
+
+    /// Handles timeouts of connections and forgets closed ones
+    pub(super) fn tick_tcp(&self) {
+        let connections: Vec<_> = self.tcp.lock().connections.values().cloned().collect();
+        for connection in &connections {
+            connection.tick();
+        }
+        self.tcp
+            .lock()
+            .connections
+            .retain(|_, connection| !connection.is_closed());
+    }
+}
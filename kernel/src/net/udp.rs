@@ -0,0 +1,177 @@
+//! UDP sockets, each receiving datagrams sent to the port it is bound to
+//!
+//! Sockets which send before being bound get a free ephemeral port. A connected socket only
+//! receives datagrams from its peer.
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::net::{Ipv4Addr, SocketAddrV4};
+
+use core_lib::sync::AtomicMutex;
+use packet::{ethernet, ipv4, ipv4::PROTOCOL_UDP, udp, Ipv4Packet, UdpDatagram};
+
+use crate::{task::wait_queue::WaitQueue, Supervisor};
+
+use super::{NetError, Network};
+
+/// Largest payload of a datagram, which is never fragmented
+pub const MAX_PAYLOAD: usize = ethernet::MTU - ipv4::HEADER_LENGTH - udp::HEADER_LENGTH;
+/// Most datagrams waiting to be received by a socket, later ones being dropped
+const MAX_QUEUED: usize = 64;
+
+struct Addresses {
+    local: Option<SocketAddrV4>,
+    /// Peer set by `connect`
+    remote: Option<SocketAddrV4>,
+}
+
+pub struct UdpSocket {
+    addresses: AtomicMutex<Addresses>,
+    /// Received datagrams along with their senders
+    received: AtomicMutex<VecDeque<(SocketAddrV4, Vec<u8>)>>,
+    readable: WaitQueue,
+}
+
+impl UdpSocket {
+    pub fn new() -> Arc<UdpSocket> {
+        Arc::new(UdpSocket {
+            addresses: AtomicMutex::new(Addresses {
+                local: None,
+                remote: None,
+            }),
+            received: AtomicMutex::new(VecDeque::new()),
+            readable: WaitQueue::new(),
+        })
+    }
+
+    /// Local address, which is unspecified with port 0 until the socket is bound
+    pub fn local_address(&self) -> SocketAddrV4 {
+        self.addresses
+            .lock()
+            .local
+            .unwrap_or(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))
+    }
+
+    /// Binds the socket to an address, which is either unspecified or that of an interface
+    pub fn bind(self: &Arc<Self>, address: SocketAddrV4) -> Result<(), NetError> {
+        let network = Supervisor::global().network();
+        if !address.ip().is_unspecified() && !network.is_local(*address.ip()) {
+            return Err(NetError::AddressNotAvailable);
+        }
+        let mut addresses = self.addresses.lock();
+        if addresses.local.is_some() {
+            return Err(NetError::AlreadyBound);
+        }
+        let port = network.udp.lock().bind(address.port(), self)?;
+        addresses.local = Some(SocketAddrV4::new(*address.ip(), port));
+        Ok(())
+    }
+
+    /// Sets the peer to which datagrams are sent by default, and from which only they are
+    /// received
+    pub fn connect(self: &Arc<Self>, remote: SocketAddrV4) -> Result<(), NetError> {
+        Supervisor::global()
+            .network()
+            .source_address(*remote.ip())?;
+        self.bind_ephemeral()?;
+        self.addresses.lock().remote = Some(remote);
+        self.received.lock().retain(|(source, _)| *source == remote);
+        Ok(())
+    }
+
+    /// Sends a datagram to an address, or to the connected peer if it is `None`
+    pub fn send_to(
+        self: &Arc<Self>,
+        data: &[u8],
+        destination: Option<SocketAddrV4>,
+    ) -> Result<usize, NetError> {
+        let destination = destination
+            .or(self.addresses.lock().remote)
+            .ok_or(NetError::DestinationRequired)?;
+        if data.len() > MAX_PAYLOAD {
+            return Err(NetError::MessageTooLong);
+        }
+        let local = self.bind_ephemeral()?;
+        let network = Supervisor::global().network();
+        let source = match *local.ip() {
+            address if address.is_unspecified() => network.source_address(*destination.ip())?,
+            address => address,
+        };
+        let datagram = UdpDatagram {
+            source_port: local.port(),
+            destination_port: destination.port(),
+            payload: data,
+        };
+        let bytes = datagram.to_bytes(source, *destination.ip());
+        network.send_ipv4(source, *destination.ip(), PROTOCOL_UDP, &bytes)?;
+        Ok(data.len())
+    }
+
+    /// Blocks until a datagram is received, copying as much of it as fits into the buffer.
+    /// Returns the copied length and the sender
+    pub fn receive_from(&self, buffer: &mut [u8]) -> (usize, SocketAddrV4) {
+        let mut datagram = None;
+        self.readable.wait_until(|| {
+            datagram = self.received.lock().pop_front();
+            datagram.is_some()
+        });
+        let (source, data) = datagram.unwrap();
+        let length = data.len().min(buffer.len());
+        buffer[..length].copy_from_slice(&data[..length]);
+        (length, source)
+    }
+
+    /// Binds the socket to an ephemeral port if it is not bound yet, returning its address
+    fn bind_ephemeral(self: &Arc<Self>) -> Result<SocketAddrV4, NetError> {
+        match self.bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)) {
+            Ok(()) | Err(NetError::AlreadyBound) => Ok(self.local_address()),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn deliver(&self, source: SocketAddrV4, data: &[u8]) {
+        if self
+            .addresses
+            .lock()
+            .remote
+            .is_some_and(|remote| remote != source)
+        {
+            return;
+        }
+        {
+            let mut received = self.received.lock();
+            if received.len() == MAX_QUEUED {
+                return;
+            }
+            received.push_back((source, Vec::from(data)));
+        }
+        self.readable.wake_all();
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        let Some(local) = self.addresses.lock().local else {
+            return;
+        };
+        let network = Supervisor::global().network();
+        network.udp.lock().release(local.port(), self);
+    }
+}
+
+impl Network {
+    /// Passes a received datagram to the socket bound to its port, returning false if there
+    /// is none
+    pub(super) fn receive_udp(&self, packet: &Ipv4Packet, datagram: &UdpDatagram) -> bool {
+        let socket = self.udp.lock().get(datagram.destination_port);
+        let Some(socket) = socket else {
+            return false;
+        };
+        let local = *socket.local_address().ip();
+        if !local.is_unspecified() && local != packet.destination {
+            return false;
+        }
+        let source = SocketAddrV4::new(packet.source, datagram.source_port);
+        socket.deliver(source, datagram.payload);
+        true
+    }
+}
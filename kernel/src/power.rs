@@ -0,0 +1,76 @@
+//! Machine poweroff and reboot
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    drivers::{
+        syscon::{SysconPoweroff, SysconReboot},
+        DeviceRegistry,
+    },
+    kdebug, sbi,
+    traps::wfi,
+    Supervisor,
+};
+
+/// Performs poweroff and reboot using SBI System Reset extension,
+/// falling back to syscon devices if firmware does not implement it
+pub struct PowerControl {
+    has_system_reset: AtomicBool,
+}
+
+impl PowerControl {
+    pub const fn new() -> PowerControl {
+        PowerControl {
+            has_system_reset: AtomicBool::new(false),
+        }
+    }
+
+    /// Probes SBI extension, has to be called after devices are probed
+    pub fn initialize(&self, devices: &DeviceRegistry) {
+        let has_system_reset =
+            unsafe { sbi::base::probe_extension(sbi::system_reset::SYSTEM_RESET_EID) }
+                .is_ok_and(|available| available != 0);
+        self.has_system_reset
+            .store(has_system_reset, Ordering::Relaxed);
+
+        kdebug!(
+            "Power control: SBI system reset {}, syscon poweroff {}, syscon reboot {}",
+            available(has_system_reset),
+            available(devices.find::<SysconPoweroff>().is_some()),
+            available(devices.find::<SysconReboot>().is_some()),
+        );
+    }
+
+    pub fn poweroff(&self) -> ! {
+        self.system_reset(sbi::system_reset::TYPE_SHUTDOWN);
+        if let Some(syscon) = Supervisor::global().devices().find::<SysconPoweroff>() {
+            syscon.poweroff();
+        }
+        kdebug!("Poweroff failed, halting");
+        wfi()
+    }
+
+    pub fn reboot(&self) -> ! {
+        self.system_reset(sbi::system_reset::TYPE_COLD_REBOOT);
+        if let Some(syscon) = Supervisor::global().devices().find::<SysconReboot>() {
+            syscon.reboot();
+        }
+        kdebug!("Reboot failed, halting");
+        wfi()
+    }
+
+    fn system_reset(&self, reset_type: u32) {
+        if self.has_system_reset.load(Ordering::Relaxed) {
+            // returns only on failure
+            let _ = unsafe { sbi::system_reset::reset(reset_type, sbi::system_reset::REASON_NONE) };
+        }
+    }
+}
+
+fn available(is_available: bool) -> &'static str {
+    if is_available {
+        "available"
+    } else {
+        "unavailable"
+    }
+}
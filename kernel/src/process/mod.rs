@@ -0,0 +1,472 @@
+//! User processes, each running in its own address space
+//!
+//! A process is created from an ELF executable linked to be loaded in the user part of
+//! address spaces. Its main task enters user mode at the ELF entrypoint, with a stack
+//! at the end of the user part holding the arguments.
+//!
+//! A process can fork, getting a child whose memory is shared with it copy-on-write, replace
+//! its program with another executable and wait for its children to exit.
+
+pub mod signal;
+pub mod syscall;
+pub mod trap;
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    fmt::Display,
+    mem,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use core_lib::{
+    sync::AtomicMutex,
+    syscall::{exited_status, Errno, SIGCHLD},
+};
+use elf::{
+    machine::Machine,
+    segment::{Segment, SegmentType},
+    Elf, ElfError,
+};
+use snafu::{ensure, OptionExt, Snafu};
+use vfs::{FileTable, OpenFlags};
+
+use signal::Signals;
+
+use crate::{
+    fs::{console::DebugConsole, CONSOLE_PATH},
+    ipc::handle::HandleTable,
+    kdebug,
+    memory::{
+        mapping::Mapping,
+        page::PAGE_SIZE,
+        paging::{self, AddressSpace, PageFlags, USER_END, USER_START},
+    },
+    task::{self, mutex::Mutex, wait_queue::WaitQueue, Task},
+    traps::without_interrupts,
+    Supervisor,
+};
+
+const USER_STACK_SIZE: usize = 64 * 1024;
+/// Initial stack pointer of the main task
+const USER_STACK_TOP: usize = USER_END;
+/// Part of the address space where `mmap` places mappings, below the stack
+const MMAP_START: usize = 0x30_0000_0000;
+const MMAP_END: usize = USER_STACK_TOP - USER_STACK_SIZE;
+/// Most arguments passed to a program
+pub const MAX_ARGUMENTS: usize = 64;
+/// Most bytes taken by arguments on the stack, along with pointers to them
+const MAX_ARGUMENTS_SIZE: usize = USER_STACK_SIZE / 2;
+
+/// Processes by their ids, for sending signals to them. Locked with interrupts disabled, as
+/// processes can be dropped by interrupt handlers
+static PROCESSES: AtomicMutex<BTreeMap<ProcessId, Weak<Process>>> =
+    AtomicMutex::new(BTreeMap::new());
+
+/// Reason for an executable not being loaded
+#[derive(Debug, Snafu)]
+pub enum LoadError {
+    #[snafu(display("Invaild ELF file: {reason}"))]
+    InvaildElf { reason: ElfError },
+    #[snafu(display("Executable is not a RISC-V one"))]
+    InvaildMachine,
+    #[snafu(display("Executable has no entrypoint"))]
+    MissingEntrypoint,
+    #[snafu(display("Segment at 0x{address:x} is outside of the user part of address space"))]
+    InvaildSegment { address: usize },
+    #[snafu(display("Arguments do not fit on the stack"))]
+    ArgumentsTooLong,
+}
+
+impl LoadError {
+    /// Error number reported to user programs
+    pub fn errno(&self) -> Errno {
+        match self {
+            LoadError::ArgumentsTooLong => Errno::E2BIG,
+            _ => Errno::ENOEXEC,
+        }
+    }
+}
+
+/// Registers which a loaded program starts with
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramStart {
+    pub entrypoint: usize,
+    pub stack_pointer: usize,
+    pub argc: usize,
+    /// Address of pointers to the arguments, followed by a null one
+    pub argv: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProcessId(usize);
+
+impl ProcessId {
+    pub fn as_usize(self) -> usize {
+        self.0
+    }
+}
+
+impl Display for ProcessId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+pub struct Process {
+    id: ProcessId,
+    /// Name of the executable, changed by `execve`
+    name: AtomicMutex<String>,
+    address_space: Mutex<AddressSpace>,
+    /// Kept outside of the lock, as it is needed on every task switch
+    satp: AtomicUsize,
+    files: Mutex<FileTable>,
+    handles: Mutex<HandleTable>,
+    parent: Option<Weak<Process>>,
+    /// Children which have not been waited for yet
+    children: AtomicMutex<Vec<Arc<Process>>>,
+    /// Wait status, set once the process exits
+    status: AtomicMutex<Option<i32>>,
+    /// Woken when a child exits
+    child_exited: WaitQueue,
+    /// Locked with interrupts disabled, as signals can be sent by interrupt handlers
+    signals: AtomicMutex<Signals>,
+    /// Woken when a stopped process is continued
+    continued: WaitQueue,
+}
+
+impl Process {
+    fn new(
+        name: &str,
+        address_space: AddressSpace,
+        files: FileTable,
+        signals: Signals,
+        parent: Option<&Arc<Process>>,
+    ) -> Arc<Process> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+        let process = Arc::new(Process {
+            id: ProcessId(NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+            name: AtomicMutex::new(String::from(name)),
+            satp: AtomicUsize::new(address_space.satp()),
+            address_space: Mutex::new(address_space),
+            files: Mutex::new(files),
+            handles: Mutex::new(HandleTable::new()),
+            parent: parent.map(Arc::downgrade),
+            children: AtomicMutex::new(Vec::new()),
+            status: AtomicMutex::new(None),
+            child_exited: WaitQueue::new(),
+            signals: AtomicMutex::new(signals),
+            continued: WaitQueue::new(),
+        });
+        let registered = Arc::downgrade(&process);
+        without_interrupts(|| PROCESSES.lock().insert(process.id, registered));
+        process
+    }
+
+    /// Finds a process by its id, also if it exited but was not waited for yet
+    pub fn find(id: usize) -> Option<Arc<Process>> {
+        without_interrupts(|| PROCESSES.lock().get(&ProcessId(id)).and_then(Weak::upgrade))
+    }
+
+    /// Creates a child with a copy-on-write copy of the address space, the same open files and
+    /// signal actions. IPC handles are not inherited
+    pub fn fork(self: &Arc<Self>) -> Arc<Process> {
+        let address_space = self.address_space.lock().fork();
+        let files = self.files.lock().clone();
+        let signals = without_interrupts(|| self.signals.lock().fork());
+        let child = Process::new(&self.name(), address_space, files, signals, Some(self));
+        without_interrupts(|| self.children.lock().push(child.clone()));
+        child
+    }
+
+    /// Replaces the address space with a new one holding an executable, which has to be done
+    /// by a task of the process. Old address space is kept if the executable cannot be loaded.
+    /// Signals with handlers get their default actions back
+    pub fn exec(&self, name: &str, image: &[u8], args: &[&str]) -> Result<ProgramStart, LoadError> {
+        let (address_space, start) = load(image, args)?;
+        let mut old = {
+            let mut current = self.address_space.lock();
+            without_interrupts(|| {
+                let old = mem::replace(&mut *current, address_space);
+                self.satp.store(current.satp(), Ordering::Relaxed);
+                // SAFETY: new address space maps the kernel and is kept alive by the process
+                unsafe { paging::activate(current.satp()) };
+                old
+            })
+        };
+        old.unmap_all();
+        without_interrupts(|| self.signals.lock().reset_handlers());
+        *self.name.lock() = String::from(name);
+        Ok(start)
+    }
+
+    /// Reaps an exited child, either a given one or any, returning its id and wait status.
+    /// Without blocking, `None` is returned if no matching child has exited yet
+    pub fn wait(&self, pid: Option<usize>, block: bool) -> Result<Option<(ProcessId, i32)>, Errno> {
+        let mut reaped = Ok(None);
+        let mut reap = || {
+            let mut children = self.children.lock();
+            let mut matching = children
+                .iter()
+                .filter(|child| pid.is_none_or(|pid| child.id.0 == pid))
+                .peekable();
+            if matching.peek().is_none() {
+                reaped = Err(Errno::ECHILD);
+                return true;
+            }
+            let exited = matching.find_map(|child| Some((child.id, (*child.status.lock())?)));
+            let Some((id, status)) = exited else {
+                return false;
+            };
+            // freed outside of the condition, with interrupts enabled
+            let index = children.iter().position(|child| child.id == id).unwrap();
+            reaped = Ok(Some((children.remove(index), status)));
+            true
+        };
+        if block {
+            self.child_exited.wait_until(reap);
+        } else {
+            without_interrupts(|| {
+                reap();
+            });
+        }
+        reaped.map(|reaped| reaped.map(|(child, status)| (child.id, status)))
+    }
+
+    pub fn id(&self) -> ProcessId {
+        self.id
+    }
+
+    pub fn address_space(&self) -> &Mutex<AddressSpace> {
+        &self.address_space
+    }
+
+    pub fn files(&self) -> &Mutex<FileTable> {
+        &self.files
+    }
+
+    /// Handles to IPC objects
+    pub fn handles(&self) -> &Mutex<HandleTable> {
+        &self.handles
+    }
+
+    /// Value of `satp` register selecting the address space of the process
+    pub fn satp(&self) -> usize {
+        self.satp.load(Ordering::Relaxed)
+    }
+
+    pub fn name(&self) -> String {
+        self.name.lock().clone()
+    }
+
+    /// Copies memory of the process into a buffer
+    pub fn copy_from_user(&self, address: usize, buffer: &mut [u8]) -> Result<(), Errno> {
+        self.address_space
+            .lock()
+            .copy_from_user(address, buffer)
+            .map_err(|_| Errno::EFAULT)
+    }
+
+    /// Copies data into memory of the process
+    pub fn copy_to_user(&self, address: usize, data: &[u8]) -> Result<(), Errno> {
+        self.address_space
+            .lock()
+            .copy_to_user(address, data)
+            .map_err(|_| Errno::EFAULT)
+    }
+
+    /// Reads a null-terminated string of at most `max_length` bytes from memory of the process
+    pub fn read_string(&self, address: usize, max_length: usize) -> Result<String, Errno> {
+        let mut bytes = Vec::new();
+        let mut current = address;
+        while bytes.len() < max_length {
+            // reading up to the end of a page cannot fault if the string ends before it
+            let chunk_length = (PAGE_SIZE - current % PAGE_SIZE).min(max_length - bytes.len());
+            let start = bytes.len();
+            bytes.resize(start + chunk_length, 0);
+            self.copy_from_user(current, &mut bytes[start..])?;
+            if let Some(end) = bytes[start..].iter().position(|byte| *byte == 0) {
+                bytes.truncate(start + end);
+                return String::from_utf8(bytes).map_err(|_| Errno::EINVAL);
+            }
+            current += chunk_length;
+        }
+        Err(Errno::ENAMETOOLONG)
+    }
+
+    /// Places a mapping of `length` bytes in the part of the address space used by `mmap`
+    pub fn map(&self, length: usize, mapping: Mapping) -> Result<usize, Errno> {
+        self.address_space
+            .lock()
+            .map(MMAP_START..MMAP_END, length, mapping)
+            .ok_or(Errno::ENOMEM)
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        without_interrupts(|| PROCESSES.lock().remove(&self.id));
+    }
+}
+
+impl Display for Process {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} (pid {})", *self.name.lock(), self.id)
+    }
+}
+
+/// Table with the console open as standard input, output and error
+fn standard_files() -> FileTable {
+    let console = Supervisor::global()
+        .vfs()
+        .open(CONSOLE_PATH, OpenFlags::READ_WRITE, 0)
+        .unwrap_or_else(|_| DebugConsole::open());
+    let mut files = FileTable::new();
+    for _ in 0..3 {
+        files.insert(console.clone()).unwrap();
+    }
+    files
+}
+
+/// Maps pages of a loadable segment, copying its data and zeroing the rest of its memory
+fn load_segment(address_space: &mut AddressSpace, segment: &Segment) -> Result<(), LoadError> {
+    let start = segment.vaddr();
+    let data = segment.data();
+    let end = start
+        .checked_add(segment.memory_size())
+        .filter(|end| *end <= MMAP_START)
+        .context(InvaildSegmentSnafu { address: start })?;
+    ensure!(
+        start >= USER_START && data.len() <= end - start,
+        InvaildSegmentSnafu { address: start }
+    );
+    let data_end = start + data.len();
+
+    let mut flags = PageFlags::empty();
+    flags.set(PageFlags::READABLE, segment.readable());
+    flags.set(PageFlags::WRITEABLE, segment.writeable());
+    flags.set(PageFlags::EXECUTABLE, segment.executable());
+
+    let first_page = start - start % PAGE_SIZE;
+    for page in (first_page..end).step_by(PAGE_SIZE) {
+        let contents = address_space.map_page(page, flags);
+        let from = start.max(page);
+        let to = end.min(page + PAGE_SIZE);
+        let copied_to = data_end.clamp(from, to);
+        contents[from - page..copied_to - page]
+            .copy_from_slice(&data[from - start..copied_to - start]);
+        // bss part
+        contents[copied_to - page..to - page].fill(0);
+    }
+    Ok(())
+}
+
+/// Creates an address space with an executable loaded and arguments on the stack
+fn load(image: &[u8], args: &[&str]) -> Result<(AddressSpace, ProgramStart), LoadError> {
+    let elf = Elf::from_bytes(image).map_err(|reason| LoadError::InvaildElf { reason })?;
+    ensure!(elf.machine() == Machine::RISCV, InvaildMachineSnafu);
+    let entrypoint = elf.entrypoint().context(MissingEntrypointSnafu)?;
+
+    let mut address_space = AddressSpace::new();
+    for segment in elf.segments() {
+        if let SegmentType::Load = segment.segment_type() {
+            load_segment(&mut address_space, &segment)?;
+        }
+    }
+    let stack_flags = PageFlags::READABLE | PageFlags::WRITEABLE;
+    for page in (USER_STACK_TOP - USER_STACK_SIZE..USER_STACK_TOP).step_by(PAGE_SIZE) {
+        address_space.map_page(page, stack_flags);
+    }
+
+    let (stack_pointer, argv) = push_arguments(&mut address_space, args)?;
+    let start = ProgramStart {
+        entrypoint,
+        stack_pointer,
+        argc: args.len(),
+        argv,
+    };
+    Ok((address_space, start))
+}
+
+/// Places arguments at the top of the stack, returning the stack pointer and the address of
+/// pointers to them. The stack pointer points at `argc`, followed by the pointers, a null
+/// one and an empty environment, as in the System V ABI
+fn push_arguments(
+    address_space: &mut AddressSpace,
+    args: &[&str],
+) -> Result<(usize, usize), LoadError> {
+    let words = args.len() + 3;
+    let strings: usize = args.iter().map(|arg| arg.len() + 1).sum();
+    ensure!(
+        args.len() <= MAX_ARGUMENTS && strings + words * size_of::<usize>() <= MAX_ARGUMENTS_SIZE,
+        ArgumentsTooLongSnafu
+    );
+
+    let mut top = USER_STACK_TOP;
+    let mut stack = Vec::with_capacity(words);
+    stack.push(args.len());
+    for arg in args {
+        top -= arg.len() + 1;
+        write_stack(address_space, top, arg.as_bytes());
+        write_stack(address_space, top + arg.len(), &[0]);
+        stack.push(top);
+    }
+    // null terminators of argv and envp
+    stack.extend([0, 0]);
+
+    let stack_pointer = (top - words * size_of::<usize>()) & !0xf;
+    for (index, word) in stack.iter().enumerate() {
+        let address = stack_pointer + index * size_of::<usize>();
+        write_stack(address_space, address, &word.to_ne_bytes());
+    }
+    Ok((stack_pointer, stack_pointer + size_of::<usize>()))
+}
+
+fn write_stack(address_space: &mut AddressSpace, address: usize, data: &[u8]) {
+    address_space
+        .copy_to_user(address, data)
+        .expect("stack pages are mapped writeable");
+}
+
+/// Starts a process running an executable, whose main task is returned
+pub fn spawn(name: &str, image: &[u8], args: &[&str]) -> Result<Arc<Task>, LoadError> {
+    let (address_space, start) = load(image, args)?;
+    let process = Process::new(name, address_space, standard_files(), Signals::new(), None);
+    kdebug!("Starting {}", process);
+    Ok(task::spawn_in(name, Some(process), move || {
+        trap::enter_user(&start)
+    }))
+}
+
+/// Ends the current task, which has to belong to a process. Shared file mappings are written
+/// back and files are closed first, and the parent is told about the exit
+pub fn exit(code: i32) -> ! {
+    terminate(exited_status(code), code)
+}
+
+/// Ends the current task of a process like [`exit`], reporting a wait status to the parent
+/// and exiting the task with `code`
+fn terminate(status: i32, code: i32) -> ! {
+    let process = task::current()
+        .process()
+        .cloned()
+        .expect("exit of a kernel task");
+    process.address_space.lock().unmap_all();
+    // closing pipes lets their readers see the end
+    drop(mem::take(&mut *process.files.lock()));
+    drop(mem::take(&mut *process.handles.lock()));
+    // stops the timer of a pending alarm, which the process would not get anymore
+    process.set_alarm(0);
+    without_interrupts(|| *process.status.lock() = Some(status));
+    if let Some(parent) = process.parent.as_ref().and_then(Weak::upgrade) {
+        parent.send_signal(SIGCHLD);
+        parent.child_exited.wake_all();
+    }
+    drop(process);
+    task::exit(code)
+}
@@ -0,0 +1,405 @@
+//! Signals, notifying processes of events asynchronously
+//!
+//! A sent signal stays pending until the process returns to user mode with the signal not
+//! blocked by its mask. Then the signal is ignored, its default action is taken or the
+//! handler installed for it is entered, with registers saved in a frame on the user stack
+//! which `rt_sigreturn` restores. System calls blocked in the kernel are not interrupted,
+//! except for reads from the terminal, which fail when an interrupt character is typed.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{mem, time::Duration};
+
+use core_lib::syscall::{
+    sigmask, signaled_status, Errno, SigAction, SigSet, NSIG, SA_NODEFER, SA_RESETHAND,
+    SA_RESTORER, SIGABRT, SIGALRM, SIGBUS, SIGCHLD, SIGCONT, SIGFPE, SIGILL, SIGKILL, SIGQUIT,
+    SIGSEGV, SIGSTOP, SIGTRAP, SIGTSTP, SIG_BLOCK, SIG_DFL, SIG_IGN, SIG_SETMASK, SIG_UNBLOCK,
+};
+
+use crate::{
+    kdebug,
+    task::{self, scheduler, Task},
+    time::Instant,
+    traps::without_interrupts,
+    Supervisor,
+};
+
+use super::{trap::UserFrame, Process};
+
+/// Signals which cannot be caught, ignored or blocked
+const UNCATCHABLE: SigSet = sigmask(SIGKILL) | sigmask(SIGSTOP);
+const STOP_SIGNALS: SigSet = sigmask(SIGSTOP) | sigmask(SIGTSTP);
+/// Words of a signal frame: `x0`-`x31`, `sepc` and the signal mask to restore
+const FRAME_WORDS: usize = 34;
+const RA: usize = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DefaultAction {
+    Terminate,
+    /// Terminates, dumping registers to the kernel log
+    Core,
+    Stop,
+    Continue,
+    Ignore,
+}
+
+fn default_action(signal: usize) -> DefaultAction {
+    match signal {
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV => DefaultAction::Core,
+        SIGSTOP | SIGTSTP => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        SIGCHLD => DefaultAction::Ignore,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// Signal state of a process
+pub struct Signals {
+    pending: SigSet,
+    /// Blocked signals, which stay pending until they are unblocked
+    mask: SigSet,
+    /// Actions by signal numbers minus one
+    actions: [SigAction; NSIG],
+    /// Whether the process is stopped until it gets `SIGCONT` or `SIGKILL`
+    stopped: bool,
+    alarm: Option<Instant>,
+    /// Number of alarms set so far, telling the latest one from those replaced since
+    alarms: u64,
+    /// Task sending `SIGALRM` for the latest alarm, woken to exit once it is replaced
+    alarm_timer: Option<Arc<Task>>,
+}
+
+impl Signals {
+    pub fn new() -> Signals {
+        Signals {
+            pending: 0,
+            mask: 0,
+            actions: [SigAction::default(); NSIG],
+            stopped: false,
+            alarm: None,
+            alarms: 0,
+            alarm_timer: None,
+        }
+    }
+
+    /// State of a child made by `fork`, which keeps actions and the mask, but not pending
+    /// signals or the alarm
+    pub fn fork(&self) -> Signals {
+        Signals {
+            actions: self.actions,
+            mask: self.mask,
+            ..Signals::new()
+        }
+    }
+
+    /// Restores default actions of signals with handlers, which are gone after `execve`
+    pub fn reset_handlers(&mut self) {
+        for action in &mut self.actions {
+            if action.handler != SIG_IGN {
+                *action = SigAction::default();
+            }
+        }
+    }
+
+    fn action(&self, signal: usize) -> SigAction {
+        self.actions[signal - 1]
+    }
+
+    fn is_ignored(&self, signal: usize) -> bool {
+        match self.action(signal).handler {
+            SIG_IGN => true,
+            SIG_DFL => default_action(signal) == DefaultAction::Ignore,
+            _ => false,
+        }
+    }
+
+    /// Marks a signal pending, returning whether it continued a stopped process. Ignored
+    /// signals are dropped, unless they are blocked and could be handled once unblocked
+    fn send(&mut self, signal: usize) -> bool {
+        let mut continued = false;
+        if signal == SIGKILL || signal == SIGCONT {
+            self.pending &= !STOP_SIGNALS;
+            continued = mem::replace(&mut self.stopped, false);
+        }
+        if STOP_SIGNALS & sigmask(signal) != 0 {
+            self.pending &= !sigmask(SIGCONT);
+        }
+        if !self.is_ignored(signal) || self.mask & sigmask(signal) != 0 {
+            self.pending |= sigmask(signal);
+        }
+        continued
+    }
+
+    /// Takes the unblocked pending signal with the lowest number, along with its action.
+    /// Process is marked stopped right away for stop signals, so that `SIGCONT` sent before
+    /// it actually stops is not missed
+    fn take(&mut self) -> Option<(usize, SigAction)> {
+        let deliverable = self.pending & !self.mask;
+        if deliverable == 0 {
+            return None;
+        }
+        let signal = deliverable.trailing_zeros() as usize + 1;
+        self.pending &= !sigmask(signal);
+        let action = self.action(signal);
+        if action.handler == SIG_DFL && default_action(signal) == DefaultAction::Stop {
+            self.stopped = true;
+        }
+        Some((signal, action))
+    }
+}
+
+impl Process {
+    /// Sends a signal, which is delivered when the process next returns to user mode. Can be
+    /// called from interrupt handlers
+    pub fn send_signal(&self, signal: usize) {
+        let continued = without_interrupts(|| self.signals.lock().send(signal));
+        if continued {
+            self.continued.wake_all();
+        }
+    }
+
+    /// Sends a signal caused by the process itself, e.g. by a fault, which cannot be blocked
+    /// or ignored, as the process would not get past its cause
+    pub fn force_signal(&self, signal: usize) {
+        without_interrupts(|| {
+            let mut signals = self.signals.lock();
+            signals.mask &= !sigmask(signal);
+            if signals.action(signal).handler == SIG_IGN {
+                signals.actions[signal - 1] = SigAction::default();
+            }
+            signals.pending |= sigmask(signal);
+        });
+    }
+
+    /// Whether a signal can be delivered on return to user mode
+    pub fn has_deliverable_signal(&self) -> bool {
+        without_interrupts(|| {
+            let signals = self.signals.lock();
+            signals.pending & !signals.mask != 0
+        })
+    }
+
+    /// Installs an action for a signal, if one is given, returning the previous action
+    pub fn signal_action(
+        &self,
+        signal: usize,
+        action: Option<SigAction>,
+    ) -> Result<SigAction, Errno> {
+        if !(1..=NSIG).contains(&signal) {
+            return Err(Errno::EINVAL);
+        }
+        let Some(action) = action else {
+            return Ok(without_interrupts(|| self.signals.lock().action(signal)));
+        };
+        let is_handler = action.handler != SIG_DFL && action.handler != SIG_IGN;
+        if UNCATCHABLE & sigmask(signal) != 0 || (is_handler && action.flags & SA_RESTORER == 0) {
+            return Err(Errno::EINVAL);
+        }
+        Ok(without_interrupts(|| {
+            let mut signals = self.signals.lock();
+            let previous = mem::replace(&mut signals.actions[signal - 1], action);
+            // pending signals which became ignored are dropped
+            if signals.is_ignored(signal) {
+                signals.pending &= !sigmask(signal);
+            }
+            previous
+        }))
+    }
+
+    /// Changes the signal mask with `SIG_*` operation if a set is given, returning the
+    /// previous mask
+    pub fn signal_mask(&self, how: usize, set: Option<SigSet>) -> Result<SigSet, Errno> {
+        if ![SIG_BLOCK, SIG_UNBLOCK, SIG_SETMASK].contains(&how) {
+            return Err(Errno::EINVAL);
+        }
+        Ok(without_interrupts(|| {
+            let mut signals = self.signals.lock();
+            let previous = signals.mask;
+            if let Some(set) = set {
+                let mask = match how {
+                    SIG_BLOCK => previous | set,
+                    SIG_UNBLOCK => previous & !set,
+                    _ => set,
+                };
+                signals.mask = mask & !UNCATCHABLE;
+            }
+            previous
+        }))
+    }
+
+    /// Sends `SIGALRM` after a number of seconds, replacing the alarm set before, or only
+    /// cancels it for 0. Returns seconds which were left of the previous alarm, rounded up
+    pub fn set_alarm(&self, seconds: u64) -> u64 {
+        let now = Supervisor::global().clock().now();
+        // alarms too far in the future to represent never go off
+        let deadline = (seconds > 0).then(|| {
+            now.checked_add(Duration::from_secs(seconds))
+                .unwrap_or(Instant::MAX)
+        });
+        let (previous, number, replaced) = without_interrupts(|| {
+            let mut signals = self.signals.lock();
+            signals.alarms += 1;
+            let previous = mem::replace(&mut signals.alarm, deadline);
+            (previous, signals.alarms, signals.alarm_timer.take())
+        });
+        if let Some(replaced) = replaced {
+            scheduler::wake_sleeper(replaced);
+        }
+
+        if let Some(deadline) = deadline {
+            let id = self.id.as_usize();
+            let timer = task::spawn("alarm", move || {
+                scheduler::sleep_until_unless(deadline, || {
+                    Process::find(id).is_none_or(|process| !process.is_alarm_current(number))
+                });
+                if let Some(process) = Process::find(id) {
+                    process.ring_alarm(number);
+                }
+                0
+            });
+            without_interrupts(|| {
+                let mut signals = self.signals.lock();
+                if signals.alarms == number {
+                    signals.alarm_timer = Some(timer);
+                }
+            });
+        }
+        previous.map_or(0, |previous| {
+            let left = previous - now;
+            left.as_secs()
+                .saturating_add(u64::from(left.subsec_nanos() > 0))
+        })
+    }
+
+    fn is_alarm_current(&self, number: u64) -> bool {
+        without_interrupts(|| self.signals.lock().alarms == number)
+    }
+
+    /// Sends `SIGALRM` for an alarm which went off, unless it was replaced
+    fn ring_alarm(&self, number: u64) {
+        let current = without_interrupts(|| {
+            let mut signals = self.signals.lock();
+            let current = signals.alarms == number && signals.alarm.is_some();
+            if current {
+                signals.alarm = None;
+                signals.alarm_timer = None;
+            }
+            current
+        });
+        if current {
+            self.send_signal(SIGALRM);
+        }
+    }
+
+    /// Blocks the current task until the process is continued
+    fn stop(&self, signal: usize) {
+        kdebug!("{}: stopped by signal {}", self, signal);
+        self.continued
+            .wait_until(|| without_interrupts(|| !self.signals.lock().stopped));
+        kdebug!("{}: continued", self);
+    }
+
+    /// Saves registers in a signal frame on the user stack and makes them enter the handler
+    fn enter_handler(
+        &self,
+        frame: &mut UserFrame,
+        signal: usize,
+        action: SigAction,
+    ) -> Result<(), Errno> {
+        let mask = without_interrupts(|| self.signals.lock().mask);
+        let mut saved = [0; FRAME_WORDS];
+        saved[..32].copy_from_slice(&frame.registers);
+        saved[32] = frame.sepc;
+        saved[33] = mask as usize;
+        let bytes: Vec<u8> = saved.iter().flat_map(|word| word.to_ne_bytes()).collect();
+        let address = frame.registers[UserFrame::SP]
+            .checked_sub(bytes.len())
+            .ok_or(Errno::EFAULT)?
+            & !0xf;
+        self.copy_to_user(address, &bytes)?;
+
+        without_interrupts(|| {
+            let mut signals = self.signals.lock();
+            signals.mask |= action.mask & !UNCATCHABLE;
+            if action.flags & SA_NODEFER == 0 {
+                signals.mask |= sigmask(signal);
+            }
+            if action.flags & SA_RESETHAND != 0 {
+                signals.actions[signal - 1] = SigAction::default();
+            }
+        });
+        frame.registers[UserFrame::SP] = address;
+        frame.registers[UserFrame::A0] = signal;
+        frame.registers[RA] = action.restorer;
+        frame.sepc = action.handler;
+        Ok(())
+    }
+}
+
+/// Delivers pending signals of the current task's process before it returns to user mode
+/// with given registers. At most one handler is entered, the rest of signals being delivered
+/// once it makes a system call or is interrupted
+pub fn deliver(frame: &mut UserFrame) {
+    let Some(process) = task::current().process().cloned() else {
+        return;
+    };
+    while let Some((signal, action)) = without_interrupts(|| process.signals.lock().take()) {
+        match action.handler {
+            SIG_IGN => {}
+            SIG_DFL => match default_action(signal) {
+                DefaultAction::Ignore | DefaultAction::Continue => {}
+                DefaultAction::Stop => process.stop(signal),
+                DefaultAction::Terminate => terminate(process, signal, None),
+                DefaultAction::Core => terminate(process, signal, Some(frame)),
+            },
+            _ => match process.enter_handler(frame, signal, action) {
+                Ok(()) => return,
+                // the stack cannot take the frame
+                Err(_) if signal == SIGSEGV => terminate(process, signal, Some(frame)),
+                Err(_) => process.force_signal(SIGSEGV),
+            },
+        }
+    }
+}
+
+/// Restores registers and the signal mask saved in the signal frame at the stack pointer,
+/// when a handler returns through its restorer
+pub fn sigreturn(frame: &mut UserFrame) {
+    let process = task::current()
+        .process()
+        .cloned()
+        .expect("system call made by a kernel task");
+    let mut bytes = [0; FRAME_WORDS * size_of::<usize>()];
+    if process
+        .copy_from_user(frame.registers[UserFrame::SP], &mut bytes)
+        .is_err()
+    {
+        process.force_signal(SIGSEGV);
+        return;
+    }
+    let mut saved = bytes
+        .chunks_exact(size_of::<usize>())
+        .map(|word| usize::from_ne_bytes(word.try_into().unwrap()));
+    // x0 is not restored
+    saved.next();
+    for (register, value) in frame.registers[1..].iter_mut().zip(&mut saved) {
+        *register = value;
+    }
+    frame.sepc = saved.next().unwrap();
+    let mask = saved.next().unwrap() as SigSet;
+    without_interrupts(|| process.signals.lock().mask = mask & !UNCATCHABLE);
+}
+
+/// Ends the process because of a signal, dumping registers first if they are given
+fn terminate(process: Arc<Process>, signal: usize, core: Option<&UserFrame>) -> ! {
+    match core {
+        Some(frame) => kdebug!(
+            "{}: terminated by signal {}, registers: {:x?}",
+            process,
+            signal,
+            frame
+        ),
+        None => kdebug!("{}: terminated by signal {}", process, signal),
+    }
+    drop(process);
+    super::terminate(signaled_status(signal, core.is_some()), 128 + signal as i32)
+}
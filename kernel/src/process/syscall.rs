@@ -0,0 +1,395 @@
+//! System calls made by user programs with `ecall`, see [`core_lib::syscall`] for the ABI
+
+use alloc::{string::String, vec::Vec};
+use core::time::Duration;
+
+use core_lib::syscall::{self, encode_result, Errno, SigAction, SigSet, Timespec, NSIG};
+use vfs::FileType;
+
+use crate::{
+    fs::{self as filesystem, syscall as fs},
+    ipc::syscall as ipc,
+    kdebug,
+    memory::{
+        mapping::Mapping,
+        object::VmObject,
+        page::PAGE_SIZE,
+        paging::{PageFlags, USER_END, USER_START},
+    },
+    net::syscall as net,
+    task, Supervisor,
+};
+
+use super::{
+    signal,
+    trap::{self, UserFrame},
+    Process, MAX_ARGUMENTS,
+};
+
+const ALL_PERMISSIONS: PageFlags = PageFlags::READABLE
+    .union(PageFlags::WRITEABLE)
+    .union(PageFlags::EXECUTABLE);
+
+const A0: usize = 10;
+const A7: usize = 17;
+/// Longest argument passed to `execve`
+const ARGUMENT_MAX: usize = 4096;
+
+pub type Arguments = [usize; 6];
+type Handler = fn(&Process, Arguments) -> Result<usize, Errno>;
+
+/// Handlers by system call numbers
+const HANDLERS: &[(usize, Handler)] = &[
+    (syscall::IOCTL, fs::ioctl),
+    (syscall::OPENAT, fs::openat),
+    (syscall::CLOSE, fs::close),
+    (syscall::PIPE2, fs::pipe2),
+    (syscall::GETDENTS64, fs::getdents64),
+    (syscall::LSEEK, fs::lseek),
+    (syscall::READ, fs::read),
+    (syscall::WRITE, fs::write),
+    (syscall::NEWFSTATAT, fs::newfstatat),
+    (syscall::FSTAT, fs::fstat),
+    (syscall::SYNC, fs::sync),
+    (syscall::NANOSLEEP, nanosleep),
+    (syscall::SCHED_YIELD, sched_yield),
+    (syscall::KILL, kill),
+    (syscall::RT_SIGACTION, rt_sigaction),
+    (syscall::RT_SIGPROCMASK, rt_sigprocmask),
+    (syscall::GETPID, getpid),
+    (syscall::SOCKET, net::socket),
+    (syscall::BIND, net::bind),
+    (syscall::LISTEN, net::listen),
+    (syscall::ACCEPT, net::accept),
+    (syscall::CONNECT, net::connect),
+    (syscall::GETSOCKNAME, net::getsockname),
+    (syscall::SENDTO, net::sendto),
+    (syscall::RECVFROM, net::recvfrom),
+    (syscall::MUNMAP, munmap),
+    (syscall::CLONE, clone),
+    (syscall::EXECVE, execve),
+    (syscall::MMAP, mmap),
+    (syscall::MPROTECT, mprotect),
+    (syscall::WAIT4, wait4),
+    (syscall::ENDPOINT_CREATE, ipc::endpoint_create),
+    (syscall::HANDLE_DUPLICATE, ipc::handle_duplicate),
+    (syscall::HANDLE_CLOSE, ipc::handle_close),
+    (syscall::IPC_SEND, ipc::ipc_send),
+    (syscall::IPC_RECEIVE, ipc::ipc_receive),
+    (syscall::IPC_REPLY, ipc::ipc_reply),
+    (syscall::MEMORY_CREATE, ipc::memory_create),
+    (syscall::MEMORY_MAP, ipc::memory_map),
+    (syscall::ALARM, alarm),
+];
+
+/// Handles a system call of the current task, storing its result in `a0` of the frame
+pub fn handle(frame: &mut UserFrame) {
+    let number = frame.registers[A7];
+    let arguments: Arguments = frame.registers[A0..A0 + 6].try_into().unwrap();
+    // exit does not return, so it cannot have anything borrowed
+    if number == syscall::EXIT {
+        super::exit(arguments[0] as i32);
+    }
+    // restores all registers, including a0
+    if number == syscall::RT_SIGRETURN {
+        signal::sigreturn(frame);
+        return;
+    }
+
+    let process = task::current()
+        .process()
+        .cloned()
+        .expect("system call made by a kernel task");
+    let result = match HANDLERS.iter().find(|(n, _)| *n == number) {
+        Some((_, handler)) => handler(&process, arguments),
+        None => {
+            kdebug!("{}: unknown system call {}", process, number);
+            Err(Errno::ENOSYS)
+        }
+    };
+    frame.registers[A0] = encode_result(result);
+}
+
+fn nanosleep(process: &Process, [request, remaining, ..]: Arguments) -> Result<usize, Errno> {
+    let mut bytes = [0u8; size_of::<Timespec>()];
+    process.copy_from_user(request, &mut bytes)?;
+    let (seconds, nanoseconds) = bytes.split_at(size_of::<i64>());
+    let seconds = i64::from_le_bytes(seconds.try_into().unwrap());
+    let nanoseconds = i64::from_le_bytes(nanoseconds.try_into().unwrap());
+    if seconds < 0 || !(0..1_000_000_000).contains(&nanoseconds) {
+        return Err(Errno::EINVAL);
+    }
+
+    task::sleep(Duration::new(seconds as u64, nanoseconds as u32));
+    // sleep is never interrupted, so nothing remains
+    if remaining != 0 {
+        process.copy_to_user(remaining, &[0; size_of::<Timespec>()])?;
+    }
+    Ok(0)
+}
+
+fn sched_yield(_process: &Process, _arguments: Arguments) -> Result<usize, Errno> {
+    task::yield_now();
+    Ok(0)
+}
+
+fn getpid(process: &Process, _arguments: Arguments) -> Result<usize, Errno> {
+    Ok(process.id().as_usize())
+}
+
+/// Forks the calling process, whose child starts with the registers of the calling task
+fn clone(_process: &Process, [flags, stack, ..]: Arguments) -> Result<usize, Errno> {
+    if (flags != syscall::SIGCHLD && flags != 0) || stack != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let task = task::current();
+    let process = task.process().expect("system call made by a kernel task");
+    let frame = task.user_frame().expect("system call made on a boot stack");
+    // SAFETY: frame of the current task holds registers saved by the system call
+    let mut registers = unsafe { (*frame).clone() };
+    registers.registers[A0] = 0;
+
+    let child = process.fork();
+    let pid = child.id().as_usize();
+    task::spawn_in(&child.name(), Some(child), move || {
+        trap::resume_user(registers)
+    });
+    Ok(pid)
+}
+
+/// Replaces the program of the calling process. The environment is ignored
+fn execve(process: &Process, [path, argv, _envp, ..]: Arguments) -> Result<usize, Errno> {
+    let path = fs::read_path(process, syscall::AT_FDCWD, path)?;
+    let args = read_arguments(process, argv)?;
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let image =
+        filesystem::read_file(Supervisor::global().vfs(), &path).map_err(|error| error.errno())?;
+
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let start = process
+        .exec(name, &image, &args)
+        .map_err(|error| error.errno())?;
+    let frame = task::current()
+        .user_frame()
+        .expect("system call made on a boot stack");
+    // SAFETY: frame of the current task is only used by it, and restored on return
+    unsafe { *frame = UserFrame::program(&start) };
+    // a0 is set to the result, which is argc
+    Ok(start.argc)
+}
+
+/// Reads a null-terminated array of pointers to null-terminated strings
+fn read_arguments(process: &Process, address: usize) -> Result<Vec<String>, Errno> {
+    let mut args = Vec::new();
+    if address == 0 {
+        return Ok(args);
+    }
+    loop {
+        let mut pointer = [0; size_of::<usize>()];
+        let offset = args.len() * size_of::<usize>();
+        process.copy_from_user(
+            address.checked_add(offset).ok_or(Errno::EFAULT)?,
+            &mut pointer,
+        )?;
+        let pointer = usize::from_ne_bytes(pointer);
+        if pointer == 0 {
+            return Ok(args);
+        }
+        if args.len() == MAX_ARGUMENTS {
+            return Err(Errno::E2BIG);
+        }
+        let arg = process
+            .read_string(pointer, ARGUMENT_MAX)
+            .map_err(|error| match error {
+                Errno::ENAMETOOLONG => Errno::E2BIG,
+                error => error,
+            })?;
+        args.push(arg);
+    }
+}
+
+/// Waits for a child to exit, returning 0 if none has with `WNOHANG`. Resource usage is not
+/// reported
+fn wait4(process: &Process, [pid, status, options, ..]: Arguments) -> Result<usize, Errno> {
+    if options & !syscall::WNOHANG != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let pid = match pid as isize {
+        -1 => None,
+        pid if pid > 0 => Some(pid as usize),
+        _ => return Err(Errno::EINVAL),
+    };
+    let Some((child, code)) = process.wait(pid, options & syscall::WNOHANG == 0)? else {
+        return Ok(0);
+    };
+    if status != 0 {
+        process.copy_to_user(status, &code.to_ne_bytes())?;
+    }
+    Ok(child.as_usize())
+}
+
+/// Sends a signal to a process given by a positive id, as there are no process groups
+fn kill(_process: &Process, [pid, signal, ..]: Arguments) -> Result<usize, Errno> {
+    if pid as isize <= 0 || signal > NSIG {
+        return Err(Errno::EINVAL);
+    }
+    let target = Process::find(pid).ok_or(Errno::ESRCH)?;
+    if signal != 0 {
+        target.send_signal(signal);
+    }
+    Ok(0)
+}
+
+fn rt_sigaction(
+    process: &Process,
+    [signal, action, old, size, ..]: Arguments,
+) -> Result<usize, Errno> {
+    if size != size_of::<SigSet>() {
+        return Err(Errno::EINVAL);
+    }
+    let action = if action != 0 {
+        let mut bytes = [0u8; size_of::<SigAction>()];
+        process.copy_from_user(action, &mut bytes)?;
+        // SAFETY: any bytes make a valid SigAction, which is read unaligned
+        Some(unsafe { (bytes.as_ptr() as *const SigAction).read_unaligned() })
+    } else {
+        None
+    };
+    let previous = process.signal_action(signal, action)?;
+    if old != 0 {
+        // SAFETY: SigAction has no padding, so all of its bytes are initialized
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                &previous as *const SigAction as *const u8,
+                size_of::<SigAction>(),
+            )
+        };
+        process.copy_to_user(old, bytes)?;
+    }
+    Ok(0)
+}
+
+fn rt_sigprocmask(process: &Process, [how, set, old, size, ..]: Arguments) -> Result<usize, Errno> {
+    if size != size_of::<SigSet>() {
+        return Err(Errno::EINVAL);
+    }
+    let set = if set != 0 {
+        let mut bytes = [0u8; size_of::<SigSet>()];
+        process.copy_from_user(set, &mut bytes)?;
+        Some(SigSet::from_ne_bytes(bytes))
+    } else {
+        None
+    };
+    let previous = process.signal_mask(how, set)?;
+    if old != 0 {
+        process.copy_to_user(old, &previous.to_ne_bytes())?;
+    }
+    Ok(0)
+}
+
+fn alarm(process: &Process, [seconds, ..]: Arguments) -> Result<usize, Errno> {
+    Ok(process.set_alarm(seconds as u64) as usize)
+}
+
+/// Maps memory at an address chosen by the kernel, ignoring the hint. Pages are filled in
+/// when they are first accessed
+fn mmap(
+    process: &Process,
+    [_address, length, protection, flags, fd, offset]: Arguments,
+) -> Result<usize, Errno> {
+    let protection = protection_flags(protection)?;
+    let shared = match flags & !syscall::MAP_ANONYMOUS {
+        syscall::MAP_SHARED => true,
+        syscall::MAP_PRIVATE => false,
+        _ => return Err(Errno::EINVAL),
+    };
+    if length == 0 || !offset.is_multiple_of(PAGE_SIZE) {
+        return Err(Errno::EINVAL);
+    }
+
+    let mapping = if flags & syscall::MAP_ANONYMOUS != 0 {
+        if offset != 0 {
+            return Err(Errno::EINVAL);
+        }
+        if shared {
+            let object = VmObject::anonymous(length);
+            Mapping::object(object, 0, true, protection, ALL_PERMISSIONS)
+                .map_err(|error| error.errno())?
+        } else {
+            Mapping::anonymous(protection)
+        }
+    } else {
+        let file = process
+            .files()
+            .lock()
+            .get(fd)
+            .map_err(|error| error.errno())?;
+        if file.metadata().file_type != FileType::Regular {
+            return Err(Errno::ENODEV);
+        }
+        if !file.flags().readable() {
+            return Err(Errno::EACCES);
+        }
+        let mut maximum = ALL_PERMISSIONS;
+        // private mappings are copies, which can always be written
+        if shared && !file.flags().writeable() {
+            maximum.remove(PageFlags::WRITEABLE);
+        }
+        let object = VmObject::file(file.dentry());
+        Mapping::object(object, offset, shared, protection, maximum)
+            .map_err(|error| error.errno())?
+    };
+    process.map(length, mapping)
+}
+
+fn munmap(process: &Process, [address, length, ..]: Arguments) -> Result<usize, Errno> {
+    check_range(address, length)?;
+    process.address_space().lock().unmap(address, length);
+    Ok(0)
+}
+
+fn mprotect(
+    process: &Process,
+    [address, length, protection, ..]: Arguments,
+) -> Result<usize, Errno> {
+    check_range(address, length)?;
+    let protection = protection_flags(protection)?;
+    process
+        .address_space()
+        .lock()
+        .protect(address, length, protection)
+        .map_err(|error| error.errno())?;
+    Ok(0)
+}
+
+/// Converts `PROT_*` protection to permissions of pages
+pub fn protection_flags(protection: usize) -> Result<PageFlags, Errno> {
+    let supported = syscall::PROT_READ | syscall::PROT_WRITE | syscall::PROT_EXEC;
+    if protection & !supported != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut flags = PageFlags::empty();
+    // writeable pages which are not readable are reserved in Sv39
+    flags.set(
+        PageFlags::READABLE,
+        protection & (syscall::PROT_READ | syscall::PROT_WRITE) != 0,
+    );
+    flags.set(PageFlags::WRITEABLE, protection & syscall::PROT_WRITE != 0);
+    flags.set(PageFlags::EXECUTABLE, protection & syscall::PROT_EXEC != 0);
+    Ok(flags)
+}
+
+/// Checks that a range passed to `munmap` or `mprotect` is page-aligned user memory
+fn check_range(address: usize, length: usize) -> Result<(), Errno> {
+    let end = length
+        .checked_next_multiple_of(PAGE_SIZE)
+        .and_then(|length| address.checked_add(length));
+    if length == 0
+        || !address.is_multiple_of(PAGE_SIZE)
+        || address < USER_START
+        || end.is_none_or(|end| end > USER_END)
+    {
+        return Err(Errno::EINVAL);
+    }
+    Ok(())
+}
@@ -0,0 +1,148 @@
+//! Entering user mode and handling traps taken in it
+
+use core_lib::syscall::{SIGBUS, SIGILL, SIGSEGV, SIGTRAP};
+
+use crate::{
+    csr::{self, Csr},
+    kdebug,
+    memory::{mapping::FaultError, paging::PageFlags},
+    task,
+    traps::{enable_interrupts, handle_interrupt, ExceptionCode, TrapCause, TrapCauseDescription},
+};
+
+use super::{signal, syscall, ProgramStart};
+
+extern "C" {
+    fn user_return(frame: *mut UserFrame) -> !;
+}
+
+const SSTATUS_SPP: usize = 1 << 8;
+const SSTATUS_SPIE: usize = 1 << 5;
+const SSTATUS_SIE: usize = 1 << 1;
+const SSTATUS_FS: usize = 0b11 << 13;
+
+/// Registers of user mode, saved at the top of the kernel stack of a task by a trap taken
+/// in user mode, in the layout used by `entry.S`
+#[repr(C)]
+#[derive(Debug, Default, Clone)]
+pub struct UserFrame {
+    /// `x0`-`x31`, `x0` slot being unused
+    pub registers: [usize; 32],
+    pub sepc: usize,
+    pub sstatus: usize,
+    /// `tp` of the kernel, which keeps the hart id
+    kernel_tp: usize,
+    _alignment: usize,
+}
+
+impl UserFrame {
+    pub const SP: usize = 2;
+    pub const A0: usize = 10;
+    pub const A1: usize = 11;
+
+    /// Registers at the start of a loaded program, with `argc` and `argv` in `a0` and `a1`
+    pub fn program(start: &ProgramStart) -> UserFrame {
+        let mut frame = UserFrame::default();
+        frame.registers[UserFrame::SP] = start.stack_pointer;
+        frame.registers[UserFrame::A0] = start.argc;
+        frame.registers[UserFrame::A1] = start.argv;
+        frame.sepc = start.entrypoint;
+        // sret enters user mode with interrupts enabled. Floating point registers are not saved
+        // on task switches yet, so the FPU stays off
+        let sstatus = unsafe { csr::sstatus::read() };
+        frame.sstatus = (sstatus & !(SSTATUS_SPP | SSTATUS_SIE | SSTATUS_FS)) | SSTATUS_SPIE;
+        frame
+    }
+}
+
+/// Starts executing a program loaded in the address space of the current task's process
+pub fn enter_user(start: &ProgramStart) -> ! {
+    resume_user(UserFrame::program(start))
+}
+
+/// Continues user code of the current task's process with given registers, e.g. in a child
+/// made by `fork`
+pub fn resume_user(registers: UserFrame) -> ! {
+    let frame = task::current()
+        .user_frame()
+        .expect("task running on a boot stack cannot enter user mode");
+    // SAFETY: user frame is not used by anything else before user mode is entered
+    let frame = unsafe { &mut *frame };
+    *frame = registers;
+
+    // SAFETY: frame holds a valid user state and the address space of the process is active
+    unsafe { user_return(frame) }
+}
+
+/// Called by `entry.S` for traps taken in user mode, which resumes user code after it returns.
+/// Runs with interrupts disabled, except for system calls and delivery of signals
+#[no_mangle]
+extern "C" fn user_trap_handler_rs(frame: &mut UserFrame) {
+    handle_trap(frame);
+
+    let task = task::current();
+    if task
+        .process()
+        .is_some_and(|process| process.has_deliverable_signal())
+    {
+        drop(task);
+        // handlers are entered by writing to user stacks, which may fault
+        unsafe { enable_interrupts() };
+        signal::deliver(frame);
+    }
+}
+
+fn handle_trap(frame: &mut UserFrame) {
+    match TrapCause::current().into() {
+        TrapCauseDescription::Interrupt(code) => handle_interrupt(code),
+        TrapCauseDescription::Trap(ExceptionCode::UserEnvironmentCall) => {
+            // resumes after the ecall instruction
+            frame.sepc += 4;
+            // system calls may block, `user_return` disables interrupts again
+            unsafe { enable_interrupts() };
+            syscall::handle(frame);
+        }
+        TrapCauseDescription::Trap(exception) => {
+            let address = unsafe { csr::stval::read() };
+            let access = match exception {
+                ExceptionCode::InstructionPageFault => Some(PageFlags::EXECUTABLE),
+                ExceptionCode::LoadPageFault => Some(PageFlags::READABLE),
+                ExceptionCode::StorePageFault => Some(PageFlags::WRITEABLE),
+                _ => None,
+            };
+            let task = task::current();
+            let process = task.process().expect("user trap of a kernel task");
+            let mut signal = match exception {
+                ExceptionCode::IllegalInstruction => SIGILL,
+                ExceptionCode::Breakpoint => SIGTRAP,
+                ExceptionCode::InstructionMisaligned
+                | ExceptionCode::LoadMisaligned
+                | ExceptionCode::StoreMisaligned => SIGBUS,
+                _ => SIGSEGV,
+            };
+            if let Some(access) = access {
+                // pages may have to be read from files
+                unsafe { enable_interrupts() };
+                let result = process.address_space().lock().handle_fault(address, access);
+                match result {
+                    Ok(()) => return,
+                    Err(error) => {
+                        kdebug!("{}: page fault not resolved: {}", task, error);
+                        if let FaultError::BeyondEnd | FaultError::Io { .. } = error {
+                            signal = SIGBUS;
+                        }
+                    }
+                }
+            }
+            kdebug!(
+                "{}: {:?} at 0x{:x} (stval 0x{:x}), sending signal {}",
+                task,
+                exception,
+                frame.sepc,
+                address,
+                signal
+            );
+            process.force_signal(signal);
+        }
+    }
+}
@@ -1,40 +1,51 @@
 //! Wrapper arround SBI calls
 
+use core::arch::asm;
+
 #[derive(Debug)]
 pub struct Error;
 pub type Result = core::result::Result<i64, Error>;
 
+/// Performs an SBI call with up to 6 arguments
+unsafe fn call(eid: usize, fid: usize, args: &[usize]) -> Result {
+    let arg = |i: usize| args.get(i).copied().unwrap_or(0);
+    let error: i64;
+    let value: i64;
+
+    asm! {
+        "ecall",
+        inlateout("a0") arg(0) => error,
+        inlateout("a1") arg(1) => value,
+        in("a2") arg(2),
+        in("a3") arg(3),
+        in("a4") arg(4),
+        in("a5") arg(5),
+        in("a6") fid,
+        in("a7") eid,
+    }
+
+    if error < 0 {
+        Err(Error)
+    } else {
+        Ok(value)
+    }
+}
+
 macro_rules! sbi_call {
-    {$fname:ident, eid: $eid:expr, fid: $fid:expr, args: [$arg0:ident : $arg0_type:ident]} => {
-        pub unsafe fn $fname($arg0: $arg0_type) -> super::Result {
-            use core::arch::asm;
-
-            let mut error: i64;
-            let mut value: i64;
-
-            asm! {
-                "mv a7, {extension_id}",
-                "mv a6, {fid}",
-                "mv a0, {arg0}",
-                "ecall",
-                "mv {error}, a0",
-                "mv {value}, a1",
-                extension_id = in(reg) $eid,
-                fid = in(reg) $fid,
-                arg0 = in(reg) $arg0,
-                error = out(reg) error,
-                value = out(reg) value,
-            }
-
-            if error < 0 {
-                Err(super::Error)
-            } else {
-                Ok(value)
-            }
+    {$fname:ident, eid: $eid:expr, fid: $fid:expr, args: [$($arg:ident : $arg_type:ident),*]} => {
+        pub unsafe fn $fname($($arg: $arg_type),*) -> super::Result {
+            super::call($eid, $fid, &[$($arg as usize),*])
         }
     }
 }
 
+pub mod base {
+    const BASE_EID: usize = 0x10;
+    sbi_call! {
+        probe_extension, eid: BASE_EID, fid: 0x3, args: [extension_id: usize]
+    }
+}
+
 pub mod timer {
     const TIME_EID: usize = 0x54494D45;
     sbi_call! {
@@ -48,3 +59,30 @@ pub mod debug_console {
         write_byte, eid: DEBUG_CONSOLE_EID, fid: 0x2, args: [byte: u8]
     }
 }
+
+pub mod system_reset {
+    pub const SYSTEM_RESET_EID: usize = 0x53525354;
+
+    pub const TYPE_SHUTDOWN: u32 = 0x0;
+    pub const TYPE_COLD_REBOOT: u32 = 0x1;
+
+    pub const REASON_NONE: u32 = 0x0;
+
+    sbi_call! {
+        reset, eid: SYSTEM_RESET_EID, fid: 0x0, args: [reset_type: u32, reset_reason: u32]
+    }
+}
+
+pub mod ipi {
+    const IPI_EID: usize = 0x735049;
+    sbi_call! {
+        send_ipi, eid: IPI_EID, fid: 0x0, args: [hart_mask: usize, hart_mask_base: usize]
+    }
+}
+
+pub mod hart_state {
+    const HSM_EID: usize = 0x48534D;
+    sbi_call! {
+        hart_start, eid: HSM_EID, fid: 0x0, args: [hart_id: usize, start_addr: usize, opaque: usize]
+    }
+}
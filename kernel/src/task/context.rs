@@ -0,0 +1,42 @@
+//! Saved registers of a task which is not running
+
+use core::arch::global_asm;
+
+global_asm!(include_str!("../switch.S"));
+
+extern "C" {
+    fn switch_to(from: *mut Context, to: *const Context);
+    fn task_entry();
+}
+
+/// Registers preserved across a call to `switch_to`, in the order used by it
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Context {
+    ra: usize,
+    sp: usize,
+    s: [usize; 12],
+}
+
+impl Context {
+    /// Context of a task which has not run yet, starting with `entry(argument)` on given stack
+    pub fn new(stack_top: usize, entry: extern "C" fn(usize) -> !, argument: usize) -> Context {
+        let mut s = [0; 12];
+        s[0] = entry as usize;
+        s[1] = argument;
+        Context {
+            ra: task_entry as *const () as usize,
+            sp: stack_top,
+            s,
+        }
+    }
+}
+
+/// Saves current registers to `from` and resumes the task saved in `to`.
+/// Returns when some other hart switches back to `from`
+///
+/// # Safety
+/// Both contexts have to be valid. `from` cannot be resumed before this function saves it
+pub unsafe fn switch(from: *mut Context, to: *const Context) {
+    switch_to(from, to);
+}
@@ -0,0 +1,231 @@
+//! Kernel threads
+//!
+//! Each task has its own kernel stack. A task gives up its hart by calling
+//! [`scheduler::schedule`], either directly or from the timer interrupt handler, which saves
+//! its callee-saved registers in a [`Context`] and resumes another task from where it has
+//! called `schedule` itself.
+
+mod context;
+pub mod mutex;
+pub mod policy;
+mod run_queue;
+pub mod scheduler;
+mod stack;
+pub mod wait_queue;
+
+use alloc::{boxed::Box, string::String, sync::Arc};
+use core::{
+    cell::UnsafeCell,
+    fmt::Display,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use core_lib::sync::AtomicMutex;
+
+use crate::{
+    process::{trap::UserFrame, Process},
+    traps::{enable_interrupts, without_interrupts},
+    Supervisor,
+};
+
+use context::Context;
+use policy::{Policy, Rank, Scheduling};
+use scheduler::PolicyError;
+use wait_queue::WaitQueue;
+
+pub use stack::KernelStack;
+
+/// Code run by a task, returning its exit code
+type TaskEntry = Box<dyn FnOnce() -> i32 + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(usize);
+
+impl Display for TaskId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// Waiting in a run queue
+    Ready,
+    Running,
+    /// Waiting for a wake-up, e.g. in a [`WaitQueue`]
+    Blocked,
+    /// Exited, will never run again
+    Dead,
+}
+
+pub struct Task {
+    id: TaskId,
+    name: String,
+    state: AtomicMutex<TaskState>,
+    context: UnsafeCell<Context>,
+    /// Stack of a task, `None` for tasks running on a boot stack
+    stack: AtomicMutex<Option<KernelStack>>,
+    /// Set while the task runs on some hart, until its context is saved after switching out
+    on_cpu: AtomicBool,
+    /// Id of the hart the task last ran on, or is bound to if it is not a normal one
+    hart: AtomicUsize,
+    scheduling: AtomicMutex<Scheduling>,
+    /// Process the task runs in, `None` for kernel tasks
+    process: Option<Arc<Process>>,
+    entry: AtomicMutex<Option<TaskEntry>>,
+    exit_code: AtomicMutex<Option<i32>>,
+    exited: WaitQueue,
+}
+
+// SAFETY: context is accessed only by the hart switching from or to the task, which is
+// guaranteed to be a single one by `on_cpu` flag
+unsafe impl Sync for Task {}
+
+impl Task {
+    fn new(
+        name: &str,
+        stack: Option<KernelStack>,
+        process: Option<Arc<Process>>,
+        entry: Option<TaskEntry>,
+    ) -> Task {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        Task {
+            id: TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+            name: String::from(name),
+            state: AtomicMutex::new(TaskState::Ready),
+            context: UnsafeCell::new(Context::default()),
+            stack: AtomicMutex::new(stack),
+            on_cpu: AtomicBool::new(false),
+            hart: AtomicUsize::new(0),
+            scheduling: AtomicMutex::new(Scheduling::new()),
+            process,
+            entry: AtomicMutex::new(entry),
+            exit_code: AtomicMutex::new(None),
+            exited: WaitQueue::new(),
+        }
+    }
+
+    /// Creates a task with a new stack, which will start by running `entry`
+    fn with_entry(name: &str, process: Option<Arc<Process>>, entry: TaskEntry) -> Arc<Task> {
+        let stack = KernelStack::new();
+        let stack_top = stack.top();
+        let task = Arc::new(Task::new(name, Some(stack), process, Some(entry)));
+
+        let context = Context::new(stack_top, task_start, Arc::as_ptr(&task) as usize);
+        // SAFETY: task is not visible to any hart yet
+        unsafe { *task.context.get() = context };
+        task
+    }
+
+    /// Wraps execution flow which is already running on the current hart, e.g. the boot code
+    fn from_current_flow(name: &str) -> Arc<Task> {
+        let task = Task::new(name, None, None, None);
+        *task.state.lock() = TaskState::Running;
+        task.on_cpu.store(true, Ordering::Relaxed);
+        Arc::new(task)
+    }
+
+    pub fn process(&self) -> Option<&Arc<Process>> {
+        self.process.as_ref()
+    }
+
+    /// Place where user registers of the task are saved, `None` for tasks on boot stacks
+    pub fn user_frame(&self) -> Option<*mut UserFrame> {
+        without_interrupts(|| self.stack.lock().as_ref().map(KernelStack::user_frame))
+    }
+
+    pub fn policy(&self) -> Policy {
+        without_interrupts(|| self.scheduling.lock().policy)
+    }
+
+    /// Changes the scheduling policy of the task. Deadline tasks are admitted only if some hart
+    /// has enough bandwidth left for them, and are bound to that hart
+    pub fn set_policy(&self, policy: Policy) -> Result<(), PolicyError> {
+        scheduler::set_policy(self, policy)
+    }
+
+    fn rank(&self) -> Rank {
+        without_interrupts(|| self.scheduling.lock().rank())
+    }
+
+    /// Blocks until the task exits, returning its exit code
+    pub fn join(&self) -> i32 {
+        self.exited
+            .wait_until(|| without_interrupts(|| self.exit_code.lock().is_some()));
+        without_interrupts(|| self.exit_code.lock().unwrap())
+    }
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} ({})", self.name, self.id)
+    }
+}
+
+/// Starts a new kernel thread. Its exit code is the value returned by `entry`
+pub fn spawn(name: &str, entry: impl FnOnce() -> i32 + Send + 'static) -> Arc<Task> {
+    spawn_in(name, None, entry)
+}
+
+/// Starts a new task, running in the address space of given process
+pub fn spawn_in(
+    name: &str,
+    process: Option<Arc<Process>>,
+    entry: impl FnOnce() -> i32 + Send + 'static,
+) -> Arc<Task> {
+    let task = Task::with_entry(name, process, Box::new(entry));
+    scheduler::enqueue(task.clone());
+    task
+}
+
+/// Returns the task running on the current hart
+pub fn current() -> Arc<Task> {
+    scheduler::current()
+}
+
+/// Terminates the current task with given exit code
+pub fn exit(code: i32) -> ! {
+    let task = current();
+    // gives back the bandwidth of a deadline task
+    let _ = task.set_policy(Policy::Normal);
+    without_interrupts(|| {
+        *task.exit_code.lock() = Some(code);
+        *task.state.lock() = TaskState::Dead;
+        task.exited.wake_all();
+    });
+    drop(task);
+
+    scheduler::schedule();
+    unreachable!("dead task was scheduled")
+}
+
+/// Lets other ready tasks run before continuing
+pub fn yield_now() {
+    scheduler::schedule();
+}
+
+/// Blocks the current task for at least given time
+pub fn sleep(duration: Duration) {
+    let now = Supervisor::global().clock().now();
+    scheduler::sleep_until(now + duration);
+}
+
+/// Ends the current period of a deadline task, blocking it until the next one
+pub fn wait_next_period() {
+    scheduler::wait_next_period();
+}
+
+/// Entry point of every spawned task, called by `task_entry` with a pointer to the task
+extern "C" fn task_start(task: usize) -> ! {
+    scheduler::finish_switch();
+    // tasks are switched with interrupts disabled
+    unsafe { enable_interrupts() };
+
+    // SAFETY: task is kept alive by the scheduler while it is running
+    let task = unsafe { &*(task as *const Task) };
+    let entry = without_interrupts(|| task.entry.lock().take());
+    let code = entry.map_or(0, |entry| entry());
+    exit(code)
+}
@@ -0,0 +1,109 @@
+use alloc::sync::Arc;
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+};
+
+use core_lib::sync::AtomicMutex;
+
+use crate::traps::without_interrupts;
+
+use super::{current, policy::Rank, scheduler, wait_queue::WaitQueue, Task};
+
+/// A lock blocking tasks waiting for it, with priority inheritance
+///
+/// While a task waits for the mutex, its owner runs with at least the waiter's rank, so that
+/// tasks ranked between them cannot delay the waiter indefinitely. The inherited rank is given
+/// back on unlock, which is exact as long as nested mutexes are unlocked in reverse order.
+pub struct Mutex<T> {
+    state: AtomicMutex<MutexState>,
+    unlocked: WaitQueue,
+    value: UnsafeCell<T>,
+}
+
+struct MutexState {
+    owner: Option<Arc<Task>>,
+    /// Rank inherited by the owner before waiters of this mutex raised it
+    restore: Option<Option<Rank>>,
+}
+
+// SAFETY: value is accessed only by the owner of the mutex
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Mutex<T> {
+        Mutex {
+            state: AtomicMutex::new(MutexState {
+                owner: None,
+                restore: None,
+            }),
+            unlocked: WaitQueue::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Blocks until the mutex is available
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        let task = current();
+        loop {
+            let acquired = without_interrupts(|| {
+                let mut state = self.state.lock();
+                let Some(owner) = &state.owner else {
+                    state.owner = Some(task.clone());
+                    return true;
+                };
+                let rank = task.rank();
+                if rank > owner.rank() {
+                    let owner = owner.clone();
+                    if state.restore.is_none() {
+                        state.restore = Some(owner.scheduling.lock().inherited);
+                    }
+                    scheduler::set_inherited(&owner, Some(rank));
+                }
+                false
+            });
+            if acquired {
+                return MutexGuard { mutex: self };
+            }
+            self.unlocked
+                .wait_until(|| without_interrupts(|| self.state.lock().owner.is_none()));
+        }
+    }
+
+    fn unlock(&self) {
+        let (owner, restore) = without_interrupts(|| {
+            let mut state = self.state.lock();
+            (state.owner.take(), state.restore.take())
+        });
+        self.unlocked.wake_all();
+        if let (Some(owner), Some(inherited)) = (owner, restore) {
+            scheduler::set_inherited(&owner, inherited);
+        }
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: guard exists only while the mutex is owned
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: guard exists only while the mutex is owned
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
@@ -0,0 +1,176 @@
+//! Scheduling classes and per-task scheduling parameters
+
+use core::{cmp::Reverse, fmt::Display, time::Duration};
+
+use crate::time::Instant;
+
+/// Highest priority of a real-time task
+pub const MAX_REALTIME_PRIORITY: u8 = 99;
+
+/// How a task competes for harts with others
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Round-robin among other normal tasks
+    Normal,
+    /// Fixed priority, higher ones run first, round-robin among equal ones
+    RealTime(u8),
+    /// Periodic task getting `runtime` in each `period`, ending with its deadline.
+    /// Earliest deadline runs first
+    Deadline { runtime: Duration, period: Duration },
+}
+
+impl Policy {
+    pub fn class(&self) -> Class {
+        match self {
+            Policy::Normal => Class::Normal,
+            Policy::RealTime(_) => Class::RealTime,
+            Policy::Deadline { .. } => Class::Deadline,
+        }
+    }
+}
+
+/// Group of policies. Tasks of a class always run before tasks of classes following it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Deadline,
+    RealTime,
+    Normal,
+}
+
+impl Class {
+    pub const ALL: [Class; 3] = [Class::Deadline, Class::RealTime, Class::Normal];
+
+    pub(super) fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Precedence of a task when choosing the next one to run, greater ones run first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) enum Rank {
+    Normal,
+    RealTime(u8),
+    Deadline(Reverse<Instant>),
+}
+
+/// Scheduling state of a task
+pub(super) struct Scheduling {
+    pub policy: Policy,
+    /// Rank inherited from tasks waiting for a mutex held by the task
+    pub inherited: Option<Rank>,
+    /// Start of the current period of a deadline task, its deadline is the start of the next one
+    pub period_start: Instant,
+    /// Runtime left in the current period of a deadline task
+    pub budget: Duration,
+    /// When the task was last made ready to run, to measure scheduling latency
+    pub ready_since: Option<Instant>,
+    /// When the task was last switched to or charged for running
+    pub running_since: Instant,
+}
+
+impl Scheduling {
+    pub const fn new() -> Scheduling {
+        Scheduling {
+            policy: Policy::Normal,
+            inherited: None,
+            period_start: Instant::ZERO,
+            budget: Duration::ZERO,
+            ready_since: None,
+            running_since: Instant::ZERO,
+        }
+    }
+
+    /// Rank of the task, including the inherited one
+    pub fn rank(&self) -> Rank {
+        let own = match self.policy {
+            Policy::Normal => Rank::Normal,
+            Policy::RealTime(priority) => Rank::RealTime(priority),
+            Policy::Deadline { period, .. } => Rank::Deadline(Reverse(self.period_start + period)),
+        };
+        self.inherited.map_or(own, |inherited| own.max(inherited))
+    }
+
+    /// Starts the next period of a deadline task which has not started yet or is still
+    /// running, replenishing its runtime. Returns whether the current deadline was missed
+    pub fn next_period(&mut self, now: Instant) -> bool {
+        let Policy::Deadline { runtime, period } = self.policy else {
+            return false;
+        };
+        let deadline = self.period_start + period;
+        self.period_start = deadline;
+        while self.period_start + period <= now {
+            self.period_start = self.period_start + period;
+        }
+        self.budget = runtime;
+        now > deadline
+    }
+}
+
+/// Scheduling statistics of a class
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClassStatistics {
+    /// Number of switches to tasks of the class
+    pub dispatches: u64,
+    /// Number of switches from tasks which were still able to run, preempted or yielding
+    pub preemptions: u64,
+    pub run_time: Duration,
+    /// Sum of times from tasks becoming ready to them running
+    pub total_latency: Duration,
+    pub max_latency: Duration,
+    /// Number of periods in which deadline tasks finished after their deadlines
+    pub deadline_misses: u64,
+    /// Number of times deadline tasks used up their runtime before the end of a period
+    pub throttles: u64,
+}
+
+impl ClassStatistics {
+    pub const fn new() -> ClassStatistics {
+        ClassStatistics {
+            dispatches: 0,
+            preemptions: 0,
+            run_time: Duration::ZERO,
+            total_latency: Duration::ZERO,
+            max_latency: Duration::ZERO,
+            deadline_misses: 0,
+            throttles: 0,
+        }
+    }
+
+    pub fn average_latency(&self) -> Duration {
+        match self.dispatches {
+            0 => Duration::ZERO,
+            dispatches => self.total_latency / dispatches as u32,
+        }
+    }
+
+    pub(super) fn record_latency(&mut self, latency: Duration) {
+        self.total_latency += latency;
+        self.max_latency = self.max_latency.max(latency);
+    }
+
+    pub(super) fn add(&mut self, other: &ClassStatistics) {
+        self.dispatches += other.dispatches;
+        self.preemptions += other.preemptions;
+        self.run_time += other.run_time;
+        self.total_latency += other.total_latency;
+        self.max_latency = self.max_latency.max(other.max_latency);
+        self.deadline_misses += other.deadline_misses;
+        self.throttles += other.throttles;
+    }
+}
+
+impl Display for ClassStatistics {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} dispatches, {} preemptions, ran {:?}, latency avg {:?} max {:?}, {} missed deadlines, {} throttles",
+            self.dispatches,
+            self.preemptions,
+            self.run_time,
+            self.average_latency(),
+            self.max_latency,
+            self.deadline_misses,
+            self.throttles
+        )
+    }
+}
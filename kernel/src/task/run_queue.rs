@@ -0,0 +1,112 @@
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    vec::Vec,
+};
+use core::cmp::Reverse;
+
+use crate::time::Instant;
+
+use super::{policy::Rank, Task};
+
+/// Tasks ready to run on a hart, ordered by their ranks at the time they were queued
+pub(super) struct RunQueue {
+    /// Deadline tasks with their deadlines, few enough to be searched linearly
+    deadline: Vec<(Reverse<Instant>, Arc<Task>)>,
+    /// Real-time tasks by priority, each level is round-robin
+    realtime: BTreeMap<u8, VecDeque<Arc<Task>>>,
+    normal: VecDeque<Arc<Task>>,
+}
+
+impl RunQueue {
+    pub const fn new() -> RunQueue {
+        RunQueue {
+            deadline: Vec::new(),
+            realtime: BTreeMap::new(),
+            normal: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.deadline.len()
+            + self.realtime.values().map(VecDeque::len).sum::<usize>()
+            + self.normal.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push(&mut self, task: Arc<Task>) {
+        match task.rank() {
+            Rank::Deadline(deadline) => self.deadline.push((deadline, task)),
+            Rank::RealTime(priority) => self.realtime.entry(priority).or_default().push_back(task),
+            Rank::Normal => self.normal.push_back(task),
+        }
+    }
+
+    /// Rank of the task [`RunQueue::pop`] would return
+    pub fn best_rank(&self) -> Option<Rank> {
+        if let Some((deadline, _)) = self.deadline.iter().max_by_key(|(deadline, _)| *deadline) {
+            Some(Rank::Deadline(*deadline))
+        } else if let Some(priority) = self.realtime.keys().next_back() {
+            Some(Rank::RealTime(*priority))
+        } else {
+            self.normal.front().map(|_| Rank::Normal)
+        }
+    }
+
+    /// Takes the task with the greatest rank
+    pub fn pop(&mut self) -> Option<Arc<Task>> {
+        let earliest = self
+            .deadline
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, (deadline, _))| *deadline)
+            .map(|(index, _)| index);
+        if let Some(index) = earliest {
+            return Some(self.deadline.swap_remove(index).1);
+        }
+        if let Some(mut level) = self.realtime.last_entry() {
+            let task = level.get_mut().pop_front();
+            if level.get().is_empty() {
+                level.remove();
+            }
+            return task;
+        }
+        self.normal.pop_front()
+    }
+
+    /// Number of tasks which may be moved to other harts
+    pub fn stealable(&self) -> usize {
+        self.normal.len()
+    }
+
+    /// Takes the normal task queued most recently, to be run on another hart
+    pub fn steal(&mut self) -> Option<Arc<Task>> {
+        self.normal.pop_back()
+    }
+
+    /// Removes a task from the queue, returning it if it was queued
+    pub fn remove(&mut self, task: &Task) -> Option<Arc<Task>> {
+        let is_task = |queued: &Arc<Task>| core::ptr::eq(Arc::as_ptr(queued), task);
+
+        if let Some(index) = self.deadline.iter().position(|(_, queued)| is_task(queued)) {
+            return Some(self.deadline.swap_remove(index).1);
+        }
+        let realtime = self
+            .realtime
+            .iter()
+            .find_map(|(priority, level)| Some((*priority, level.iter().position(is_task)?)));
+        if let Some((priority, index)) = realtime {
+            let level = self.realtime.get_mut(&priority)?;
+            let removed = level.remove(index);
+            if level.is_empty() {
+                self.realtime.remove(&priority);
+            }
+            return removed;
+        }
+        let index = self.normal.iter().position(is_task)?;
+        self.normal.remove(index)
+    }
+}
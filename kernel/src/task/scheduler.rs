@@ -0,0 +1,656 @@
+//! Preemptive scheduling of tasks on harts
+//!
+//! Every hart has its own run queue. A task runs until it blocks, yields, uses up its time
+//! slice, which is enforced by the timer interrupt, or a task of a greater rank becomes ready.
+//! Deadline tasks run first, earliest deadline first, then real-time ones by priority, then
+//! normal ones; tasks of the same rank take turns. New tasks go to the least loaded hart and
+//! woken ones to the hart they last ran on. Harts with empty queues steal normal tasks from the
+//! busiest one, while real-time and deadline tasks stay on their harts. A hart with nothing to
+//! do runs its idle task, which waits for an interrupt.
+//!
+//! Deadline tasks are admitted only while the sum of `runtime / period` of tasks bound to a hart
+//! stays within [`MAX_DEADLINE_UTILIZATION`], and are throttled until their next period once
+//! they use up their runtime.
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use core_lib::sync::AtomicMutex;
+use snafu::{ensure, Snafu};
+
+use crate::{
+    hart::{self, MAX_HARTS},
+    kdebug,
+    memory::paging,
+    sbi,
+    time::Instant,
+    traps::{raise_software_interrupt, wait_for_interrupt, without_interrupts},
+    Supervisor,
+};
+
+use super::{
+    policy::{Class, ClassStatistics, Policy, Rank, MAX_REALTIME_PRIORITY},
+    run_queue::RunQueue,
+    Task, TaskState,
+};
+
+const DEFAULT_TIME_SLICE: Duration = Duration::from_millis(10);
+/// Share of a hart's time which may be reserved by deadline tasks, in parts per million
+pub const MAX_DEADLINE_UTILIZATION: u64 = 950_000;
+
+/// Reason for refusing a scheduling policy
+#[derive(Debug, Snafu)]
+pub enum PolicyError {
+    #[snafu(display("Real-time priority {priority} is above {MAX_REALTIME_PRIORITY}"))]
+    InvaildPriority { priority: u8 },
+    #[snafu(display("Runtime has to be positive and not longer than the period"))]
+    InvaildRuntime,
+    #[snafu(display("No hart has enough bandwidth left for the deadline task"))]
+    NotAdmitted,
+}
+
+/// Scheduling state of a single hart
+struct HartTasks {
+    current: AtomicMutex<Option<Arc<Task>>>,
+    idle: AtomicMutex<Option<Arc<Task>>>,
+    /// Task switched from, released by the task switched to once its context is saved
+    previous: AtomicMutex<Option<Arc<Task>>>,
+    queue: AtomicMutex<RunQueue>,
+    /// Tasks sleeping until given instants, woken by the hart's timer
+    sleepers: AtomicMutex<Vec<(Instant, Arc<Task>)>>,
+    /// End of the current task's time slice or runtime, `None` when running the idle task
+    slice_end: AtomicMutex<Option<Instant>>,
+    /// Utilization reserved by deadline tasks bound to the hart, in parts per million
+    deadline_utilization: AtomicU64,
+    statistics: AtomicMutex<[ClassStatistics; 3]>,
+}
+
+impl HartTasks {
+    const fn new() -> HartTasks {
+        HartTasks {
+            current: AtomicMutex::new(None),
+            idle: AtomicMutex::new(None),
+            previous: AtomicMutex::new(None),
+            queue: AtomicMutex::new(RunQueue::new()),
+            sleepers: AtomicMutex::new(Vec::new()),
+            slice_end: AtomicMutex::new(None),
+            deadline_utilization: AtomicU64::new(0),
+            statistics: AtomicMutex::new([const { ClassStatistics::new() }; 3]),
+        }
+    }
+
+    fn is_idle(&self, task: &Arc<Task>) -> bool {
+        self.idle
+            .lock()
+            .as_ref()
+            .is_some_and(|idle| Arc::ptr_eq(idle, task))
+    }
+}
+
+static HARTS: [HartTasks; MAX_HARTS] = [const { HartTasks::new() }; MAX_HARTS];
+/// Bitmask of harts running the scheduler
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+/// Bitmask of harts waiting for an interrupt in their idle tasks
+static IDLE_HARTS: AtomicUsize = AtomicUsize::new(0);
+static TIME_SLICE_NANOS: AtomicU64 = AtomicU64::new(DEFAULT_TIME_SLICE.as_nanos() as u64);
+
+fn local() -> &'static HartTasks {
+    &HARTS[hart::current_id()]
+}
+
+fn now() -> Instant {
+    Supervisor::global().clock().now()
+}
+
+pub fn time_slice() -> Duration {
+    Duration::from_nanos(TIME_SLICE_NANOS.load(Ordering::Relaxed))
+}
+
+/// Sets how long a task may run before it is preempted, if other tasks are ready
+pub fn set_time_slice(slice: Duration) {
+    TIME_SLICE_NANOS.store(slice.as_nanos() as u64, Ordering::Relaxed);
+}
+
+/// Turns the boot flow of the boot hart into a task named `main` and creates its idle task
+pub fn initialize_boot_hart() {
+    let main = Task::from_current_flow("main");
+    let idle = Task::with_entry("idle", None, Box::new(|| idle_loop()));
+    initialize_hart(main, idle);
+    without_interrupts(start_slice);
+}
+
+/// Turns the boot flow of a secondary hart into its idle task
+pub fn run_secondary_hart() -> ! {
+    let idle = Task::from_current_flow("idle");
+    initialize_hart(idle.clone(), idle);
+    idle_loop()
+}
+
+fn initialize_hart(current: Arc<Task>, idle: Arc<Task>) {
+    let hart = local();
+    without_interrupts(|| {
+        current.hart.store(hart::current_id(), Ordering::Relaxed);
+        *hart.current.lock() = Some(current);
+        *hart.idle.lock() = Some(idle);
+    });
+    ONLINE_HARTS.fetch_or(1 << hart::current_id(), Ordering::AcqRel);
+}
+
+fn idle_loop() -> ! {
+    let hart_mask = 1 << hart::current_id();
+    loop {
+        schedule();
+        // interrupts stay disabled between checking the queue and `wfi`, so that
+        // a notification about a new task results in a pending interrupt and wakes the hart
+        without_interrupts(|| {
+            if local().queue.lock().is_empty() {
+                IDLE_HARTS.fetch_or(hart_mask, Ordering::AcqRel);
+                wait_for_interrupt();
+                IDLE_HARTS.fetch_and(!hart_mask, Ordering::AcqRel);
+            }
+        });
+    }
+}
+
+pub fn current() -> Arc<Task> {
+    without_interrupts(|| local().current.lock().clone()).expect("scheduler not initialized")
+}
+
+/// Makes a blocked task ready to run on the hart it last ran on
+pub fn wake(task: Arc<Task>) {
+    let is_blocked = without_interrupts(|| {
+        let mut state = task.state.lock();
+        let is_blocked = *state == TaskState::Blocked;
+        if is_blocked {
+            *state = TaskState::Ready;
+        }
+        is_blocked
+    });
+    if is_blocked {
+        let hart_id = task.hart.load(Ordering::Relaxed);
+        push_to(hart_id, task);
+    }
+}
+
+/// Adds a new task to the run queue of the least loaded hart
+pub(super) fn enqueue(task: Arc<Task>) {
+    let hart_id = online_harts()
+        .min_by_key(|id| without_interrupts(|| HARTS[*id].queue.lock().len()))
+        .unwrap_or(hart::current_id());
+    push_to(hart_id, task);
+}
+
+fn push_to(hart_id: usize, task: Arc<Task>) {
+    without_interrupts(|| {
+        task.scheduling.lock().ready_since.get_or_insert(now());
+        HARTS[hart_id].queue.lock().push(task);
+    });
+    check_preemption(hart_id);
+}
+
+/// Makes a hart switch tasks if its current one is outranked by a queued one
+fn check_preemption(hart_id: usize) {
+    if !is_outranked(&HARTS[hart_id]) {
+        return;
+    }
+    if hart_id == hart::current_id() {
+        raise_software_interrupt();
+    } else {
+        notify(1 << hart_id);
+    }
+}
+
+fn is_outranked(hart: &HartTasks) -> bool {
+    without_interrupts(|| {
+        let Some(best) = hart.queue.lock().best_rank() else {
+            return false;
+        };
+        let current = hart.current.lock().clone();
+        match current {
+            Some(current) if !hart.is_idle(&current) => best > current.rank(),
+            _ => true,
+        }
+    })
+}
+
+fn online_harts() -> impl Iterator<Item = usize> {
+    let online = ONLINE_HARTS.load(Ordering::Acquire);
+    (0..MAX_HARTS).filter(move |id| online & (1 << id) != 0)
+}
+
+/// Interrupts given harts, so that they check their run queues
+fn notify(hart_mask: usize) {
+    if let Err(error) = unsafe { sbi::ipi::send_ipi(hart_mask, 0) } {
+        kdebug!("Cannot send IPI: {:?}", error);
+    }
+}
+
+/// Blocks the current task until given instant
+pub fn sleep_until(deadline: Instant) {
+    let task = current();
+    without_interrupts(|| {
+        let hart = local();
+        *task.state.lock() = TaskState::Blocked;
+        hart.sleepers.lock().push((deadline, task));
+        program_timer(hart);
+    });
+    schedule();
+}
+
+/// Blocks the current task until given instant, unless `cancelled` returns true
+///
+/// Condition is checked with the state of the task locked, so a task changing it and then
+/// calling [`wake_sleeper`] cannot be missed.
+pub fn sleep_until_unless(deadline: Instant, cancelled: impl FnOnce() -> bool) {
+    let task = current();
+    let blocked = without_interrupts(|| {
+        let mut state = task.state.lock();
+        if cancelled() {
+            return false;
+        }
+        *state = TaskState::Blocked;
+        let hart = local();
+        hart.sleepers.lock().push((deadline, task.clone()));
+        program_timer(hart);
+        true
+    });
+    if blocked {
+        schedule();
+    }
+}
+
+/// Wakes a task sleeping until some instant before it comes
+pub fn wake_sleeper(task: Arc<Task>) {
+    let hart_id = task.hart.load(Ordering::Relaxed);
+    without_interrupts(|| {
+        HARTS[hart_id]
+            .sleepers
+            .lock()
+            .retain(|(_, sleeper)| !Arc::ptr_eq(sleeper, &task))
+    });
+    wake(task);
+}
+
+/// Ends the current period of a deadline task, blocking it until the next one starts
+pub(super) fn wait_next_period() {
+    let task = current();
+    let release = without_interrupts(|| {
+        let mut scheduling = task.scheduling.lock();
+        if scheduling.policy.class() != Class::Deadline {
+            return None;
+        }
+        let missed = scheduling.next_period(now());
+        let release = scheduling.period_start;
+        drop(scheduling);
+        if missed {
+            local().statistics.lock()[Class::Deadline.index()].deadline_misses += 1;
+        }
+        Some(release)
+    });
+    if let Some(release) = release {
+        sleep_until(release);
+    }
+}
+
+/// Changes the policy of a task, see [`Task::set_policy`]
+pub(super) fn set_policy(task: &Task, policy: Policy) -> Result<(), PolicyError> {
+    let utilization = match policy {
+        Policy::Normal => 0,
+        Policy::RealTime(priority) => {
+            ensure!(
+                priority <= MAX_REALTIME_PRIORITY,
+                InvaildPrioritySnafu { priority }
+            );
+            0
+        }
+        Policy::Deadline { runtime, period } => {
+            ensure!(!runtime.is_zero() && runtime <= period, InvaildRuntimeSnafu);
+            utilization_of(runtime, period)
+        }
+    };
+
+    without_interrupts(|| {
+        let old_hart = task.hart.load(Ordering::Relaxed);
+        let old_utilization = match task.scheduling.lock().policy {
+            Policy::Deadline { runtime, period } => utilization_of(runtime, period),
+            _ => 0,
+        };
+        let old_bandwidth = &HARTS[old_hart].deadline_utilization;
+        old_bandwidth.fetch_sub(old_utilization, Ordering::AcqRel);
+
+        let hart_id = if utilization == 0 {
+            old_hart
+        } else if let Some(hart_id) = reserve_bandwidth(utilization) {
+            hart_id
+        } else {
+            old_bandwidth.fetch_add(old_utilization, Ordering::AcqRel);
+            return NotAdmittedSnafu.fail();
+        };
+
+        {
+            let mut scheduling = task.scheduling.lock();
+            scheduling.policy = policy;
+            if let Policy::Deadline { runtime, .. } = policy {
+                scheduling.period_start = now();
+                scheduling.budget = runtime;
+            }
+        }
+        task.hart.store(hart_id, Ordering::Relaxed);
+        // queued task has to be queued again with its new rank
+        let queued = HARTS[old_hart].queue.lock().remove(task);
+        if let Some(queued) = queued {
+            push_to(hart_id, queued);
+        }
+        Ok(())
+    })?;
+
+    check_preemption(hart::current_id());
+    Ok(())
+}
+
+/// Share of time reserved by a deadline task, in parts per million
+fn utilization_of(runtime: Duration, period: Duration) -> u64 {
+    (runtime.as_nanos() * 1_000_000).div_ceil(period.as_nanos()) as u64
+}
+
+/// Reserves bandwidth on the online hart with the least of it reserved already
+fn reserve_bandwidth(utilization: u64) -> Option<usize> {
+    let mut harts: Vec<usize> = online_harts().collect();
+    harts.sort_by_key(|id| HARTS[*id].deadline_utilization.load(Ordering::Acquire));
+    harts.into_iter().find(|id| {
+        HARTS[*id]
+            .deadline_utilization
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |reserved| {
+                Some(reserved + utilization).filter(|total| *total <= MAX_DEADLINE_UTILIZATION)
+            })
+            .is_ok()
+    })
+}
+
+/// Changes the rank a task inherited through a mutex, moving it within its run queue
+pub(super) fn set_inherited(task: &Task, inherited: Option<Rank>) {
+    without_interrupts(|| {
+        task.scheduling.lock().inherited = inherited;
+        let hart_id = task.hart.load(Ordering::Relaxed);
+        let queued = HARTS[hart_id].queue.lock().remove(task);
+        if let Some(queued) = queued {
+            push_to(hart_id, queued);
+        }
+    });
+    // current task may have lost its inherited rank
+    check_preemption(hart::current_id());
+}
+
+/// Statistics of a class, summed over all harts
+pub fn statistics(class: Class) -> ClassStatistics {
+    let mut total = ClassStatistics::new();
+    for hart in &HARTS {
+        let statistics = without_interrupts(|| hart.statistics.lock()[class.index()]);
+        total.add(&statistics);
+    }
+    total
+}
+
+/// Switches to the ready task of the greatest rank, if it is not the current one. A running
+/// task is put back at the end of its run queue, blocked and dead ones are not
+pub fn schedule() {
+    without_interrupts(|| {
+        let hart = local();
+        let current = hart
+            .current
+            .lock()
+            .clone()
+            .expect("scheduler not initialized");
+        let is_idle = hart.is_idle(&current);
+        let now = now();
+        if !is_idle {
+            charge(hart, &current, now);
+        }
+
+        let is_running = *current.state.lock() == TaskState::Running;
+        if is_running && !is_idle {
+            let is_exhausted = {
+                let scheduling = current.scheduling.lock();
+                scheduling.policy.class() == Class::Deadline && scheduling.budget.is_zero()
+            };
+            if is_exhausted {
+                throttle(hart, &current, now);
+            } else {
+                *current.state.lock() = TaskState::Ready;
+                // a task bound to another hart in the meantime moves there
+                push_to(current.hart.load(Ordering::Relaxed), current.clone());
+            }
+        }
+
+        // queue has to be unlocked before stealing from other ones
+        let next = hart.queue.lock().pop();
+        let next = match next.or_else(steal) {
+            Some(next) => next,
+            None => hart.idle.lock().clone().expect("scheduler not initialized"),
+        };
+        if Arc::ptr_eq(&next, &current) {
+            // still the greatest rank, or woken up before it managed to block
+            *current.state.lock() = TaskState::Running;
+            current.scheduling.lock().ready_since = None;
+            return;
+        }
+
+        if is_running && !is_idle {
+            let class = current.scheduling.lock().policy.class();
+            hart.statistics.lock()[class.index()].preemptions += 1;
+        }
+        switch(hart, current, next, now);
+    })
+}
+
+/// Accounts time the task has run since it was switched to or last charged
+fn charge(hart: &HartTasks, task: &Task, now: Instant) {
+    let (class, ran) = {
+        let mut scheduling = task.scheduling.lock();
+        let ran = now - scheduling.running_since;
+        scheduling.running_since = now;
+        if scheduling.policy.class() == Class::Deadline {
+            scheduling.budget = scheduling.budget.saturating_sub(ran);
+        }
+        (scheduling.policy.class(), ran)
+    };
+    hart.statistics.lock()[class.index()].run_time += ran;
+}
+
+/// Blocks a deadline task which used up its runtime until its next period
+fn throttle(hart: &HartTasks, task: &Arc<Task>, now: Instant) {
+    let (missed, release) = {
+        let mut scheduling = task.scheduling.lock();
+        (scheduling.next_period(now), scheduling.period_start)
+    };
+    {
+        let mut statistics = hart.statistics.lock();
+        let statistics = &mut statistics[Class::Deadline.index()];
+        statistics.throttles += 1;
+        if missed {
+            statistics.deadline_misses += 1;
+        }
+    }
+    *task.state.lock() = TaskState::Blocked;
+    hart.sleepers.lock().push((release, task.clone()));
+}
+
+/// Takes a normal task from the end of the longest run queue of other harts
+fn steal() -> Option<Arc<Task>> {
+    let (victim, length) = busiest_hart()?;
+    if length == 0 {
+        return None;
+    }
+    HARTS[victim].queue.lock().steal()
+}
+
+/// Finds the other hart with the most tasks which may be stolen
+fn busiest_hart() -> Option<(usize, usize)> {
+    online_harts()
+        .filter(|id| *id != hart::current_id())
+        .map(|id| (id, HARTS[id].queue.lock().stealable()))
+        .max_by_key(|(_, length)| *length)
+}
+
+fn switch(hart: &HartTasks, current: Arc<Task>, next: Arc<Task>, now: Instant) {
+    // next task may still be switching out on another hart
+    while next.on_cpu.load(Ordering::Acquire) {
+        spin_loop();
+    }
+    next.on_cpu.store(true, Ordering::Relaxed);
+    next.hart.store(hart::current_id(), Ordering::Relaxed);
+    *next.state.lock() = TaskState::Running;
+
+    let (class, latency) = {
+        let mut scheduling = next.scheduling.lock();
+        scheduling.running_since = now;
+        let latency = scheduling.ready_since.take().map(|ready| now - ready);
+        (scheduling.policy.class(), latency)
+    };
+    if !hart.is_idle(&next) {
+        let mut statistics = hart.statistics.lock();
+        let statistics = &mut statistics[class.index()];
+        statistics.dispatches += 1;
+        if let Some(latency) = latency {
+            statistics.record_latency(latency);
+        }
+    }
+
+    let satp = next.process().map_or(0, |process| process.satp());
+    // SAFETY: kernel is mapped the same way in all address spaces,
+    // which live at least as long as their tasks
+    unsafe { paging::activate(satp) };
+
+    let from = current.context.get();
+    let to = next.context.get();
+    *hart.previous.lock() = Some(current);
+    *hart.current.lock() = Some(next);
+
+    // SAFETY: previous task is marked as not on CPU only after the switch,
+    // both tasks are kept alive by the hart
+    unsafe { super::context::switch(from, to) };
+    finish_switch();
+}
+
+/// Releases the task switched from and starts a time slice of the new one.
+/// Called first thing after each switch
+pub(super) fn finish_switch() {
+    let previous = local().previous.lock().take();
+    if let Some(previous) = previous {
+        if *previous.state.lock() == TaskState::Dead {
+            // not running on the stack anymore
+            previous.stack.lock().take();
+        }
+        previous.on_cpu.store(false, Ordering::Release);
+    }
+    start_slice();
+}
+
+/// Starts a time slice of the current task, which for deadline tasks lasts until they use up
+/// their runtime
+fn start_slice() {
+    let hart = local();
+    let current = hart
+        .current
+        .lock()
+        .clone()
+        .expect("scheduler not initialized");
+    *hart.slice_end.lock() = if hart.is_idle(&current) {
+        None
+    } else {
+        let scheduling = current.scheduling.lock();
+        match scheduling.policy {
+            Policy::Deadline { .. } => Some(now() + scheduling.budget),
+            _ => Some(now() + time_slice()),
+        }
+    };
+    program_timer(hart);
+}
+
+/// Sets the timer to the end of the time slice or the earliest sleeper's deadline
+fn program_timer(hart: &HartTasks) {
+    let slice_end = *hart.slice_end.lock();
+    let wake_up = hart
+        .sleepers
+        .lock()
+        .iter()
+        .map(|(deadline, _)| *deadline)
+        .min();
+    let next = match (slice_end, wake_up) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    Supervisor::global().clock().set_timer(next);
+}
+
+/// Handles the timer interrupt: wakes sleeping tasks, balances load and preempts
+/// the current task if its time slice is over
+pub fn timer_interrupt() {
+    let hart = local();
+    let now = now();
+
+    let mut expired = Vec::new();
+    hart.sleepers.lock().retain(|(deadline, task)| {
+        let is_expired = *deadline <= now;
+        if is_expired {
+            expired.push(task.clone());
+        }
+        !is_expired
+    });
+    for task in expired {
+        wake(task);
+    }
+
+    balance();
+
+    let slice_expired = hart.slice_end.lock().is_some_and(|end| end <= now);
+    let current = hart
+        .current
+        .lock()
+        .clone()
+        .expect("scheduler not initialized");
+    if slice_expired || hart.is_idle(&current) {
+        drop(current);
+        schedule();
+    }
+
+    // the task may have been switched out and resumed on another hart since
+    let hart = local();
+    if hart.slice_end.lock().is_some_and(|end| end <= now) {
+        start_slice();
+    } else {
+        program_timer(hart);
+    }
+}
+
+/// Handles the software interrupt, which is raised when a task of a greater rank than
+/// the current one becomes ready
+pub fn software_interrupt() {
+    if is_outranked(local()) {
+        schedule();
+    }
+}
+
+/// Moves a normal task from the busiest hart if it has at least two more of them queued than
+/// this one, and wakes idle harts if this one has tasks waiting
+fn balance() {
+    let local_length = local().queue.lock().stealable();
+    if let Some((victim, length)) = busiest_hart() {
+        if length > local_length + 1 {
+            let task = HARTS[victim].queue.lock().steal();
+            if let Some(task) = task {
+                local().queue.lock().push(task);
+            }
+        }
+    }
+
+    let idle_harts = IDLE_HARTS.load(Ordering::Acquire) & !(1 << hart::current_id());
+    if local_length > 0 && idle_harts != 0 {
+        // one idle hart is enough to steal a task
+        notify(idle_harts & idle_harts.wrapping_neg());
+    }
+}
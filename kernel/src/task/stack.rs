@@ -0,0 +1,46 @@
+use core::{alloc::Layout, ptr::NonNull};
+
+use alloc::alloc::{alloc, dealloc, handle_alloc_error};
+
+use crate::process::trap::UserFrame;
+
+pub const STACK_SIZE: usize = 32 * 1024;
+const STACK_ALIGNMENT: usize = 16;
+
+/// Kernel stack of a task, allocated on the heap. Its top is reserved for registers
+/// of user mode, saved there by traps
+pub struct KernelStack(NonNull<u8>);
+
+// SAFETY: stack memory is exclusively owned
+unsafe impl Send for KernelStack {}
+
+impl KernelStack {
+    pub fn new() -> KernelStack {
+        // SAFETY: layout has non-zero size
+        let bottom = unsafe { alloc(Self::layout()) };
+        match NonNull::new(bottom) {
+            Some(bottom) => KernelStack(bottom),
+            None => handle_alloc_error(Self::layout()),
+        }
+    }
+
+    /// Initial value of the stack pointer, as the stack grows down
+    pub fn top(&self) -> usize {
+        self.user_frame() as usize
+    }
+
+    pub fn user_frame(&self) -> *mut UserFrame {
+        (self.0.as_ptr() as usize + STACK_SIZE - size_of::<UserFrame>()) as *mut UserFrame
+    }
+
+    fn layout() -> Layout {
+        Layout::from_size_align(STACK_SIZE, STACK_ALIGNMENT).unwrap()
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        // SAFETY: stack was allocated with the same layout
+        unsafe { dealloc(self.0.as_ptr(), Self::layout()) };
+    }
+}
@@ -0,0 +1,51 @@
+use alloc::{collections::VecDeque, sync::Arc};
+
+use core_lib::sync::AtomicMutex;
+
+use crate::traps::without_interrupts;
+
+use super::{current, scheduler, Task, TaskState};
+
+/// Tasks blocked until some condition becomes true
+pub struct WaitQueue {
+    waiters: AtomicMutex<VecDeque<Arc<Task>>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> WaitQueue {
+        WaitQueue {
+            waiters: AtomicMutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Blocks the current task until `condition` returns true
+    ///
+    /// Condition is checked with the queue locked, so a task changing it and then calling
+    /// [`WaitQueue::wake_all`] cannot be missed.
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        loop {
+            let blocked = without_interrupts(|| {
+                let mut waiters = self.waiters.lock();
+                if condition() {
+                    return false;
+                }
+                let task = current();
+                *task.state.lock() = TaskState::Blocked;
+                waiters.push_back(task);
+                true
+            });
+            if !blocked {
+                return;
+            }
+            scheduler::schedule();
+        }
+    }
+
+    /// Wakes all waiting tasks, so that they check their conditions again
+    pub fn wake_all(&self) {
+        let waiters = without_interrupts(|| core::mem::take(&mut *self.waiters.lock()));
+        for task in waiters {
+            scheduler::wake(task);
+        }
+    }
+}
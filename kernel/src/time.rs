@@ -0,0 +1,201 @@
+//! Monotonic and wall-clock time
+
+use core::{
+    arch::asm,
+    fmt::Display,
+    ops::{Add, Sub},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use core_lib::time::DateTime;
+use devicetree::FlattenedDeviceTree;
+
+use crate::sbi;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Reads a value of the `time` counter
+#[inline]
+pub fn rdtime() -> u64 {
+    let time: u64;
+    // SAFETY: reading time counter has no side effects
+    unsafe { asm!("rdtime {t}", t = out(reg) time) };
+    time
+}
+
+/// A measurement of the monotonic clock, relative to the `time` counter reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(Duration);
+
+impl Instant {
+    /// The `time` counter reset
+    pub const ZERO: Instant = Instant(Duration::ZERO);
+    /// The latest representable instant, which never comes
+    pub const MAX: Instant = Instant(Duration::MAX);
+
+    /// Time elapsed since the `time` counter reset (usually, the machine boot)
+    pub fn since_boot(&self) -> Duration {
+        self.0
+    }
+
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+
+    /// Returns `None` if the result cannot be represented
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        Instant(self.0 + rhs)
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Self::Output {
+        self.duration_since(rhs)
+    }
+}
+
+/// A measurement of the wall clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemTime(Duration);
+
+impl SystemTime {
+    pub const UNIX_EPOCH: SystemTime = SystemTime(Duration::ZERO);
+
+    pub fn date_time(&self) -> DateTime {
+        DateTime::from_unix(self.0.as_secs(), self.0.subsec_nanos())
+    }
+}
+
+impl Add<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        SystemTime(self.0 + rhs)
+    }
+}
+
+impl Display for SystemTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.date_time())
+    }
+}
+
+/// Kernel's clock, combining the `time` counter with the wall-clock time read at boot
+pub struct Clock {
+    /// Frequency of the `time` counter, 0 if not yet known
+    timebase_frequency: AtomicU64,
+    /// Nanoseconds since the UNIX epoch at the `time` counter reset, 0 if not yet known
+    boot_time_nanos: AtomicU64,
+}
+
+impl Clock {
+    pub const fn new() -> Clock {
+        Clock {
+            timebase_frequency: AtomicU64::new(0),
+            boot_time_nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// Reads `time` counter frequency from the `/cpus` node
+    pub fn initialize_from_devicetree(&self, dt: &FlattenedDeviceTree) {
+        let frequency = dt
+            .root()
+            .expect("Cannot read device tree root")
+            .child("cpus")
+            .expect("Devicetree does not have /cpus node")
+            .property("timebase-frequency")
+            .expect("/cpus does not have timebase-frequency")
+            .usize()
+            .expect("Invaild timebase-frequency property type");
+        self.timebase_frequency
+            .store(frequency as u64, Ordering::Relaxed);
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.timebase_frequency.load(Ordering::Relaxed) != 0
+    }
+
+    /// Reads the monotonic clock
+    pub fn now(&self) -> Instant {
+        Instant(self.ticks_to_duration(rdtime()))
+    }
+
+    /// Sets the wall-clock time, assuming it was read at the given instant
+    pub fn set_system_time(&self, time: SystemTime, at: Instant) {
+        let boot_time = time.0.saturating_sub(at.0);
+        self.boot_time_nanos
+            .store(boot_time.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Reads the wall clock, if it is known
+    pub fn system_time(&self) -> Option<SystemTime> {
+        match self.boot_time_nanos.load(Ordering::Relaxed) {
+            0 => None,
+            boot_time => Some(SystemTime(
+                Duration::from_nanos(boot_time) + self.now().since_boot(),
+            )),
+        }
+    }
+
+    /// Returns the best available representation of the current time
+    pub fn timestamp(&self) -> Timestamp {
+        if let Some(time) = self.system_time() {
+            Timestamp::Wall(time)
+        } else if self.is_initialized() {
+            Timestamp::Uptime(self.now())
+        } else {
+            Timestamp::Unknown
+        }
+    }
+
+    /// Requests a timer interrupt on the current hart at given instant, or cancels it
+    pub fn set_timer(&self, at: Option<Instant>) {
+        let ticks = at.map_or(u64::MAX, |at| self.duration_to_ticks(at.0));
+        unsafe { sbi::timer::set(ticks) }.expect("Cannot set timer");
+    }
+
+    /// Converts a `time` counter value to a duration
+    pub fn ticks_to_duration(&self, ticks: u64) -> Duration {
+        match self.timebase_frequency.load(Ordering::Relaxed) as u128 {
+            0 => Duration::ZERO,
+            frequency => Duration::from_nanos((ticks as u128 * NANOS_PER_SEC / frequency) as u64),
+        }
+    }
+
+    /// Converts a duration to a number of `time` counter ticks, saturating at the largest one
+    pub fn duration_to_ticks(&self, duration: Duration) -> u64 {
+        let frequency = self.timebase_frequency.load(Ordering::Relaxed) as u128;
+        u64::try_from(duration.as_nanos() * frequency / NANOS_PER_SEC).unwrap_or(u64::MAX)
+    }
+}
+
+/// Time shown in kernel logs
+pub enum Timestamp {
+    Wall(SystemTime),
+    Uptime(Instant),
+    Unknown,
+}
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Timestamp::Wall(time) => write!(f, "{}", time),
+            Timestamp::Uptime(instant) => {
+                let uptime = instant.since_boot();
+                write!(f, "{:>5}.{:06}", uptime.as_secs(), uptime.subsec_micros())
+            }
+            Timestamp::Unknown => write!(f, "{:>12}", "-"),
+        }
+    }
+}
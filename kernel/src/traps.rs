@@ -1,9 +1,11 @@
 use core::arch::asm;
-use core::fmt::Write;
 
 use crate::{
     csr::{self, Csr},
-    sbi, Supervisor,
+    drivers::plic::Plic,
+    hart, kdebug,
+    task::scheduler,
+    Supervisor,
 };
 
 extern "C" {
@@ -11,6 +13,7 @@ extern "C" {
 }
 
 const SIE_MASK: usize = 1 << 1;
+const SSIP_MASK: usize = 1 << 1;
 
 #[inline]
 pub unsafe fn initialize_interrupts() {
@@ -28,14 +31,37 @@ pub unsafe fn disable_interrupts() {
     csr::sstatus::clear_bits(SIE_MASK);
 }
 
+/// Makes a software interrupt pending on the current hart, taken once interrupts are enabled
+pub fn raise_software_interrupt() {
+    unsafe { csr::sip::set_bits(SSIP_MASK) };
+}
+
+/// Runs a closure with interrupts disabled on the current hart, restoring previous state after
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let were_enabled = unsafe { csr::sstatus::read() } & SIE_MASK != 0;
+    unsafe { disable_interrupts() };
+    let result = f();
+    if were_enabled {
+        unsafe { enable_interrupts() };
+    }
+    result
+}
+
 #[repr(transparent)]
 #[derive(Debug, Clone, Copy)]
 pub struct TrapCause(usize);
 
+impl TrapCause {
+    /// Reads the cause of the trap being handled
+    pub fn current() -> TrapCause {
+        TrapCause(unsafe { csr::scause::read() })
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum TrapCauseDescription {
     Interrupt(InterruptCode),
-    Trap(usize),
+    Trap(ExceptionCode),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -48,6 +74,24 @@ pub enum InterruptCode {
     Platform(usize),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExceptionCode {
+    InstructionMisaligned,
+    InstructionAccessFault,
+    IllegalInstruction,
+    Breakpoint,
+    LoadMisaligned,
+    LoadAccessFault,
+    StoreMisaligned,
+    StoreAccessFault,
+    UserEnvironmentCall,
+    SupervisorEnvironmentCall,
+    InstructionPageFault,
+    LoadPageFault,
+    StorePageFault,
+    Other(usize),
+}
+
 impl From<TrapCause> for TrapCauseDescription {
     fn from(value: TrapCause) -> Self {
         let interrupt_mask = 0x1 << 63;
@@ -64,7 +108,23 @@ impl From<TrapCause> for TrapCauseDescription {
             };
             TrapCauseDescription::Interrupt(code)
         } else {
-            TrapCauseDescription::Trap(value.0)
+            let code = match value.0 {
+                0 => ExceptionCode::InstructionMisaligned,
+                1 => ExceptionCode::InstructionAccessFault,
+                2 => ExceptionCode::IllegalInstruction,
+                3 => ExceptionCode::Breakpoint,
+                4 => ExceptionCode::LoadMisaligned,
+                5 => ExceptionCode::LoadAccessFault,
+                6 => ExceptionCode::StoreMisaligned,
+                7 => ExceptionCode::StoreAccessFault,
+                8 => ExceptionCode::UserEnvironmentCall,
+                9 => ExceptionCode::SupervisorEnvironmentCall,
+                12 => ExceptionCode::InstructionPageFault,
+                13 => ExceptionCode::LoadPageFault,
+                15 => ExceptionCode::StorePageFault,
+                other => ExceptionCode::Other(other),
+            };
+            TrapCauseDescription::Trap(code)
         }
     }
 }
@@ -99,14 +159,27 @@ impl InterruptMask {
 
 #[no_mangle]
 pub unsafe extern "C" fn trap_handler_rs(cause: TrapCause) {
-    let cause: TrapCauseDescription = cause.into();
-    let supervisor = Supervisor::global();
-
-    match cause {
-        TrapCauseDescription::Interrupt(InterruptCode::Timer) => {
-            let mut debug_output = supervisor.debug_output();
-            writeln!(debug_output, "timer interrupt received").unwrap();
-            unsafe { sbi::timer::set(0xffffffffffffffff) }.unwrap();
+    match cause.into() {
+        TrapCauseDescription::Interrupt(code) => handle_interrupt(code),
+        other => {
+            panic!("unhandled trap: {:?}", other)
+        }
+    }
+}
+
+/// Handles an interrupt taken either in the kernel or in user mode
+pub fn handle_interrupt(code: InterruptCode) {
+    match code {
+        InterruptCode::Timer => {
+            scheduler::timer_interrupt();
+        }
+        InterruptCode::Software => {
+            // IPIs and raised software interrupts only make harts check their run queues
+            unsafe { csr::sip::clear_bits(SSIP_MASK) };
+            scheduler::software_interrupt();
+        }
+        InterruptCode::External => {
+            handle_external_interrupts(Supervisor::global());
         }
         other => {
             panic!("unhandled interrupt: {:?}", other)
@@ -114,6 +187,27 @@ pub unsafe extern "C" fn trap_handler_rs(cause: TrapCause) {
     }
 }
 
+/// Claims and completes all pending external interrupts of the current hart
+fn handle_external_interrupts(supervisor: &Supervisor) {
+    let Some(plic) = supervisor.devices().find::<Plic>() else {
+        return;
+    };
+    let hart_id = hart::current_id();
+    while let Some(irq) = plic.claim(hart_id) {
+        if !plic.dispatch(irq) {
+            kdebug!("Unhandled external interrupt {}", irq);
+        }
+        plic.complete(hart_id, irq);
+    }
+}
+
+/// Waits until an interrupt becomes pending. Works with interrupts disabled as well,
+/// in which case it is not handled until they are enabled
+pub fn wait_for_interrupt() {
+    // SAFETY: this instruction only stalls the hart
+    unsafe { asm!("wfi") }
+}
+
 pub fn wfi() -> ! {
     // safety: this instruction hangs processor until an interrupt is received
     unsafe { asm!("wfi") }
@@ -0,0 +1,144 @@
+//! Address Resolution Protocol (RFC 826), finding MAC addresses of IPv4 hosts on a link
+//!
+//! Only Ethernet hardware addresses and IPv4 protocol addresses are handled.
+
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+use crate::{ethernet::ETHERTYPE_IPV4, read_array, read_u16, MacAddress, PacketError};
+
+const HARDWARE_ETHERNET: u16 = 1;
+pub const PACKET_LENGTH: usize = 28;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpOperation {
+    Request = 1,
+    Reply = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArpPacket {
+    pub operation: ArpOperation,
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv4Addr,
+    /// Unknown, and usually zero, in requests
+    pub target_mac: MacAddress,
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpPacket {
+    /// Asks who has `target_ip`
+    pub fn request(sender_mac: MacAddress, sender_ip: Ipv4Addr, target_ip: Ipv4Addr) -> ArpPacket {
+        ArpPacket {
+            operation: ArpOperation::Request,
+            sender_mac,
+            sender_ip,
+            target_mac: MacAddress::default(),
+            target_ip,
+        }
+    }
+
+    /// Answers a request for our address, which is its target
+    pub fn reply(&self, mac: MacAddress) -> ArpPacket {
+        ArpPacket {
+            operation: ArpOperation::Reply,
+            sender_mac: mac,
+            sender_ip: self.target_ip,
+            target_mac: self.sender_mac,
+            target_ip: self.sender_ip,
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<ArpPacket, PacketError> {
+        if bytes.len() < PACKET_LENGTH {
+            return Err(PacketError::Truncated);
+        }
+        if read_u16(bytes, 0) != HARDWARE_ETHERNET
+            || read_u16(bytes, 2) != ETHERTYPE_IPV4
+            || bytes[4] != 6
+            || bytes[5] != 4
+        {
+            return Err(PacketError::Unsupported {
+                what: "ARP address types",
+            });
+        }
+        let operation = match read_u16(bytes, 6) {
+            1 => ArpOperation::Request,
+            2 => ArpOperation::Reply,
+            _ => {
+                return Err(PacketError::Unsupported {
+                    what: "ARP operation",
+                })
+            }
+        };
+        Ok(ArpPacket {
+            operation,
+            sender_mac: MacAddress(read_array(bytes, 8)),
+            sender_ip: Ipv4Addr::from(read_array::<4>(bytes, 14)),
+            target_mac: MacAddress(read_array(bytes, 18)),
+            target_ip: Ipv4Addr::from(read_array::<4>(bytes, 24)),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PACKET_LENGTH);
+        bytes.extend_from_slice(&HARDWARE_ETHERNET.to_be_bytes());
+        bytes.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        bytes.extend_from_slice(&[6, 4]);
+        bytes.extend_from_slice(&(self.operation as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.sender_mac.0);
+        bytes.extend_from_slice(&self.sender_ip.octets());
+        bytes.extend_from_slice(&self.target_mac.0);
+        bytes.extend_from_slice(&self.target_ip.octets());
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::net::Ipv4Addr;
+
+    use super::{ArpOperation, ArpPacket, PACKET_LENGTH};
+    use crate::{MacAddress, PacketError};
+
+    #[test]
+    fn test_request_and_reply() {
+        let ours = MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+        let theirs = MacAddress([0x52, 0x55, 0x0a, 0x00, 0x02, 0x02]);
+        let request = ArpPacket::request(
+            theirs,
+            Ipv4Addr::new(10, 0, 2, 2),
+            Ipv4Addr::new(10, 0, 2, 15),
+        );
+        let bytes = request.to_bytes();
+        assert_eq!(bytes.len(), PACKET_LENGTH);
+        assert_eq!(&bytes[..8], &[0, 1, 8, 0, 6, 4, 0, 1]);
+        assert_eq!(ArpPacket::parse(&bytes), Ok(request));
+
+        let reply = ArpPacket::parse(&bytes).unwrap().reply(ours);
+        assert_eq!(reply.operation, ArpOperation::Reply);
+        assert_eq!(reply.sender_mac, ours);
+        assert_eq!(reply.sender_ip, Ipv4Addr::new(10, 0, 2, 15));
+        assert_eq!(reply.target_mac, theirs);
+        assert_eq!(reply.target_ip, Ipv4Addr::new(10, 0, 2, 2));
+    }
+
+    #[test]
+    fn test_invaild_packets() {
+        let mut bytes = ArpPacket::request(
+            MacAddress::default(),
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+        )
+        .to_bytes();
+        assert_eq!(
+            ArpPacket::parse(&bytes[..PACKET_LENGTH - 1]),
+            Err(PacketError::Truncated)
+        );
+        bytes[7] = 3;
+        assert!(matches!(
+            ArpPacket::parse(&bytes),
+            Err(PacketError::Unsupported { .. })
+        ));
+    }
+}
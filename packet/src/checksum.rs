@@ -0,0 +1,91 @@
+//! Internet checksum (RFC 1071), the ones' complement of the ones' complement sum of 16-bit
+//! words, used by IPv4, ICMP, UDP and TCP
+
+use core::net::Ipv4Addr;
+
+/// Sum of data being checksummed, which may be added in several parts
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Checksum {
+    sum: u64,
+}
+
+impl Checksum {
+    pub fn new() -> Checksum {
+        Checksum::default()
+    }
+
+    /// Adds bytes to the sum. Only the last part may have an odd length, as it is padded
+    /// with a zero byte
+    pub fn add(&mut self, data: &[u8]) -> &mut Checksum {
+        let mut words = data.chunks_exact(2);
+        for word in &mut words {
+            self.sum += u16::from_be_bytes([word[0], word[1]]) as u64;
+        }
+        if let [last] = words.remainder() {
+            self.sum += (*last as u64) << 8;
+        }
+        self
+    }
+
+    /// Adds the pseudo-header covered by UDP and TCP checksums
+    pub fn add_pseudo_header(
+        &mut self,
+        source: Ipv4Addr,
+        destination: Ipv4Addr,
+        protocol: u8,
+        length: usize,
+    ) -> &mut Checksum {
+        self.add(&source.octets())
+            .add(&destination.octets())
+            .add(&[0, protocol])
+            .add(&(length as u16).to_be_bytes())
+    }
+
+    /// Checksum of the added data. Data including a valid checksum gives 0
+    pub fn finish(&self) -> u16 {
+        let mut sum = self.sum;
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+}
+
+/// Checksum of a single buffer
+pub fn checksum(data: &[u8]) -> u16 {
+    Checksum::new().add(data).finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use core::net::Ipv4Addr;
+
+    use super::{checksum, Checksum};
+
+    #[test]
+    fn test_checksum() {
+        // example from RFC 1071
+        let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        assert_eq!(checksum(&data), !0xddf2);
+        assert_eq!(checksum(&[]), 0xffff);
+        // odd lengths are padded
+        assert_eq!(checksum(&[0x12, 0x34, 0x56]), !0x6834);
+
+        let mut parts = Checksum::new();
+        parts.add(&data[..4]).add(&data[4..]);
+        assert_eq!(parts.finish(), checksum(&data));
+    }
+
+    #[test]
+    fn test_pseudo_header() {
+        let mut pseudo = Checksum::new();
+        pseudo.add_pseudo_header(
+            Ipv4Addr::new(10, 0, 2, 15),
+            Ipv4Addr::new(10, 0, 2, 2),
+            17,
+            8,
+        );
+        let expected = checksum(&[10, 0, 2, 15, 10, 0, 2, 2, 0, 17, 0, 8]);
+        assert_eq!(pseudo.finish(), expected);
+    }
+}
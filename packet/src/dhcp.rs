@@ -0,0 +1,358 @@
+//! Dynamic Host Configuration Protocol (RFC 2131), leasing addresses to IPv4 hosts
+//!
+//! Messages are BOOTP ones carrying options (RFC 2132). Of the options, those needed to
+//! lease an address and find the default gateway are understood, others are skipped when
+//! parsing.
+
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+use crate::{read_array, read_u16, MacAddress, PacketError};
+
+pub const SERVER_PORT: u16 = 67;
+pub const CLIENT_PORT: u16 = 68;
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+const HARDWARE_ETHERNET: u8 = 1;
+/// Asks servers to broadcast replies, as the client cannot receive unicast ones before it
+/// is configured
+const FLAG_BROADCAST: u16 = 0x8000;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Length of the BOOTP part, which is followed by the magic cookie and options
+const HEADER_LENGTH: usize = 236;
+/// Length of sent messages, padded as if options took the whole 312 bytes of the original
+/// options field, since some servers ignore shorter messages
+const MESSAGE_LENGTH: usize = HEADER_LENGTH + 312;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_REQUESTED_ADDRESS: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_IDENTIFIER: u8 = 54;
+const OPTION_PARAMETER_REQUEST: u8 = 55;
+const OPTION_RENEWAL_TIME: u8 = 58;
+const OPTION_REBINDING_TIME: u8 = 59;
+const OPTION_END: u8 = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhcpMessageType {
+    Discover = 1,
+    Offer = 2,
+    Request = 3,
+    Decline = 4,
+    Ack = 5,
+    Nak = 6,
+    Release = 7,
+}
+
+impl DhcpMessageType {
+    /// Whether messages of the type are sent by servers
+    fn is_reply(self) -> bool {
+        matches!(
+            self,
+            DhcpMessageType::Offer | DhcpMessageType::Ack | DhcpMessageType::Nak
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DhcpMessage {
+    pub kind: DhcpMessageType,
+    /// Chosen by the client and copied to replies, to match them with requests
+    pub transaction: u32,
+    /// Whether the server has to broadcast replies
+    pub broadcast: bool,
+    /// Address of a client renewing its lease
+    pub client_address: Ipv4Addr,
+    /// Address offered or assigned to the client
+    pub your_address: Ipv4Addr,
+    pub client_mac: MacAddress,
+    pub subnet_mask: Option<Ipv4Addr>,
+    /// First of the routers, which is used as the default gateway
+    pub router: Option<Ipv4Addr>,
+    /// Address from an offer, which the client asks for
+    pub requested_address: Option<Ipv4Addr>,
+    pub server_identifier: Option<Ipv4Addr>,
+    /// Length of the lease in seconds, like the times of renewing and rebinding it
+    pub lease_time: Option<u32>,
+    pub renewal_time: Option<u32>,
+    pub rebinding_time: Option<u32>,
+}
+
+impl DhcpMessage {
+    /// Message without addresses and options
+    pub fn new(kind: DhcpMessageType, transaction: u32, client_mac: MacAddress) -> DhcpMessage {
+        DhcpMessage {
+            kind,
+            transaction,
+            broadcast: false,
+            client_address: Ipv4Addr::UNSPECIFIED,
+            your_address: Ipv4Addr::UNSPECIFIED,
+            client_mac,
+            subnet_mask: None,
+            router: None,
+            requested_address: None,
+            server_identifier: None,
+            lease_time: None,
+            renewal_time: None,
+            rebinding_time: None,
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<DhcpMessage, PacketError> {
+        if bytes.len() < HEADER_LENGTH + MAGIC_COOKIE.len() {
+            return Err(PacketError::Truncated);
+        }
+        if bytes[1] != HARDWARE_ETHERNET || bytes[2] != 6 {
+            return Err(PacketError::Unsupported {
+                what: "DHCP hardware address type",
+            });
+        }
+        if !matches!(bytes[0], OP_REQUEST | OP_REPLY)
+            || bytes[HEADER_LENGTH..HEADER_LENGTH + 4] != MAGIC_COOKIE
+        {
+            return Err(PacketError::InvaildHeader);
+        }
+        let mut message = DhcpMessage {
+            broadcast: read_u16(bytes, 10) & FLAG_BROADCAST != 0,
+            client_address: Ipv4Addr::from(read_array::<4>(bytes, 12)),
+            your_address: Ipv4Addr::from(read_array::<4>(bytes, 16)),
+            ..DhcpMessage::new(
+                DhcpMessageType::Discover,
+                u32::from_be_bytes(read_array(bytes, 4)),
+                MacAddress(read_array(bytes, 28)),
+            )
+        };
+        let mut kind = None;
+
+        let mut options = &bytes[HEADER_LENGTH + 4..];
+        while let Some(&code) = options.first() {
+            if code == OPTION_END {
+                break;
+            }
+            if code == OPTION_PAD {
+                options = &options[1..];
+                continue;
+            }
+            let length = *options.get(1).ok_or(PacketError::InvaildHeader)? as usize;
+            let data = options
+                .get(2..2 + length)
+                .ok_or(PacketError::InvaildHeader)?;
+            match code {
+                OPTION_MESSAGE_TYPE => kind = Some(parse_type(data)?),
+                OPTION_SUBNET_MASK => message.subnet_mask = Some(parse_address(data)?),
+                OPTION_ROUTER => message.router = Some(parse_address(data)?),
+                OPTION_REQUESTED_ADDRESS => message.requested_address = Some(parse_address(data)?),
+                OPTION_SERVER_IDENTIFIER => message.server_identifier = Some(parse_address(data)?),
+                OPTION_LEASE_TIME => message.lease_time = Some(parse_seconds(data)?),
+                OPTION_RENEWAL_TIME => message.renewal_time = Some(parse_seconds(data)?),
+                OPTION_REBINDING_TIME => message.rebinding_time = Some(parse_seconds(data)?),
+                _ => {}
+            }
+            options = &options[2 + length..];
+        }
+
+        message.kind = kind.ok_or(PacketError::InvaildHeader)?;
+        if message.kind.is_reply() != (bytes[0] == OP_REPLY) {
+            return Err(PacketError::InvaildHeader);
+        }
+        Ok(message)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MESSAGE_LENGTH);
+        let op = if self.kind.is_reply() {
+            OP_REPLY
+        } else {
+            OP_REQUEST
+        };
+        // hardware address type and length, and hops
+        bytes.extend_from_slice(&[op, HARDWARE_ETHERNET, 6, 0]);
+        bytes.extend_from_slice(&self.transaction.to_be_bytes());
+        // seconds elapsed since the client started
+        bytes.extend_from_slice(&[0, 0]);
+        let flags = if self.broadcast { FLAG_BROADCAST } else { 0 };
+        bytes.extend_from_slice(&flags.to_be_bytes());
+        bytes.extend_from_slice(&self.client_address.octets());
+        bytes.extend_from_slice(&self.your_address.octets());
+        // addresses of the next server and of a relay agent
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&self.client_mac.0);
+        // rest of the hardware address, server host name and boot file name
+        bytes.resize(HEADER_LENGTH, 0);
+        bytes.extend_from_slice(&MAGIC_COOKIE);
+
+        push_option(&mut bytes, OPTION_MESSAGE_TYPE, &[self.kind as u8]);
+        let addresses = [
+            (OPTION_SUBNET_MASK, self.subnet_mask),
+            (OPTION_ROUTER, self.router),
+            (OPTION_REQUESTED_ADDRESS, self.requested_address),
+            (OPTION_SERVER_IDENTIFIER, self.server_identifier),
+        ];
+        for (code, address) in addresses {
+            if let Some(address) = address {
+                push_option(&mut bytes, code, &address.octets());
+            }
+        }
+        let times = [
+            (OPTION_LEASE_TIME, self.lease_time),
+            (OPTION_RENEWAL_TIME, self.renewal_time),
+            (OPTION_REBINDING_TIME, self.rebinding_time),
+        ];
+        for (code, seconds) in times {
+            if let Some(seconds) = seconds {
+                push_option(&mut bytes, code, &seconds.to_be_bytes());
+            }
+        }
+        if !self.kind.is_reply() {
+            push_option(
+                &mut bytes,
+                OPTION_PARAMETER_REQUEST,
+                &[OPTION_SUBNET_MASK, OPTION_ROUTER],
+            );
+        }
+        bytes.push(OPTION_END);
+        bytes.resize(bytes.len().max(MESSAGE_LENGTH), OPTION_PAD);
+        bytes
+    }
+}
+
+fn push_option(bytes: &mut Vec<u8>, code: u8, data: &[u8]) {
+    bytes.extend_from_slice(&[code, data.len() as u8]);
+    bytes.extend_from_slice(data);
+}
+
+fn parse_type(data: &[u8]) -> Result<DhcpMessageType, PacketError> {
+    match data {
+        [1] => Ok(DhcpMessageType::Discover),
+        [2] => Ok(DhcpMessageType::Offer),
+        [3] => Ok(DhcpMessageType::Request),
+        [4] => Ok(DhcpMessageType::Decline),
+        [5] => Ok(DhcpMessageType::Ack),
+        [6] => Ok(DhcpMessageType::Nak),
+        [7] => Ok(DhcpMessageType::Release),
+        [_] => Err(PacketError::Unsupported {
+            what: "DHCP message type",
+        }),
+        _ => Err(PacketError::InvaildHeader),
+    }
+}
+
+/// Parses the first address of an option holding a list of them
+fn parse_address(data: &[u8]) -> Result<Ipv4Addr, PacketError> {
+    if data.is_empty() || !data.len().is_multiple_of(4) {
+        return Err(PacketError::InvaildHeader);
+    }
+    Ok(Ipv4Addr::from(read_array::<4>(data, 0)))
+}
+
+fn parse_seconds(data: &[u8]) -> Result<u32, PacketError> {
+    let data: [u8; 4] = data.try_into().map_err(|_| PacketError::InvaildHeader)?;
+    Ok(u32::from_be_bytes(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use core::net::Ipv4Addr;
+
+    use super::{DhcpMessage, DhcpMessageType, HEADER_LENGTH, MESSAGE_LENGTH};
+    use crate::{MacAddress, PacketError};
+
+    const MAC: MacAddress = MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+
+    fn offer() -> DhcpMessage {
+        DhcpMessage {
+            your_address: Ipv4Addr::new(10, 0, 2, 15),
+            subnet_mask: Some(Ipv4Addr::new(255, 255, 255, 0)),
+            router: Some(Ipv4Addr::new(10, 0, 2, 2)),
+            server_identifier: Some(Ipv4Addr::new(10, 0, 2, 2)),
+            lease_time: Some(86400),
+            ..DhcpMessage::new(DhcpMessageType::Offer, 0xdeadbeef, MAC)
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let discover = DhcpMessage {
+            broadcast: true,
+            ..DhcpMessage::new(DhcpMessageType::Discover, 0xdeadbeef, MAC)
+        };
+        let bytes = discover.to_bytes();
+        assert_eq!(bytes.len(), MESSAGE_LENGTH);
+        assert_eq!(&bytes[..4], &[1, 1, 6, 0]);
+        assert_eq!(&bytes[28..34], &MAC.0);
+        assert_eq!(DhcpMessage::parse(&bytes), Ok(discover));
+
+        let bytes = offer().to_bytes();
+        assert_eq!(bytes[0], 2);
+        assert_eq!(DhcpMessage::parse(&bytes), Ok(offer()));
+
+        let request = DhcpMessage {
+            client_address: Ipv4Addr::new(10, 0, 2, 15),
+            renewal_time: Some(60),
+            rebinding_time: Some(90),
+            ..DhcpMessage::new(DhcpMessageType::Request, 1, MAC)
+        };
+        assert_eq!(DhcpMessage::parse(&request.to_bytes()), Ok(request));
+    }
+
+    #[test]
+    fn test_options() {
+        let mut bytes = offer().to_bytes();
+        bytes.truncate(HEADER_LENGTH + 4);
+        // pad, two routers, an unknown option, message type and lease time
+        bytes.extend_from_slice(&[0, 3, 8, 10, 0, 2, 3, 10, 0, 2, 4, 12, 2, b'o', b's']);
+        bytes.extend_from_slice(&[53, 1, 5, 51, 4, 0, 0, 0x0e, 0x10, 255]);
+        let message = DhcpMessage::parse(&bytes).unwrap();
+        assert_eq!(message.kind, DhcpMessageType::Ack);
+        assert_eq!(message.router, Some(Ipv4Addr::new(10, 0, 2, 3)));
+        assert_eq!(message.lease_time, Some(3600));
+        assert_eq!(message.subnet_mask, None);
+        assert_eq!(message.your_address, Ipv4Addr::new(10, 0, 2, 15));
+
+        // lease time of a wrong length
+        let length = bytes.len();
+        bytes[length - 6] = 3;
+        assert_eq!(DhcpMessage::parse(&bytes), Err(PacketError::InvaildHeader));
+        // option running past the message
+        bytes.truncate(length - 4);
+        bytes[length - 6] = 4;
+        assert_eq!(DhcpMessage::parse(&bytes), Err(PacketError::InvaildHeader));
+    }
+
+    #[test]
+    fn test_invaild_messages() {
+        let bytes = offer().to_bytes();
+        assert_eq!(
+            DhcpMessage::parse(&bytes[..HEADER_LENGTH]),
+            Err(PacketError::Truncated)
+        );
+
+        let mut cookie = bytes.clone();
+        cookie[HEADER_LENGTH] = 0;
+        assert_eq!(DhcpMessage::parse(&cookie), Err(PacketError::InvaildHeader));
+
+        // offers are replies
+        let mut op = bytes.clone();
+        op[0] = 1;
+        assert_eq!(DhcpMessage::parse(&op), Err(PacketError::InvaildHeader));
+
+        let mut kind = bytes.clone();
+        kind[HEADER_LENGTH + 6] = 42;
+        assert_eq!(
+            DhcpMessage::parse(&kind),
+            Err(PacketError::Unsupported {
+                what: "DHCP message type"
+            })
+        );
+
+        // BOOTP reply without a message type
+        let mut bootp = bytes;
+        bootp.truncate(HEADER_LENGTH + 4);
+        bootp.push(255);
+        assert_eq!(DhcpMessage::parse(&bootp), Err(PacketError::InvaildHeader));
+    }
+}
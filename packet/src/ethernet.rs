@@ -0,0 +1,109 @@
+//! Ethernet II frames, without VLAN tags or the frame check sequence
+
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Formatter};
+
+use crate::{read_array, read_u16, PacketError};
+
+pub const HEADER_LENGTH: usize = 14;
+/// Largest payload of a frame
+pub const MTU: usize = 1500;
+/// Shortest frame without the frame check sequence, shorter ones are padded
+const MIN_LENGTH: usize = 60;
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xff; 6]);
+
+    /// Whether frames sent to the address are received by a group of hosts, which includes
+    /// the broadcast address
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 1 != 0
+    }
+}
+
+impl Display for MacAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EthernetFrame<'a> {
+    pub destination: MacAddress,
+    pub source: MacAddress,
+    pub ethertype: u16,
+    /// Payload, which may be followed by padding
+    pub payload: &'a [u8],
+}
+
+impl<'a> EthernetFrame<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<EthernetFrame<'a>, PacketError> {
+        if bytes.len() < HEADER_LENGTH {
+            return Err(PacketError::Truncated);
+        }
+        Ok(EthernetFrame {
+            destination: MacAddress(read_array(bytes, 0)),
+            source: MacAddress(read_array(bytes, 6)),
+            ethertype: read_u16(bytes, 12),
+            payload: &bytes[HEADER_LENGTH..],
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MIN_LENGTH.max(HEADER_LENGTH + self.payload.len()));
+        bytes.extend_from_slice(&self.destination.0);
+        bytes.extend_from_slice(&self.source.0);
+        bytes.extend_from_slice(&self.ethertype.to_be_bytes());
+        bytes.extend_from_slice(self.payload);
+        if bytes.len() < MIN_LENGTH {
+            bytes.resize(MIN_LENGTH, 0);
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::{EthernetFrame, MacAddress, ETHERTYPE_ARP, HEADER_LENGTH};
+    use crate::PacketError;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let frame = EthernetFrame {
+            destination: MacAddress::BROADCAST,
+            source: MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]),
+            ethertype: ETHERTYPE_ARP,
+            payload: b"payload",
+        };
+        let bytes = frame.to_bytes();
+        assert_eq!(bytes.len(), 60);
+        assert_eq!(&bytes[12..14], &[0x08, 0x06]);
+
+        let parsed = EthernetFrame::parse(&bytes).unwrap();
+        assert_eq!(parsed.destination, MacAddress::BROADCAST);
+        assert_eq!(parsed.source, frame.source);
+        assert_eq!(parsed.ethertype, ETHERTYPE_ARP);
+        assert!(parsed.payload.starts_with(b"payload"));
+        assert_eq!(
+            EthernetFrame::parse(&bytes[..HEADER_LENGTH - 1]),
+            Err(PacketError::Truncated)
+        );
+    }
+
+    #[test]
+    fn test_mac_address() {
+        let address = MacAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+        assert_eq!(address.to_string(), "52:54:00:12:34:56");
+        assert!(!address.is_multicast());
+        assert!(MacAddress::BROADCAST.is_multicast());
+    }
+}
@@ -0,0 +1,126 @@
+//! Internet Control Message Protocol (RFC 792), carrying echo requests of `ping` and errors
+//! about delivering packets
+
+use alloc::vec::Vec;
+
+use crate::{checksum::checksum, ipv4, read_array, PacketError};
+
+pub const HEADER_LENGTH: usize = 8;
+
+// message types
+pub const ECHO_REPLY: u8 = 0;
+pub const DESTINATION_UNREACHABLE: u8 = 3;
+pub const ECHO_REQUEST: u8 = 8;
+
+// codes of destination unreachable messages
+pub const PORT_UNREACHABLE: u8 = 3;
+
+/// Bytes of the payload of a packet quoted by an error message
+const QUOTED_PAYLOAD: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IcmpPacket<'a> {
+    pub kind: u8,
+    pub code: u8,
+    /// Rest of the header, the identifier and sequence number of echo messages
+    pub rest: [u8; 4],
+    pub payload: &'a [u8],
+}
+
+impl<'a> IcmpPacket<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<IcmpPacket<'a>, PacketError> {
+        if bytes.len() < HEADER_LENGTH {
+            return Err(PacketError::Truncated);
+        }
+        if checksum(bytes) != 0 {
+            return Err(PacketError::InvaildChecksum);
+        }
+        Ok(IcmpPacket {
+            kind: bytes[0],
+            code: bytes[1],
+            rest: read_array(bytes, 4),
+            payload: &bytes[HEADER_LENGTH..],
+        })
+    }
+
+    /// Answers an echo request with the same identifier, sequence number and data
+    pub fn echo_reply(&self) -> IcmpPacket<'a> {
+        IcmpPacket {
+            kind: ECHO_REPLY,
+            code: 0,
+            rest: self.rest,
+            payload: self.payload,
+        }
+    }
+
+    /// Error about an IPv4 packet not being delivered, quoting its header and the beginning
+    /// of its payload
+    pub fn unreachable(code: u8, packet: &'a [u8]) -> IcmpPacket<'a> {
+        let header_length = packet
+            .first()
+            .map_or(0, |byte| (byte & 0xf) as usize * 4)
+            .max(ipv4::HEADER_LENGTH);
+        let quoted = packet.len().min(header_length + QUOTED_PAYLOAD);
+        IcmpPacket {
+            kind: DESTINATION_UNREACHABLE,
+            code,
+            rest: [0; 4],
+            payload: &packet[..quoted],
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LENGTH + self.payload.len());
+        bytes.extend_from_slice(&[self.kind, self.code, 0, 0]);
+        bytes.extend_from_slice(&self.rest);
+        bytes.extend_from_slice(self.payload);
+        let sum = checksum(&bytes);
+        bytes[2..4].copy_from_slice(&sum.to_be_bytes());
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IcmpPacket, DESTINATION_UNREACHABLE, ECHO_REPLY, ECHO_REQUEST, PORT_UNREACHABLE};
+    use crate::PacketError;
+
+    #[test]
+    fn test_echo() {
+        // echo request with identifier 1 and sequence number 7, as sent by ping
+        let request = IcmpPacket {
+            kind: ECHO_REQUEST,
+            code: 0,
+            rest: [0, 1, 0, 7],
+            payload: b"abcdefgh",
+        };
+        let mut bytes = request.to_bytes();
+        assert_eq!(&bytes[..4], &[8, 0, 0x66, 0x62]);
+        let parsed = IcmpPacket::parse(&bytes).unwrap();
+        assert_eq!(parsed, request);
+
+        let reply = parsed.echo_reply().to_bytes();
+        let reply = IcmpPacket::parse(&reply).unwrap();
+        assert_eq!(reply.kind, ECHO_REPLY);
+        assert_eq!(reply.rest, [0, 1, 0, 7]);
+        assert_eq!(reply.payload, b"abcdefgh");
+
+        bytes[9] ^= 1;
+        assert_eq!(IcmpPacket::parse(&bytes), Err(PacketError::InvaildChecksum));
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let mut packet = [0u8; 40];
+        packet[0] = 0x45;
+        let message = IcmpPacket::unreachable(PORT_UNREACHABLE, &packet);
+        assert_eq!(message.kind, DESTINATION_UNREACHABLE);
+        assert_eq!(message.payload.len(), 28);
+        assert_eq!(
+            IcmpPacket::unreachable(PORT_UNREACHABLE, &packet[..24])
+                .payload
+                .len(),
+            24
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! Internet Protocol version 4 (RFC 791) packets and subnets
+//!
+//! Options are skipped when parsing and never sent. Fragments are not reassembled, so they
+//! are refused, and sent packets have the "don't fragment" flag set.
+
+use alloc::vec::Vec;
+use core::{
+    fmt::{self, Display, Formatter},
+    net::Ipv4Addr,
+    str::FromStr,
+};
+
+use crate::{checksum::checksum, read_array, read_u16, PacketError};
+
+pub const HEADER_LENGTH: usize = 20;
+
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+
+/// Time to live of sent packets
+pub const DEFAULT_TTL: u8 = 64;
+
+const VERSION: u8 = 4;
+const DONT_FRAGMENT: u16 = 0x4000;
+const MORE_FRAGMENTS: u16 = 0x2000;
+const FRAGMENT_OFFSET: u16 = 0x1fff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Packet<'a> {
+    pub source: Ipv4Addr,
+    pub destination: Ipv4Addr,
+    pub protocol: u8,
+    pub ttl: u8,
+    pub identification: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Ipv4Packet<'a> {
+    /// Parses a packet, dropping any padding after its total length
+    pub fn parse(bytes: &'a [u8]) -> Result<Ipv4Packet<'a>, PacketError> {
+        if bytes.len() < HEADER_LENGTH {
+            return Err(PacketError::Truncated);
+        }
+        if bytes[0] >> 4 != VERSION {
+            return Err(PacketError::InvaildHeader);
+        }
+        let header_length = (bytes[0] & 0xf) as usize * 4;
+        let total_length = read_u16(bytes, 2) as usize;
+        if header_length < HEADER_LENGTH || total_length < header_length {
+            return Err(PacketError::InvaildHeader);
+        }
+        if total_length > bytes.len() {
+            return Err(PacketError::Truncated);
+        }
+        if checksum(&bytes[..header_length]) != 0 {
+            return Err(PacketError::InvaildChecksum);
+        }
+        let fragment = read_u16(bytes, 6);
+        if fragment & (MORE_FRAGMENTS | FRAGMENT_OFFSET) != 0 {
+            return Err(PacketError::Unsupported {
+                what: "fragmented packets",
+            });
+        }
+        Ok(Ipv4Packet {
+            source: Ipv4Addr::from(read_array::<4>(bytes, 12)),
+            destination: Ipv4Addr::from(read_array::<4>(bytes, 16)),
+            protocol: bytes[9],
+            ttl: bytes[8],
+            identification: read_u16(bytes, 4),
+            payload: &bytes[header_length..total_length],
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let total_length = HEADER_LENGTH + self.payload.len();
+        let mut bytes = Vec::with_capacity(total_length);
+        bytes.push(VERSION << 4 | (HEADER_LENGTH / 4) as u8);
+        // type of service
+        bytes.push(0);
+        bytes.extend_from_slice(&(total_length as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.identification.to_be_bytes());
+        bytes.extend_from_slice(&DONT_FRAGMENT.to_be_bytes());
+        bytes.push(self.ttl);
+        bytes.push(self.protocol);
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(&self.source.octets());
+        bytes.extend_from_slice(&self.destination.octets());
+        let header_checksum = checksum(&bytes);
+        bytes[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+        bytes.extend_from_slice(self.payload);
+        bytes
+    }
+}
+
+/// Address of a host along with the length of its subnet's prefix, e.g. `10.0.2.15/24`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Cidr {
+    pub address: Ipv4Addr,
+    pub prefix: u8,
+}
+
+impl Ipv4Cidr {
+    pub fn new(address: Ipv4Addr, prefix: u8) -> Option<Ipv4Cidr> {
+        (prefix <= 32).then_some(Ipv4Cidr { address, prefix })
+    }
+
+    pub fn netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0))
+    }
+
+    /// First address of the subnet
+    pub fn network(&self) -> Ipv4Addr {
+        self.address & self.netmask()
+    }
+
+    /// Last address of the subnet, to which broadcasts are sent
+    pub fn broadcast(&self) -> Ipv4Addr {
+        self.address | !self.netmask()
+    }
+
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        address & self.netmask() == self.network()
+    }
+}
+
+impl Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+impl FromStr for Ipv4Cidr {
+    type Err = PacketError;
+
+    fn from_str(s: &str) -> Result<Ipv4Cidr, PacketError> {
+        let (address, prefix) = s.split_once('/').ok_or(PacketError::InvaildAddress)?;
+        let address = address.parse().map_err(|_| PacketError::InvaildAddress)?;
+        let prefix = prefix.parse().map_err(|_| PacketError::InvaildAddress)?;
+        Ipv4Cidr::new(address, prefix).ok_or(PacketError::InvaildAddress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use core::net::Ipv4Addr;
+
+    use super::{Ipv4Cidr, Ipv4Packet, PROTOCOL_UDP};
+    use crate::PacketError;
+
+    /// Header of a UDP packet of 115 bytes, with checksum 0xb861
+    const HEADER: [u8; 20] = [
+        0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8, 0x00,
+        0x01, 0xc0, 0xa8, 0x00, 0xc7,
+    ];
+
+    #[test]
+    fn test_parse() {
+        let mut bytes = HEADER.to_vec();
+        bytes.resize(0x73 + 4, 0xaa);
+        let packet = Ipv4Packet::parse(&bytes).unwrap();
+        assert_eq!(packet.source, Ipv4Addr::new(192, 168, 0, 1));
+        assert_eq!(packet.destination, Ipv4Addr::new(192, 168, 0, 199));
+        assert_eq!(packet.protocol, PROTOCOL_UDP);
+        assert_eq!(packet.ttl, 64);
+        // padding is dropped
+        assert_eq!(packet.payload.len(), 0x73 - 20);
+
+        assert_eq!(
+            Ipv4Packet::parse(&bytes[..100]),
+            Err(PacketError::Truncated)
+        );
+        bytes[11] ^= 1;
+        assert_eq!(Ipv4Packet::parse(&bytes), Err(PacketError::InvaildChecksum));
+        bytes[11] ^= 1;
+        bytes[0] = 0x65;
+        assert_eq!(Ipv4Packet::parse(&bytes), Err(PacketError::InvaildHeader));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let packet = Ipv4Packet {
+            source: Ipv4Addr::new(10, 0, 2, 15),
+            destination: Ipv4Addr::new(10, 0, 2, 2),
+            protocol: PROTOCOL_UDP,
+            ttl: 64,
+            identification: 0x1234,
+            payload: b"data",
+        };
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes.len(), 24);
+        assert_eq!(Ipv4Packet::parse(&bytes), Ok(packet));
+    }
+
+    #[test]
+    fn test_fragments() {
+        let mut bytes = HEADER.to_vec();
+        bytes.resize(0x73, 0);
+        // more fragments follow, the checksum being adjusted for the changed flag
+        bytes[6] = 0x60;
+        bytes[10..12].copy_from_slice(&(0xb861u16 - 0x2000).to_be_bytes());
+        assert!(matches!(
+            Ipv4Packet::parse(&bytes),
+            Err(PacketError::Unsupported { .. })
+        ));
+    }
+
+    #[test]
+    fn test_cidr() {
+        let cidr: Ipv4Cidr = "10.0.2.15/24".parse().unwrap();
+        assert_eq!(cidr.address, Ipv4Addr::new(10, 0, 2, 15));
+        assert_eq!(cidr.netmask(), Ipv4Addr::new(255, 255, 255, 0));
+        assert_eq!(cidr.network(), Ipv4Addr::new(10, 0, 2, 0));
+        assert_eq!(cidr.broadcast(), Ipv4Addr::new(10, 0, 2, 255));
+        assert!(cidr.contains(Ipv4Addr::new(10, 0, 2, 2)));
+        assert!(!cidr.contains(Ipv4Addr::new(10, 0, 3, 2)));
+        assert_eq!(cidr.to_string(), "10.0.2.15/24");
+
+        let everything: Ipv4Cidr = "0.0.0.0/0".parse().unwrap();
+        assert_eq!(everything.netmask(), Ipv4Addr::UNSPECIFIED);
+        assert!(everything.contains(Ipv4Addr::new(1, 2, 3, 4)));
+
+        for invaild in ["10.0.2.15", "10.0.2.15/33", "10.0.2/24", "10.0.2.15/x"] {
+            assert_eq!(
+                invaild.parse::<Ipv4Cidr>(),
+                Err(PacketError::InvaildAddress)
+            );
+        }
+    }
+}
@@ -0,0 +1,50 @@
+#![no_std]
+
+//! Wire formats of network protocols: Ethernet, ARP, IPv4, ICMP, UDP, TCP and DHCP
+//!
+//! Each protocol has a packet type which borrows its payload when parsed from received
+//! bytes and is serialized with `to_bytes`. Parsing checks lengths and checksums, so
+//! a parsed packet can be trusted to be well-formed. Multi-byte fields are big-endian.
+
+extern crate alloc;
+
+pub mod arp;
+pub mod checksum;
+pub mod dhcp;
+pub mod ethernet;
+pub mod icmp;
+pub mod ipv4;
+pub mod tcp;
+pub mod udp;
+
+use snafu::Snafu;
+
+pub use arp::{ArpOperation, ArpPacket};
+pub use dhcp::{DhcpMessage, DhcpMessageType};
+pub use ethernet::{EthernetFrame, MacAddress};
+pub use icmp::IcmpPacket;
+pub use ipv4::{Ipv4Cidr, Ipv4Packet};
+pub use tcp::{TcpFlags, TcpSegment};
+pub use udp::UdpDatagram;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Snafu)]
+pub enum PacketError {
+    #[snafu(display("Packet truncated"))]
+    Truncated,
+    #[snafu(display("Invaild header"))]
+    InvaildHeader,
+    #[snafu(display("Invaild checksum"))]
+    InvaildChecksum,
+    #[snafu(display("Invaild address"))]
+    InvaildAddress,
+    #[snafu(display("Unsupported {what}"))]
+    Unsupported { what: &'static str },
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_array<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    bytes[offset..offset + N].try_into().unwrap()
+}
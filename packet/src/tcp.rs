@@ -0,0 +1,255 @@
+//! Transmission Control Protocol segments (RFC 9293)
+//!
+//! Of the options, only the maximum segment size is understood, others are skipped when
+//! parsing.
+
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+use bitflags::bitflags;
+
+use crate::{checksum::Checksum, ipv4::PROTOCOL_TCP, read_array, read_u16, PacketError};
+
+/// Length of a header without options
+pub const HEADER_LENGTH: usize = 20;
+
+const OPTION_END: u8 = 0;
+const OPTION_NOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+const OPTION_MSS_LENGTH: usize = 4;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TcpFlags: u8 {
+        const FIN = 1 << 0;
+        const SYN = 1 << 1;
+        const RST = 1 << 2;
+        const PSH = 1 << 3;
+        const ACK = 1 << 4;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpSegment<'a> {
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub sequence: u32,
+    /// Next sequence number expected from the peer, valid with [`TcpFlags::ACK`]
+    pub acknowledgment: u32,
+    pub flags: TcpFlags,
+    pub window: u16,
+    /// Maximum segment size option, sent in SYN segments
+    pub mss: Option<u16>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> TcpSegment<'a> {
+    /// Parses the payload of an IPv4 packet between given addresses, which are covered by
+    /// the checksum
+    pub fn parse(
+        bytes: &'a [u8],
+        source: Ipv4Addr,
+        destination: Ipv4Addr,
+    ) -> Result<TcpSegment<'a>, PacketError> {
+        if bytes.len() < HEADER_LENGTH {
+            return Err(PacketError::Truncated);
+        }
+        let header_length = (bytes[12] >> 4) as usize * 4;
+        if header_length < HEADER_LENGTH {
+            return Err(PacketError::InvaildHeader);
+        }
+        if header_length > bytes.len() {
+            return Err(PacketError::Truncated);
+        }
+        let sum = Checksum::new()
+            .add_pseudo_header(source, destination, PROTOCOL_TCP, bytes.len())
+            .add(bytes)
+            .finish();
+        if sum != 0 {
+            return Err(PacketError::InvaildChecksum);
+        }
+        Ok(TcpSegment {
+            source_port: read_u16(bytes, 0),
+            destination_port: read_u16(bytes, 2),
+            sequence: u32::from_be_bytes(read_array(bytes, 4)),
+            acknowledgment: u32::from_be_bytes(read_array(bytes, 8)),
+            flags: TcpFlags::from_bits_truncate(bytes[13]),
+            window: read_u16(bytes, 14),
+            mss: parse_mss(&bytes[HEADER_LENGTH..header_length])?,
+            payload: &bytes[header_length..],
+        })
+    }
+
+    /// Length in sequence space, which counts SYN and FIN flags as well as the payload
+    pub fn sequence_length(&self) -> u32 {
+        let flags = self.flags & (TcpFlags::SYN | TcpFlags::FIN);
+        self.payload.len() as u32 + flags.bits().count_ones()
+    }
+
+    pub fn to_bytes(&self, source: Ipv4Addr, destination: Ipv4Addr) -> Vec<u8> {
+        let options = if self.mss.is_some() {
+            OPTION_MSS_LENGTH
+        } else {
+            0
+        };
+        let header_length = HEADER_LENGTH + options;
+        let length = header_length + self.payload.len();
+        let mut bytes = Vec::with_capacity(length);
+        bytes.extend_from_slice(&self.source_port.to_be_bytes());
+        bytes.extend_from_slice(&self.destination_port.to_be_bytes());
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.extend_from_slice(&self.acknowledgment.to_be_bytes());
+        bytes.push(((header_length / 4) as u8) << 4);
+        bytes.push(self.flags.bits());
+        bytes.extend_from_slice(&self.window.to_be_bytes());
+        // checksum and urgent pointer
+        bytes.extend_from_slice(&[0; 4]);
+        if let Some(mss) = self.mss {
+            bytes.extend_from_slice(&[OPTION_MSS, OPTION_MSS_LENGTH as u8]);
+            bytes.extend_from_slice(&mss.to_be_bytes());
+        }
+        bytes.extend_from_slice(self.payload);
+        let sum = Checksum::new()
+            .add_pseudo_header(source, destination, PROTOCOL_TCP, length)
+            .add(&bytes)
+            .finish();
+        bytes[16..18].copy_from_slice(&sum.to_be_bytes());
+        bytes
+    }
+}
+
+/// Finds the maximum segment size among options
+fn parse_mss(mut options: &[u8]) -> Result<Option<u16>, PacketError> {
+    let mut mss = None;
+    while let Some(&kind) = options.first() {
+        match kind {
+            OPTION_END => break,
+            OPTION_NOP => options = &options[1..],
+            _ => {
+                let length = *options.get(1).ok_or(PacketError::InvaildHeader)? as usize;
+                if length < 2 || length > options.len() {
+                    return Err(PacketError::InvaildHeader);
+                }
+                if kind == OPTION_MSS {
+                    if length != OPTION_MSS_LENGTH {
+                        return Err(PacketError::InvaildHeader);
+                    }
+                    mss = Some(read_u16(options, 2));
+                }
+                options = &options[length..];
+            }
+        }
+    }
+    Ok(mss)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::net::Ipv4Addr;
+
+    use super::{TcpFlags, TcpSegment};
+    use crate::{checksum::Checksum, ipv4::PROTOCOL_TCP, PacketError};
+
+    const SOURCE: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+    const DESTINATION: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+
+    fn set_checksum(bytes: &mut [u8]) {
+        bytes[16..18].fill(0);
+        let sum = Checksum::new()
+            .add_pseudo_header(SOURCE, DESTINATION, PROTOCOL_TCP, bytes.len())
+            .add(bytes)
+            .finish();
+        bytes[16..18].copy_from_slice(&sum.to_be_bytes());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let syn = TcpSegment {
+            source_port: 49152,
+            destination_port: 7,
+            sequence: 0x12345678,
+            acknowledgment: 0,
+            flags: TcpFlags::SYN,
+            window: 65535,
+            mss: Some(1460),
+            payload: &[],
+        };
+        let bytes = syn.to_bytes(SOURCE, DESTINATION);
+        assert_eq!(bytes.len(), 24);
+        assert_eq!(bytes[12], 6 << 4);
+        assert_eq!(TcpSegment::parse(&bytes, SOURCE, DESTINATION), Ok(syn));
+        assert_eq!(syn.sequence_length(), 1);
+
+        let data = TcpSegment {
+            flags: TcpFlags::ACK | TcpFlags::PSH | TcpFlags::FIN,
+            acknowledgment: 1,
+            mss: None,
+            payload: b"hello",
+            ..syn
+        };
+        let mut bytes = data.to_bytes(SOURCE, DESTINATION);
+        assert_eq!(TcpSegment::parse(&bytes, SOURCE, DESTINATION), Ok(data));
+        assert_eq!(data.sequence_length(), 6);
+
+        bytes[20] ^= 1;
+        assert_eq!(
+            TcpSegment::parse(&bytes, SOURCE, DESTINATION),
+            Err(PacketError::InvaildChecksum)
+        );
+    }
+
+    #[test]
+    fn test_options() {
+        // NOP, window scale which is skipped, MSS and end of options
+        let header = [
+            0x00, 0x07, 0xc0, 0x00, 0, 0, 0, 1, 0, 0, 0, 0, 0x80, 0x02, 0x10, 0x00, 0, 0, 0, 0, 1,
+            3, 3, 7, 2, 4, 0x05, 0xb4, 0, 0, 0, 0,
+        ];
+        let mut bytes = header.to_vec();
+        set_checksum(&mut bytes);
+        let segment = TcpSegment::parse(&bytes, SOURCE, DESTINATION).unwrap();
+        assert_eq!(segment.mss, Some(1460));
+        assert_eq!(segment.flags, TcpFlags::SYN);
+        assert_eq!(segment.window, 4096);
+        assert!(segment.payload.is_empty());
+
+        // option running past the header
+        bytes[22] = 12;
+        set_checksum(&mut bytes);
+        assert_eq!(
+            TcpSegment::parse(&bytes, SOURCE, DESTINATION),
+            Err(PacketError::InvaildHeader)
+        );
+    }
+
+    #[test]
+    fn test_invaild_lengths() {
+        let bytes = TcpSegment {
+            source_port: 1,
+            destination_port: 2,
+            sequence: 0,
+            acknowledgment: 0,
+            flags: TcpFlags::RST,
+            window: 0,
+            mss: None,
+            payload: &[],
+        }
+        .to_bytes(SOURCE, DESTINATION);
+        assert_eq!(
+            TcpSegment::parse(&bytes[..19], SOURCE, DESTINATION),
+            Err(PacketError::Truncated)
+        );
+        let mut long_header = bytes.clone();
+        long_header[12] = 6 << 4;
+        assert_eq!(
+            TcpSegment::parse(&long_header, SOURCE, DESTINATION),
+            Err(PacketError::Truncated)
+        );
+        let mut short_header = bytes;
+        short_header[12] = 4 << 4;
+        assert_eq!(
+            TcpSegment::parse(&short_header, SOURCE, DESTINATION),
+            Err(PacketError::InvaildHeader)
+        );
+    }
+}
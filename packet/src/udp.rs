@@ -0,0 +1,132 @@
+//! User Datagram Protocol (RFC 768)
+
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+use crate::{checksum::Checksum, ipv4::PROTOCOL_UDP, read_u16, PacketError};
+
+pub const HEADER_LENGTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpDatagram<'a> {
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> UdpDatagram<'a> {
+    /// Parses the payload of an IPv4 packet between given addresses, which are covered by
+    /// the checksum. A zero checksum means the sender did not compute one
+    pub fn parse(
+        bytes: &'a [u8],
+        source: Ipv4Addr,
+        destination: Ipv4Addr,
+    ) -> Result<UdpDatagram<'a>, PacketError> {
+        if bytes.len() < HEADER_LENGTH {
+            return Err(PacketError::Truncated);
+        }
+        let length = read_u16(bytes, 4) as usize;
+        if length < HEADER_LENGTH {
+            return Err(PacketError::InvaildHeader);
+        }
+        let bytes = bytes.get(..length).ok_or(PacketError::Truncated)?;
+        if read_u16(bytes, 6) != 0 {
+            let sum = Checksum::new()
+                .add_pseudo_header(source, destination, PROTOCOL_UDP, length)
+                .add(bytes)
+                .finish();
+            if sum != 0 {
+                return Err(PacketError::InvaildChecksum);
+            }
+        }
+        Ok(UdpDatagram {
+            source_port: read_u16(bytes, 0),
+            destination_port: read_u16(bytes, 2),
+            payload: &bytes[HEADER_LENGTH..],
+        })
+    }
+
+    pub fn to_bytes(&self, source: Ipv4Addr, destination: Ipv4Addr) -> Vec<u8> {
+        let length = HEADER_LENGTH + self.payload.len();
+        let mut bytes = Vec::with_capacity(length);
+        bytes.extend_from_slice(&self.source_port.to_be_bytes());
+        bytes.extend_from_slice(&self.destination_port.to_be_bytes());
+        bytes.extend_from_slice(&(length as u16).to_be_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(self.payload);
+        let sum = match Checksum::new()
+            .add_pseudo_header(source, destination, PROTOCOL_UDP, length)
+            .add(&bytes)
+            .finish()
+        {
+            // zero stands for no checksum, its complement is sent instead
+            0 => 0xffff,
+            sum => sum,
+        };
+        bytes[6..8].copy_from_slice(&sum.to_be_bytes());
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::net::Ipv4Addr;
+
+    use super::UdpDatagram;
+    use crate::PacketError;
+
+    const SOURCE: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+    const DESTINATION: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+
+    #[test]
+    fn test_roundtrip() {
+        let datagram = UdpDatagram {
+            source_port: 49152,
+            destination_port: 7,
+            payload: b"hello",
+        };
+        let mut bytes = datagram.to_bytes(SOURCE, DESTINATION);
+        assert_eq!(bytes.len(), 13);
+        assert_eq!(
+            UdpDatagram::parse(&bytes, SOURCE, DESTINATION),
+            Ok(datagram)
+        );
+        // addresses are covered by the checksum
+        assert_eq!(
+            UdpDatagram::parse(&bytes, DESTINATION, DESTINATION),
+            Err(PacketError::InvaildChecksum)
+        );
+
+        // without a checksum, nothing is verified
+        bytes[6..8].fill(0);
+        assert_eq!(
+            UdpDatagram::parse(&bytes, DESTINATION, DESTINATION),
+            Ok(datagram)
+        );
+    }
+
+    #[test]
+    fn test_lengths() {
+        let bytes = UdpDatagram {
+            source_port: 1,
+            destination_port: 2,
+            payload: b"data",
+        }
+        .to_bytes(SOURCE, DESTINATION);
+        assert_eq!(
+            UdpDatagram::parse(&bytes[..10], SOURCE, DESTINATION),
+            Err(PacketError::Truncated)
+        );
+        let mut padded = bytes.clone();
+        padded.extend_from_slice(&[0; 6]);
+        let parsed = UdpDatagram::parse(&padded, SOURCE, DESTINATION).unwrap();
+        assert_eq!(parsed.payload, b"data");
+
+        let mut short = bytes;
+        short[4..6].copy_from_slice(&4u16.to_be_bytes());
+        assert_eq!(
+            UdpDatagram::parse(&short, SOURCE, DESTINATION),
+            Err(PacketError::InvaildHeader)
+        );
+    }
+}
@@ -0,0 +1,4 @@
+fn main() {
+    println!("cargo:rerun-if-changed=user/linker.ld");
+    println!("cargo:rustc-link-arg-bins=-Tuser/linker.ld");
+}
@@ -0,0 +1,127 @@
+#![no_std]
+#![no_main]
+
+//! Echo server on port 7. A child process sends every UDP datagram back to its sender, while
+//! the server accepts TCP connections, each one echoed by a process of its own until the
+//! peer closes it
+
+use core_lib::syscall::{SockAddrIn, IPPROTO_TCP, IPPROTO_UDP, SOCK_DGRAM, SOCK_STREAM, WNOHANG};
+use user::{println, syscall};
+
+const PORT: u16 = 7;
+const BACKLOG: usize = 8;
+
+#[no_mangle]
+fn main() -> i32 {
+    match syscall::fork() {
+        Ok(0) => serve_udp(),
+        Ok(_) => serve_tcp(),
+        Err(errno) => {
+            println!("echod: cannot fork: {:?}", errno);
+            1
+        }
+    }
+}
+
+/// Creates a socket bound to the echo port
+fn bind(kind: usize, protocol: usize) -> Option<usize> {
+    let socket = match syscall::socket(kind, protocol) {
+        Ok(socket) => socket,
+        Err(errno) => {
+            println!("echod: cannot create a socket: {:?}", errno);
+            return None;
+        }
+    };
+    if let Err(errno) = syscall::bind(socket, &SockAddrIn::new([0; 4], PORT)) {
+        println!("echod: cannot bind port {}: {:?}", PORT, errno);
+        return None;
+    }
+    Some(socket)
+}
+
+fn serve_udp() -> i32 {
+    let Some(socket) = bind(SOCK_DGRAM, IPPROTO_UDP) else {
+        return 1;
+    };
+    println!("echod: listening on UDP port {}", PORT);
+
+    let mut buffer = [0u8; 2048];
+    loop {
+        let (length, peer) = match syscall::recvfrom(socket, &mut buffer) {
+            Ok(received) => received,
+            Err(errno) => {
+                println!("echod: cannot receive: {:?}", errno);
+                return 1;
+            }
+        };
+        if let Err(errno) = syscall::sendto(socket, &buffer[..length], Some(&peer)) {
+            println!("echod: cannot reply: {:?}", errno);
+        }
+    }
+}
+
+fn serve_tcp() -> i32 {
+    let Some(socket) = bind(SOCK_STREAM, IPPROTO_TCP) else {
+        return 1;
+    };
+    if let Err(errno) = syscall::listen(socket, BACKLOG) {
+        println!("echod: cannot listen: {:?}", errno);
+        return 1;
+    }
+    println!("echod: listening on TCP port {}", PORT);
+
+    loop {
+        let (connection, peer) = match syscall::accept(socket) {
+            Ok(accepted) => accepted,
+            Err(errno) => {
+                println!("echod: cannot accept: {:?}", errno);
+                return 1;
+            }
+        };
+        let [a, b, c, d] = peer.address;
+        println!(
+            "echod: connection from {}.{}.{}.{}:{}",
+            a,
+            b,
+            c,
+            d,
+            peer.port()
+        );
+        match syscall::fork() {
+            Ok(0) => {
+                let _ = syscall::close(socket);
+                syscall::exit(echo(connection));
+            }
+            Ok(_) => {}
+            Err(errno) => println!("echod: cannot fork: {:?}", errno),
+        }
+        let _ = syscall::close(connection);
+        // reap connections which were closed in the meantime
+        while let Ok(Some(_)) = syscall::waitpid(None, WNOHANG) {}
+    }
+}
+
+/// Sends everything read from a connection back, until the peer closes it
+fn echo(connection: usize) -> i32 {
+    let mut buffer = [0u8; 2048];
+    loop {
+        let length = match syscall::read(connection, &mut buffer) {
+            Ok(0) => return 0,
+            Ok(length) => length,
+            Err(errno) => {
+                println!("echod: cannot receive: {:?}", errno);
+                return 1;
+            }
+        };
+        let mut sent = 0;
+        while sent < length {
+            match syscall::write(connection, &buffer[sent..length]) {
+                Ok(written) => sent += written,
+                Err(errno) => {
+                    println!("echod: cannot reply: {:?}", errno);
+                    return 1;
+                }
+            }
+        }
+    }
+}
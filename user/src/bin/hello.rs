@@ -0,0 +1,19 @@
+#![no_std]
+#![no_main]
+
+//! Prints its arguments, used to check that `execve` passes them
+
+use user::{args, println, syscall};
+
+#[no_mangle]
+fn main() -> i32 {
+    println!(
+        "hello: pid {}, {} arguments",
+        syscall::getpid(),
+        args().count()
+    );
+    for (index, arg) in args().enumerate() {
+        println!("hello: argument {}: {:?}", index, arg);
+    }
+    0
+}
@@ -0,0 +1,336 @@
+#![no_std]
+#![no_main]
+
+//! First program started by the kernel
+
+use core::{
+    ffi::CStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use core_lib::syscall::{
+    core_dumped, exit_code, sigmask, term_signal, Dirent, SockAddrIn, ICANON, IPPROTO_TCP,
+    IPPROTO_UDP, MAP_PRIVATE, O_CREAT, O_DIRECTORY, O_RDONLY, O_RDWR, O_TRUNC, PROT_READ,
+    PROT_WRITE, RIGHT_SEND, RIGHT_TRANSFER, SIGALRM, SIGSEGV, SIGTERM, SIGUSR1, SIG_BLOCK,
+    SIG_UNBLOCK, SOCK_DGRAM, SOCK_STREAM,
+};
+use user::{
+    println,
+    syscall::{self, Errno, IpcMessage, SeekFrom},
+};
+
+/// Placed in bss, which has to be zeroed by the loader
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[no_mangle]
+fn main() -> i32 {
+    println!("init: hello from pid {}", syscall::getpid());
+    if let Err(errno) = list_directory(c"/") {
+        println!("init: cannot list /: {:?}", errno);
+    }
+    if let Err(errno) = check_files() {
+        println!("init: file check failed: {:?}", errno);
+    }
+    if let Err(errno) = check_pipe() {
+        println!("init: pipe check failed: {:?}", errno);
+    }
+    if let Err(errno) = check_ipc() {
+        println!("init: IPC check failed: {:?}", errno);
+    }
+    if let Err(errno) = check_mappings() {
+        println!("init: mapping check failed: {:?}", errno);
+    }
+    if let Err(errno) = check_fork() {
+        println!("init: fork check failed: {:?}", errno);
+    }
+    if let Err(errno) = check_signals() {
+        println!("init: signal check failed: {:?}", errno);
+    }
+    if let Err(errno) = check_sockets() {
+        println!("init: socket check failed: {:?}", errno);
+    }
+    match syscall::tcgetattr(0) {
+        Ok(termios) => println!(
+            "init: standard input is a terminal, canonical: {}",
+            termios.local_flags & ICANON != 0
+        ),
+        Err(errno) => println!("init: standard input is not a terminal: {:?}", errno),
+    }
+
+    let mut squares = [0u64; 64];
+    for (i, square) in squares.iter_mut().enumerate() {
+        *square = (i * i) as u64;
+        COUNTER.fetch_add(1, Ordering::Relaxed);
+    }
+    let sum: u64 = squares.iter().sum();
+    println!(
+        "init: sum of {} squares is {}",
+        COUNTER.load(Ordering::Relaxed),
+        sum
+    );
+
+    syscall::sleep(Duration::from_millis(100)).unwrap();
+    syscall::yield_now();
+
+    const LENGTH: usize = 3 * 4096;
+    let memory = syscall::mmap_anonymous(LENGTH, PROT_READ | PROT_WRITE).unwrap();
+    // SAFETY: mapping is readable and writeable for its length, and used only here
+    let memory = unsafe { core::slice::from_raw_parts_mut(memory, LENGTH) };
+    for (i, byte) in memory.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let checksum: usize = memory.iter().map(|byte| *byte as usize).sum();
+    println!(
+        "init: mapped {} bytes at {:p}, checksum {}",
+        LENGTH,
+        memory.as_ptr(),
+        checksum
+    );
+
+    (sum / COUNTER.load(Ordering::Relaxed)) as i32
+}
+
+fn list_directory(path: &CStr) -> Result<(), Errno> {
+    let fd = syscall::open(path, O_RDONLY | O_DIRECTORY, 0)?;
+    println!("init: contents of {:?}:", path);
+    let mut buffer = [0u8; 512];
+    loop {
+        let length = syscall::getdents(fd, &mut buffer)?;
+        if length == 0 {
+            break;
+        }
+        for dirent in Dirent::decode_all(&buffer[..length]) {
+            println!("init:   {} (inode {})", dirent.name, dirent.inode);
+        }
+    }
+    syscall::close(fd)
+}
+
+/// Writes a file in the root filesystem and reads it back
+fn check_files() -> Result<(), Errno> {
+    const MESSAGE: &[u8] = b"written by init";
+    let fd = syscall::open(c"/hello.txt", O_RDWR | O_CREAT | O_TRUNC, 0o644)?;
+    syscall::write(fd, MESSAGE)?;
+    syscall::seek(fd, SeekFrom::Start(0))?;
+    let mut buffer = [0u8; 32];
+    let read = syscall::read(fd, &mut buffer)?;
+    syscall::close(fd)?;
+    syscall::sync()?;
+    let stat = syscall::stat(c"/hello.txt")?;
+    println!(
+        "init: /hello.txt has {} bytes, mode {:o}, read back {:?}",
+        stat.size,
+        stat.mode,
+        core::str::from_utf8(&buffer[..read]).unwrap_or("<binary>")
+    );
+    Ok(())
+}
+
+/// Checks that rights of handles are enforced and that sending fails once no handle can
+/// receive from an endpoint
+fn check_ipc() -> Result<(), Errno> {
+    let endpoint = syscall::endpoint_create()?;
+    let sender = syscall::handle_duplicate(endpoint, RIGHT_SEND | RIGHT_TRANSFER)?;
+    let refused = syscall::handle_duplicate(sender, RIGHT_SEND);
+    syscall::handle_close(endpoint)?;
+    let message = IpcMessage::new(b"nobody listens").unwrap();
+    let sent = syscall::ipc_send(sender, &message);
+    syscall::handle_close(sender)?;
+    println!(
+        "init: duplicating without the right: {:?}, sending without receivers: {:?}",
+        refused.err(),
+        sent.err()
+    );
+    Ok(())
+}
+
+/// Binds UDP sockets, checking that a port cannot be used twice and that unconnected sockets
+/// need a destination, then does the same for a listening TCP socket, which cannot be read
+fn check_sockets() -> Result<(), Errno> {
+    let first = syscall::socket(SOCK_DGRAM, IPPROTO_UDP)?;
+    syscall::bind(first, &SockAddrIn::new([0; 4], 0))?;
+    let port = syscall::getsockname(first)?.port();
+    let second = syscall::socket(SOCK_DGRAM, IPPROTO_UDP)?;
+    let reused = syscall::bind(second, &SockAddrIn::new([0; 4], port));
+    let unaddressed = syscall::sendto(first, b"nowhere", None);
+    syscall::close(second)?;
+    syscall::close(first)?;
+    println!(
+        "init: bound UDP port {}, binding it again: {:?}, sending without destination: {:?}",
+        port,
+        reused.err(),
+        unaddressed.err()
+    );
+
+    let listener = syscall::socket(SOCK_STREAM, IPPROTO_TCP)?;
+    syscall::listen(listener, 1)?;
+    let port = syscall::getsockname(listener)?.port();
+    let other = syscall::socket(SOCK_STREAM, IPPROTO_TCP)?;
+    let reused = syscall::bind(other, &SockAddrIn::new([0; 4], port));
+    let unconnected = syscall::read(listener, &mut [0; 1]);
+    syscall::close(other)?;
+    syscall::close(listener)?;
+    println!(
+        "init: listening on TCP port {}, binding it again: {:?}, reading the listener: {:?}",
+        port,
+        reused.err(),
+        unconnected.err()
+    );
+    Ok(())
+}
+
+/// Maps a memory object twice, checking that both mappings share pages, then maps the file
+/// written by `check_files`, once more privately to read it into the mapping
+fn check_mappings() -> Result<(), Errno> {
+    const LENGTH: usize = 4096;
+    let memory = syscall::memory_create(LENGTH)?;
+    let first = syscall::memory_map(memory, LENGTH, PROT_READ | PROT_WRITE, 0)?;
+    let second = syscall::memory_map(memory, LENGTH, PROT_READ, 0)?;
+    syscall::handle_close(memory)?;
+    // SAFETY: both mappings are readable for their length and the first one is writeable
+    let shared = unsafe {
+        first.write_volatile(42);
+        second.read_volatile()
+    };
+    // SAFETY: nothing uses the mappings anymore
+    unsafe {
+        syscall::munmap(first, LENGTH)?;
+        syscall::munmap(second, LENGTH)?;
+    }
+
+    let size = (syscall::stat(c"/hello.txt")?.size as usize).min(LENGTH);
+    let fd = syscall::open(c"/hello.txt", O_RDONLY, 0)?;
+    let file = syscall::mmap(LENGTH, PROT_READ, MAP_PRIVATE, fd, 0);
+    // the kernel writes into a private copy of a page nothing touched yet
+    let copy = syscall::mmap(LENGTH, PROT_READ | PROT_WRITE, MAP_PRIVATE, fd, 0);
+    let read = copy.and_then(|copy| {
+        // SAFETY: the mapping is writeable for its length and not used elsewhere
+        syscall::read(fd, unsafe { core::slice::from_raw_parts_mut(copy, size) })
+    });
+    syscall::close(fd)?;
+    let (file, copy) = (file?, copy?);
+    // SAFETY: mapping is readable up to the end of the file
+    let contents = unsafe { core::slice::from_raw_parts(file, size) };
+    println!(
+        "init: byte written through a shared mapping read back as {}, file mapped as {:?}",
+        shared,
+        core::str::from_utf8(contents).unwrap_or("<binary>")
+    );
+    println!(
+        "init: read {:?} bytes into a private file mapping, same as the file: {}",
+        read,
+        // SAFETY: the mapping is readable for its length
+        read.is_ok_and(|_| unsafe { core::slice::from_raw_parts(copy, size) } == contents)
+    );
+    // SAFETY: contents are not used anymore
+    unsafe {
+        syscall::munmap(copy, LENGTH)?;
+        syscall::munmap(file, LENGTH)
+    }
+}
+
+/// Forks a child which modifies memory shared copy-on-write, checking that the parent does
+/// not see it, then forks one which runs `/bin/hello`
+fn check_fork() -> Result<(), Errno> {
+    static VALUE: AtomicU64 = AtomicU64::new(1);
+    let child = syscall::fork()?;
+    if child == 0 {
+        VALUE.store(2, Ordering::Relaxed);
+        syscall::exit(VALUE.load(Ordering::Relaxed) as i32);
+    }
+    let (_, status) = syscall::waitpid(Some(child), 0)?.unwrap();
+    println!(
+        "init: child {} exited with {:?}, value seen by the parent: {}",
+        child,
+        exit_code(status),
+        VALUE.load(Ordering::Relaxed)
+    );
+
+    let child = syscall::fork()?;
+    if child == 0 {
+        let errno = syscall::execve(c"/bin/hello", &[c"hello", c"from", c"init"]);
+        println!("init: cannot run /bin/hello: {:?}", errno);
+        syscall::exit(127);
+    }
+    let (_, status) = syscall::waitpid(None, 0)?.unwrap();
+    println!("init: /bin/hello exited with {:?}", exit_code(status));
+    Ok(())
+}
+
+/// Counts `SIGUSR1` signals handled by init
+static SIGNALS: AtomicU64 = AtomicU64::new(0);
+
+extern "C" fn count_signal(_signal: usize) {
+    SIGNALS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Checks that a blocked signal is handled only once unblocked, then that children are
+/// terminated by default actions of signals sent by init, a fault and an alarm
+fn check_signals() -> Result<(), Errno> {
+    syscall::signal(SIGUSR1, count_signal)?;
+    syscall::kill(syscall::getpid(), SIGUSR1)?;
+    syscall::sigprocmask(SIG_BLOCK, Some(sigmask(SIGUSR1)))?;
+    syscall::kill(syscall::getpid(), SIGUSR1)?;
+    let while_blocked = SIGNALS.load(Ordering::Relaxed);
+    syscall::sigprocmask(SIG_UNBLOCK, Some(sigmask(SIGUSR1)))?;
+    println!(
+        "init: handled SIGUSR1 {} times while blocked, {} times in total",
+        while_blocked,
+        SIGNALS.load(Ordering::Relaxed)
+    );
+
+    let killed = fork_child(|| loop {
+        let _ = syscall::sleep(Duration::from_millis(10));
+    })?;
+    syscall::kill(killed, SIGTERM)?;
+    let segfault = fork_child(|| {
+        // SAFETY: none, the write faults on purpose
+        unsafe { core::ptr::null_mut::<u8>().write_volatile(1) };
+    })?;
+    let alarm = fork_child(|| {
+        syscall::alarm(1);
+        loop {
+            let _ = syscall::sleep(Duration::from_millis(10));
+        }
+    })?;
+    for (child, expected) in [(killed, SIGTERM), (segfault, SIGSEGV), (alarm, SIGALRM)] {
+        let (_, status) = syscall::waitpid(Some(child), 0)?.unwrap();
+        println!(
+            "init: child {} terminated by signal {:?} (expected {}), core dumped: {}",
+            child,
+            term_signal(status),
+            expected,
+            core_dumped(status)
+        );
+    }
+    Ok(())
+}
+
+/// Forks a child running a function, which exits with 0 if it returns
+fn fork_child(run: impl FnOnce()) -> Result<usize, Errno> {
+    let child = syscall::fork()?;
+    if child == 0 {
+        run();
+        syscall::exit(0);
+    }
+    Ok(child)
+}
+
+/// Passes a message through a pipe, then checks that its read end reports the end of file
+fn check_pipe() -> Result<(), Errno> {
+    const MESSAGE: &[u8] = b"sent through a pipe";
+    let (reader, writer) = syscall::pipe()?;
+    syscall::write(writer, MESSAGE)?;
+    syscall::close(writer)?;
+    let mut buffer = [0u8; 32];
+    let read = syscall::read(reader, &mut buffer)?;
+    let end = syscall::read(reader, &mut buffer[read..])?;
+    syscall::close(reader)?;
+    println!(
+        "init: read {:?} from a pipe, then {} bytes",
+        core::str::from_utf8(&buffer[..read]).unwrap_or("<binary>"),
+        end
+    );
+    Ok(())
+}
@@ -0,0 +1,96 @@
+#![no_std]
+
+//! Runtime of losgatos user programs
+//!
+//! A program defines `#[no_mangle] fn main() -> i32` and is linked with `user/linker.ld`,
+//! which places it at the start of the user part of address spaces. Its arguments are
+//! available through [`args`].
+
+pub mod syscall;
+
+use core::{
+    ffi::{c_char, CStr},
+    fmt,
+    panic::PanicInfo,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+pub use syscall::exit;
+
+const STDOUT: usize = 1;
+const STDERR: usize = 2;
+
+static ARGC: AtomicUsize = AtomicUsize::new(0);
+static ARGV: AtomicPtr<*const c_char> = AtomicPtr::new(core::ptr::null_mut());
+
+extern "Rust" {
+    fn main() -> i32;
+}
+
+/// Entered by the kernel with the arguments in `a0` and `a1`
+#[no_mangle]
+#[link_section = ".text.entry"]
+extern "C" fn _start(argc: usize, argv: *mut *const c_char) -> ! {
+    ARGC.store(argc, Ordering::Relaxed);
+    ARGV.store(argv, Ordering::Relaxed);
+    // SAFETY: `main` is provided by the program
+    let code = unsafe { main() };
+    exit(code)
+}
+
+/// Arguments the program was started with, the first one usually being its name
+pub fn args() -> impl Iterator<Item = &'static CStr> {
+    let argv = ARGV.load(Ordering::Relaxed);
+    (0..ARGC.load(Ordering::Relaxed)).map(move |index| {
+        // SAFETY: the kernel places argc valid pointers to null-terminated strings on the
+        // stack, which are never freed
+        unsafe { CStr::from_ptr(*argv.add(index)) }
+    })
+}
+
+/// Formatted output to a file descriptor
+pub struct Output(usize);
+
+impl Output {
+    pub fn stdout() -> Output {
+        Output(STDOUT)
+    }
+
+    pub fn stderr() -> Output {
+        Output(STDERR)
+    }
+}
+
+impl fmt::Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut data = s.as_bytes();
+        while !data.is_empty() {
+            let written = syscall::write(self.0, data).map_err(|_| fmt::Error)?;
+            data = &data[written..];
+        }
+        Ok(())
+    }
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {{
+        use core::fmt::Write;
+        let _ = write!($crate::Output::stdout(), $($arg)*);
+    }};
+}
+
+#[macro_export]
+macro_rules! println {
+    ($($arg:tt)*) => {{
+        use core::fmt::Write;
+        let _ = writeln!($crate::Output::stdout(), $($arg)*);
+    }};
+}
+
+#[panic_handler]
+fn panic_handler(panic: &PanicInfo) -> ! {
+    use core::fmt::Write;
+    let _ = writeln!(Output::stderr(), "{}", panic);
+    exit(101)
+}
@@ -0,0 +1,491 @@
+//! Wrappers of system calls, see [`core_lib::syscall`] for the ABI
+
+use core::{
+    arch::{asm, global_asm},
+    ffi::CStr,
+    time::Duration,
+};
+
+use core_lib::syscall::{
+    self, decode_result, Timespec, AF_INET, AT_FDCWD, SA_RESTORER, SEEK_CUR, SEEK_END, SEEK_SET,
+    SIGCHLD, SIG_DFL, SIG_IGN, TCGETS, TCSETS, WNOHANG,
+};
+pub use core_lib::syscall::{
+    Dirent, Errno, IpcMessage, SigAction, SigSet, SockAddrIn, Stat, Termios,
+};
+
+extern "C" {
+    /// Returns from a signal handler with `rt_sigreturn`
+    fn restore_signal();
+}
+
+global_asm!(
+    ".global restore_signal",
+    "restore_signal:",
+    "li a7, {number}",
+    "ecall",
+    number = const syscall::RT_SIGRETURN,
+);
+
+/// Makes a system call with up to six arguments
+///
+/// # Safety
+/// Arguments have to be valid for the call, e.g. pointers to memory it accesses
+unsafe fn call(number: usize, arguments: [usize; 6]) -> Result<usize, Errno> {
+    let result: usize;
+    asm!(
+        "ecall",
+        inlateout("a0") arguments[0] => result,
+        in("a1") arguments[1],
+        in("a2") arguments[2],
+        in("a3") arguments[3],
+        in("a4") arguments[4],
+        in("a5") arguments[5],
+        in("a7") number,
+        options(nostack)
+    );
+    decode_result(result)
+}
+
+/// Opens a file with `O_*` flags, creating it with given mode if `O_CREAT` is set
+pub fn open(path: &CStr, flags: usize, mode: u32) -> Result<usize, Errno> {
+    let path = path.as_ptr() as usize;
+    // SAFETY: path is null-terminated
+    unsafe {
+        call(
+            syscall::OPENAT,
+            [AT_FDCWD, path, flags, mode as usize, 0, 0],
+        )
+    }
+}
+
+pub fn close(fd: usize) -> Result<(), Errno> {
+    // SAFETY: close takes no pointers
+    unsafe { call(syscall::CLOSE, [fd, 0, 0, 0, 0, 0]) }.map(|_| ())
+}
+
+/// Reads bytes from a file descriptor, returning how many of them were read
+pub fn read(fd: usize, buffer: &mut [u8]) -> Result<usize, Errno> {
+    let (address, length) = (buffer.as_mut_ptr() as usize, buffer.len());
+    // SAFETY: buffer is writeable for its length
+    unsafe { call(syscall::READ, [fd, address, length, 0, 0, 0]) }
+}
+
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// Moves the offset of a file, returning the new one
+pub fn seek(fd: usize, position: SeekFrom) -> Result<u64, Errno> {
+    let (offset, whence) = match position {
+        SeekFrom::Start(offset) => (offset as usize, SEEK_SET),
+        SeekFrom::Current(offset) => (offset as usize, SEEK_CUR),
+        SeekFrom::End(offset) => (offset as usize, SEEK_END),
+    };
+    // SAFETY: lseek takes no pointers
+    unsafe { call(syscall::LSEEK, [fd, offset, whence, 0, 0, 0]) }.map(|offset| offset as u64)
+}
+
+pub fn stat(path: &CStr) -> Result<Stat, Errno> {
+    let mut stat = Stat::default();
+    let (path, address) = (path.as_ptr() as usize, &mut stat as *mut Stat as usize);
+    // SAFETY: path is null-terminated and stat is writeable
+    unsafe { call(syscall::NEWFSTATAT, [AT_FDCWD, path, address, 0, 0, 0]) }?;
+    Ok(stat)
+}
+
+pub fn fstat(fd: usize) -> Result<Stat, Errno> {
+    let mut stat = Stat::default();
+    let address = &mut stat as *mut Stat as usize;
+    // SAFETY: stat is writeable
+    unsafe { call(syscall::FSTAT, [fd, address, 0, 0, 0, 0]) }?;
+    Ok(stat)
+}
+
+/// Reads directory entries into a buffer, returning the length of the records stored in it,
+/// which can be decoded with [`Dirent::decode_all`]. 0 means the end of the directory
+pub fn getdents(fd: usize, buffer: &mut [u8]) -> Result<usize, Errno> {
+    let (address, length) = (buffer.as_mut_ptr() as usize, buffer.len());
+    // SAFETY: buffer is writeable for its length
+    unsafe { call(syscall::GETDENTS64, [fd, address, length, 0, 0, 0]) }
+}
+
+/// Writes bytes to a file descriptor, returning how many of them were written
+pub fn write(fd: usize, data: &[u8]) -> Result<usize, Errno> {
+    // SAFETY: data is readable for its length
+    unsafe {
+        call(
+            syscall::WRITE,
+            [fd, data.as_ptr() as usize, data.len(), 0, 0, 0],
+        )
+    }
+}
+
+/// Creates a pipe, returning descriptors of its read and write ends
+pub fn pipe() -> Result<(usize, usize), Errno> {
+    let mut fds = [0i32; 2];
+    // SAFETY: fds is writeable and holds two i32 values
+    unsafe { call(syscall::PIPE2, [fds.as_mut_ptr() as usize, 0, 0, 0, 0, 0]) }?;
+    Ok((fds[0] as usize, fds[1] as usize))
+}
+
+/// Gets settings of a terminal, failing with `ENOTTY` for other files
+pub fn tcgetattr(fd: usize) -> Result<Termios, Errno> {
+    let mut termios = Termios::default();
+    let address = &mut termios as *mut Termios as usize;
+    // SAFETY: termios is writeable
+    unsafe { call(syscall::IOCTL, [fd, TCGETS, address, 0, 0, 0]) }?;
+    Ok(termios)
+}
+
+/// Changes settings of a terminal, e.g. to raw mode with [`Termios::make_raw`]
+pub fn tcsetattr(fd: usize, termios: &Termios) -> Result<(), Errno> {
+    let address = termios as *const Termios as usize;
+    // SAFETY: termios is readable
+    unsafe { call(syscall::IOCTL, [fd, TCSETS, address, 0, 0, 0]) }.map(|_| ())
+}
+
+/// Writes modified data of all filesystems to disks
+pub fn sync() -> Result<(), Errno> {
+    // SAFETY: sync takes no pointers
+    unsafe { call(syscall::SYNC, [0; 6]) }.map(|_| ())
+}
+
+/// Ends the program with given exit code
+pub fn exit(code: i32) -> ! {
+    // SAFETY: exit takes no pointers
+    let _ = unsafe { call(syscall::EXIT, [code as usize, 0, 0, 0, 0, 0]) };
+    unreachable!("exit returned")
+}
+
+pub fn getpid() -> usize {
+    // SAFETY: getpid takes no arguments
+    unsafe { call(syscall::GETPID, [0; 6]) }.unwrap()
+}
+
+/// Creates a child process with a copy of the memory, returning its pid to the parent and 0
+/// to the child
+pub fn fork() -> Result<usize, Errno> {
+    // SAFETY: clone is used like fork, so the child continues with copies of everything
+    unsafe { call(syscall::CLONE, [SIGCHLD, 0, 0, 0, 0, 0]) }
+}
+
+/// Replaces the program with an executable, passing it arguments. Returns only on failure
+pub fn execve(path: &CStr, args: &[&CStr]) -> Errno {
+    // pointers to the arguments, followed by a null one
+    let mut argv = [core::ptr::null(); 65];
+    if args.len() >= argv.len() {
+        return Errno::E2BIG;
+    }
+    for (pointer, arg) in argv.iter_mut().zip(args) {
+        *pointer = arg.as_ptr();
+    }
+    let argv = argv.as_ptr() as usize;
+    let envp = [core::ptr::null::<u8>()];
+    let envp = envp.as_ptr() as usize;
+    // SAFETY: path and arguments are null-terminated, and argv and envp are null-terminated
+    // arrays
+    match unsafe {
+        call(
+            syscall::EXECVE,
+            [path.as_ptr() as usize, argv, envp, 0, 0, 0],
+        )
+    } {
+        Ok(_) => unreachable!("execve returned"),
+        Err(errno) => errno,
+    }
+}
+
+/// Waits for a child, any one if `pid` is not given, to exit, returning its pid and status.
+/// With `WNOHANG`, `None` is returned if none has exited yet
+pub fn waitpid(pid: Option<usize>, options: usize) -> Result<Option<(usize, i32)>, Errno> {
+    let pid = pid.unwrap_or(usize::MAX);
+    let mut status = 0i32;
+    let address = &mut status as *mut i32 as usize;
+    // SAFETY: status is writeable
+    let child = unsafe { call(syscall::WAIT4, [pid, address, options, 0, 0, 0]) }?;
+    if child == 0 && options & WNOHANG != 0 {
+        return Ok(None);
+    }
+    Ok(Some((child, status)))
+}
+
+/// Sends a signal to a process, only checking that it exists for signal 0
+pub fn kill(pid: usize, signal: usize) -> Result<(), Errno> {
+    // SAFETY: kill takes no pointers
+    unsafe { call(syscall::KILL, [pid, signal, 0, 0, 0, 0]) }.map(|_| ())
+}
+
+/// Installs an action for a signal, returning the previous one. Handlers return through
+/// a restorer provided by the runtime if the action has none
+pub fn sigaction(signal: usize, action: &SigAction) -> Result<SigAction, Errno> {
+    let mut action = *action;
+    if action.handler != SIG_DFL && action.handler != SIG_IGN && action.flags & SA_RESTORER == 0 {
+        action.flags |= SA_RESTORER;
+        action.restorer = restore_signal as *const () as usize;
+    }
+    let mut previous = SigAction::default();
+    let address = &action as *const SigAction as usize;
+    let old = &mut previous as *mut SigAction as usize;
+    let arguments = [signal, address, old, size_of::<SigSet>(), 0, 0];
+    // SAFETY: action is readable, previous one is writeable and the restorer makes
+    // rt_sigreturn
+    unsafe { call(syscall::RT_SIGACTION, arguments) }?;
+    Ok(previous)
+}
+
+/// Makes a signal call a handler, which gets the signal number
+pub fn signal(signal: usize, handler: extern "C" fn(usize)) -> Result<SigAction, Errno> {
+    let action = SigAction {
+        handler: handler as usize,
+        ..SigAction::default()
+    };
+    sigaction(signal, &action)
+}
+
+/// Changes the mask of blocked signals with a `SIG_*` operation, if a set is given, returning
+/// the previous mask
+pub fn sigprocmask(how: usize, set: Option<SigSet>) -> Result<SigSet, Errno> {
+    let mut previous: SigSet = 0;
+    let set = set.as_ref().map_or(0, |set| set as *const SigSet as usize);
+    let old = &mut previous as *mut SigSet as usize;
+    let arguments = [how, set, old, size_of::<SigSet>(), 0, 0];
+    // SAFETY: set is readable and previous mask is writeable
+    unsafe { call(syscall::RT_SIGPROCMASK, arguments) }?;
+    Ok(previous)
+}
+
+/// Sends `SIGALRM` after a number of seconds, cancelling the previous alarm, which is all
+/// that is done for 0. Returns seconds which were left of the previous alarm
+pub fn alarm(seconds: u64) -> u64 {
+    // SAFETY: alarm takes no pointers
+    unsafe { call(syscall::ALARM, [seconds as usize, 0, 0, 0, 0, 0]) }.unwrap() as u64
+}
+
+/// Lets other tasks run before continuing
+pub fn yield_now() {
+    // SAFETY: sched_yield takes no arguments
+    let _ = unsafe { call(syscall::SCHED_YIELD, [0; 6]) };
+}
+
+/// Blocks for at least given time
+pub fn sleep(duration: Duration) -> Result<(), Errno> {
+    let request = Timespec {
+        seconds: duration.as_secs() as i64,
+        nanoseconds: duration.subsec_nanos() as i64,
+    };
+    let request = &request as *const Timespec as usize;
+    // SAFETY: request points to a timespec and no remaining time is requested
+    unsafe { call(syscall::NANOSLEEP, [request, 0, 0, 0, 0, 0]) }.map(|_| ())
+}
+
+/// Maps zeroed private memory of at least `length` bytes with given `PROT_*` protection
+pub fn mmap_anonymous(length: usize, protection: usize) -> Result<*mut u8, Errno> {
+    let flags = syscall::MAP_PRIVATE | syscall::MAP_ANONYMOUS;
+    mmap(length, protection, flags, usize::MAX, 0)
+}
+
+/// Maps `length` bytes of a file starting at a page-aligned offset, or anonymous memory with
+/// `MAP_ANONYMOUS`, with given `PROT_*` protection and `MAP_*` flags
+pub fn mmap(
+    length: usize,
+    protection: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> Result<*mut u8, Errno> {
+    // SAFETY: the call maps new memory, so it does not affect memory in use
+    unsafe { call(syscall::MMAP, [0, length, protection, flags, fd, offset]) }
+        .map(|address| address as *mut u8)
+}
+
+/// Unmaps pages of a range
+///
+/// # Safety
+/// Memory of the range must not be used afterwards
+pub unsafe fn munmap(address: *mut u8, length: usize) -> Result<(), Errno> {
+    call(syscall::MUNMAP, [address as usize, length, 0, 0, 0, 0]).map(|_| ())
+}
+
+/// Changes `PROT_*` protection of mapped pages in a range
+pub fn mprotect(address: *mut u8, length: usize, protection: usize) -> Result<(), Errno> {
+    // SAFETY: accessing pages without permissions stops the program, but does not make it
+    // use invalid memory
+    unsafe {
+        call(
+            syscall::MPROTECT,
+            [address as usize, length, protection, 0, 0, 0],
+        )
+    }
+    .map(|_| ())
+}
+
+/// Creates a zeroed memory object of at least `size` bytes, returning a handle with all
+/// rights to it
+pub fn memory_create(size: usize) -> Result<usize, Errno> {
+    // SAFETY: memory_create takes no pointers
+    unsafe { call(syscall::MEMORY_CREATE, [size, 0, 0, 0, 0, 0]) }
+}
+
+/// Maps `length` bytes of a memory object starting at a page-aligned offset, sharing them
+/// with all other mappings of it
+pub fn memory_map(
+    handle: usize,
+    length: usize,
+    protection: usize,
+    offset: usize,
+) -> Result<*mut u8, Errno> {
+    // SAFETY: the call maps new memory, so it does not affect memory in use
+    unsafe {
+        call(
+            syscall::MEMORY_MAP,
+            [handle, length, protection, offset, 0, 0],
+        )
+    }
+    .map(|address| address as *mut u8)
+}
+
+/// Creates an IPC endpoint, returning a handle with all `RIGHT_*` rights to it
+pub fn endpoint_create() -> Result<usize, Errno> {
+    // SAFETY: endpoint_create takes no arguments
+    unsafe { call(syscall::ENDPOINT_CREATE, [0; 6]) }
+}
+
+/// Makes another handle to the same object, with a subset of the `RIGHT_*` rights
+pub fn handle_duplicate(handle: usize, rights: u32) -> Result<usize, Errno> {
+    // SAFETY: handle_duplicate takes no pointers
+    unsafe {
+        call(
+            syscall::HANDLE_DUPLICATE,
+            [handle, rights as usize, 0, 0, 0, 0],
+        )
+    }
+}
+
+pub fn handle_close(handle: usize) -> Result<(), Errno> {
+    // SAFETY: handle_close takes no pointers
+    unsafe { call(syscall::HANDLE_CLOSE, [handle, 0, 0, 0, 0, 0]) }.map(|_| ())
+}
+
+/// Sends a message to an endpoint, blocking until it is replied to
+pub fn ipc_send(handle: usize, message: &IpcMessage) -> Result<IpcMessage, Errno> {
+    let mut reply = IpcMessage::default();
+    let message = message as *const IpcMessage as usize;
+    let address = &mut reply as *mut IpcMessage as usize;
+    // SAFETY: message is readable and reply is writeable
+    unsafe { call(syscall::IPC_SEND, [handle, message, address, 0, 0, 0]) }?;
+    Ok(reply)
+}
+
+/// Blocks until a message is sent to an endpoint, returning it along with a handle to reply
+/// with
+pub fn ipc_receive(handle: usize) -> Result<(IpcMessage, usize), Errno> {
+    let mut message = IpcMessage::default();
+    let address = &mut message as *mut IpcMessage as usize;
+    // SAFETY: message is writeable
+    let reply = unsafe { call(syscall::IPC_RECEIVE, [handle, address, 0, 0, 0, 0]) }?;
+    Ok((message, reply))
+}
+
+/// Replies to a received message, which closes the reply handle
+pub fn ipc_reply(handle: usize, message: &IpcMessage) -> Result<(), Errno> {
+    let message = message as *const IpcMessage as usize;
+    // SAFETY: message is readable
+    unsafe { call(syscall::IPC_REPLY, [handle, message, 0, 0, 0, 0]) }.map(|_| ())
+}
+
+/// Creates a socket of an `AF_INET` type, returning its file descriptor
+pub fn socket(kind: usize, protocol: usize) -> Result<usize, Errno> {
+    // SAFETY: socket takes no pointers
+    unsafe { call(syscall::SOCKET, [AF_INET, kind, protocol, 0, 0, 0]) }
+}
+
+pub fn bind(fd: usize, address: &SockAddrIn) -> Result<(), Errno> {
+    let (address, length) = (
+        address as *const SockAddrIn as usize,
+        size_of::<SockAddrIn>(),
+    );
+    // SAFETY: address is readable for its length
+    unsafe { call(syscall::BIND, [fd, address, length, 0, 0, 0]) }.map(|_| ())
+}
+
+pub fn connect(fd: usize, address: &SockAddrIn) -> Result<(), Errno> {
+    let (address, length) = (
+        address as *const SockAddrIn as usize,
+        size_of::<SockAddrIn>(),
+    );
+    // SAFETY: address is readable for its length
+    unsafe { call(syscall::CONNECT, [fd, address, length, 0, 0, 0]) }.map(|_| ())
+}
+
+/// Makes a stream socket accept connections, queueing up to `backlog` of them
+pub fn listen(fd: usize, backlog: usize) -> Result<(), Errno> {
+    // SAFETY: listen takes no pointers
+    unsafe { call(syscall::LISTEN, [fd, backlog, 0, 0, 0, 0]) }.map(|_| ())
+}
+
+/// Waits for a connection to a listening socket, returning a new socket for it and the
+/// address of the peer
+pub fn accept(fd: usize) -> Result<(usize, SockAddrIn), Errno> {
+    let mut address = SockAddrIn::default();
+    let mut length = size_of::<SockAddrIn>() as u32;
+    let pointers = (
+        &mut address as *mut SockAddrIn as usize,
+        &mut length as *mut u32 as usize,
+    );
+    // SAFETY: address and length are writeable
+    let accepted = unsafe { call(syscall::ACCEPT, [fd, pointers.0, pointers.1, 0, 0, 0]) }?;
+    Ok((accepted, address))
+}
+
+pub fn getsockname(fd: usize) -> Result<SockAddrIn, Errno> {
+    let mut address = SockAddrIn::default();
+    let mut length = size_of::<SockAddrIn>() as u32;
+    let pointers = (
+        &mut address as *mut SockAddrIn as usize,
+        &mut length as *mut u32 as usize,
+    );
+    // SAFETY: address and length are writeable
+    unsafe { call(syscall::GETSOCKNAME, [fd, pointers.0, pointers.1, 0, 0, 0]) }?;
+    Ok(address)
+}
+
+/// Sends a datagram to an address, or to the connected peer if it is `None`
+pub fn sendto(fd: usize, data: &[u8], address: Option<&SockAddrIn>) -> Result<usize, Errno> {
+    let (buffer, length) = (data.as_ptr() as usize, data.len());
+    let (address, address_length) = match address {
+        Some(address) => (
+            address as *const SockAddrIn as usize,
+            size_of::<SockAddrIn>(),
+        ),
+        None => (0, 0),
+    };
+    // SAFETY: data and address are readable for their lengths
+    unsafe {
+        call(
+            syscall::SENDTO,
+            [fd, buffer, length, 0, address, address_length],
+        )
+    }
+}
+
+/// Receives a datagram, returning its length and sender
+pub fn recvfrom(fd: usize, buffer: &mut [u8]) -> Result<(usize, SockAddrIn), Errno> {
+    let mut address = SockAddrIn::default();
+    let mut address_length = size_of::<SockAddrIn>() as u32;
+    let (buffer, length) = (buffer.as_mut_ptr() as usize, buffer.len());
+    let pointers = (
+        &mut address as *mut SockAddrIn as usize,
+        &mut address_length as *mut u32 as usize,
+    );
+    // SAFETY: buffer is writeable for its length, address and its length are writeable
+    let received = unsafe {
+        call(
+            syscall::RECVFROM,
+            [fd, buffer, length, 0, pointers.0, pointers.1],
+        )
+    }?;
+    Ok((received, address))
+}
@@ -0,0 +1,92 @@
+//! Devices storing data in fixed-size blocks, which disk filesystems are built on
+
+use alloc::{vec, vec::Vec};
+
+use core_lib::sync::AtomicMutex;
+
+use crate::error::{VfsError, VfsResult};
+
+/// Storage read and written in whole blocks
+pub trait BlockDevice: Send + Sync {
+    /// Size of a block in bytes, a power of two
+    fn block_size(&self) -> usize;
+
+    fn block_count(&self) -> u64;
+
+    /// Reads consecutive blocks starting at `block`, the buffer length being a multiple
+    /// of the block size
+    fn read_blocks(&self, block: u64, buffer: &mut [u8]) -> VfsResult<()>;
+
+    /// Writes consecutive blocks starting at `block`, the data length being a multiple
+    /// of the block size
+    fn write_blocks(&self, block: u64, data: &[u8]) -> VfsResult<()>;
+
+    /// Checks whether writes are rejected by the device
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// Waits until written data reaches persistent storage
+    fn flush(&self) -> VfsResult<()> {
+        Ok(())
+    }
+}
+
+/// Checks that a transfer covers whole blocks inside of a device
+pub fn check_range(device: &dyn BlockDevice, block: u64, length: usize) -> VfsResult<()> {
+    let blocks = (length / device.block_size()) as u64;
+    let end = block.checked_add(blocks).ok_or(VfsError::InvaildArgument)?;
+    if !length.is_multiple_of(device.block_size()) || end > device.block_count() {
+        return Err(VfsError::InvaildArgument);
+    }
+    Ok(())
+}
+
+/// Block device keeping its contents in memory
+pub struct MemoryDisk {
+    block_size: usize,
+    data: AtomicMutex<Vec<u8>>,
+}
+
+impl MemoryDisk {
+    /// Creates a zeroed disk
+    pub fn new(block_size: usize, blocks: u64) -> MemoryDisk {
+        MemoryDisk {
+            block_size,
+            data: AtomicMutex::new(vec![0; block_size * blocks as usize]),
+        }
+    }
+
+    /// Creates a disk with given contents, which are padded to whole blocks
+    pub fn from_bytes(block_size: usize, mut data: Vec<u8>) -> MemoryDisk {
+        data.resize(data.len().next_multiple_of(block_size), 0);
+        MemoryDisk {
+            block_size,
+            data: AtomicMutex::new(data),
+        }
+    }
+}
+
+impl BlockDevice for MemoryDisk {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        (self.data.lock().len() / self.block_size) as u64
+    }
+
+    fn read_blocks(&self, block: u64, buffer: &mut [u8]) -> VfsResult<()> {
+        check_range(self, block, buffer.len())?;
+        let start = block as usize * self.block_size;
+        buffer.copy_from_slice(&self.data.lock()[start..start + buffer.len()]);
+        Ok(())
+    }
+
+    fn write_blocks(&self, block: u64, data: &[u8]) -> VfsResult<()> {
+        check_range(self, block, data.len())?;
+        let start = block as usize * self.block_size;
+        self.data.lock()[start..start + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+}
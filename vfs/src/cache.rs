@@ -0,0 +1,537 @@
+//! Cache of blocks shared by all block devices, sitting between filesystems and devices
+//!
+//! Devices are wrapped in [`CachedDevice`], which is a [`BlockDevice`] itself. Reads are
+//! served from the cache when possible, with further blocks read ahead for sequential
+//! accesses. Writes only mark cached blocks dirty, which are written to the device when
+//! evicted, by [`BufferCache::write_back`] or by flushing the device. Devices are read and
+//! written with the cache unlocked, so a slow one does not hold up others.
+
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use core_lib::sync::AtomicMutex;
+
+use crate::{
+    block::{check_range, BlockDevice},
+    error::{VfsError, VfsResult},
+};
+
+/// Most blocks read ahead of a sequential read
+const MAX_READ_AHEAD: u64 = 32;
+/// Blocks read ahead once a read turns out to be sequential
+const MIN_READ_AHEAD: u64 = 4;
+
+/// Device index and block number
+type Key = (usize, u64);
+
+struct Buffer {
+    data: Box<[u8]>,
+    dirty: bool,
+    last_used: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStatistics {
+    pub hits: u64,
+    pub misses: u64,
+    /// Blocks read from devices without being requested
+    pub read_ahead: u64,
+    pub cached: usize,
+    pub dirty: usize,
+}
+
+struct State {
+    devices: Vec<Arc<dyn BlockDevice>>,
+    buffers: BTreeMap<Key, Buffer>,
+    /// Cached blocks by the time they were last used, least recently used first
+    lru: BTreeMap<u64, Key>,
+    clock: u64,
+    /// Bytes of cached blocks
+    used: usize,
+    statistics: CacheStatistics,
+}
+
+impl State {
+    fn touch(&mut self, key: Key) {
+        let buffer = self.buffers.get_mut(&key).unwrap();
+        self.lru.remove(&buffer.last_used);
+        self.clock += 1;
+        buffer.last_used = self.clock;
+        self.lru.insert(self.clock, key);
+    }
+
+    /// Caches a block, unless `replace` is unset and the block is already cached
+    fn insert(&mut self, key: Key, data: &[u8], dirty: bool, replace: bool) {
+        match self.buffers.get_mut(&key) {
+            Some(_) if !replace => return,
+            Some(buffer) => {
+                buffer.data.copy_from_slice(data);
+                buffer.dirty |= dirty;
+            }
+            None => {
+                self.used += data.len();
+                let buffer = Buffer {
+                    data: data.into(),
+                    dirty,
+                    last_used: 0,
+                };
+                self.buffers.insert(key, buffer);
+            }
+        }
+        self.touch(key);
+    }
+
+    /// Evicts least recently used clean blocks until the cache fits its capacity. Returns
+    /// dirty blocks which have to be written back before they are evicted too
+    fn evict(&mut self, capacity: usize) -> Vec<Key> {
+        let mut used = self.used;
+        let mut victims = Vec::new();
+        for key in self.lru.values() {
+            if used <= capacity {
+                break;
+            }
+            used -= self.buffers[key].data.len();
+            victims.push(*key);
+        }
+        let mut dirty = Vec::new();
+        for key in victims {
+            if self.buffers[&key].dirty {
+                dirty.push(key);
+                continue;
+            }
+            let buffer = self.buffers.remove(&key).unwrap();
+            self.lru.remove(&buffer.last_used);
+            self.used -= buffer.data.len();
+        }
+        dirty
+    }
+
+    /// Dirty blocks of a device, or of all devices
+    fn dirty(&self, device: Option<usize>) -> Vec<Key> {
+        self.buffers
+            .iter()
+            .filter(|(key, buffer)| buffer.dirty && device.is_none_or(|device| key.0 == device))
+            .map(|(key, _)| *key)
+            .collect()
+    }
+
+    /// Copies blocks out of the cache, merging consecutive ones into single writes
+    fn runs(&self, mut keys: Vec<Key>) -> Vec<Run> {
+        keys.sort_unstable();
+        let mut runs: Vec<Run> = Vec::new();
+        for (id, block) in keys {
+            let data = &self.buffers[&(id, block)].data;
+            match runs.last_mut() {
+                Some(run) if run.id == id && run.first + run.count == block => {
+                    run.data.extend_from_slice(data);
+                    run.count += 1;
+                }
+                _ => runs.push(Run {
+                    device: self.devices[id].clone(),
+                    id,
+                    first: block,
+                    count: 1,
+                    data: data.to_vec(),
+                }),
+            }
+        }
+        runs
+    }
+
+    /// Marks blocks of a written run clean, except those changed since it was copied
+    fn mark_clean(&mut self, run: &Run) {
+        let block_size = run.data.len() / run.count as usize;
+        for (offset, data) in run.data.chunks(block_size).enumerate() {
+            let key = (run.id, run.first + offset as u64);
+            if let Some(buffer) = self.buffers.get_mut(&key) {
+                if *buffer.data == *data {
+                    buffer.dirty = false;
+                }
+            }
+        }
+    }
+}
+
+/// Consecutive dirty blocks copied out of the cache, written with the cache unlocked
+struct Run {
+    device: Arc<dyn BlockDevice>,
+    id: usize,
+    first: u64,
+    count: u64,
+    data: Vec<u8>,
+}
+
+pub struct BufferCache {
+    state: AtomicMutex<State>,
+    /// Most bytes of blocks kept in memory
+    capacity: AtomicUsize,
+}
+
+impl BufferCache {
+    /// Creates an empty cache, which does not keep blocks until it is given a capacity
+    pub const fn new() -> BufferCache {
+        BufferCache {
+            state: AtomicMutex::new(State {
+                devices: Vec::new(),
+                buffers: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                clock: 0,
+                used: 0,
+                statistics: CacheStatistics {
+                    hits: 0,
+                    misses: 0,
+                    read_ahead: 0,
+                    cached: 0,
+                    dirty: 0,
+                },
+            }),
+            capacity: AtomicUsize::new(0),
+        }
+    }
+
+    /// Changes the amount of memory the cache may use, evicting blocks beyond it
+    pub fn set_capacity(&self, bytes: usize) -> VfsResult<()> {
+        self.capacity.store(bytes, Ordering::Relaxed);
+        self.shrink()
+    }
+
+    /// Wraps a device, so that its blocks are cached
+    pub fn attach(&'static self, device: Arc<dyn BlockDevice>) -> Arc<CachedDevice> {
+        let id = {
+            let mut state = self.state.lock();
+            state.devices.push(device.clone());
+            state.devices.len() - 1
+        };
+        Arc::new(CachedDevice {
+            cache: self,
+            id,
+            device,
+            read_ahead: AtomicMutex::new(ReadAhead { next: 0, window: 0 }),
+        })
+    }
+
+    /// Writes all dirty blocks to their devices, returning how many there were
+    pub fn write_back(&self) -> VfsResult<usize> {
+        self.write_back_device(None)
+    }
+
+    /// Writes all dirty blocks and waits until devices store them
+    pub fn sync(&self) -> VfsResult<()> {
+        self.write_back_device(None)?;
+        let devices = self.state.lock().devices.clone();
+        devices.iter().try_for_each(|device| device.flush())
+    }
+
+    pub fn statistics(&self) -> CacheStatistics {
+        let state = self.state.lock();
+        CacheStatistics {
+            cached: state.buffers.len(),
+            dirty: state.buffers.values().filter(|buffer| buffer.dirty).count(),
+            ..state.statistics
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    /// Writes dirty blocks of a device, or of all devices, returning how many there were
+    fn write_back_device(&self, device: Option<usize>) -> VfsResult<usize> {
+        let runs = {
+            let state = self.state.lock();
+            state.runs(state.dirty(device))
+        };
+        let count = runs.iter().map(|run| run.count as usize).sum();
+        self.write_runs(runs)?;
+        Ok(count)
+    }
+
+    /// Evicts blocks beyond the capacity, writing dirty ones back first
+    fn shrink(&self) -> VfsResult<()> {
+        let capacity = self.capacity();
+        let runs = {
+            let mut state = self.state.lock();
+            let dirty = state.evict(capacity);
+            state.runs(dirty)
+        };
+        if runs.is_empty() {
+            return Ok(());
+        }
+        self.write_runs(runs)?;
+        // blocks dirtied again in the meantime stay until the next eviction
+        self.state.lock().evict(capacity);
+        Ok(())
+    }
+
+    /// Writes runs to their devices without the cache locked, so that other devices and
+    /// cached blocks can be used meanwhile
+    fn write_runs(&self, runs: Vec<Run>) -> VfsResult<()> {
+        for run in runs {
+            run.device.write_blocks(run.first, &run.data)?;
+            self.state.lock().mark_clean(&run);
+        }
+        Ok(())
+    }
+}
+
+impl Default for BufferCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Detection of sequential reads
+struct ReadAhead {
+    /// Block following the last read
+    next: u64,
+    /// Blocks read ahead of the next miss
+    window: u64,
+}
+
+/// Block device accessed through a [`BufferCache`]
+pub struct CachedDevice {
+    cache: &'static BufferCache,
+    id: usize,
+    device: Arc<dyn BlockDevice>,
+    read_ahead: AtomicMutex<ReadAhead>,
+}
+
+impl CachedDevice {
+    /// The device without the cache
+    pub fn inner(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
+    }
+}
+
+impl BlockDevice for CachedDevice {
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.device.block_count()
+    }
+
+    fn read_only(&self) -> bool {
+        self.device.read_only()
+    }
+
+    fn read_blocks(&self, block: u64, buffer: &mut [u8]) -> VfsResult<()> {
+        check_range(self, block, buffer.len())?;
+        let block_size = self.block_size();
+        let count = (buffer.len() / block_size) as u64;
+        if count == 0 {
+            return Ok(());
+        }
+        let mut window = {
+            let mut read_ahead = self.read_ahead.lock();
+            // the window grows while reads stay sequential
+            read_ahead.window = if block == read_ahead.next {
+                (read_ahead.window * 2).clamp(MIN_READ_AHEAD, MAX_READ_AHEAD)
+            } else {
+                0
+            };
+            read_ahead.next = block + count;
+            read_ahead.window
+        };
+
+        let mut index = 0;
+        while index < count {
+            let missing = {
+                let mut state = self.cache.state.lock();
+                while index < count {
+                    let key = (self.id, block + index);
+                    let Some(cached) = state.buffers.get(&key) else {
+                        break;
+                    };
+                    buffer[index as usize * block_size..][..block_size]
+                        .copy_from_slice(&cached.data);
+                    state.touch(key);
+                    state.statistics.hits += 1;
+                    index += 1;
+                }
+                (index..count)
+                    .take_while(|index| !state.buffers.contains_key(&(self.id, block + index)))
+                    .count() as u64
+            };
+            if missing == 0 {
+                break;
+            }
+
+            // reads all missing blocks at once, along with those read ahead, without the
+            // cache locked
+            let first = block + index;
+            let extra = window.min(self.block_count() - (first + missing));
+            let mut data = vec![0; ((missing + extra) * block_size as u64) as usize];
+            self.device.read_blocks(first, &mut data)?;
+            let mut state = self.cache.state.lock();
+            state.statistics.misses += missing;
+            state.statistics.read_ahead += extra;
+            for (offset, chunk) in data.chunks(block_size).enumerate() {
+                let offset = offset as u64;
+                let key = (self.id, first + offset);
+                // blocks written in the meantime are kept instead of those read
+                state.insert(key, chunk, false, false);
+                if offset < missing {
+                    buffer[(index + offset) as usize * block_size..][..block_size]
+                        .copy_from_slice(&state.buffers[&key].data);
+                }
+            }
+            index += missing;
+            window = 0;
+        }
+
+        self.cache.shrink()
+    }
+
+    fn write_blocks(&self, block: u64, data: &[u8]) -> VfsResult<()> {
+        if self.read_only() {
+            return Err(VfsError::ReadOnly);
+        }
+        check_range(self, block, data.len())?;
+        {
+            let mut state = self.cache.state.lock();
+            for (index, chunk) in data.chunks(self.block_size()).enumerate() {
+                state.insert((self.id, block + index as u64), chunk, true, true);
+            }
+        }
+        self.cache.shrink()
+    }
+
+    fn flush(&self) -> VfsResult<()> {
+        self.cache.write_back_device(Some(self.id))?;
+        self.device.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{boxed::Box, sync::Arc, vec};
+
+    use super::{BufferCache, CachedDevice};
+    use crate::{block::MemoryDisk, BlockDevice, VfsResult};
+
+    fn cache(capacity: usize) -> &'static BufferCache {
+        let cache = Box::leak(Box::new(BufferCache::new()));
+        cache.set_capacity(capacity).unwrap();
+        cache
+    }
+
+    #[test]
+    fn test_write_back() {
+        let cache = cache(64 * 512);
+        let disk = Arc::new(MemoryDisk::new(512, 128));
+        let cached = cache.attach(disk.clone());
+        cached.write_blocks(3, &vec![7; 1024]).unwrap();
+
+        let mut buffer = vec![0; 512];
+        disk.read_blocks(4, &mut buffer).unwrap();
+        assert!(buffer.iter().all(|byte| *byte == 0));
+        cached.read_blocks(4, &mut buffer).unwrap();
+        assert!(buffer.iter().all(|byte| *byte == 7));
+        assert_eq!(cache.statistics().dirty, 2);
+
+        assert_eq!(cache.write_back(), Ok(2));
+        disk.read_blocks(4, &mut buffer).unwrap();
+        assert!(buffer.iter().all(|byte| *byte == 7));
+        assert_eq!(cache.statistics().dirty, 0);
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let cache = cache(4 * 512);
+        let disk = Arc::new(MemoryDisk::new(512, 128));
+        let cached = cache.attach(disk.clone());
+        let mut buffer = vec![0; 512];
+        for block in [10, 20, 30, 40] {
+            cached.write_blocks(block, &[block as u8; 512]).unwrap();
+        }
+        // block 10 is used again, so 20 is the one evicted and written back
+        cached.read_blocks(10, &mut buffer).unwrap();
+        cached.write_blocks(50, &[50; 512]).unwrap();
+        let statistics = cache.statistics();
+        assert_eq!((statistics.cached, statistics.dirty), (4, 4));
+        disk.read_blocks(20, &mut buffer).unwrap();
+        assert_eq!(buffer[0], 20);
+        disk.read_blocks(10, &mut buffer).unwrap();
+        assert_eq!(buffer[0], 0);
+    }
+
+    #[test]
+    fn test_sequential_read_ahead() {
+        let cache = cache(256 * 512);
+        let disk = Arc::new(MemoryDisk::new(512, 128));
+        let cached = cache.attach(disk.clone());
+        let mut buffer = vec![0; 512];
+        for block in 0..16 {
+            cached.read_blocks(block, &mut buffer).unwrap();
+        }
+        let statistics = cache.statistics();
+        assert_eq!(statistics.hits + statistics.misses, 16);
+        assert!(statistics.misses < 8, "{:?}", statistics);
+        assert!(statistics.read_ahead > 0);
+
+        // random reads do not read ahead
+        let before = cache.statistics().read_ahead;
+        for block in [100, 60, 90] {
+            cached.read_blocks(block, &mut buffer).unwrap();
+        }
+        assert_eq!(cache.statistics().read_ahead, before);
+    }
+
+    /// Disk which reads another cached device while it is written, as another task would
+    struct BusyDisk {
+        disk: MemoryDisk,
+        other: Arc<CachedDevice>,
+    }
+
+    impl BlockDevice for BusyDisk {
+        fn block_size(&self) -> usize {
+            self.disk.block_size()
+        }
+
+        fn block_count(&self) -> u64 {
+            self.disk.block_count()
+        }
+
+        fn read_blocks(&self, block: u64, buffer: &mut [u8]) -> VfsResult<()> {
+            self.disk.read_blocks(block, buffer)
+        }
+
+        fn write_blocks(&self, block: u64, data: &[u8]) -> VfsResult<()> {
+            let mut buffer = vec![0; 512];
+            self.other.read_blocks(0, &mut buffer)?;
+            assert_eq!(buffer[0], 9);
+            self.disk.write_blocks(block, data)
+        }
+    }
+
+    #[test]
+    fn test_cache_usable_while_writing_back() {
+        let cache = cache(64 * 512);
+        let other = cache.attach(Arc::new(MemoryDisk::new(512, 8)));
+        other.write_blocks(0, &[9; 512]).unwrap();
+        let busy = cache.attach(Arc::new(BusyDisk {
+            disk: MemoryDisk::new(512, 8),
+            other,
+        }));
+        busy.write_blocks(2, &[1; 1024]).unwrap();
+        busy.flush().unwrap();
+        assert_eq!(cache.statistics().dirty, 1);
+        assert_eq!(cache.write_back(), Ok(1));
+    }
+
+    #[test]
+    fn test_devices_kept_apart() {
+        let cache = cache(64 * 512);
+        let first = cache.attach(Arc::new(MemoryDisk::new(512, 8)));
+        let second = cache.attach(Arc::new(MemoryDisk::new(512, 8)));
+        first.write_blocks(1, &[1; 512]).unwrap();
+        second.write_blocks(1, &[2; 512]).unwrap();
+        let mut buffer = vec![0; 512];
+        first.read_blocks(1, &mut buffer).unwrap();
+        assert_eq!(buffer[0], 1);
+        second.flush().unwrap();
+        assert_eq!(cache.statistics().dirty, 1);
+    }
+}
@@ -0,0 +1,162 @@
+//! Directory entries, caching path components looked up in filesystems
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+
+use core_lib::sync::AtomicMutex;
+
+use crate::{
+    error::{VfsError, VfsResult},
+    inode::{FileType, Inode},
+};
+
+/// A name bound to an inode
+///
+/// Dentries keep their parents alive, while parents only cache their children as long as
+/// something else uses them, e.g. an open file or a mount.
+pub struct Dentry {
+    name: String,
+    inode: Arc<dyn Inode>,
+    parent: Option<Arc<Dentry>>,
+    /// Mount point hidden by this dentry, if it is the root of a mounted filesystem
+    covered: Option<Arc<Dentry>>,
+    children: AtomicMutex<BTreeMap<String, Weak<Dentry>>>,
+    /// Root of the filesystem mounted over this dentry
+    mounted: AtomicMutex<Option<Arc<Dentry>>>,
+}
+
+impl Dentry {
+    /// Creates the root dentry of a filesystem, mounted over `covered` unless it is the root
+    /// of the whole tree
+    pub(crate) fn new_root(inode: Arc<dyn Inode>, covered: Option<Arc<Dentry>>) -> Arc<Dentry> {
+        Arc::new(Dentry {
+            name: covered
+                .as_ref()
+                .map_or_else(String::new, |covered| covered.name.clone()),
+            inode,
+            parent: None,
+            covered,
+            children: AtomicMutex::new(BTreeMap::new()),
+            mounted: AtomicMutex::new(None),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    pub fn file_type(&self) -> FileType {
+        self.inode.metadata().file_type
+    }
+
+    /// Parent directory, crossing mount points. The root is its own parent
+    pub fn parent(self: &Arc<Self>) -> Arc<Dentry> {
+        match (&self.parent, &self.covered) {
+            (Some(parent), _) => parent.clone(),
+            (None, Some(covered)) => covered.parent(),
+            (None, None) => self.clone(),
+        }
+    }
+
+    /// Absolute path of the dentry
+    pub fn path(self: &Arc<Self>) -> String {
+        let mut names = Vec::new();
+        let mut current = self.clone();
+        loop {
+            let parent = current.parent();
+            if Arc::ptr_eq(&parent, &current) {
+                break;
+            }
+            names.push(current.name.clone());
+            current = parent;
+        }
+        if names.is_empty() {
+            return "/".to_string();
+        }
+        names.iter().rev().fold(String::new(), |mut path, name| {
+            path.push('/');
+            path.push_str(name);
+            path
+        })
+    }
+
+    /// Finds a child by name, giving the root of a filesystem mounted over it if there is one
+    pub fn lookup(self: &Arc<Self>, name: &str) -> VfsResult<Arc<Dentry>> {
+        let cached = self.children.lock().get(name).and_then(Weak::upgrade);
+        let child = match cached {
+            Some(child) => child,
+            None => {
+                let inode = self.inode.lookup(name)?;
+                self.insert(name, inode)
+            }
+        };
+        Ok(child.mounted().unwrap_or(child))
+    }
+
+    /// Creates a file, directory or symlink in the directory
+    pub fn create(
+        self: &Arc<Self>,
+        name: &str,
+        file_type: FileType,
+        permissions: u32,
+    ) -> VfsResult<Arc<Dentry>> {
+        let inode = self.inode.create(name, file_type, permissions)?;
+        Ok(self.insert(name, inode))
+    }
+
+    pub fn symlink(self: &Arc<Self>, name: &str, target: &str) -> VfsResult<Arc<Dentry>> {
+        let inode = self.inode.symlink(name, target)?;
+        Ok(self.insert(name, inode))
+    }
+
+    /// Removes a child, unless something is mounted over it
+    pub fn unlink(self: &Arc<Self>, name: &str) -> VfsResult<()> {
+        let cached = self.children.lock().get(name).and_then(Weak::upgrade);
+        if cached.is_some_and(|child| child.mounted().is_some()) {
+            return Err(VfsError::Busy);
+        }
+        self.inode.unlink(name)?;
+        self.children.lock().remove(name);
+        Ok(())
+    }
+
+    pub(crate) fn mounted(&self) -> Option<Arc<Dentry>> {
+        self.mounted.lock().clone()
+    }
+
+    /// Whether the dentry is the root of a filesystem
+    pub(crate) fn is_mount_root(&self) -> bool {
+        self.parent.is_none()
+    }
+
+    pub(crate) fn set_mounted(&self, root: Option<Arc<Dentry>>) {
+        *self.mounted.lock() = root;
+    }
+
+    /// Caches a child, unless another lookup cached it first
+    fn insert(self: &Arc<Self>, name: &str, inode: Arc<dyn Inode>) -> Arc<Dentry> {
+        let mut children = self.children.lock();
+        if let Some(existing) = children.get(name).and_then(Weak::upgrade) {
+            return existing;
+        }
+        let child = Arc::new(Dentry {
+            name: name.to_string(),
+            inode,
+            parent: Some(self.clone()),
+            covered: None,
+            children: AtomicMutex::new(BTreeMap::new()),
+            mounted: AtomicMutex::new(None),
+        });
+        children.retain(|_, child| child.strong_count() > 0);
+        children.insert(name.to_string(), Arc::downgrade(&child));
+        child
+    }
+}
@@ -0,0 +1,145 @@
+//! Filesystem listing device nodes provided by the kernel, usually mounted at `/dev`
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+};
+
+use core_lib::sync::AtomicMutex;
+
+use crate::{
+    error::{VfsError, VfsResult},
+    inode::{DirEntry, FileSystem, FileType, Inode, Metadata},
+    MAX_NAME_LENGTH,
+};
+
+pub struct DevFs {
+    root: Arc<DevDirectory>,
+}
+
+impl DevFs {
+    pub fn new() -> Arc<DevFs> {
+        Arc::new(DevFs {
+            root: Arc::new(DevDirectory {
+                devices: AtomicMutex::new(BTreeMap::new()),
+            }),
+        })
+    }
+
+    /// Adds a device node, which has to be a character device
+    pub fn add(&self, name: &str, device: Arc<dyn Inode>) -> VfsResult<()> {
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return Err(VfsError::InvaildPath);
+        }
+        if name.len() > MAX_NAME_LENGTH {
+            return Err(VfsError::NameTooLong);
+        }
+        if device.metadata().file_type != FileType::CharDevice {
+            return Err(VfsError::InvaildArgument);
+        }
+        let mut devices = self.root.devices.lock();
+        if devices.contains_key(name) {
+            return Err(VfsError::AlreadyExists);
+        }
+        devices.insert(name.to_string(), device);
+        Ok(())
+    }
+}
+
+impl FileSystem for DevFs {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+/// The only directory, holding all devices
+struct DevDirectory {
+    devices: AtomicMutex<BTreeMap<String, Arc<dyn Inode>>>,
+}
+
+impl Inode for DevDirectory {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            inode: 1,
+            file_type: FileType::Directory,
+            permissions: 0o755,
+            size: self.devices.lock().len() as u64,
+            links: 2,
+        }
+    }
+
+    fn lookup(&self, name: &str) -> VfsResult<Arc<dyn Inode>> {
+        self.devices
+            .lock()
+            .get(name)
+            .cloned()
+            .ok_or(VfsError::NotFound)
+    }
+
+    fn read_dir(&self, index: usize) -> VfsResult<Option<DirEntry>> {
+        let devices = self.devices.lock();
+        Ok(devices.iter().nth(index).map(|(name, device)| {
+            let metadata = device.metadata();
+            DirEntry {
+                name: name.clone(),
+                inode: metadata.inode,
+                file_type: metadata.file_type,
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+
+    use super::DevFs;
+    use crate::{
+        inode::{FileSystem, FileType, Inode, Metadata},
+        VfsError, VfsResult,
+    };
+
+    struct Zero;
+
+    impl Inode for Zero {
+        fn metadata(&self) -> Metadata {
+            Metadata {
+                inode: 5,
+                file_type: FileType::CharDevice,
+                permissions: 0o666,
+                size: 0,
+                links: 1,
+            }
+        }
+
+        fn read_at(&self, _offset: u64, buffer: &mut [u8]) -> VfsResult<usize> {
+            buffer.fill(0);
+            Ok(buffer.len())
+        }
+    }
+
+    #[test]
+    fn test_devices() {
+        let devfs = DevFs::new();
+        devfs.add("zero", Arc::new(Zero)).unwrap();
+        assert_eq!(
+            devfs.add("zero", Arc::new(Zero)),
+            Err(VfsError::AlreadyExists)
+        );
+        assert_eq!(devfs.add("a/b", Arc::new(Zero)), Err(VfsError::InvaildPath));
+
+        let root = devfs.root();
+        let mut buffer = [1; 4];
+        assert_eq!(root.lookup("zero").unwrap().read_at(0, &mut buffer), Ok(4));
+        assert_eq!(buffer, [0; 4]);
+        assert!(matches!(root.lookup("null"), Err(VfsError::NotFound)));
+        let entry = root.read_dir(0).unwrap().unwrap();
+        assert_eq!((entry.name.as_str(), entry.inode), ("zero", 5));
+        assert_eq!(root.read_dir(1), Ok(None));
+    }
+}
@@ -0,0 +1,93 @@
+//! Error types
+
+use core_lib::syscall::Errno;
+use snafu::Snafu;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Snafu)]
+pub enum VfsError {
+    #[snafu(display("No such file or directory"))]
+    NotFound,
+    #[snafu(display("Not a directory"))]
+    NotADirectory,
+    #[snafu(display("Is a directory"))]
+    IsADirectory,
+    #[snafu(display("File already exists"))]
+    AlreadyExists,
+    #[snafu(display("Directory is not empty"))]
+    NotEmpty,
+    #[snafu(display("Invaild path"))]
+    InvaildPath,
+    #[snafu(display("Name is too long"))]
+    NameTooLong,
+    #[snafu(display("Too many levels of symbolic links"))]
+    TooManySymlinks,
+    #[snafu(display("No space left on filesystem"))]
+    NoSpace,
+    #[snafu(display("File is too large"))]
+    FileTooLarge,
+    #[snafu(display("Filesystem is read-only"))]
+    ReadOnly,
+    #[snafu(display("File is in use"))]
+    Busy,
+    #[snafu(display("Bad file descriptor"))]
+    BadFileDescriptor,
+    #[snafu(display("Too many open files"))]
+    TooManyFiles,
+    #[snafu(display("Invaild argument"))]
+    InvaildArgument,
+    #[snafu(display("Operation not supported by the file"))]
+    Unsupported,
+    #[snafu(display("I/O error"))]
+    Io,
+    #[snafu(display("Pipe has no readers left"))]
+    BrokenPipe,
+    #[snafu(display("Interrupted before any data was transferred"))]
+    Interrupted,
+    #[snafu(display("Not a terminal"))]
+    NotATerminal,
+    #[snafu(display("Bad address"))]
+    BadAddress,
+    #[snafu(display("Socket is not connected"))]
+    NotConnected,
+    #[snafu(display("Network is unreachable"))]
+    NetworkUnreachable,
+    #[snafu(display("Connection reset by peer"))]
+    ConnectionReset,
+    #[snafu(display("Connection timed out"))]
+    TimedOut,
+}
+
+impl VfsError {
+    /// Error number reported to user programs
+    pub fn errno(&self) -> Errno {
+        match self {
+            VfsError::NotFound => Errno::ENOENT,
+            VfsError::NotADirectory => Errno::ENOTDIR,
+            VfsError::IsADirectory => Errno::EISDIR,
+            VfsError::AlreadyExists => Errno::EEXIST,
+            VfsError::NotEmpty => Errno::ENOTEMPTY,
+            VfsError::InvaildPath => Errno::ENOENT,
+            VfsError::NameTooLong => Errno::ENAMETOOLONG,
+            VfsError::TooManySymlinks => Errno::ELOOP,
+            VfsError::NoSpace => Errno::ENOSPC,
+            VfsError::FileTooLarge => Errno::EFBIG,
+            VfsError::ReadOnly => Errno::EROFS,
+            VfsError::Busy => Errno::EBUSY,
+            VfsError::BadFileDescriptor => Errno::EBADF,
+            VfsError::TooManyFiles => Errno::EMFILE,
+            VfsError::InvaildArgument => Errno::EINVAL,
+            VfsError::Unsupported => Errno::EPERM,
+            VfsError::Io => Errno::EIO,
+            VfsError::BrokenPipe => Errno::EPIPE,
+            VfsError::Interrupted => Errno::EINTR,
+            VfsError::NotATerminal => Errno::ENOTTY,
+            VfsError::BadAddress => Errno::EFAULT,
+            VfsError::NotConnected => Errno::ENOTCONN,
+            VfsError::NetworkUnreachable => Errno::ENETUNREACH,
+            VfsError::ConnectionReset => Errno::ECONNRESET,
+            VfsError::TimedOut => Errno::ETIMEDOUT,
+        }
+    }
+}
+
+pub type VfsResult<T> = Result<T, VfsError>;
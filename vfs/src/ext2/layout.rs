@@ -0,0 +1,203 @@
+//! On-disk structures of ext2, all of them little-endian
+
+use alloc::{string::String, vec::Vec};
+
+use crate::error::{VfsError, VfsResult};
+
+/// Byte offset of the superblock from the start of the volume
+pub const SUPERBLOCK_OFFSET: u64 = 1024;
+pub const SUPERBLOCK_SIZE: usize = 1024;
+pub const ROOT_INODE: u32 = 2;
+pub const DIRECT_BLOCKS: usize = 12;
+const MAGIC: u16 = 0xef53;
+const GOOD_OLD_REVISION: u32 = 0;
+const GOOD_OLD_INODE_SIZE: usize = 128;
+const GROUP_DESCRIPTOR_SIZE: usize = 32;
+/// Directory entries record their file type instead of only the inode doing so
+pub const FEATURE_FILETYPE: u32 = 0x0002;
+/// Incompatible features which do not change how the volume is read
+const SUPPORTED_INCOMPATIBLE_FEATURES: u32 = FEATURE_FILETYPE;
+
+// file type bits of modes
+const MODE_TYPE_MASK: u16 = 0xf000;
+pub const MODE_REGULAR: u16 = 0x8000;
+pub const MODE_DIRECTORY: u16 = 0x4000;
+pub const MODE_SYMLINK: u16 = 0xa000;
+pub const MODE_CHAR_DEVICE: u16 = 0x2000;
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Superblock {
+    pub inodes_count: u32,
+    pub blocks_count: u32,
+    pub first_data_block: u32,
+    pub block_size: usize,
+    pub blocks_per_group: u32,
+    pub inodes_per_group: u32,
+    pub inode_size: usize,
+    pub incompatible_features: u32,
+}
+
+impl Superblock {
+    pub fn parse(data: &[u8]) -> VfsResult<Superblock> {
+        if u16_at(data, 56) != MAGIC {
+            return Err(VfsError::InvaildArgument);
+        }
+        let log_block_size = u32_at(data, 24);
+        let revision = u32_at(data, 76);
+        let superblock = Superblock {
+            inodes_count: u32_at(data, 0),
+            blocks_count: u32_at(data, 4),
+            first_data_block: u32_at(data, 20),
+            block_size: 1024usize.checked_shl(log_block_size).unwrap_or(0),
+            blocks_per_group: u32_at(data, 32),
+            inodes_per_group: u32_at(data, 40),
+            inode_size: if revision == GOOD_OLD_REVISION {
+                GOOD_OLD_INODE_SIZE
+            } else {
+                u16_at(data, 88) as usize
+            },
+            incompatible_features: if revision == GOOD_OLD_REVISION {
+                0
+            } else {
+                u32_at(data, 96)
+            },
+        };
+
+        let valid = log_block_size <= 6
+            && superblock.blocks_per_group > 0
+            && superblock.inodes_per_group > 0
+            && superblock.inode_size.is_power_of_two()
+            && (GOOD_OLD_INODE_SIZE..=superblock.block_size).contains(&superblock.inode_size)
+            && superblock.first_data_block < superblock.blocks_count
+            && ROOT_INODE <= superblock.inodes_count;
+        if !valid {
+            return Err(VfsError::InvaildArgument);
+        }
+        if superblock.incompatible_features & !SUPPORTED_INCOMPATIBLE_FEATURES != 0 {
+            return Err(VfsError::Unsupported);
+        }
+        Ok(superblock)
+    }
+
+    pub fn group_count(&self) -> u32 {
+        (self.blocks_count - self.first_data_block).div_ceil(self.blocks_per_group)
+    }
+
+    /// Block holding the group descriptor table, following the superblock
+    pub fn group_descriptors_block(&self) -> u32 {
+        self.first_data_block + 1
+    }
+
+    pub fn group_descriptors_size(&self) -> usize {
+        self.group_count() as usize * GROUP_DESCRIPTOR_SIZE
+    }
+}
+
+/// Parses inode table locations out of the group descriptor table
+pub fn inode_tables(data: &[u8], groups: u32) -> Vec<u32> {
+    (0..groups as usize)
+        .map(|group| u32_at(data, group * GROUP_DESCRIPTOR_SIZE + 8))
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct RawInode {
+    pub mode: u16,
+    pub size: u64,
+    pub links: u16,
+    /// Storage used, in 512-byte units
+    pub sectors: u32,
+    pub file_acl: u32,
+    /// Direct, indirect, doubly and triply indirect blocks, or the target of a short symlink
+    pub blocks: [u32; 15],
+    /// Raw bytes of `blocks`
+    pub inline: [u8; 60],
+}
+
+impl RawInode {
+    pub fn parse(data: &[u8]) -> RawInode {
+        let mode = u16_at(data, 0);
+        let mut size = u32_at(data, 4) as u64;
+        // the field holds upper bits of the size for regular files
+        if mode & MODE_TYPE_MASK == MODE_REGULAR {
+            size |= (u32_at(data, 108) as u64) << 32;
+        }
+        RawInode {
+            mode,
+            size,
+            links: u16_at(data, 26),
+            sectors: u32_at(data, 28),
+            file_acl: u32_at(data, 104),
+            blocks: core::array::from_fn(|index| u32_at(data, 40 + index * 4)),
+            inline: data[40..100].try_into().unwrap(),
+        }
+    }
+
+    pub fn file_type(&self) -> u16 {
+        self.mode & MODE_TYPE_MASK
+    }
+
+    /// Checks whether a symlink stores its target in the inode instead of a block
+    pub fn is_fast_symlink(&self, block_size: usize) -> bool {
+        let acl_sectors = if self.file_acl != 0 {
+            (block_size / 512) as u32
+        } else {
+            0
+        };
+        self.file_type() == MODE_SYMLINK && self.sectors.saturating_sub(acl_sectors) == 0
+    }
+}
+
+/// Entry of a directory block
+pub struct RawDirEntry {
+    pub inode: u32,
+    /// Type recorded in the entry, 0 if it is unknown
+    pub file_type: u8,
+    pub name: String,
+}
+
+// file types of directory entries
+pub const ENTRY_REGULAR: u8 = 1;
+pub const ENTRY_DIRECTORY: u8 = 2;
+pub const ENTRY_CHAR_DEVICE: u8 = 3;
+pub const ENTRY_SYMLINK: u8 = 7;
+
+/// Parses entries of a directory block, including unused ones with inode 0
+pub fn parse_directory_block(
+    block: &[u8],
+    features: u32,
+    entries: &mut Vec<RawDirEntry>,
+) -> VfsResult<()> {
+    let mut offset = 0;
+    while offset + 8 <= block.len() {
+        let inode = u32_at(block, offset);
+        let record_length = u16_at(block, offset + 4) as usize;
+        let name_length = block[offset + 6] as usize;
+        if record_length < 8
+            || offset + record_length > block.len()
+            || 8 + name_length > record_length
+        {
+            return Err(VfsError::Io);
+        }
+        let name = &block[offset + 8..offset + 8 + name_length];
+        entries.push(RawDirEntry {
+            inode,
+            file_type: if features & FEATURE_FILETYPE != 0 {
+                block[offset + 7]
+            } else {
+                0
+            },
+            name: String::from_utf8_lossy(name).into_owned(),
+        });
+        offset += record_length;
+    }
+    Ok(())
+}
@@ -0,0 +1,489 @@
+//! Read-only ext2 filesystem on a block device
+//!
+//! Images made with `mke2fs -t ext2` can be mounted, as the features it enables either do
+//! not matter for reading or keep a layout readable the old way. Hashed directory indices
+//! are ignored and directories are scanned linearly instead.
+
+mod layout;
+
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+
+use crate::{
+    block::BlockDevice,
+    error::{VfsError, VfsResult},
+    inode::{DirEntry, FileSystem, FileType, Inode, Metadata},
+};
+
+use layout::{
+    inode_tables, parse_directory_block, RawDirEntry, RawInode, Superblock, DIRECT_BLOCKS,
+    ENTRY_CHAR_DEVICE, ENTRY_DIRECTORY, ENTRY_REGULAR, ENTRY_SYMLINK, MODE_CHAR_DEVICE,
+    MODE_DIRECTORY, MODE_REGULAR, MODE_SYMLINK, ROOT_INODE, SUPERBLOCK_OFFSET, SUPERBLOCK_SIZE,
+};
+
+/// Longest symlink target read from data blocks
+const MAX_SYMLINK_LENGTH: u64 = 4096;
+
+pub struct Ext2 {
+    root: Arc<Ext2Inode>,
+}
+
+impl Ext2 {
+    /// Opens an ext2 volume, failing with [`VfsError::InvaildArgument`] if the device holds
+    /// none and with [`VfsError::Unsupported`] if it uses features which are not handled
+    pub fn new(device: Arc<dyn BlockDevice>) -> VfsResult<Arc<Ext2>> {
+        let mut data = vec![0; SUPERBLOCK_SIZE];
+        read_bytes(&*device, SUPERBLOCK_OFFSET, &mut data)?;
+        let superblock = Superblock::parse(&data)?;
+        if !superblock.block_size.is_multiple_of(device.block_size())
+            || superblock.blocks_count as u64 * superblock.block_size as u64
+                > device.block_count() * device.block_size() as u64
+        {
+            return Err(VfsError::InvaildArgument);
+        }
+
+        let mut descriptors = vec![0; superblock.group_descriptors_size()];
+        let offset = superblock.group_descriptors_block() as u64 * superblock.block_size as u64;
+        read_bytes(&*device, offset, &mut descriptors)?;
+        let volume = Arc::new(Volume {
+            device,
+            inode_tables: inode_tables(&descriptors, superblock.group_count()),
+            superblock,
+        });
+        let root = volume.inode(ROOT_INODE)?;
+        if root.file_type()? != FileType::Directory {
+            return Err(VfsError::InvaildArgument);
+        }
+        Ok(Arc::new(Ext2 { root }))
+    }
+}
+
+impl FileSystem for Ext2 {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+/// Reads bytes at any offset of a device
+fn read_bytes(device: &dyn BlockDevice, offset: u64, buffer: &mut [u8]) -> VfsResult<()> {
+    let block_size = device.block_size() as u64;
+    let first = offset / block_size;
+    let end = (offset + buffer.len() as u64).div_ceil(block_size);
+    let mut blocks = vec![0; ((end - first) * block_size) as usize];
+    device.read_blocks(first, &mut blocks)?;
+    let start = (offset % block_size) as usize;
+    buffer.copy_from_slice(&blocks[start..start + buffer.len()]);
+    Ok(())
+}
+
+struct Volume {
+    device: Arc<dyn BlockDevice>,
+    superblock: Superblock,
+    /// First block of the inode table of each group
+    inode_tables: Vec<u32>,
+}
+
+impl Volume {
+    fn block_size(&self) -> usize {
+        self.superblock.block_size
+    }
+
+    fn read_block(&self, block: u32, buffer: &mut [u8]) -> VfsResult<()> {
+        if block >= self.superblock.blocks_count {
+            return Err(VfsError::Io);
+        }
+        let device_blocks = (self.block_size() / self.device.block_size()) as u64;
+        self.device
+            .read_blocks(block as u64 * device_blocks, buffer)
+    }
+
+    fn inode(self: &Arc<Volume>, number: u32) -> VfsResult<Arc<Ext2Inode>> {
+        if number == 0 || number > self.superblock.inodes_count {
+            return Err(VfsError::Io);
+        }
+        let group = (number - 1) / self.superblock.inodes_per_group;
+        let index = (number - 1) % self.superblock.inodes_per_group;
+        let table = *self.inode_tables.get(group as usize).ok_or(VfsError::Io)?;
+        let offset = table as u64 * self.block_size() as u64
+            + index as u64 * self.superblock.inode_size as u64;
+
+        let mut block = vec![0; self.block_size()];
+        self.read_block((offset / self.block_size() as u64) as u32, &mut block)?;
+        let start = (offset % self.block_size() as u64) as usize;
+        Ok(Arc::new(Ext2Inode {
+            volume: self.clone(),
+            number,
+            raw: RawInode::parse(&block[start..start + self.superblock.inode_size]),
+        }))
+    }
+
+    /// Finds the block holding a block of a file, 0 standing for a hole
+    fn map_block(&self, raw: &RawInode, index: u64) -> VfsResult<u32> {
+        let per_block = (self.block_size() / 4) as u64;
+        if index < DIRECT_BLOCKS as u64 {
+            return Ok(raw.blocks[index as usize]);
+        }
+        // indices of pointers to follow at each level of indirection
+        let mut remaining = index - DIRECT_BLOCKS as u64;
+        let mut span = per_block;
+        for level in 0..3 {
+            if remaining < span {
+                let mut block = raw.blocks[DIRECT_BLOCKS + level];
+                let mut data = vec![0; self.block_size()];
+                for depth in (0..=level).rev() {
+                    if block == 0 {
+                        return Ok(0);
+                    }
+                    self.read_block(block, &mut data)?;
+                    let entry = (remaining / per_block.pow(depth as u32) % per_block) as usize;
+                    block = u32::from_le_bytes(data[entry * 4..entry * 4 + 4].try_into().unwrap());
+                }
+                return Ok(block);
+            }
+            remaining -= span;
+            span *= per_block;
+        }
+        Err(VfsError::FileTooLarge)
+    }
+}
+
+pub struct Ext2Inode {
+    volume: Arc<Volume>,
+    number: u32,
+    raw: RawInode,
+}
+
+impl Ext2Inode {
+    fn file_type(&self) -> VfsResult<FileType> {
+        match self.raw.file_type() {
+            MODE_REGULAR => Ok(FileType::Regular),
+            MODE_DIRECTORY => Ok(FileType::Directory),
+            MODE_SYMLINK => Ok(FileType::Symlink),
+            MODE_CHAR_DEVICE => Ok(FileType::CharDevice),
+            _ => Err(VfsError::Unsupported),
+        }
+    }
+
+    /// Reads contents stored in data blocks, zero-filling holes
+    fn read_data(&self, offset: u64, buffer: &mut [u8]) -> VfsResult<usize> {
+        let block_size = self.volume.block_size() as u64;
+        let end = self
+            .raw
+            .size
+            .min(offset.saturating_add(buffer.len() as u64));
+        if end <= offset {
+            return Ok(0);
+        }
+        let mut data = vec![0; block_size as usize];
+        let mut position = offset;
+        while position < end {
+            let start = (position % block_size) as usize;
+            let length = (block_size as usize - start).min((end - position) as usize);
+            let target = &mut buffer[(position - offset) as usize..][..length];
+            match self.volume.map_block(&self.raw, position / block_size)? {
+                0 => target.fill(0),
+                block => {
+                    self.volume.read_block(block, &mut data)?;
+                    target.copy_from_slice(&data[start..start + length]);
+                }
+            }
+            position += length as u64;
+        }
+        Ok((end - offset) as usize)
+    }
+
+    /// Lists entries of a directory, without `.`, `..` and unused ones
+    fn entries(&self) -> VfsResult<Vec<RawDirEntry>> {
+        if self.raw.file_type() != MODE_DIRECTORY {
+            return Err(VfsError::NotADirectory);
+        }
+        let block_size = self.volume.block_size();
+        let mut data = vec![0; block_size];
+        let mut entries = Vec::new();
+        for index in 0..self.raw.size.div_ceil(block_size as u64) {
+            let read = self.read_data(index * block_size as u64, &mut data)?;
+            let features = self.volume.superblock.incompatible_features;
+            parse_directory_block(&data[..read], features, &mut entries)?;
+        }
+        entries.retain(|entry| entry.inode != 0 && entry.name != "." && entry.name != "..");
+        Ok(entries)
+    }
+
+    fn entry_type(&self, entry: &RawDirEntry) -> VfsResult<FileType> {
+        match entry.file_type {
+            ENTRY_REGULAR => Ok(FileType::Regular),
+            ENTRY_DIRECTORY => Ok(FileType::Directory),
+            ENTRY_SYMLINK => Ok(FileType::Symlink),
+            ENTRY_CHAR_DEVICE => Ok(FileType::CharDevice),
+            // older volumes record types only in inodes
+            0 => self.volume.inode(entry.inode)?.file_type(),
+            _ => Err(VfsError::Unsupported),
+        }
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            inode: self.number as u64,
+            // nodes of other types are never looked up
+            file_type: self.file_type().unwrap_or(FileType::Regular),
+            permissions: (self.raw.mode & 0o7777) as u32,
+            size: self.raw.size,
+            links: self.raw.links as u32,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> VfsResult<usize> {
+        match self.raw.file_type() {
+            MODE_REGULAR => self.read_data(offset, buffer),
+            _ => Err(VfsError::Unsupported),
+        }
+    }
+
+    fn write_at(&self, _offset: u64, _data: &[u8]) -> VfsResult<usize> {
+        Err(VfsError::ReadOnly)
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult<()> {
+        Err(VfsError::ReadOnly)
+    }
+
+    fn lookup(&self, name: &str) -> VfsResult<Arc<dyn Inode>> {
+        let entries = self.entries()?;
+        let entry = entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or(VfsError::NotFound)?;
+        let inode = self.volume.inode(entry.inode)?;
+        // sockets, pipes and block devices are left out
+        if inode.file_type().is_err() {
+            return Err(VfsError::NotFound);
+        }
+        Ok(inode)
+    }
+
+    fn create(
+        &self,
+        _name: &str,
+        _file_type: FileType,
+        _permissions: u32,
+    ) -> VfsResult<Arc<dyn Inode>> {
+        Err(VfsError::ReadOnly)
+    }
+
+    fn symlink(&self, _name: &str, _target: &str) -> VfsResult<Arc<dyn Inode>> {
+        Err(VfsError::ReadOnly)
+    }
+
+    fn unlink(&self, _name: &str) -> VfsResult<()> {
+        Err(VfsError::ReadOnly)
+    }
+
+    fn read_dir(&self, index: usize) -> VfsResult<Option<DirEntry>> {
+        let mut entries = self.entries()?.into_iter().filter_map(|entry| {
+            let file_type = self.entry_type(&entry);
+            match file_type {
+                Ok(file_type) => Some(Ok(DirEntry {
+                    name: entry.name,
+                    inode: entry.inode as u64,
+                    file_type,
+                })),
+                Err(VfsError::Unsupported) => None,
+                Err(error) => Some(Err(error)),
+            }
+        });
+        entries.nth(index).transpose()
+    }
+
+    fn read_link(&self) -> VfsResult<String> {
+        if self.raw.file_type() != MODE_SYMLINK {
+            return Err(VfsError::Unsupported);
+        }
+        let target = if self.raw.is_fast_symlink(self.volume.block_size()) {
+            let length = (self.raw.size as usize).min(self.raw.inline.len());
+            self.raw.inline[..length].to_vec()
+        } else {
+            let mut target = vec![0; self.raw.size.min(MAX_SYMLINK_LENGTH) as usize];
+            let read = self.read_data(0, &mut target)?;
+            target.truncate(read);
+            target
+        };
+        String::from_utf8(target).map_err(|_| VfsError::InvaildPath)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::String, sync::Arc, vec, vec::Vec};
+
+    use super::Ext2;
+    use crate::{block::MemoryDisk, FileSystem, FileType, Inode, VfsError};
+
+    const BLOCK_SIZE: usize = 1024;
+    const INODE_SIZE: usize = 128;
+    const INODE_TABLE: usize = 5;
+
+    /// Builds a volume in memory, with a single group of 64 blocks and 16 inodes
+    struct Image {
+        data: Vec<u8>,
+        next_block: u32,
+    }
+
+    impl Image {
+        fn new() -> Image {
+            let mut data = vec![0; 64 * BLOCK_SIZE];
+            let superblock = &mut data[1024..2048];
+            for (offset, value) in [(0, 16u32), (4, 64), (20, 1), (24, 0), (32, 8192), (40, 16)] {
+                superblock[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            }
+            superblock[56..58].copy_from_slice(&0xef53u16.to_le_bytes());
+            superblock[76..80].copy_from_slice(&1u32.to_le_bytes());
+            superblock[88..90].copy_from_slice(&(INODE_SIZE as u16).to_le_bytes());
+            superblock[96..100].copy_from_slice(&2u32.to_le_bytes());
+            // group descriptor in block 2, the inode table takes blocks 5 and 6
+            data[2048 + 8..2048 + 12].copy_from_slice(&(INODE_TABLE as u32).to_le_bytes());
+            Image {
+                data,
+                next_block: 7,
+            }
+        }
+
+        fn block(&mut self, contents: &[u8]) -> u32 {
+            let block = self.next_block;
+            self.next_block += 1;
+            let start = block as usize * BLOCK_SIZE;
+            self.data[start..start + contents.len()].copy_from_slice(contents);
+            block
+        }
+
+        fn inode(&mut self, number: u32, mode: u16, size: u32, blocks: &[u32]) {
+            let start = INODE_TABLE * BLOCK_SIZE + (number as usize - 1) * INODE_SIZE;
+            let inode = &mut self.data[start..start + INODE_SIZE];
+            inode[0..2].copy_from_slice(&mode.to_le_bytes());
+            inode[4..8].copy_from_slice(&size.to_le_bytes());
+            inode[26..28].copy_from_slice(&1u16.to_le_bytes());
+            let sectors = blocks.iter().filter(|block| **block != 0).count() as u32 * 2;
+            inode[28..32].copy_from_slice(&sectors.to_le_bytes());
+            for (index, block) in blocks.iter().enumerate() {
+                inode[40 + index * 4..44 + index * 4].copy_from_slice(&block.to_le_bytes());
+            }
+        }
+
+        fn directory(&mut self, number: u32, entries: &[(&str, u32, u8)]) {
+            let mut block = vec![0; BLOCK_SIZE];
+            let mut offset = 0;
+            for (index, (name, inode, file_type)) in entries.iter().enumerate() {
+                let length = if index + 1 == entries.len() {
+                    BLOCK_SIZE - offset
+                } else {
+                    (8 + name.len()).next_multiple_of(4)
+                };
+                block[offset..offset + 4].copy_from_slice(&inode.to_le_bytes());
+                block[offset + 4..offset + 6].copy_from_slice(&(length as u16).to_le_bytes());
+                block[offset + 6] = name.len() as u8;
+                block[offset + 7] = *file_type;
+                block[offset + 8..offset + 8 + name.len()].copy_from_slice(name.as_bytes());
+                offset += length;
+            }
+            let block = self.block(&block);
+            self.inode(number, 0o040755, BLOCK_SIZE as u32, &[block]);
+        }
+
+        fn mount(self) -> Arc<Ext2> {
+            Ext2::new(Arc::new(MemoryDisk::from_bytes(512, self.data))).unwrap()
+        }
+    }
+
+    fn sample() -> Arc<Ext2> {
+        let mut image = Image::new();
+        image.directory(
+            2,
+            &[
+                (".", 2, 2),
+                ("..", 2, 2),
+                ("big", 12, 1),
+                ("link", 13, 7),
+                ("dir", 14, 2),
+                ("fifo", 15, 5),
+            ],
+        );
+        // 14 blocks, where the 13th is a hole, reached through the indirect block
+        let mut blocks: Vec<u32> = (0..12)
+            .map(|index| image.block(&[b'a' + index as u8; BLOCK_SIZE]))
+            .collect();
+        let last = image.block(b"tail");
+        let mut indirect = vec![0u8; BLOCK_SIZE];
+        indirect[4..8].copy_from_slice(&last.to_le_bytes());
+        blocks.push(image.block(&indirect));
+        image.inode(12, 0o100644, 13 * BLOCK_SIZE as u32 + 4, &blocks);
+
+        image.inode(13, 0o120777, 8, &[]);
+        let start = INODE_TABLE * BLOCK_SIZE + 12 * INODE_SIZE + 40;
+        image.data[start..start + 8].copy_from_slice(b"dir/file");
+
+        image.directory(14, &[(".", 14, 2), ("..", 2, 2), ("file", 16, 1)]);
+        image.inode(15, 0o010644, 0, &[]);
+        let file = image.block(b"hello");
+        image.inode(16, 0o100444, 5, &[file]);
+        image.mount()
+    }
+
+    fn names(directory: &Arc<dyn Inode>) -> Vec<String> {
+        (0..)
+            .map_while(|index| directory.read_dir(index).unwrap())
+            .map(|entry| entry.name)
+            .collect()
+    }
+
+    #[test]
+    fn test_directories() {
+        let ext2 = sample();
+        let root = ext2.root();
+        assert_eq!(names(&root), ["big", "link", "dir"]);
+        assert!(matches!(root.lookup("fifo"), Err(VfsError::NotFound)));
+        let directory = root.lookup("dir").unwrap();
+        assert_eq!(directory.metadata().file_type, FileType::Directory);
+        let file = directory.lookup("file").unwrap();
+        assert_eq!(file.metadata().permissions, 0o444);
+        let mut buffer = [0; 16];
+        assert_eq!(file.read_at(0, &mut buffer), Ok(5));
+        assert_eq!(&buffer[..5], b"hello");
+    }
+
+    #[test]
+    fn test_indirect_blocks_and_holes() {
+        let ext2 = sample();
+        let file = ext2.root().lookup("big").unwrap();
+        let mut buffer = vec![0xff; 14 * BLOCK_SIZE];
+        assert_eq!(file.read_at(0, &mut buffer), Ok(13 * BLOCK_SIZE + 4));
+        assert!(buffer[11 * BLOCK_SIZE..12 * BLOCK_SIZE]
+            .iter()
+            .all(|byte| *byte == b'l'));
+        assert!(buffer[12 * BLOCK_SIZE..13 * BLOCK_SIZE]
+            .iter()
+            .all(|byte| *byte == 0));
+        assert_eq!(&buffer[13 * BLOCK_SIZE..13 * BLOCK_SIZE + 4], b"tail");
+    }
+
+    #[test]
+    fn test_read_only() {
+        let ext2 = sample();
+        let root = ext2.root();
+        assert_eq!(
+            root.lookup("link").unwrap().read_link().unwrap(),
+            "dir/file"
+        );
+        assert!(matches!(
+            root.create("new", FileType::Regular, 0o644),
+            Err(VfsError::ReadOnly)
+        ));
+        assert_eq!(
+            root.lookup("big").unwrap().write_at(0, b"x"),
+            Err(VfsError::ReadOnly)
+        );
+    }
+}
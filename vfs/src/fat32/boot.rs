@@ -0,0 +1,169 @@
+//! Boot sector, describing the layout of a FAT32 volume
+
+use alloc::vec;
+
+use crate::{
+    block::BlockDevice,
+    error::{VfsError, VfsResult},
+};
+
+const SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FSINFO_TRAIL_SIGNATURE: u32 = 0xaa55_0000;
+/// Volumes with fewer clusters are FAT12 or FAT16 ones
+const MIN_CLUSTERS: u32 = 65525;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BootSector {
+    pub sector_size: usize,
+    pub sectors_per_cluster: u32,
+    pub reserved_sectors: u32,
+    pub fat_count: u32,
+    pub sectors_per_fat: u32,
+    pub total_sectors: u32,
+    pub root_cluster: u32,
+    /// Sector of the FSInfo structure, 0 if there is none
+    pub fsinfo_sector: u32,
+}
+
+impl BootSector {
+    pub fn read(device: &dyn BlockDevice) -> VfsResult<BootSector> {
+        let mut sector = vec![0; device.block_size().max(512)];
+        device.read_blocks(0, &mut sector)?;
+
+        let u16_at = |offset: usize| u16::from_le_bytes([sector[offset], sector[offset + 1]]);
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(sector[offset..offset + 4].try_into().unwrap());
+        let boot = BootSector {
+            sector_size: u16_at(11) as usize,
+            sectors_per_cluster: sector[13] as u32,
+            reserved_sectors: u16_at(14) as u32,
+            fat_count: sector[16] as u32,
+            sectors_per_fat: u32_at(36),
+            total_sectors: match u16_at(19) {
+                0 => u32_at(32),
+                total => total as u32,
+            },
+            root_cluster: u32_at(44),
+            fsinfo_sector: u16_at(48) as u32,
+        };
+
+        let valid = sector[510..512] == SIGNATURE
+            && boot.sector_size.is_power_of_two()
+            && (512..=4096).contains(&boot.sector_size)
+            && boot.sector_size.is_multiple_of(device.block_size())
+            && boot.sectors_per_cluster.is_power_of_two()
+            && boot.reserved_sectors > 0
+            && boot.fat_count > 0
+            // FAT32 has no fixed root directory and no 16-bit FAT size
+            && u16_at(17) == 0
+            && u16_at(22) == 0
+            && boot.sectors_per_fat > 0
+            && boot.data_sector() < boot.total_sectors
+            && boot.cluster_count() >= MIN_CLUSTERS
+            && (2..boot.cluster_count() + 2).contains(&boot.root_cluster);
+        if !valid {
+            return Err(VfsError::InvaildArgument);
+        }
+        Ok(boot)
+    }
+
+    pub fn cluster_size(&self) -> usize {
+        self.sector_size * self.sectors_per_cluster as usize
+    }
+
+    /// First sector of the data region, where cluster 2 starts
+    pub fn data_sector(&self) -> u32 {
+        self.reserved_sectors + self.fat_count * self.sectors_per_fat
+    }
+
+    /// Number of data clusters, numbered from 2
+    pub fn cluster_count(&self) -> u32 {
+        let data_sectors = self.total_sectors.saturating_sub(self.data_sector());
+        let clusters = data_sectors / self.sectors_per_cluster;
+        // the FAT may be too small to describe all of them
+        clusters.min((self.sectors_per_fat * self.sector_size as u32 / 4).saturating_sub(2))
+    }
+
+    pub fn cluster_sector(&self, cluster: u32) -> u64 {
+        self.data_sector() as u64 + (cluster as u64 - 2) * self.sectors_per_cluster as u64
+    }
+}
+
+/// Writes the next free cluster hint to the FSInfo sector, marking the free count unknown
+pub fn write_fsinfo(device: &dyn BlockDevice, boot: &BootSector, next_free: u32) -> VfsResult<()> {
+    if boot.fsinfo_sector == 0 {
+        return Ok(());
+    }
+    let mut sector = vec![0; boot.sector_size];
+    let blocks_per_sector = (boot.sector_size / device.block_size()) as u64;
+    let block = boot.fsinfo_sector as u64 * blocks_per_sector;
+    device.read_blocks(block, &mut sector)?;
+    if sector[0..4] != FSINFO_LEAD_SIGNATURE.to_le_bytes() {
+        return Ok(());
+    }
+    sector[488..492].copy_from_slice(&u32::MAX.to_le_bytes());
+    sector[492..496].copy_from_slice(&next_free.to_le_bytes());
+    device.write_blocks(block, &sector)
+}
+
+/// Creates an empty FAT32 volume spanning a whole device with 512-byte blocks
+pub fn format(device: &dyn BlockDevice, sectors_per_cluster: u32) -> VfsResult<()> {
+    const SECTOR_SIZE: usize = 512;
+    const RESERVED_SECTORS: u32 = 32;
+    const FAT_COUNT: u32 = 2;
+    if device.block_size() != SECTOR_SIZE || !sectors_per_cluster.is_power_of_two() {
+        return Err(VfsError::InvaildArgument);
+    }
+    let total_sectors =
+        u32::try_from(device.block_count()).map_err(|_| VfsError::InvaildArgument)?;
+    // each FAT sector describes 128 clusters
+    let clusters_estimate = total_sectors.saturating_sub(RESERVED_SECTORS) / sectors_per_cluster;
+    let sectors_per_fat = (clusters_estimate + 2).div_ceil(128);
+
+    let mut boot = vec![0u8; SECTOR_SIZE];
+    boot[0..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+    boot[3..11].copy_from_slice(b"LOSGATOS");
+    boot[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+    boot[13] = sectors_per_cluster as u8;
+    boot[14..16].copy_from_slice(&(RESERVED_SECTORS as u16).to_le_bytes());
+    boot[16] = FAT_COUNT as u8;
+    boot[21] = 0xf8;
+    boot[32..36].copy_from_slice(&total_sectors.to_le_bytes());
+    boot[36..40].copy_from_slice(&sectors_per_fat.to_le_bytes());
+    boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+    boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+    boot[66] = 0x29;
+    boot[71..82].copy_from_slice(b"NO NAME    ");
+    boot[82..90].copy_from_slice(b"FAT32   ");
+    boot[510..512].copy_from_slice(&SIGNATURE);
+    device.write_blocks(0, &boot)?;
+
+    let mut fsinfo = vec![0u8; SECTOR_SIZE];
+    fsinfo[0..4].copy_from_slice(&FSINFO_LEAD_SIGNATURE.to_le_bytes());
+    fsinfo[484..488].copy_from_slice(&FSINFO_STRUCT_SIGNATURE.to_le_bytes());
+    fsinfo[488..492].copy_from_slice(&u32::MAX.to_le_bytes());
+    fsinfo[492..496].copy_from_slice(&3u32.to_le_bytes());
+    fsinfo[508..512].copy_from_slice(&FSINFO_TRAIL_SIGNATURE.to_le_bytes());
+    device.write_blocks(1, &fsinfo)?;
+
+    // media descriptor, reserved entry and the root directory, which is a single cluster
+    let zero = vec![0u8; SECTOR_SIZE];
+    let mut first_fat_sector = zero.clone();
+    first_fat_sector[0..4].copy_from_slice(&0x0fff_fff8u32.to_le_bytes());
+    first_fat_sector[4..8].copy_from_slice(&0x0fff_ffffu32.to_le_bytes());
+    first_fat_sector[8..12].copy_from_slice(&0x0fff_ffffu32.to_le_bytes());
+    for fat in 0..FAT_COUNT {
+        let start = RESERVED_SECTORS + fat * sectors_per_fat;
+        device.write_blocks(start as u64, &first_fat_sector)?;
+        for sector in 1..sectors_per_fat {
+            device.write_blocks((start + sector) as u64, &zero)?;
+        }
+    }
+    let root = RESERVED_SECTORS + FAT_COUNT * sectors_per_fat;
+    for sector in 0..sectors_per_cluster {
+        device.write_blocks((root + sector) as u64, &zero)?;
+    }
+    Ok(())
+}
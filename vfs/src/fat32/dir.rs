@@ -0,0 +1,448 @@
+//! Directory entries, with long names spread over slots preceding 8.3 ones
+
+use alloc::{string::String, vec::Vec};
+use core::ops::Range;
+
+use crate::{
+    error::{VfsError, VfsResult},
+    MAX_NAME_LENGTH,
+};
+
+pub const SLOT_SIZE: usize = 32;
+pub const ATTRIBUTE_READ_ONLY: u8 = 0x01;
+pub const ATTRIBUTE_VOLUME_LABEL: u8 = 0x08;
+pub const ATTRIBUTE_DIRECTORY: u8 = 0x10;
+pub const ATTRIBUTE_ARCHIVE: u8 = 0x20;
+const ATTRIBUTE_LONG_NAME: u8 = 0x0f;
+/// First name byte of deleted entries
+pub const DELETED: u8 = 0xe5;
+/// First name byte of the entry after the last one
+const END: u8 = 0x00;
+const LAST_LONG_SLOT: u8 = 0x40;
+/// Case flags for 8.3 names, set by Windows instead of storing a long name
+const LOWERCASE_BASE: u8 = 0x08;
+const LOWERCASE_EXTENSION: u8 = 0x10;
+const CHARACTERS_PER_SLOT: usize = 13;
+/// Offsets of UCS-2 characters in a long name slot
+const LONG_NAME_OFFSETS: [usize; CHARACTERS_PER_SLOT] =
+    [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// 1980-01-01, the earliest date there is
+const DEFAULT_DATE: u16 = 0x0021;
+
+pub type Slot = [u8; SLOT_SIZE];
+
+/// Contents of an 8.3 entry
+#[derive(Debug, Clone, Copy)]
+pub struct ShortEntry {
+    pub name: [u8; 11],
+    pub attributes: u8,
+    pub case_flags: u8,
+    pub first_cluster: u32,
+    pub size: u32,
+}
+
+impl ShortEntry {
+    pub fn decode(slot: &Slot) -> ShortEntry {
+        let u16_at = |offset: usize| u16::from_le_bytes([slot[offset], slot[offset + 1]]);
+        ShortEntry {
+            name: slot[0..11].try_into().unwrap(),
+            attributes: slot[11],
+            case_flags: slot[12],
+            first_cluster: (u16_at(20) as u32) << 16 | u16_at(26) as u32,
+            size: u32::from_le_bytes(slot[28..32].try_into().unwrap()),
+        }
+    }
+
+    pub fn encode(&self) -> Slot {
+        let mut slot = [0; SLOT_SIZE];
+        slot[0..11].copy_from_slice(&self.name);
+        slot[11] = self.attributes;
+        slot[12] = self.case_flags;
+        for offset in [16, 18, 24] {
+            slot[offset..offset + 2].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+        }
+        slot[20..22].copy_from_slice(&((self.first_cluster >> 16) as u16).to_le_bytes());
+        slot[26..28].copy_from_slice(&(self.first_cluster as u16).to_le_bytes());
+        slot[28..32].copy_from_slice(&self.size.to_le_bytes());
+        slot
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTRIBUTE_DIRECTORY != 0
+    }
+
+    /// Name shown when there is no long one
+    fn display_name(&self) -> String {
+        let part = |bytes: &[u8], lowercase: bool| {
+            let mut part = String::new();
+            for &byte in bytes.iter().take_while(|byte| **byte != b' ') {
+                let byte = if lowercase {
+                    byte.to_ascii_lowercase()
+                } else {
+                    byte
+                };
+                part.push(if byte.is_ascii() { byte as char } else { '_' });
+            }
+            part
+        };
+        let mut name = self.name;
+        // 0xe5 is a valid first character, stored as 0x05 not to mark the entry deleted
+        if name[0] == 0x05 {
+            name[0] = DELETED;
+        }
+        let mut display = part(&name[..8], self.case_flags & LOWERCASE_BASE != 0);
+        let extension = part(&name[8..], self.case_flags & LOWERCASE_EXTENSION != 0);
+        if !extension.is_empty() {
+            display.push('.');
+            display.push_str(&extension);
+        }
+        display
+    }
+}
+
+/// Directory entry, along with the slots it occupies
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub short: ShortEntry,
+    /// Indices of slots holding the long name and the 8.3 entry, which is the last one
+    pub slots: Range<usize>,
+}
+
+impl Entry {
+    pub fn short_slot(&self) -> usize {
+        self.slots.end - 1
+    }
+}
+
+/// Slots of a directory, telling apart free ones
+pub struct Listing {
+    pub entries: Vec<Entry>,
+    /// Whether a slot may be reused, with slots past the end being all free
+    pub free: Vec<bool>,
+}
+
+impl Listing {
+    /// Parses consecutive slots of a directory, skipping `.`, `..` and volume labels
+    pub fn parse(slots: &[Slot]) -> Listing {
+        let mut entries = Vec::new();
+        let mut free = Vec::with_capacity(slots.len());
+        let mut long_name = LongName::default();
+        let mut ended = false;
+        for (index, slot) in slots.iter().enumerate() {
+            ended |= slot[0] == END;
+            free.push(ended || slot[0] == DELETED);
+            if ended || slot[0] == DELETED {
+                long_name = LongName::default();
+                continue;
+            }
+            if slot[11] & 0x3f == ATTRIBUTE_LONG_NAME {
+                long_name.push(index, slot);
+                continue;
+            }
+            let short = ShortEntry::decode(slot);
+            let long = core::mem::take(&mut long_name);
+            if short.attributes & ATTRIBUTE_VOLUME_LABEL != 0 || short.name[0] == b'.' {
+                continue;
+            }
+            let (name, start) = match long.finish(checksum(&short.name)) {
+                Some((name, start)) => (name, start),
+                None => (short.display_name(), index),
+            };
+            entries.push(Entry {
+                name,
+                short,
+                slots: start..index + 1,
+            });
+        }
+        Listing { entries, free }
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Entry> {
+        // names are case-insensitive, as in other implementations
+        self.entries
+            .iter()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Finds `count` consecutive free slots, which may extend past the listed ones
+    pub fn free_run(&self, count: usize) -> usize {
+        let mut run = 0;
+        for (index, free) in self.free.iter().enumerate() {
+            run = if *free { run + 1 } else { 0 };
+            if run == count {
+                return index + 1 - count;
+            }
+        }
+        self.free.len() - run
+    }
+
+    fn short_name_taken(&self, name: &[u8; 11]) -> bool {
+        self.entries.iter().any(|entry| &entry.short.name == name)
+    }
+}
+
+/// Long name collected from slots preceding an 8.3 entry
+#[derive(Default)]
+struct LongName {
+    /// Characters of the slots seen so far, from the last one
+    parts: Vec<[u16; CHARACTERS_PER_SLOT]>,
+    checksum: u8,
+    start: usize,
+    /// Order number the next slot should have
+    expected: u8,
+    valid: bool,
+}
+
+impl LongName {
+    fn push(&mut self, index: usize, slot: &Slot) {
+        let order = slot[0] & !LAST_LONG_SLOT;
+        if slot[0] & LAST_LONG_SLOT != 0 {
+            *self = LongName {
+                parts: Vec::new(),
+                checksum: slot[13],
+                start: index,
+                expected: order,
+                valid: order > 0,
+            };
+        } else if order != self.expected || slot[13] != self.checksum {
+            self.valid = false;
+        }
+        if !self.valid || order == 0 {
+            self.valid = false;
+            return;
+        }
+        self.parts.push(
+            LONG_NAME_OFFSETS.map(|offset| u16::from_le_bytes([slot[offset], slot[offset + 1]])),
+        );
+        self.expected = order - 1;
+    }
+
+    /// Returns the name and the slot it starts at, if it belongs to an entry with a given checksum
+    fn finish(self, checksum: u8) -> Option<(String, usize)> {
+        if !self.valid || self.expected != 0 || self.checksum != checksum {
+            return None;
+        }
+        let characters = self
+            .parts
+            .iter()
+            .rev()
+            .flatten()
+            .copied()
+            .take_while(|character| *character != 0);
+        let name: String = char::decode_utf16(characters)
+            .map(|character| character.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        Some((name, self.start))
+    }
+}
+
+pub fn checksum(short_name: &[u8; 11]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, byte| sum.rotate_right(1).wrapping_add(*byte))
+}
+
+/// Checks whether a name may be stored in a directory
+pub fn validate_name(name: &str) -> VfsResult<()> {
+    if name.encode_utf16().count() > MAX_NAME_LENGTH {
+        return Err(VfsError::NameTooLong);
+    }
+    let invalid = |character: char| character < ' ' || "\"*/:<>?\\|".contains(character);
+    if name.is_empty() || name == "." || name == ".." || name.contains(invalid) {
+        return Err(VfsError::InvaildPath);
+    }
+    Ok(())
+}
+
+/// Builds slots storing a new entry, with a long name unless the name fits an 8.3 one
+pub fn encode_entry(listing: &Listing, name: &str, mut short: ShortEntry) -> VfsResult<Vec<Slot>> {
+    if let Some((short_name, case_flags)) = exact_short_name(name) {
+        if listing.short_name_taken(&short_name) {
+            return Err(VfsError::AlreadyExists);
+        }
+        short.name = short_name;
+        short.case_flags = case_flags;
+        return Ok(alloc::vec![short.encode()]);
+    }
+
+    short.name = (1..1_000_000)
+        .map(|number| generate_short_name(name, number))
+        .find(|short_name| !listing.short_name_taken(short_name))
+        .ok_or(VfsError::NoSpace)?;
+    short.case_flags = 0;
+    let checksum = checksum(&short.name);
+
+    let mut characters: Vec<u16> = name.encode_utf16().collect();
+    let count = characters.len().div_ceil(CHARACTERS_PER_SLOT);
+    // a terminator is stored unless the name fills the slots, the rest is padding
+    if !characters.len().is_multiple_of(CHARACTERS_PER_SLOT) {
+        characters.push(0);
+    }
+    characters.resize(count * CHARACTERS_PER_SLOT, 0xffff);
+
+    let mut slots = Vec::with_capacity(count + 1);
+    for order in (1..=count).rev() {
+        let mut slot = [0; SLOT_SIZE];
+        slot[0] = order as u8 | if order == count { LAST_LONG_SLOT } else { 0 };
+        slot[11] = ATTRIBUTE_LONG_NAME;
+        slot[13] = checksum;
+        let part = &characters[(order - 1) * CHARACTERS_PER_SLOT..order * CHARACTERS_PER_SLOT];
+        for (character, offset) in part.iter().zip(LONG_NAME_OFFSETS) {
+            slot[offset..offset + 2].copy_from_slice(&character.to_le_bytes());
+        }
+        slots.push(slot);
+    }
+    slots.push(short.encode());
+    Ok(slots)
+}
+
+fn is_short_name_character(byte: u8) -> bool {
+    byte.is_ascii_uppercase() || byte.is_ascii_digit() || b"!#$%&'()-@^_`{}~".contains(&byte)
+}
+
+/// Converts a name to an 8.3 one with case flags, if no long name is needed to store it
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, extension) = match name.rsplit_once('.') {
+        Some((base, extension)) => (base, extension),
+        None => (name, ""),
+    };
+    let mut case_flags = 0;
+    let mut short = [b' '; 11];
+    for (part, range, lowercase_flag) in [
+        (base, 0..8, LOWERCASE_BASE),
+        (extension, 8..11, LOWERCASE_EXTENSION),
+    ] {
+        if part.len() > range.len() {
+            return None;
+        }
+        let lower = part.bytes().any(|byte| byte.is_ascii_lowercase());
+        let upper = part.bytes().any(|byte| byte.is_ascii_uppercase());
+        if lower && upper {
+            return None;
+        }
+        if lower {
+            case_flags |= lowercase_flag;
+        }
+        for (index, byte) in part.bytes().enumerate() {
+            let byte = byte.to_ascii_uppercase();
+            if !is_short_name_character(byte) {
+                return None;
+            }
+            short[range.start + index] = byte;
+        }
+    }
+    (!base.is_empty()).then_some((short, case_flags))
+}
+
+/// Makes an 8.3 name with a numeric tail for a name stored as a long one
+fn generate_short_name(name: &str, number: u32) -> [u8; 11] {
+    let (base, extension) = match name.rsplit_once('.') {
+        Some((base, extension)) if !base.is_empty() => (base, extension),
+        _ => (name, ""),
+    };
+    let convert = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|character| *character != ' ' && *character != '.')
+            .map(|character| {
+                let byte = if character.is_ascii() {
+                    character.to_ascii_uppercase() as u8
+                } else {
+                    b'_'
+                };
+                if is_short_name_character(byte) {
+                    byte
+                } else {
+                    b'_'
+                }
+            })
+            .collect()
+    };
+
+    let mut short = [b' '; 11];
+    let tail = alloc::format!("~{number}");
+    let base = convert(base);
+    let kept = base.len().min(8 - tail.len());
+    short[..kept].copy_from_slice(&base[..kept]);
+    short[kept..kept + tail.len()].copy_from_slice(tail.as_bytes());
+    for (index, byte) in convert(extension).into_iter().take(3).enumerate() {
+        short[8 + index] = byte;
+    }
+    short
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::{encode_entry, Listing, ShortEntry, ATTRIBUTE_ARCHIVE};
+
+    fn short_entry() -> ShortEntry {
+        ShortEntry {
+            name: [b' '; 11],
+            attributes: ATTRIBUTE_ARCHIVE,
+            case_flags: 0,
+            first_cluster: 0,
+            size: 0,
+        }
+    }
+
+    #[test]
+    fn test_names_round_trip() {
+        let mut slots = Vec::new();
+        for name in [
+            "README.TXT",
+            "notes.md",
+            "A Long File Name With Spaces.text",
+            "żółw.txt",
+            "exactly13char",
+        ] {
+            let listing = Listing::parse(&slots);
+            slots.extend(encode_entry(&listing, name, short_entry()).unwrap());
+        }
+        let listing = Listing::parse(&slots);
+        let names: Vec<&str> = listing
+            .entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "README.TXT",
+                "notes.md",
+                "A Long File Name With Spaces.text",
+                "żółw.txt",
+                "exactly13char"
+            ]
+        );
+        // short names stored without a long one
+        assert_eq!(listing.entries[0].slots.len(), 1);
+        assert_eq!(listing.entries[1].slots.len(), 1);
+        assert_eq!(&listing.entries[2].short.name, b"ALONGF~1TEX");
+        assert!(listing.find("readme.txt").is_some());
+    }
+
+    #[test]
+    fn test_numeric_tails() {
+        let mut slots = Vec::new();
+        for name in ["Long name one.txt", "Long name two.txt"] {
+            let listing = Listing::parse(&slots);
+            slots.extend(encode_entry(&listing, name, short_entry()).unwrap());
+        }
+        let listing = Listing::parse(&slots);
+        assert_eq!(&listing.entries[0].short.name, b"LONGNA~1TXT");
+        assert_eq!(&listing.entries[1].short.name, b"LONGNA~2TXT");
+    }
+
+    #[test]
+    fn test_orphaned_long_name() {
+        let listing = Listing::parse(&[]);
+        let mut slots = encode_entry(&listing, "Long name.txt", short_entry()).unwrap();
+        // a changed 8.3 name does not match the checksum anymore
+        slots[1][0] = b'X';
+        let listing = Listing::parse(&slots);
+        assert_eq!(listing.entries[0].name, "XONGNA~1.TXT");
+        assert_eq!(listing.entries[0].slots, 1..2);
+    }
+}
@@ -0,0 +1,715 @@
+//! FAT32 filesystem on a block device
+//!
+//! File data is written straight to the device, while FAT sectors are cached and written
+//! back on [`FileSystem::sync`]. Names are case-insensitive and stored as long names
+//! whenever they do not fit an 8.3 one. There are no permissions beyond the read-only
+//! attribute, symlinks or timestamps.
+
+mod boot;
+mod dir;
+mod table;
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+
+use core_lib::sync::AtomicMutex;
+
+use crate::{
+    block::BlockDevice,
+    error::{VfsError, VfsResult},
+    inode::{DirEntry, FileSystem, FileType, Inode, Metadata},
+};
+
+pub use boot::format;
+use boot::BootSector;
+use dir::{
+    encode_entry, validate_name, Listing, ShortEntry, Slot, ATTRIBUTE_ARCHIVE, ATTRIBUTE_DIRECTORY,
+    ATTRIBUTE_READ_ONLY, DELETED, SLOT_SIZE,
+};
+use table::{FatTable, FREE};
+
+const ROOT_INODE: u64 = 1;
+
+pub struct Fat32 {
+    volume: Arc<Volume>,
+    root: Arc<FatInode>,
+}
+
+impl Fat32 {
+    /// Opens a FAT32 volume, failing with [`VfsError::InvaildArgument`] if the device
+    /// holds none
+    pub fn new(device: Arc<dyn BlockDevice>) -> VfsResult<Arc<Fat32>> {
+        let boot = BootSector::read(&*device)?;
+        let volume = Arc::new(Volume {
+            fat: AtomicMutex::new(FatTable::new(device.clone(), boot)),
+            device,
+            boot,
+            directories: AtomicMutex::new(()),
+            entries: AtomicMutex::new(()),
+            nodes: AtomicMutex::new(BTreeMap::new()),
+        });
+        let root = Arc::new(FatInode {
+            volume: volume.clone(),
+            location: None,
+            directory: true,
+            state: AtomicMutex::new(NodeState {
+                first_cluster: boot.root_cluster,
+                size: 0,
+                read_only: false,
+                removed: false,
+            }),
+        });
+        Ok(Arc::new(Fat32 { volume, root }))
+    }
+}
+
+impl FileSystem for Fat32 {
+    fn name(&self) -> &'static str {
+        "fat32"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> VfsResult<()> {
+        self.volume.sync()
+    }
+}
+
+struct Volume {
+    device: Arc<dyn BlockDevice>,
+    boot: BootSector,
+    fat: AtomicMutex<FatTable>,
+    /// Serializes changes to directories
+    directories: AtomicMutex<()>,
+    /// Serializes updates of single slots, which rewrite whole sectors
+    entries: AtomicMutex<()>,
+    /// Nodes in use by the location of their 8.3 entry, so each file has a single one
+    nodes: AtomicMutex<BTreeMap<u64, Weak<FatInode>>>,
+}
+
+impl Volume {
+    fn sector_block(&self, sector: u64) -> u64 {
+        sector * (self.boot.sector_size / self.device.block_size()) as u64
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.boot.cluster_size()
+    }
+
+    fn read_cluster(&self, cluster: u32, buffer: &mut [u8]) -> VfsResult<()> {
+        let block = self.sector_block(self.boot.cluster_sector(cluster));
+        self.device.read_blocks(block, buffer)
+    }
+
+    fn write_cluster(&self, cluster: u32, data: &[u8]) -> VfsResult<()> {
+        let block = self.sector_block(self.boot.cluster_sector(cluster));
+        self.device.write_blocks(block, data)
+    }
+
+    fn chain(&self, first: u32) -> VfsResult<Vec<u32>> {
+        self.fat.lock().chain(first)
+    }
+
+    /// Reads all slots of a directory, along with the clusters holding them
+    fn read_slots(&self, first: u32) -> VfsResult<(Vec<u32>, Vec<Slot>)> {
+        let chain = self.chain(first)?;
+        let mut data = vec![0; self.cluster_size()];
+        let mut slots = Vec::with_capacity(chain.len() * self.cluster_size() / SLOT_SIZE);
+        for cluster in &chain {
+            self.read_cluster(*cluster, &mut data)?;
+            slots.extend(
+                data.chunks_exact(SLOT_SIZE)
+                    .map(|slot| Slot::try_from(slot).unwrap()),
+            );
+        }
+        Ok((chain, slots))
+    }
+
+    /// Byte offset on the device of a directory slot
+    fn slot_location(&self, chain: &[u32], index: usize) -> u64 {
+        let per_cluster = self.cluster_size() / SLOT_SIZE;
+        let sector = self.boot.cluster_sector(chain[index / per_cluster]);
+        sector * self.boot.sector_size as u64 + ((index % per_cluster) * SLOT_SIZE) as u64
+    }
+
+    /// Changes bytes of a slot through `f`
+    fn update_slot(&self, location: u64, f: impl FnOnce(&mut [u8])) -> VfsResult<()> {
+        let _guard = self.entries.lock();
+        let sector_size = self.boot.sector_size as u64;
+        let block = self.sector_block(location / sector_size);
+        let offset = (location % sector_size) as usize;
+        let mut sector = vec![0; self.boot.sector_size];
+        self.device.read_blocks(block, &mut sector)?;
+        f(&mut sector[offset..offset + SLOT_SIZE]);
+        self.device.write_blocks(block, &sector)
+    }
+
+    /// Returns the node of an entry, creating it if it is not in use
+    fn node(self: &Arc<Volume>, location: u64, short: &ShortEntry) -> Arc<FatInode> {
+        let mut nodes = self.nodes.lock();
+        if let Some(node) = nodes.get(&location).and_then(Weak::upgrade) {
+            return node;
+        }
+        nodes.retain(|_, node| node.strong_count() > 0);
+        let node = Arc::new(FatInode {
+            volume: self.clone(),
+            location: Some(location),
+            directory: short.is_directory(),
+            state: AtomicMutex::new(NodeState {
+                first_cluster: short.first_cluster,
+                size: short.size,
+                read_only: short.attributes & ATTRIBUTE_READ_ONLY != 0,
+                removed: false,
+            }),
+        });
+        nodes.insert(location, Arc::downgrade(&node));
+        node
+    }
+
+    /// Allocates a cluster filled with zeroes, appending it to a chain ending at `last`
+    fn allocate_zeroed(&self, last: Option<u32>) -> VfsResult<u32> {
+        let cluster = self.fat.lock().allocate(last)?;
+        if let Err(error) = self.write_cluster(cluster, &vec![0; self.cluster_size()]) {
+            self.free_clusters(last, cluster);
+            return Err(error);
+        }
+        Ok(cluster)
+    }
+
+    /// Gives back a cluster appended to a chain by a failed operation
+    fn free_clusters(&self, last: Option<u32>, first: u32) {
+        let mut fat = self.fat.lock();
+        let _ = match last {
+            Some(last) => fat.truncate_after(last),
+            None => fat.free_chain(first),
+        };
+    }
+
+    fn sync(&self) -> VfsResult<()> {
+        let next_free = {
+            let mut fat = self.fat.lock();
+            fat.flush()?;
+            fat.next_free()
+        };
+        boot::write_fsinfo(&*self.device, &self.boot, next_free)?;
+        self.device.flush()
+    }
+}
+
+impl Drop for Volume {
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
+}
+
+struct NodeState {
+    /// First cluster of the data, 0 for empty files
+    first_cluster: u32,
+    /// Size of a file, always 0 for directories
+    size: u32,
+    read_only: bool,
+    /// Set once the entry is gone while the node is in use, its clusters are freed on drop
+    removed: bool,
+}
+
+pub struct FatInode {
+    volume: Arc<Volume>,
+    /// Location of the 8.3 entry on the device, `None` for the root directory
+    location: Option<u64>,
+    directory: bool,
+    state: AtomicMutex<NodeState>,
+}
+
+impl FatInode {
+    fn inode(&self) -> u64 {
+        self.location
+            .map_or(ROOT_INODE, |location| location / SLOT_SIZE as u64)
+    }
+
+    fn listing(&self) -> VfsResult<(Vec<u32>, Vec<Slot>, Listing)> {
+        if !self.directory {
+            return Err(VfsError::NotADirectory);
+        }
+        let first_cluster = self.state.lock().first_cluster;
+        let (chain, slots) = self.volume.read_slots(first_cluster)?;
+        let listing = Listing::parse(&slots);
+        Ok((chain, slots, listing))
+    }
+
+    /// Writes the first cluster and size of a file to its entry
+    fn update_entry(&self, state: &NodeState) -> VfsResult<()> {
+        let Some(location) = self.location.filter(|_| !state.removed) else {
+            return Ok(());
+        };
+        self.volume.update_slot(location, |slot| {
+            let mut short = ShortEntry::decode(&Slot::try_from(&*slot).unwrap());
+            short.first_cluster = state.first_cluster;
+            short.size = state.size;
+            short.attributes |= ATTRIBUTE_ARCHIVE;
+            slot.copy_from_slice(&short.encode());
+        })
+    }
+
+    /// Writes data to a file, allocating clusters as needed and growing it past the end
+    fn write_locked(&self, state: &mut NodeState, offset: u64, data: &[u8]) -> VfsResult<usize> {
+        let cluster_size = self.volume.cluster_size() as u64;
+        let end = offset
+            .checked_add(data.len() as u64)
+            .filter(|end| *end <= u32::MAX as u64)
+            .ok_or(VfsError::FileTooLarge)?;
+
+        // writes as much as fits on the volume
+        let mut chain = self.volume.chain(state.first_cluster)?;
+        let wanted = end.div_ceil(cluster_size) as usize;
+        let mut allocation_error = None;
+        {
+            let mut fat = self.volume.fat.lock();
+            while chain.len() < wanted {
+                match fat.allocate(chain.last().copied()) {
+                    Ok(cluster) => chain.push(cluster),
+                    Err(error) => {
+                        allocation_error = Some(error);
+                        break;
+                    }
+                }
+            }
+        }
+        if let Some(&first) = chain.first() {
+            state.first_cluster = first;
+        }
+        let end = end.min(chain.len() as u64 * cluster_size);
+        if end <= offset {
+            return Err(allocation_error.unwrap_or(VfsError::NoSpace));
+        }
+
+        let mut buffer = vec![0; cluster_size as usize];
+        let mut position = offset;
+        while position < end {
+            let cluster = chain[(position / cluster_size) as usize];
+            let start = (position % cluster_size) as usize;
+            let length = (cluster_size as usize - start).min((end - position) as usize);
+            let source = &data[(position - offset) as usize..][..length];
+            if length == cluster_size as usize {
+                self.volume.write_cluster(cluster, source)?;
+            } else {
+                self.volume.read_cluster(cluster, &mut buffer)?;
+                buffer[start..start + length].copy_from_slice(source);
+                self.volume.write_cluster(cluster, &buffer)?;
+            }
+            position += length as u64;
+        }
+
+        state.size = state.size.max(end as u32);
+        self.update_entry(state)?;
+        Ok((end - offset) as usize)
+    }
+
+    /// Fills a file with zeroes up to `size`
+    fn zero_fill(&self, state: &mut NodeState, size: u64) -> VfsResult<()> {
+        let zeroes = vec![0; self.volume.cluster_size()];
+        while (state.size as u64) < size {
+            let length = (size - state.size as u64).min(zeroes.len() as u64) as usize;
+            self.write_locked(state, state.size as u64, &zeroes[..length])?;
+        }
+        Ok(())
+    }
+
+    /// Adds slots to a directory, extending it with new clusters if there is no room
+    fn insert_slots(
+        &self,
+        chain: &mut Vec<u32>,
+        listing: &Listing,
+        slots: &[Slot],
+    ) -> VfsResult<u64> {
+        let start = listing.free_run(slots.len());
+        let per_cluster = self.volume.cluster_size() / SLOT_SIZE;
+        let original_length = chain.len();
+        while chain.len() * per_cluster < start + slots.len() {
+            match self.volume.allocate_zeroed(chain.last().copied()) {
+                Ok(cluster) => chain.push(cluster),
+                Err(error) => {
+                    if chain.len() > original_length {
+                        let mut fat = self.volume.fat.lock();
+                        let _ = fat.truncate_after(chain[original_length - 1]);
+                    }
+                    return Err(error);
+                }
+            }
+        }
+        for (index, slot) in slots.iter().enumerate() {
+            let location = self.volume.slot_location(chain, start + index);
+            self.volume
+                .update_slot(location, |data| data.copy_from_slice(slot))?;
+        }
+        Ok(self.volume.slot_location(chain, start + slots.len() - 1))
+    }
+}
+
+impl Drop for FatInode {
+    fn drop(&mut self) {
+        let state = self.state.lock();
+        if state.removed {
+            let _ = self.volume.fat.lock().free_chain(state.first_cluster);
+        }
+    }
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> Metadata {
+        let state = self.state.lock();
+        let (file_type, permissions) = if self.directory {
+            (FileType::Directory, 0o755)
+        } else {
+            (FileType::Regular, 0o644)
+        };
+        Metadata {
+            inode: self.inode(),
+            file_type,
+            permissions: if state.read_only {
+                permissions & !0o222
+            } else {
+                permissions
+            },
+            size: state.size as u64,
+            links: 1,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> VfsResult<usize> {
+        if self.directory {
+            return Err(VfsError::Unsupported);
+        }
+        let state = self.state.lock();
+        let cluster_size = self.volume.cluster_size() as u64;
+        let end = (state.size as u64).min(offset.saturating_add(buffer.len() as u64));
+        if end <= offset {
+            return Ok(0);
+        }
+        let chain = self.volume.chain(state.first_cluster)?;
+        if (chain.len() as u64) < end.div_ceil(cluster_size) {
+            return Err(VfsError::Io);
+        }
+
+        let mut cluster_buffer = vec![0; cluster_size as usize];
+        let mut position = offset;
+        while position < end {
+            let cluster = chain[(position / cluster_size) as usize];
+            let start = (position % cluster_size) as usize;
+            let length = (cluster_size as usize - start).min((end - position) as usize);
+            let target = &mut buffer[(position - offset) as usize..][..length];
+            if length == cluster_size as usize {
+                self.volume.read_cluster(cluster, target)?;
+            } else {
+                self.volume.read_cluster(cluster, &mut cluster_buffer)?;
+                target.copy_from_slice(&cluster_buffer[start..start + length]);
+            }
+            position += length as u64;
+        }
+        Ok((end - offset) as usize)
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> VfsResult<usize> {
+        if self.directory {
+            return Err(VfsError::Unsupported);
+        }
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let mut state = self.state.lock();
+        if offset > state.size as u64 {
+            self.zero_fill(&mut state, offset)?;
+        }
+        self.write_locked(&mut state, offset, data)
+    }
+
+    fn truncate(&self, size: u64) -> VfsResult<()> {
+        if self.directory {
+            return Err(VfsError::Unsupported);
+        }
+        if size > u32::MAX as u64 {
+            return Err(VfsError::FileTooLarge);
+        }
+        let mut state = self.state.lock();
+        if size > state.size as u64 {
+            return self.zero_fill(&mut state, size);
+        }
+        let kept = size.div_ceil(self.volume.cluster_size() as u64) as usize;
+        {
+            let mut fat = self.volume.fat.lock();
+            let chain = fat.chain(state.first_cluster)?;
+            if kept == 0 {
+                fat.free_chain(state.first_cluster)?;
+                state.first_cluster = FREE;
+            } else if kept < chain.len() {
+                fat.truncate_after(chain[kept - 1])?;
+            }
+        }
+        state.size = size as u32;
+        self.update_entry(&state)
+    }
+
+    fn lookup(&self, name: &str) -> VfsResult<Arc<dyn Inode>> {
+        let _guard = self.volume.directories.lock();
+        let (chain, _, listing) = self.listing()?;
+        let entry = listing.find(name).ok_or(VfsError::NotFound)?;
+        let location = self.volume.slot_location(&chain, entry.short_slot());
+        Ok(self.volume.node(location, &entry.short))
+    }
+
+    fn create(
+        &self,
+        name: &str,
+        file_type: FileType,
+        permissions: u32,
+    ) -> VfsResult<Arc<dyn Inode>> {
+        let directory = match file_type {
+            FileType::Regular => false,
+            FileType::Directory => true,
+            _ => return Err(VfsError::Unsupported),
+        };
+        validate_name(name)?;
+        let _guard = self.volume.directories.lock();
+        let (mut chain, _, listing) = self.listing()?;
+        if listing.find(name).is_some() {
+            return Err(VfsError::AlreadyExists);
+        }
+
+        let mut short = ShortEntry {
+            name: [b' '; 11],
+            attributes: if directory {
+                ATTRIBUTE_DIRECTORY
+            } else {
+                ATTRIBUTE_ARCHIVE
+            },
+            case_flags: 0,
+            first_cluster: FREE,
+            size: 0,
+        };
+        if permissions & 0o222 == 0 {
+            short.attributes |= ATTRIBUTE_READ_ONLY;
+        }
+        if directory {
+            short.first_cluster = self.volume.allocate_zeroed(None)?;
+        }
+        let location = self
+            .write_dot_entries(short.first_cluster, directory)
+            .and_then(|_| encode_entry(&listing, name, short))
+            .and_then(|slots| self.insert_slots(&mut chain, &listing, &slots));
+        let location = match location {
+            Ok(location) => location,
+            Err(error) => {
+                if directory {
+                    self.volume.free_clusters(None, short.first_cluster);
+                }
+                return Err(error);
+            }
+        };
+        Ok(self.volume.node(location, &short))
+    }
+
+    fn unlink(&self, name: &str) -> VfsResult<()> {
+        let _guard = self.volume.directories.lock();
+        let (chain, slots, listing) = self.listing()?;
+        let entry = listing.find(name).ok_or(VfsError::NotFound)?;
+        let location = self.volume.slot_location(&chain, entry.short_slot());
+        let node = self.volume.node(location, &entry.short);
+        if node.directory && !node.listing()?.2.entries.is_empty() {
+            return Err(VfsError::NotEmpty);
+        }
+
+        for index in entry.slots.clone() {
+            let mut slot = slots[index];
+            slot[0] = DELETED;
+            self.volume
+                .update_slot(self.volume.slot_location(&chain, index), |data| {
+                    data.copy_from_slice(&slot)
+                })?;
+        }
+        self.volume.nodes.lock().remove(&location);
+        // clusters are freed once the node is not in use anymore
+        node.state.lock().removed = true;
+        Ok(())
+    }
+
+    fn read_dir(&self, index: usize) -> VfsResult<Option<DirEntry>> {
+        let _guard = self.volume.directories.lock();
+        let (chain, _, listing) = self.listing()?;
+        Ok(listing.entries.get(index).map(|entry| DirEntry {
+            name: entry.name.clone(),
+            inode: self.volume.slot_location(&chain, entry.short_slot()) / SLOT_SIZE as u64,
+            file_type: if entry.short.is_directory() {
+                FileType::Directory
+            } else {
+                FileType::Regular
+            },
+        }))
+    }
+
+    fn sync(&self) -> VfsResult<()> {
+        self.volume.sync()
+    }
+}
+
+impl FatInode {
+    /// Writes `.` and `..` entries to the first cluster of a new subdirectory
+    fn write_dot_entries(&self, cluster: u32, directory: bool) -> VfsResult<()> {
+        if !directory {
+            return Ok(());
+        }
+        let parent = match self.location {
+            // the root directory is referred to as cluster 0
+            None => FREE,
+            Some(_) => self.state.lock().first_cluster,
+        };
+        let mut data = vec![0; self.volume.cluster_size()];
+        for (index, (name, first_cluster)) in [(b".  ", cluster), (b".. ", parent)]
+            .into_iter()
+            .enumerate()
+        {
+            let mut short_name = [b' '; 11];
+            short_name[..3].copy_from_slice(name);
+            let short = ShortEntry {
+                name: short_name,
+                attributes: ATTRIBUTE_DIRECTORY,
+                case_flags: 0,
+                first_cluster,
+                size: 0,
+            };
+            data[index * SLOT_SIZE..][..SLOT_SIZE].copy_from_slice(&short.encode());
+        }
+        self.volume.write_cluster(cluster, &data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{format, sync::Arc, vec, vec::Vec};
+
+    use super::{format, Fat32};
+    use crate::{block::MemoryDisk, BlockDevice, FileSystem, FileType, Inode, VfsError};
+
+    /// Smallest volume with enough clusters to be a FAT32 one
+    fn disk() -> Arc<MemoryDisk> {
+        let disk = Arc::new(MemoryDisk::new(512, 70_000));
+        format(&*disk, 1).unwrap();
+        disk
+    }
+
+    fn names(directory: &Arc<dyn Inode>) -> Vec<alloc::string::String> {
+        (0..)
+            .map_while(|index| directory.read_dir(index).unwrap())
+            .map(|entry| entry.name)
+            .collect()
+    }
+
+    #[test]
+    fn test_files_persist() {
+        let disk = disk();
+        let fat = Fat32::new(disk.clone()).unwrap();
+        let directory = fat
+            .root()
+            .create("Some Directory", FileType::Directory, 0o755)
+            .unwrap();
+        let file = directory
+            .create("a file with a long name.txt", FileType::Regular, 0o644)
+            .unwrap();
+        let data: Vec<u8> = (0..5000u32).map(|index| index as u8).collect();
+        assert_eq!(file.write_at(0, &data), Ok(5000));
+        assert_eq!(file.write_at(6000, b"end"), Ok(3));
+        drop((file, directory));
+        fat.sync().unwrap();
+        drop(fat);
+
+        let fat = Fat32::new(disk).unwrap();
+        let directory = fat.root().lookup("SOME DIRECTORY").unwrap();
+        assert_eq!(directory.metadata().file_type, FileType::Directory);
+        assert_eq!(names(&directory), ["a file with a long name.txt"]);
+        let file = directory.lookup("a file with a long name.txt").unwrap();
+        assert_eq!(file.metadata().size, 6003);
+        let mut buffer = vec![0xff; 7000];
+        assert_eq!(file.read_at(0, &mut buffer), Ok(6003));
+        assert_eq!(&buffer[..5000], &data);
+        assert!(buffer[5000..6000].iter().all(|byte| *byte == 0));
+        assert_eq!(&buffer[6000..6003], b"end");
+    }
+
+    #[test]
+    fn test_directory_grows() {
+        let fat = Fat32::new(disk()).unwrap();
+        let root = fat.root();
+        // 512-byte clusters hold 16 slots, each name takes 3
+        for index in 0..40 {
+            root.create(&format!("file number {index}"), FileType::Regular, 0o644)
+                .unwrap();
+        }
+        assert_eq!(names(&root).len(), 40);
+        assert!(root.lookup("FILE NUMBER 39").is_ok());
+        assert!(matches!(
+            root.create("File Number 7", FileType::Regular, 0o644),
+            Err(VfsError::AlreadyExists)
+        ));
+    }
+
+    #[test]
+    fn test_clusters_freed() {
+        let fat = Fat32::new(disk()).unwrap();
+        let root = fat.root();
+        let file = root.create("data.bin", FileType::Regular, 0o644).unwrap();
+        file.write_at(0, &vec![1; 4096]).unwrap();
+        file.truncate(1000).unwrap();
+        assert_eq!(
+            fat.volume
+                .chain(first_cluster(&fat, "data.bin"))
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let directory = root.create("dir", FileType::Directory, 0o755).unwrap();
+        directory.create("inner", FileType::Regular, 0o644).unwrap();
+        assert_eq!(root.unlink("dir"), Err(VfsError::NotEmpty));
+        directory.unlink("inner").unwrap();
+        root.unlink("dir").unwrap();
+
+        // an open file keeps its clusters
+        root.unlink("data.bin").unwrap();
+        let mut buffer = [0; 4];
+        assert_eq!(file.read_at(0, &mut buffer), Ok(4));
+        drop((file, directory));
+        assert_eq!(names(&root).len(), 0);
+        let mut table = fat.volume.fat.lock();
+        assert!((3..10).all(|cluster| table.get(cluster) == Ok(0)));
+    }
+
+    #[test]
+    fn test_rejects_other_filesystems() {
+        let disk = Arc::new(MemoryDisk::new(512, 70_000));
+        assert!(matches!(
+            Fat32::new(disk.clone()),
+            Err(VfsError::InvaildArgument)
+        ));
+        assert_eq!(disk.block_count(), 70_000);
+    }
+
+    fn first_cluster(fat: &Fat32, name: &str) -> u32 {
+        let file = fat.root().lookup(name).unwrap();
+        let location = fat
+            .volume
+            .nodes
+            .lock()
+            .keys()
+            .copied()
+            .find(|location| location / 32 == file.metadata().inode);
+        let node = fat.volume.nodes.lock()[&location.unwrap()]
+            .upgrade()
+            .unwrap();
+        let first = node.state.lock().first_cluster;
+        first
+    }
+}
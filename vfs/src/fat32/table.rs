@@ -0,0 +1,200 @@
+//! File allocation table, linking clusters into chains
+
+use alloc::{collections::BTreeMap, sync::Arc, vec, vec::Vec};
+
+use crate::{
+    block::BlockDevice,
+    error::{VfsError, VfsResult},
+};
+
+use super::boot::BootSector;
+
+pub const FREE: u32 = 0;
+pub const END_OF_CHAIN: u32 = 0x0fff_ffff;
+const BAD_CLUSTER: u32 = 0x0fff_fff7;
+/// Upper bits of entries are reserved and kept as they are
+const ENTRY_MASK: u32 = 0x0fff_ffff;
+/// Most FAT sectors kept in memory
+const CACHED_SECTORS: usize = 64;
+
+struct CachedSector {
+    data: Vec<u8>,
+    dirty: bool,
+}
+
+/// Cached view of the FAT, writing changes to all of its copies
+pub struct FatTable {
+    device: Arc<dyn BlockDevice>,
+    boot: BootSector,
+    cache: BTreeMap<u32, CachedSector>,
+    /// Where the search for a free cluster starts
+    next_free: u32,
+}
+
+impl FatTable {
+    pub fn new(device: Arc<dyn BlockDevice>, boot: BootSector) -> FatTable {
+        FatTable {
+            device,
+            boot,
+            cache: BTreeMap::new(),
+            next_free: 2,
+        }
+    }
+
+    pub fn next_free(&self) -> u32 {
+        self.next_free
+    }
+
+    /// Returns the entry following `cluster` in its chain
+    pub fn get(&mut self, cluster: u32) -> VfsResult<u32> {
+        let (sector, offset) = self.position(cluster)?;
+        let data = &self.sector(sector)?.data;
+        Ok(u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) & ENTRY_MASK)
+    }
+
+    pub fn set(&mut self, cluster: u32, value: u32) -> VfsResult<()> {
+        let (sector, offset) = self.position(cluster)?;
+        let cached = self.sector(sector)?;
+        let entry = &mut cached.data[offset..offset + 4];
+        let previous = u32::from_le_bytes(entry.try_into().unwrap());
+        let value = (previous & !ENTRY_MASK) | (value & ENTRY_MASK);
+        entry.copy_from_slice(&value.to_le_bytes());
+        cached.dirty = true;
+        Ok(())
+    }
+
+    /// Lists clusters of a chain starting at `first`, which is empty for cluster 0
+    pub fn chain(&mut self, first: u32) -> VfsResult<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+        while cluster != FREE && !is_end(cluster) {
+            // a chain longer than the volume has to contain a loop
+            if !self.is_data_cluster(cluster) || chain.len() > self.boot.cluster_count() as usize {
+                return Err(VfsError::Io);
+            }
+            chain.push(cluster);
+            cluster = self.get(cluster)?;
+        }
+        Ok(chain)
+    }
+
+    /// Takes a free cluster, appending it to the chain ending at `last` if one is given
+    pub fn allocate(&mut self, last: Option<u32>) -> VfsResult<u32> {
+        let count = self.boot.cluster_count();
+        let start = self.next_free.clamp(2, count + 1);
+        for index in 0..count {
+            let cluster = 2 + (start - 2 + index) % count;
+            if self.get(cluster)? == FREE {
+                self.set(cluster, END_OF_CHAIN)?;
+                if let Some(last) = last {
+                    self.set(last, cluster)?;
+                }
+                self.next_free = cluster + 1;
+                return Ok(cluster);
+            }
+        }
+        Err(VfsError::NoSpace)
+    }
+
+    /// Frees all clusters of a chain
+    pub fn free_chain(&mut self, first: u32) -> VfsResult<()> {
+        for cluster in self.chain(first)? {
+            self.set(cluster, FREE)?;
+        }
+        if first != FREE {
+            self.next_free = self.next_free.min(first);
+        }
+        Ok(())
+    }
+
+    /// Cuts a chain after `last`, freeing the clusters following it
+    pub fn truncate_after(&mut self, last: u32) -> VfsResult<()> {
+        let next = self.get(last)?;
+        self.set(last, END_OF_CHAIN)?;
+        self.free_chain(next)
+    }
+
+    /// Writes modified sectors to every copy of the FAT
+    pub fn flush(&mut self) -> VfsResult<()> {
+        let sectors: Vec<u32> = self
+            .cache
+            .iter()
+            .filter(|(_, cached)| cached.dirty)
+            .map(|(sector, _)| *sector)
+            .collect();
+        for sector in sectors {
+            self.write_back(sector)?;
+        }
+        Ok(())
+    }
+
+    fn is_data_cluster(&self, cluster: u32) -> bool {
+        (2..self.boot.cluster_count() + 2).contains(&cluster)
+    }
+
+    /// Finds the FAT sector and the offset inside of it holding the entry of a cluster
+    fn position(&self, cluster: u32) -> VfsResult<(u32, usize)> {
+        if !self.is_data_cluster(cluster) {
+            return Err(VfsError::Io);
+        }
+        let offset = cluster as usize * 4;
+        Ok((
+            (offset / self.boot.sector_size) as u32,
+            offset % self.boot.sector_size,
+        ))
+    }
+
+    fn sector(&mut self, sector: u32) -> VfsResult<&mut CachedSector> {
+        if !self.cache.contains_key(&sector) {
+            if self.cache.len() >= CACHED_SECTORS {
+                self.evict()?;
+            }
+            let mut data = vec![0; self.boot.sector_size];
+            self.device.read_blocks(self.block(0, sector), &mut data)?;
+            self.cache
+                .insert(sector, CachedSector { data, dirty: false });
+        }
+        Ok(self.cache.get_mut(&sector).unwrap())
+    }
+
+    /// Drops a sector from the cache, preferring clean ones
+    fn evict(&mut self) -> VfsResult<()> {
+        let victim = self
+            .cache
+            .iter()
+            .find(|(_, cached)| !cached.dirty)
+            .or_else(|| self.cache.iter().next())
+            .map(|(sector, _)| *sector);
+        if let Some(sector) = victim {
+            self.write_back(sector)?;
+            self.cache.remove(&sector);
+        }
+        Ok(())
+    }
+
+    fn write_back(&mut self, sector: u32) -> VfsResult<()> {
+        let Some(cached) = self.cache.get(&sector) else {
+            return Ok(());
+        };
+        if !cached.dirty {
+            return Ok(());
+        }
+        for fat in 0..self.boot.fat_count {
+            self.device
+                .write_blocks(self.block(fat, sector), &cached.data)?;
+        }
+        self.cache.get_mut(&sector).unwrap().dirty = false;
+        Ok(())
+    }
+
+    /// Device block where a sector of a given FAT copy starts
+    fn block(&self, fat: u32, sector: u32) -> u64 {
+        let sector = self.boot.reserved_sectors + fat * self.boot.sectors_per_fat + sector;
+        sector as u64 * (self.boot.sector_size / self.device.block_size()) as u64
+    }
+}
+
+/// Checks whether an entry ends a chain, which bad clusters are treated as doing
+pub fn is_end(entry: u32) -> bool {
+    entry >= BAD_CLUSTER
+}
@@ -0,0 +1,57 @@
+//! Per-process tables of file descriptors
+
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{
+    error::{VfsError, VfsResult},
+    file::File,
+};
+
+/// Largest number of files a process can have open
+pub const MAX_FILES: usize = 256;
+
+#[derive(Clone, Default)]
+pub struct FileTable {
+    files: Vec<Option<Arc<File>>>,
+}
+
+impl FileTable {
+    pub fn new() -> FileTable {
+        FileTable::default()
+    }
+
+    /// Adds a file at the lowest free descriptor, which is returned
+    pub fn insert(&mut self, file: Arc<File>) -> VfsResult<usize> {
+        let fd = match self.files.iter().position(Option::is_none) {
+            Some(fd) => fd,
+            None if self.files.len() < MAX_FILES => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+            None => return Err(VfsError::TooManyFiles),
+        };
+        self.files[fd] = Some(file);
+        Ok(fd)
+    }
+
+    pub fn get(&self, fd: usize) -> VfsResult<Arc<File>> {
+        self.files
+            .get(fd)
+            .cloned()
+            .flatten()
+            .ok_or(VfsError::BadFileDescriptor)
+    }
+
+    /// Closes a descriptor, returning the file it referred to
+    pub fn remove(&mut self, fd: usize) -> VfsResult<Arc<File>> {
+        let file = self
+            .files
+            .get_mut(fd)
+            .and_then(Option::take)
+            .ok_or(VfsError::BadFileDescriptor)?;
+        while self.files.last().is_some_and(Option::is_none) {
+            self.files.pop();
+        }
+        Ok(file)
+    }
+}
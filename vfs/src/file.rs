@@ -0,0 +1,171 @@
+//! Open file descriptions, shared by file descriptors referring to the same `open`
+
+use alloc::sync::Arc;
+
+use bitflags::bitflags;
+use core_lib::{
+    sync::AtomicMutex,
+    syscall::{O_APPEND, O_CREAT, O_DIRECTORY, O_EXCL, O_NOFOLLOW, O_RDWR, O_TRUNC, O_WRONLY},
+};
+
+use crate::{
+    dentry::Dentry,
+    error::{VfsError, VfsResult},
+    inode::{DirEntry, FileType, Inode, Metadata},
+};
+
+bitflags! {
+    /// Flags of `open`, with the values used by the system call
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct OpenFlags: usize {
+        const WRITE_ONLY = O_WRONLY;
+        const READ_WRITE = O_RDWR;
+        const CREATE = O_CREAT;
+        const EXCLUSIVE = O_EXCL;
+        const TRUNCATE = O_TRUNC;
+        const APPEND = O_APPEND;
+        const DIRECTORY = O_DIRECTORY;
+        const NO_FOLLOW = O_NOFOLLOW;
+    }
+}
+
+impl OpenFlags {
+    pub fn readable(&self) -> bool {
+        !self.contains(OpenFlags::WRITE_ONLY)
+    }
+
+    pub fn writeable(&self) -> bool {
+        self.intersects(OpenFlags::WRITE_ONLY | OpenFlags::READ_WRITE)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// Entries `.` and `..` come before the ones reported by the filesystem
+const DOT_ENTRIES: usize = 2;
+
+/// A file opened with given flags, with its own offset. Devices have no offsets
+pub struct File {
+    dentry: Arc<Dentry>,
+    flags: OpenFlags,
+    /// Byte offset of a file, or index of the next entry of a directory
+    offset: AtomicMutex<u64>,
+}
+
+impl File {
+    pub(crate) fn new(dentry: Arc<Dentry>, flags: OpenFlags) -> Arc<File> {
+        Arc::new(File {
+            dentry,
+            flags,
+            offset: AtomicMutex::new(0),
+        })
+    }
+
+    /// Opens a node which is not a part of any mounted filesystem, like a pipe
+    pub fn from_inode(inode: Arc<dyn Inode>, flags: OpenFlags) -> Arc<File> {
+        File::new(Dentry::new_root(inode, None), flags)
+    }
+
+    pub fn dentry(&self) -> &Arc<Dentry> {
+        &self.dentry
+    }
+
+    pub fn flags(&self) -> OpenFlags {
+        self.flags
+    }
+
+    pub fn metadata(&self) -> Metadata {
+        self.dentry.inode().metadata()
+    }
+
+    /// Reads from the current offset, advancing it
+    pub fn read(&self, buffer: &mut [u8]) -> VfsResult<usize> {
+        if !self.flags.readable() {
+            return Err(VfsError::BadFileDescriptor);
+        }
+        match self.metadata().file_type {
+            FileType::Directory => return Err(VfsError::IsADirectory),
+            // may block for long, so the offset is not locked
+            file_type if file_type.is_stream() => return self.dentry.inode().read_at(0, buffer),
+            _ => {}
+        }
+        let mut offset = self.offset.lock();
+        let read = self.dentry.inode().read_at(*offset, buffer)?;
+        *offset += read as u64;
+        Ok(read)
+    }
+
+    /// Writes at the current offset, or at the end of the file in append mode
+    pub fn write(&self, data: &[u8]) -> VfsResult<usize> {
+        if !self.flags.writeable() {
+            return Err(VfsError::BadFileDescriptor);
+        }
+        if self.metadata().file_type.is_stream() {
+            return self.dentry.inode().write_at(0, data);
+        }
+        let mut offset = self.offset.lock();
+        if self.flags.contains(OpenFlags::APPEND) {
+            *offset = self.metadata().size;
+        }
+        let written = self.dentry.inode().write_at(*offset, data)?;
+        *offset += written as u64;
+        Ok(written)
+    }
+
+    /// Passes a device-specific request to the node, see [`Inode::ioctl`]
+    pub fn ioctl(&self, request: usize, argument: usize) -> VfsResult<usize> {
+        self.dentry.inode().ioctl(request, argument)
+    }
+
+    /// Moves the offset, returning the new one. Directories can only be rewound to
+    /// an offset returned by [`File::read_dir`]
+    pub fn seek(&self, position: SeekFrom) -> VfsResult<u64> {
+        let metadata = self.metadata();
+        let mut offset = self.offset.lock();
+        let new = match (metadata.file_type, position) {
+            (file_type, _) if file_type.is_stream() => return Err(VfsError::Unsupported),
+            (_, SeekFrom::Start(start)) => Some(start),
+            (FileType::Directory, _) => None,
+            (_, SeekFrom::Current(delta)) => offset.checked_add_signed(delta),
+            (_, SeekFrom::End(delta)) => metadata.size.checked_add_signed(delta),
+        };
+        *offset = new.ok_or(VfsError::InvaildArgument)?;
+        Ok(*offset)
+    }
+
+    /// Passes directory entries, starting at the current offset, to `accept` until it
+    /// refuses one, which is left for the next call. Returns the number of accepted entries
+    pub fn read_dir(&self, mut accept: impl FnMut(&DirEntry, u64) -> bool) -> VfsResult<usize> {
+        if self.metadata().file_type != FileType::Directory {
+            return Err(VfsError::NotADirectory);
+        }
+        let mut offset = self.offset.lock();
+        let mut accepted = 0;
+        while let Some(entry) = self.dir_entry(*offset as usize)? {
+            if !accept(&entry, *offset + 1) {
+                break;
+            }
+            *offset += 1;
+            accepted += 1;
+        }
+        Ok(accepted)
+    }
+
+    fn dir_entry(&self, index: usize) -> VfsResult<Option<DirEntry>> {
+        let dot = |name: &str, dentry: &Dentry| DirEntry {
+            name: name.into(),
+            inode: dentry.inode().metadata().inode,
+            file_type: FileType::Directory,
+        };
+        match index {
+            0 => Ok(Some(dot(".", &self.dentry))),
+            1 => Ok(Some(dot("..", &self.dentry.parent()))),
+            _ => self.dentry.inode().read_dir(index - DOT_ENTRIES),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Interface implemented by filesystems
+
+use alloc::{string::String, sync::Arc};
+use core::any::Any;
+
+use core_lib::syscall::{
+    DT_CHR, DT_DIR, DT_FIFO, DT_LNK, DT_REG, DT_SOCK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFREG,
+    S_IFSOCK,
+};
+
+use crate::error::{VfsError, VfsResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    CharDevice,
+    /// Pipe, passing data written to it to readers
+    Fifo,
+    Socket,
+}
+
+impl FileType {
+    /// File type bits of a mode
+    pub fn mode_bits(&self) -> u32 {
+        match self {
+            FileType::Regular => S_IFREG,
+            FileType::Directory => S_IFDIR,
+            FileType::Symlink => S_IFLNK,
+            FileType::CharDevice => S_IFCHR,
+            FileType::Fifo => S_IFIFO,
+            FileType::Socket => S_IFSOCK,
+        }
+    }
+
+    /// Type of a directory entry, as reported by `getdents64`
+    pub fn dirent_type(&self) -> u8 {
+        match self {
+            FileType::Regular => DT_REG,
+            FileType::Directory => DT_DIR,
+            FileType::Symlink => DT_LNK,
+            FileType::CharDevice => DT_CHR,
+            FileType::Fifo => DT_FIFO,
+            FileType::Socket => DT_SOCK,
+        }
+    }
+
+    /// Checks whether the file is a stream without offsets, whose reads may block
+    pub fn is_stream(&self) -> bool {
+        matches!(
+            self,
+            FileType::CharDevice | FileType::Fifo | FileType::Socket
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    /// Number unique within the filesystem
+    pub inode: u64,
+    pub file_type: FileType,
+    pub permissions: u32,
+    /// Length of the contents of a file or the target of a symlink
+    pub size: u64,
+    pub links: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub inode: u64,
+    pub file_type: FileType,
+}
+
+/// A file, directory or other node of a filesystem
+///
+/// The VFS checks the file type before calling an operation, so a filesystem does not need
+/// to, e.g. `lookup` is called only on directories. Operations a node does not support
+/// return [`VfsError::Unsupported`] by default.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> Metadata;
+
+    /// Reads file contents at an offset, returning how many bytes were read.
+    /// 0 means the end of the file
+    fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> VfsResult<usize> {
+        Err(VfsError::Unsupported)
+    }
+
+    /// Writes file contents at an offset, extending the file if needed
+    fn write_at(&self, _offset: u64, _data: &[u8]) -> VfsResult<usize> {
+        Err(VfsError::Unsupported)
+    }
+
+    /// Changes size of a file, zero-filling it when extended
+    fn truncate(&self, _size: u64) -> VfsResult<()> {
+        Err(VfsError::Unsupported)
+    }
+
+    /// Finds a directory entry by name, which is never `.` or `..`
+    fn lookup(&self, _name: &str) -> VfsResult<Arc<dyn Inode>> {
+        Err(VfsError::Unsupported)
+    }
+
+    /// Creates an empty file or directory in a directory
+    fn create(
+        &self,
+        _name: &str,
+        _file_type: FileType,
+        _permissions: u32,
+    ) -> VfsResult<Arc<dyn Inode>> {
+        Err(VfsError::Unsupported)
+    }
+
+    /// Creates a symlink pointing to `target` in a directory
+    fn symlink(&self, _name: &str, _target: &str) -> VfsResult<Arc<dyn Inode>> {
+        Err(VfsError::Unsupported)
+    }
+
+    /// Removes a directory entry. Directories have to be empty
+    fn unlink(&self, _name: &str) -> VfsResult<()> {
+        Err(VfsError::Unsupported)
+    }
+
+    /// Returns the entry of a directory at an index, without `.` and `..`, or `None` past
+    /// the last one. Indices of entries stay stable while a directory is not modified
+    fn read_dir(&self, _index: usize) -> VfsResult<Option<DirEntry>> {
+        Err(VfsError::Unsupported)
+    }
+
+    /// Target of a symlink
+    fn read_link(&self) -> VfsResult<String> {
+        Err(VfsError::Unsupported)
+    }
+
+    /// Handles a device-specific `ioctl` request, whose argument is usually an address in
+    /// the memory of the calling process
+    fn ioctl(&self, _request: usize, _argument: usize) -> VfsResult<usize> {
+        Err(VfsError::NotATerminal)
+    }
+
+    /// Writes modified data of the node to its storage
+    fn sync(&self) -> VfsResult<()> {
+        Ok(())
+    }
+
+    /// Kernel object behind a node which is not a part of any filesystem, like a socket,
+    /// for operations the VFS does not know about
+    fn as_any(&self) -> Option<&dyn Any> {
+        None
+    }
+}
+
+/// A mountable filesystem instance
+pub trait FileSystem: Send + Sync {
+    /// Short name of the filesystem type, e.g. `tmpfs`
+    fn name(&self) -> &'static str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Writes all modified data to storage
+    fn sync(&self) -> VfsResult<()> {
+        Ok(())
+    }
+}
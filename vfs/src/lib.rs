@@ -0,0 +1,494 @@
+#![no_std]
+
+//! Virtual filesystem, joining mounted filesystems into a single tree
+//!
+//! Filesystems implement [`Inode`] and [`FileSystem`]. The VFS caches looked up names as
+//! [`Dentry`] values, resolves paths across mount points and symlinks, and keeps open
+//! files with their offsets. Paths not starting with `/` are resolved from the root, as
+//! there are no working directories yet.
+
+extern crate alloc;
+
+pub mod block;
+pub mod cache;
+pub mod dentry;
+pub mod devfs;
+pub mod error;
+pub mod ext2;
+pub mod fat32;
+pub mod fd;
+pub mod file;
+pub mod inode;
+pub mod partition;
+pub mod tmpfs;
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use core_lib::sync::AtomicMutex;
+
+pub use block::{BlockDevice, MemoryDisk};
+pub use cache::{BufferCache, CachedDevice};
+pub use dentry::Dentry;
+pub use devfs::DevFs;
+pub use error::{VfsError, VfsResult};
+pub use ext2::Ext2;
+pub use fat32::Fat32;
+pub use fd::FileTable;
+pub use file::{File, OpenFlags, SeekFrom};
+pub use inode::{DirEntry, FileSystem, FileType, Inode, Metadata};
+pub use partition::Partition;
+pub use tmpfs::TmpFs;
+
+/// Longest name of a single path component
+pub const MAX_NAME_LENGTH: usize = 255;
+/// Largest number of symlinks followed while resolving a single path
+pub const MAX_SYMLINKS: usize = 40;
+
+/// A filesystem mounted in the tree
+pub struct Mount {
+    path: String,
+    filesystem: Arc<dyn FileSystem>,
+    /// Dentry hidden by the mount, `None` for the root filesystem. Keeps it cached
+    point: Option<Arc<Dentry>>,
+}
+
+impl Mount {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn filesystem(&self) -> &Arc<dyn FileSystem> {
+        &self.filesystem
+    }
+}
+
+pub struct Vfs {
+    root: AtomicMutex<Option<Arc<Dentry>>>,
+    mounts: AtomicMutex<Vec<Mount>>,
+}
+
+impl Vfs {
+    pub const fn new() -> Vfs {
+        Vfs {
+            root: AtomicMutex::new(None),
+            mounts: AtomicMutex::new(Vec::new()),
+        }
+    }
+
+    /// Mounts a filesystem over a directory. The first filesystem has to be mounted at `/`
+    pub fn mount(&self, path: &str, filesystem: Arc<dyn FileSystem>) -> VfsResult<()> {
+        let root_inode = filesystem.root();
+        let mounted_root = {
+            let mut root = self.root.lock();
+            if root.is_none() {
+                if path != "/" {
+                    return Err(VfsError::NotFound);
+                }
+                *root = Some(Dentry::new_root(root_inode.clone(), None));
+                true
+            } else {
+                false
+            }
+        };
+        let point = if mounted_root {
+            None
+        } else {
+            let point = self.resolve(path, true)?;
+            if point.file_type() != FileType::Directory {
+                return Err(VfsError::NotADirectory);
+            }
+            // filesystems are not stacked on a single mount point
+            if point.is_mount_root() {
+                return Err(VfsError::Busy);
+            }
+            point.set_mounted(Some(Dentry::new_root(root_inode, Some(point.clone()))));
+            Some(point)
+        };
+        self.mounts.lock().push(Mount {
+            path: String::from(path),
+            filesystem,
+            point,
+        });
+        Ok(())
+    }
+
+    /// Detaches a filesystem mounted at a path. Files open in it stay usable
+    pub fn unmount(&self, path: &str) -> VfsResult<Arc<dyn FileSystem>> {
+        let mut mounts = self.mounts.lock();
+        let index = mounts
+            .iter()
+            .position(|mount| mount.path == path)
+            .ok_or(VfsError::NotFound)?;
+        let Some(point) = &mounts[index].point else {
+            return Err(VfsError::Busy);
+        };
+        point.set_mounted(None);
+        Ok(mounts.remove(index).filesystem)
+    }
+
+    /// Calls `f` with each mount, in the order they were made
+    pub fn for_each_mount(&self, mut f: impl FnMut(&Mount)) {
+        for mount in self.mounts.lock().iter() {
+            f(mount);
+        }
+    }
+
+    pub fn root(&self) -> VfsResult<Arc<Dentry>> {
+        self.root.lock().clone().ok_or(VfsError::NotFound)
+    }
+
+    /// Finds the dentry of a path, following a symlink in its last component if `follow` is set
+    pub fn resolve(&self, path: &str, follow: bool) -> VfsResult<Arc<Dentry>> {
+        let mut symlinks = 0;
+        self.walk(self.root()?, path, follow, &mut symlinks)
+    }
+
+    pub fn open(&self, path: &str, flags: OpenFlags, permissions: u32) -> VfsResult<Arc<File>> {
+        let follow = !flags.contains(OpenFlags::NO_FOLLOW);
+        let dentry = if flags.contains(OpenFlags::CREATE) {
+            let (parent, name) = self.resolve_parent(path)?;
+            match parent.lookup(name) {
+                Ok(_) if flags.contains(OpenFlags::EXCLUSIVE) => {
+                    return Err(VfsError::AlreadyExists)
+                }
+                Ok(_) => self.resolve(path, follow)?,
+                Err(VfsError::NotFound) => parent.create(name, FileType::Regular, permissions)?,
+                Err(error) => return Err(error),
+            }
+        } else {
+            self.resolve(path, follow)?
+        };
+
+        let metadata = dentry.inode().metadata();
+        match metadata.file_type {
+            FileType::Symlink => return Err(VfsError::TooManySymlinks),
+            FileType::Directory if flags.writeable() => return Err(VfsError::IsADirectory),
+            FileType::Directory => {}
+            _ if flags.contains(OpenFlags::DIRECTORY) => return Err(VfsError::NotADirectory),
+            FileType::Regular if flags.contains(OpenFlags::TRUNCATE) && flags.writeable() => {
+                dentry.inode().truncate(0)?
+            }
+            _ => {}
+        }
+        Ok(File::new(dentry, flags))
+    }
+
+    pub fn metadata(&self, path: &str, follow: bool) -> VfsResult<Metadata> {
+        Ok(self.resolve(path, follow)?.inode().metadata())
+    }
+
+    pub fn create_dir(&self, path: &str, permissions: u32) -> VfsResult<()> {
+        let (parent, name) = self.resolve_parent(path)?;
+        parent.create(name, FileType::Directory, permissions)?;
+        Ok(())
+    }
+
+    pub fn symlink(&self, target: &str, path: &str) -> VfsResult<()> {
+        let (parent, name) = self.resolve_parent(path)?;
+        parent.symlink(name, target)?;
+        Ok(())
+    }
+
+    /// Removes a file or an empty directory
+    pub fn remove(&self, path: &str) -> VfsResult<()> {
+        let (parent, name) = self.resolve_parent(path)?;
+        parent.unlink(name)
+    }
+
+    /// Writes modified data of all mounted filesystems to storage
+    pub fn sync(&self) -> VfsResult<()> {
+        let filesystems: Vec<_> = self
+            .mounts
+            .lock()
+            .iter()
+            .map(|mount| mount.filesystem.clone())
+            .collect();
+        filesystems
+            .iter()
+            .try_for_each(|filesystem| filesystem.sync())
+    }
+
+    /// Resolves the directory containing the last component of a path, which is returned
+    /// along with it
+    fn resolve_parent<'p>(&self, path: &'p str) -> VfsResult<(Arc<Dentry>, &'p str)> {
+        let trimmed = path.trim_end_matches('/');
+        let (directory, name) = match trimmed.rsplit_once('/') {
+            Some(("", name)) => ("/", name),
+            Some((directory, name)) => (directory, name),
+            None => ("/", trimmed),
+        };
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(VfsError::InvaildPath);
+        }
+        if name.len() > MAX_NAME_LENGTH {
+            return Err(VfsError::NameTooLong);
+        }
+        let parent = self.resolve(directory, true)?;
+        if parent.file_type() != FileType::Directory {
+            return Err(VfsError::NotADirectory);
+        }
+        Ok((parent, name))
+    }
+
+    fn walk(
+        &self,
+        start: Arc<Dentry>,
+        path: &str,
+        follow: bool,
+        symlinks: &mut usize,
+    ) -> VfsResult<Arc<Dentry>> {
+        if path.is_empty() {
+            return Err(VfsError::InvaildPath);
+        }
+        let mut current = if path.starts_with('/') {
+            self.root()?
+        } else {
+            start
+        };
+        let mut components = path
+            .split('/')
+            .filter(|component| !component.is_empty() && *component != ".")
+            .peekable();
+        while let Some(name) = components.next() {
+            if current.file_type() != FileType::Directory {
+                return Err(VfsError::NotADirectory);
+            }
+            if name == ".." {
+                current = current.parent();
+                continue;
+            }
+            if name.len() > MAX_NAME_LENGTH {
+                return Err(VfsError::NameTooLong);
+            }
+
+            let child = current.lookup(name)?;
+            let last = components.peek().is_none();
+            if child.file_type() == FileType::Symlink && (follow || !last) {
+                *symlinks += 1;
+                if *symlinks > MAX_SYMLINKS {
+                    return Err(VfsError::TooManySymlinks);
+                }
+                let target = child.inode().read_link()?;
+                current = self.walk(current, &target, true, symlinks)?;
+            } else {
+                current = child;
+            }
+        }
+        if path.ends_with('/') && current.file_type() != FileType::Directory {
+            return Err(VfsError::NotADirectory);
+        }
+        Ok(current)
+    }
+}
+
+impl Default for Vfs {
+    fn default() -> Vfs {
+        Vfs::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::String, vec, vec::Vec};
+
+    use crate::{FileTable, FileType, OpenFlags, SeekFrom, TmpFs, Vfs, VfsError};
+
+    fn vfs() -> Vfs {
+        let vfs = Vfs::new();
+        vfs.mount("/", TmpFs::new(u64::MAX)).unwrap();
+        vfs
+    }
+
+    fn write_file(vfs: &Vfs, path: &str, data: &[u8]) {
+        let flags = OpenFlags::WRITE_ONLY | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+        let file = vfs.open(path, flags, 0o644).unwrap();
+        assert_eq!(file.write(data), Ok(data.len()));
+    }
+
+    fn read_file(vfs: &Vfs, path: &str) -> Vec<u8> {
+        let file = vfs.open(path, OpenFlags::empty(), 0).unwrap();
+        let mut data = vec![0; file.metadata().size as usize + 1];
+        let read = file.read(&mut data).unwrap();
+        data.truncate(read);
+        data
+    }
+
+    fn list(vfs: &Vfs, path: &str) -> Vec<String> {
+        let directory = vfs.open(path, OpenFlags::DIRECTORY, 0).unwrap();
+        let mut names = Vec::new();
+        directory
+            .read_dir(|entry, _| {
+                names.push(entry.name.clone());
+                true
+            })
+            .unwrap();
+        names
+    }
+
+    #[test]
+    fn test_path_resolution() {
+        let vfs = vfs();
+        vfs.create_dir("/a", 0o755).unwrap();
+        vfs.create_dir("/a/b", 0o755).unwrap();
+        write_file(&vfs, "/a/b/file", b"contents");
+
+        assert_eq!(read_file(&vfs, "/a/./b//file"), b"contents");
+        assert_eq!(read_file(&vfs, "a/b/../b/file"), b"contents");
+        assert_eq!(read_file(&vfs, "/../../a/b/file"), b"contents");
+        assert_eq!(vfs.resolve("/a/b/", true).unwrap().path(), "/a/b");
+        assert_eq!(
+            vfs.resolve("/a/b/file/", true).err(),
+            Some(VfsError::NotADirectory)
+        );
+        assert_eq!(
+            vfs.resolve("/a/b/file/x", true).err(),
+            Some(VfsError::NotADirectory)
+        );
+        assert_eq!(vfs.resolve("/a/c", true).err(), Some(VfsError::NotFound));
+        assert_eq!(vfs.resolve("", true).err(), Some(VfsError::InvaildPath));
+    }
+
+    #[test]
+    fn test_symlinks() {
+        let vfs = vfs();
+        vfs.create_dir("/directory", 0o755).unwrap();
+        write_file(&vfs, "/directory/file", b"target");
+        vfs.symlink("directory/file", "/absolute").unwrap();
+        vfs.symlink("file", "/directory/relative").unwrap();
+        vfs.symlink("/directory", "/link-to-directory").unwrap();
+        vfs.symlink("loop", "/loop").unwrap();
+
+        assert_eq!(read_file(&vfs, "/absolute"), b"target");
+        assert_eq!(read_file(&vfs, "/directory/relative"), b"target");
+        assert_eq!(read_file(&vfs, "/link-to-directory/relative"), b"target");
+        let link = vfs.metadata("/absolute", false).unwrap();
+        assert_eq!(link.file_type, FileType::Symlink);
+        assert_eq!(link.size, "directory/file".len() as u64);
+        assert_eq!(
+            vfs.resolve("/loop", true).err(),
+            Some(VfsError::TooManySymlinks)
+        );
+        assert_eq!(
+            vfs.open("/absolute", OpenFlags::NO_FOLLOW, 0).err(),
+            Some(VfsError::TooManySymlinks)
+        );
+    }
+
+    #[test]
+    fn test_open_flags() {
+        let vfs = vfs();
+        write_file(&vfs, "/file", b"0123456789");
+        let exclusive = OpenFlags::CREATE | OpenFlags::EXCLUSIVE;
+        assert_eq!(
+            vfs.open("/file", exclusive, 0o644).err(),
+            Some(VfsError::AlreadyExists)
+        );
+        assert_eq!(
+            vfs.open("/file", OpenFlags::DIRECTORY, 0).err(),
+            Some(VfsError::NotADirectory)
+        );
+        assert_eq!(
+            vfs.open("/", OpenFlags::READ_WRITE, 0).err(),
+            Some(VfsError::IsADirectory)
+        );
+
+        let read_only = vfs.open("/file", OpenFlags::empty(), 0).unwrap();
+        assert_eq!(read_only.write(b"x"), Err(VfsError::BadFileDescriptor));
+
+        let append = vfs
+            .open("/file", OpenFlags::WRITE_ONLY | OpenFlags::APPEND, 0)
+            .unwrap();
+        append.write(b"ab").unwrap();
+        assert_eq!(read_file(&vfs, "/file"), b"0123456789ab");
+
+        write_file(&vfs, "/file", b"new");
+        assert_eq!(read_file(&vfs, "/file"), b"new");
+    }
+
+    #[test]
+    fn test_seek() {
+        let vfs = vfs();
+        write_file(&vfs, "/file", b"0123456789");
+        let file = vfs.open("/file", OpenFlags::READ_WRITE, 0).unwrap();
+        assert_eq!(file.seek(SeekFrom::End(-3)), Ok(7));
+        let mut buffer = [0; 2];
+        file.read(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"78");
+        assert_eq!(file.seek(SeekFrom::Current(-9)), Ok(0));
+        assert_eq!(
+            file.seek(SeekFrom::Current(-1)),
+            Err(VfsError::InvaildArgument)
+        );
+        // writing past the end leaves a zero-filled hole
+        file.seek(SeekFrom::Start(12)).unwrap();
+        file.write(b"x").unwrap();
+        assert_eq!(read_file(&vfs, "/file"), b"0123456789\0\0x");
+    }
+
+    #[test]
+    fn test_directories() {
+        let vfs = vfs();
+        vfs.create_dir("/directory", 0o755).unwrap();
+        write_file(&vfs, "/directory/b", b"");
+        write_file(&vfs, "/directory/a", b"");
+        assert_eq!(list(&vfs, "/directory"), [".", "..", "a", "b"]);
+        assert_eq!(vfs.remove("/directory").err(), Some(VfsError::NotEmpty));
+        vfs.remove("/directory/a").unwrap();
+        assert_eq!(list(&vfs, "/directory"), [".", "..", "b"]);
+        assert_eq!(
+            vfs.create_dir("/directory/b", 0o755).err(),
+            Some(VfsError::AlreadyExists)
+        );
+
+        // entries refused by the callback are returned by the next call
+        let directory = vfs.open("/directory", OpenFlags::empty(), 0).unwrap();
+        assert_eq!(directory.read_dir(|entry, _| entry.name != ".."), Ok(1));
+        let mut rest = Vec::new();
+        directory
+            .read_dir(|entry, _| {
+                rest.push(entry.name.clone());
+                true
+            })
+            .unwrap();
+        assert_eq!(rest, ["..", "b"]);
+    }
+
+    #[test]
+    fn test_mounts() {
+        let vfs = vfs();
+        vfs.create_dir("/mnt", 0o755).unwrap();
+        write_file(&vfs, "/mnt/hidden", b"");
+        vfs.mount("/mnt", TmpFs::new(u64::MAX)).unwrap();
+        write_file(&vfs, "/mnt/file", b"mounted");
+
+        assert_eq!(list(&vfs, "/mnt"), [".", "..", "file"]);
+        assert_eq!(read_file(&vfs, "/mnt/../mnt/file"), b"mounted");
+        assert_eq!(vfs.resolve("/mnt/..", true).unwrap().path(), "/");
+        assert_eq!(vfs.resolve("/mnt/file", true).unwrap().path(), "/mnt/file");
+        assert_eq!(vfs.remove("/mnt").err(), Some(VfsError::Busy));
+        assert_eq!(
+            vfs.mount("/mnt", TmpFs::new(u64::MAX)).err(),
+            Some(VfsError::Busy)
+        );
+
+        let open = vfs.open("/mnt/file", OpenFlags::empty(), 0).unwrap();
+        vfs.unmount("/mnt").unwrap();
+        assert_eq!(list(&vfs, "/mnt"), [".", "..", "hidden"]);
+        let mut buffer = [0; 7];
+        assert_eq!(open.read(&mut buffer), Ok(7));
+    }
+
+    #[test]
+    fn test_file_table() {
+        let vfs = vfs();
+        let root = vfs.open("/", OpenFlags::empty(), 0).unwrap();
+        let mut files = FileTable::new();
+        assert_eq!(files.insert(root.clone()), Ok(0));
+        assert_eq!(files.insert(root.clone()), Ok(1));
+        assert_eq!(files.insert(root.clone()), Ok(2));
+        files.remove(1).unwrap();
+        assert_eq!(files.get(1).err(), Some(VfsError::BadFileDescriptor));
+        assert_eq!(files.insert(root.clone()), Ok(1));
+        assert_eq!(files.remove(7).err(), Some(VfsError::BadFileDescriptor));
+    }
+}
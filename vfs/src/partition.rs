@@ -0,0 +1,493 @@
+//! Partition tables, splitting a disk into block devices holding separate filesystems
+//!
+//! Both MBR, including logical partitions in extended ones, and GPT are read. Addresses in
+//! tables are in blocks of the disk, whose size has to be at least 512 bytes.
+
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::fmt::{self, Display};
+
+use crate::{
+    block::{check_range, BlockDevice},
+    error::{VfsError, VfsResult},
+};
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MBR_ENTRIES_OFFSET: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+/// MBR partition type covering a whole GPT disk
+const MBR_PROTECTIVE: u8 = 0xee;
+/// MBR partition types holding logical partitions
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+/// Number of the first logical partition, following the four primary ones
+const FIRST_LOGICAL: u32 = 5;
+/// Most logical partitions read, stopping loops of extended boot records
+const MAX_LOGICAL: u32 = 128;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_SIZE: usize = 92;
+const GPT_ENTRY_SIZE: usize = 128;
+/// Largest partition entry array read, 128 entries being the usual size
+const GPT_MAX_ENTRIES_SIZE: usize = 1024 * 1024;
+const GPT_NAME_LENGTH: usize = 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionType {
+    /// Type byte of an MBR entry
+    Mbr(u8),
+    /// Type GUID of a GPT entry, as stored on disk
+    Gpt([u8; 16]),
+}
+
+impl Display for PartitionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionType::Mbr(kind) => write!(f, "type 0x{:02x}", kind),
+            // the first three fields of a GUID are little-endian
+            PartitionType::Gpt(guid) => write!(
+                f,
+                "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-",
+                u32_at(guid, 0),
+                u16::from_le_bytes([guid[4], guid[5]]),
+                u16::from_le_bytes([guid[6], guid[7]]),
+                guid[8],
+                guid[9]
+            )
+            .and_then(|()| {
+                guid[10..]
+                    .iter()
+                    .try_for_each(|byte| write!(f, "{:02x}", byte))
+            }),
+        }
+    }
+}
+
+/// Part of a disk, with blocks numbered from the start of the partition
+pub struct Partition {
+    device: Arc<dyn BlockDevice>,
+    number: u32,
+    partition_type: PartitionType,
+    name: String,
+    start: u64,
+    length: u64,
+}
+
+impl Partition {
+    /// Number of the partition, counted from 1 in the table
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    pub fn partition_type(&self) -> PartitionType {
+        self.partition_type
+    }
+
+    /// Name of a GPT partition, empty for MBR ones
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// First block of the partition on the disk
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+}
+
+impl BlockDevice for Partition {
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.length
+    }
+
+    fn read_blocks(&self, block: u64, buffer: &mut [u8]) -> VfsResult<()> {
+        check_range(self, block, buffer.len())?;
+        self.device.read_blocks(self.start + block, buffer)
+    }
+
+    fn write_blocks(&self, block: u64, data: &[u8]) -> VfsResult<()> {
+        check_range(self, block, data.len())?;
+        self.device.write_blocks(self.start + block, data)
+    }
+
+    fn read_only(&self) -> bool {
+        self.device.read_only()
+    }
+
+    fn flush(&self) -> VfsResult<()> {
+        self.device.flush()
+    }
+}
+
+/// Reads the partition table of a disk, which has no partitions if there is none
+///
+/// Partitions reaching past the end of the disk are left out. A GPT disk with neither
+/// header valid is an error, so that it is not taken for an unpartitioned one.
+pub fn partitions(device: &Arc<dyn BlockDevice>) -> VfsResult<Vec<Arc<Partition>>> {
+    if device.block_size() < 512 || device.block_count() < 2 {
+        return Ok(Vec::new());
+    }
+    let mbr = read_block(device.as_ref(), 0)?;
+    if mbr[510..512] != MBR_SIGNATURE {
+        return Ok(Vec::new());
+    }
+    let entries = mbr_entries(&mbr);
+    let partitions = if entries
+        .iter()
+        .any(|(_, entry)| entry.partition_type == MBR_PROTECTIVE)
+    {
+        gpt_partitions(device)?
+    } else {
+        mbr_partitions(device, &entries)?
+    };
+    Ok(partitions
+        .into_iter()
+        .filter(|partition| {
+            partition.length > 0
+                && partition
+                    .start
+                    .checked_add(partition.length)
+                    .is_some_and(|end| end <= device.block_count())
+        })
+        .map(Arc::new)
+        .collect())
+}
+
+fn read_block(device: &dyn BlockDevice, block: u64) -> VfsResult<Vec<u8>> {
+    let mut data = vec![0; device.block_size()];
+    device.read_blocks(block, &mut data)?;
+    Ok(data)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+struct MbrEntry {
+    partition_type: u8,
+    start: u64,
+    length: u64,
+}
+
+/// Parses entries of a master or an extended boot record, leaving out empty ones
+fn mbr_entries(record: &[u8]) -> Vec<(usize, MbrEntry)> {
+    (0..4)
+        .map(|index| {
+            let entry = &record[MBR_ENTRIES_OFFSET + index * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+            let entry = MbrEntry {
+                partition_type: entry[4],
+                start: u32_at(entry, 8) as u64,
+                length: u32_at(entry, 12) as u64,
+            };
+            (index, entry)
+        })
+        .filter(|(_, entry)| entry.partition_type != 0)
+        .collect()
+}
+
+fn mbr_partitions(
+    device: &Arc<dyn BlockDevice>,
+    entries: &[(usize, MbrEntry)],
+) -> VfsResult<Vec<Partition>> {
+    let mut partitions = Vec::new();
+    for (index, entry) in entries {
+        if MBR_EXTENDED.contains(&entry.partition_type) {
+            logical_partitions(device, entry.start, &mut partitions)?;
+            continue;
+        }
+        partitions.push(Partition {
+            device: device.clone(),
+            number: *index as u32 + 1,
+            partition_type: PartitionType::Mbr(entry.partition_type),
+            name: String::new(),
+            start: entry.start,
+            length: entry.length,
+        });
+    }
+    Ok(partitions)
+}
+
+/// Follows the chain of extended boot records of an extended partition at `base`
+///
+/// Logical partitions start relative to their record, and further records relative to
+/// the extended partition.
+fn logical_partitions(
+    device: &Arc<dyn BlockDevice>,
+    base: u64,
+    partitions: &mut Vec<Partition>,
+) -> VfsResult<()> {
+    let mut record_block = base;
+    for number in FIRST_LOGICAL..FIRST_LOGICAL + MAX_LOGICAL {
+        if record_block >= device.block_count() {
+            return Ok(());
+        }
+        let record = read_block(device.as_ref(), record_block)?;
+        if record[510..512] != MBR_SIGNATURE {
+            return Ok(());
+        }
+        let mut next = None;
+        for (_, entry) in mbr_entries(&record) {
+            if MBR_EXTENDED.contains(&entry.partition_type) {
+                next = Some(base + entry.start);
+            } else if partitions.iter().all(|p| p.number != number) {
+                partitions.push(Partition {
+                    device: device.clone(),
+                    number,
+                    partition_type: PartitionType::Mbr(entry.partition_type),
+                    name: String::new(),
+                    start: record_block + entry.start,
+                    length: entry.length,
+                });
+            }
+        }
+        match next {
+            Some(block) if block > record_block => record_block = block,
+            _ => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+struct GptHeader {
+    entries_block: u64,
+    entry_count: usize,
+    entry_size: usize,
+    entries_checksum: u32,
+}
+
+fn gpt_partitions(device: &Arc<dyn BlockDevice>) -> VfsResult<Vec<Partition>> {
+    // the backup header, at the end of the disk, is used if the primary one is damaged
+    let last = device.block_count() - 1;
+    let mut found = None;
+    for block in [1, last] {
+        if let Some(header) = gpt_header(device.as_ref(), block)? {
+            if let Some(entries) = gpt_entries(device.as_ref(), &header)? {
+                found = Some((header, entries));
+                break;
+            }
+        }
+    }
+    let Some((header, entries)) = found else {
+        return Err(VfsError::Io);
+    };
+
+    let partitions = entries
+        .chunks(header.entry_size)
+        .enumerate()
+        .filter(|(_, entry)| entry[0..16].iter().any(|byte| *byte != 0))
+        .filter_map(|(index, entry)| {
+            let (first, last) = (u64_at(entry, 32), u64_at(entry, 40));
+            let units = entry[56..56 + GPT_NAME_LENGTH * 2]
+                .chunks(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .take_while(|unit| *unit != 0);
+            let name = char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect();
+            Some(Partition {
+                device: device.clone(),
+                number: index as u32 + 1,
+                partition_type: PartitionType::Gpt(entry[0..16].try_into().unwrap()),
+                name,
+                start: first,
+                length: last.checked_sub(first)? + 1,
+            })
+        })
+        .collect();
+    Ok(partitions)
+}
+
+/// Reads a GPT header, which is `None` if it is missing or damaged
+fn gpt_header(device: &dyn BlockDevice, block: u64) -> VfsResult<Option<GptHeader>> {
+    let data = read_block(device, block)?;
+    if &data[0..8] != GPT_SIGNATURE {
+        return Ok(None);
+    }
+    let size = u32_at(&data, 12) as usize;
+    if !(GPT_HEADER_SIZE..=data.len()).contains(&size) || u64_at(&data, 24) != block {
+        return Ok(None);
+    }
+    let mut header = data[..size].to_vec();
+    header[16..20].fill(0);
+    if crc32(&header) != u32_at(&data, 16) {
+        return Ok(None);
+    }
+
+    let header = GptHeader {
+        entries_block: u64_at(&data, 72),
+        entry_count: u32_at(&data, 80) as usize,
+        entry_size: u32_at(&data, 84) as usize,
+        entries_checksum: u32_at(&data, 88),
+    };
+    let valid = header.entry_size >= GPT_ENTRY_SIZE
+        && header.entry_size.is_power_of_two()
+        && header
+            .entry_count
+            .checked_mul(header.entry_size)
+            .is_some_and(|size| size <= GPT_MAX_ENTRIES_SIZE);
+    Ok(valid.then_some(header))
+}
+
+/// Reads the partition entry array of a header, which is `None` if it is damaged
+fn gpt_entries(device: &dyn BlockDevice, header: &GptHeader) -> VfsResult<Option<Vec<u8>>> {
+    let size = header.entry_count * header.entry_size;
+    let mut data = vec![0; size.next_multiple_of(device.block_size())];
+    match device.read_blocks(header.entries_block, &mut data) {
+        Ok(()) => {}
+        Err(VfsError::InvaildArgument) => return Ok(None),
+        Err(error) => return Err(error),
+    }
+    data.truncate(size);
+    Ok((crc32(&data) == header.entries_checksum).then_some(data))
+}
+
+/// CRC-32 used by GPT, the same as the one of Ethernet and zlib
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = u32::MAX;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::ToString, sync::Arc, vec, vec::Vec};
+
+    use super::{crc32, partitions, PartitionType};
+    use crate::{block::MemoryDisk, BlockDevice, VfsError};
+
+    const BLOCKS: usize = 256;
+    const LINUX: [u8; 16] = [
+        0xaf, 0x3d, 0xc6, 0x0f, 0x83, 0x84, 0x72, 0x47, 0x8e, 0x79, 0x3d, 0x69, 0xd8, 0x47, 0x7d,
+        0xe4,
+    ];
+
+    fn mbr_entry(image: &mut [u8], record: usize, index: usize, kind: u8, start: u32, length: u32) {
+        let entry = &mut image[record * 512 + 446 + index * 16..][..16];
+        entry[4] = kind;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&length.to_le_bytes());
+        image[record * 512 + 510..record * 512 + 512].copy_from_slice(&[0x55, 0xaa]);
+    }
+
+    /// Writes a GPT header at `block` with an entry array at `entries`
+    fn gpt_header(image: &mut [u8], block: usize, entries: usize, array: &[u8]) {
+        let header = &mut image[block * 512..][..92];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[24..32].copy_from_slice(&(block as u64).to_le_bytes());
+        header[72..80].copy_from_slice(&(entries as u64).to_le_bytes());
+        header[80..84].copy_from_slice(&4u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&crc32(array).to_le_bytes());
+        let checksum = crc32(header);
+        header[16..20].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    fn gpt_image() -> Vec<u8> {
+        let mut image = vec![0; BLOCKS * 512];
+        mbr_entry(&mut image, 0, 0, 0xee, 1, BLOCKS as u32 - 1);
+        let mut array = vec![0; 4 * 128];
+        array[0..16].copy_from_slice(&LINUX);
+        array[32..40].copy_from_slice(&34u64.to_le_bytes());
+        array[40..48].copy_from_slice(&99u64.to_le_bytes());
+        for (index, unit) in "root".encode_utf16().enumerate() {
+            array[56 + index * 2..][..2].copy_from_slice(&unit.to_le_bytes());
+        }
+        image[2 * 512..][..array.len()].copy_from_slice(&array);
+        image[(BLOCKS - 2) * 512..][..array.len()].copy_from_slice(&array);
+        gpt_header(&mut image, 1, 2, &array);
+        gpt_header(&mut image, BLOCKS - 1, BLOCKS - 2, &array);
+        image
+    }
+
+    fn disk(image: Vec<u8>) -> Arc<dyn BlockDevice> {
+        Arc::new(MemoryDisk::from_bytes(512, image))
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_mbr() {
+        let mut image = vec![0; BLOCKS * 512];
+        mbr_entry(&mut image, 0, 0, 0x83, 8, 40);
+        mbr_entry(&mut image, 0, 1, 0x0f, 100, 100);
+        // logical partitions start relative to their record
+        mbr_entry(&mut image, 100, 0, 0x0c, 2, 10);
+        mbr_entry(&mut image, 100, 1, 0x05, 20, 50);
+        mbr_entry(&mut image, 120, 0, 0x83, 2, 20);
+        // past the end of the disk
+        mbr_entry(&mut image, 0, 3, 0x83, 250, 40);
+        image[48 * 512 - 1] = 7;
+        let disk = disk(image);
+
+        let partitions = partitions(&disk).unwrap();
+        let found: Vec<_> = partitions
+            .iter()
+            .map(|p| (p.number(), p.partition_type(), p.start(), p.block_count()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (1, PartitionType::Mbr(0x83), 8, 40),
+                (5, PartitionType::Mbr(0x0c), 102, 10),
+                (6, PartitionType::Mbr(0x83), 122, 20),
+            ]
+        );
+
+        let mut buffer = vec![0; 512];
+        partitions[0].read_blocks(39, &mut buffer).unwrap();
+        assert_eq!(buffer[511], 7);
+        assert_eq!(
+            partitions[0].read_blocks(40, &mut buffer),
+            Err(VfsError::InvaildArgument)
+        );
+    }
+
+    #[test]
+    fn test_type_display() {
+        assert_eq!(
+            PartitionType::Gpt(LINUX).to_string(),
+            "0fc63daf-8483-4772-8e79-3d69d8477de4"
+        );
+        assert_eq!(PartitionType::Mbr(0x0c).to_string(), "type 0x0c");
+    }
+
+    #[test]
+    fn test_gpt() {
+        let partitions = partitions(&disk(gpt_image())).unwrap();
+        assert_eq!(partitions.len(), 1);
+        let partition = &partitions[0];
+        assert_eq!(partition.partition_type(), PartitionType::Gpt(LINUX));
+        assert_eq!((partition.start(), partition.block_count()), (34, 66));
+        assert_eq!(partition.name(), "root");
+    }
+
+    #[test]
+    fn test_gpt_checksums() {
+        // a damaged primary header is replaced by the backup one
+        let mut image = gpt_image();
+        image[512 + 40] ^= 1;
+        assert_eq!(partitions(&disk(image)).unwrap().len(), 1);
+
+        let mut image = gpt_image();
+        image[2 * 512] ^= 1;
+        image[(BLOCKS - 2) * 512] ^= 1;
+        assert_eq!(partitions(&disk(image)).err(), Some(VfsError::Io));
+
+        let image = vec![0; BLOCKS * 512];
+        assert!(partitions(&disk(image)).unwrap().is_empty());
+    }
+}
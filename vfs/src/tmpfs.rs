@@ -0,0 +1,366 @@
+//! Filesystem keeping everything in memory, with a limit of stored data
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use core_lib::sync::AtomicMutex;
+
+use crate::{
+    error::{VfsError, VfsResult},
+    inode::{DirEntry, FileSystem, FileType, Inode, Metadata},
+};
+
+/// State shared by all nodes of a filesystem
+struct Shared {
+    next_inode: AtomicU64,
+    /// Bytes of file contents and symlink targets
+    used: AtomicU64,
+    limit: u64,
+}
+
+impl Shared {
+    /// Accounts for up to `wanted` more bytes, returning how many fit under the limit
+    fn allocate(&self, wanted: u64) -> u64 {
+        let mut granted = 0;
+        let _ = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                granted = wanted.min(self.limit.saturating_sub(used));
+                Some(used + granted)
+            });
+        granted
+    }
+
+    fn release(&self, bytes: u64) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+pub struct TmpFs {
+    root: Arc<TmpInode>,
+    shared: Arc<Shared>,
+}
+
+impl TmpFs {
+    /// Creates an empty filesystem storing at most `limit` bytes
+    pub fn new(limit: u64) -> Arc<TmpFs> {
+        let shared = Arc::new(Shared {
+            next_inode: AtomicU64::new(1),
+            used: AtomicU64::new(0),
+            limit,
+        });
+        let root = TmpInode::new(&shared, Content::Directory(BTreeMap::new()), 0o755);
+        Arc::new(TmpFs { root, shared })
+    }
+
+    /// Bytes stored in the filesystem
+    pub fn used(&self) -> u64 {
+        self.shared.used.load(Ordering::Relaxed)
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+enum Content {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, Arc<TmpInode>>),
+    Symlink(String),
+}
+
+impl Content {
+    /// Bytes accounted in the filesystem usage
+    fn stored(&self) -> u64 {
+        match self {
+            Content::File(data) => data.len() as u64,
+            Content::Directory(_) => 0,
+            Content::Symlink(target) => target.len() as u64,
+        }
+    }
+}
+
+pub struct TmpInode {
+    inode: u64,
+    permissions: u32,
+    shared: Arc<Shared>,
+    content: AtomicMutex<Content>,
+}
+
+impl TmpInode {
+    fn new(shared: &Arc<Shared>, content: Content, permissions: u32) -> Arc<TmpInode> {
+        Arc::new(TmpInode {
+            inode: shared.next_inode.fetch_add(1, Ordering::Relaxed),
+            permissions,
+            shared: shared.clone(),
+            content: AtomicMutex::new(content),
+        })
+    }
+
+    /// Adds a node to a directory, failing if the name is taken
+    fn insert(&self, name: &str, content: Content, permissions: u32) -> VfsResult<Arc<TmpInode>> {
+        let mut directory = self.content.lock();
+        let Content::Directory(entries) = &mut *directory else {
+            return Err(VfsError::NotADirectory);
+        };
+        if entries.contains_key(name) {
+            return Err(VfsError::AlreadyExists);
+        }
+        let stored = content.stored();
+        let granted = self.shared.allocate(stored);
+        if granted < stored {
+            self.shared.release(granted);
+            return Err(VfsError::NoSpace);
+        }
+        let inode = TmpInode::new(&self.shared, content, permissions);
+        entries.insert(name.to_string(), inode.clone());
+        Ok(inode)
+    }
+
+    /// Resizes file data, accounting for the change in size
+    fn resize(&self, data: &mut Vec<u8>, size: u64) -> VfsResult<()> {
+        let current = data.len() as u64;
+        if size > current {
+            let granted = self.shared.allocate(size - current);
+            if granted < size - current {
+                self.shared.release(granted);
+                return Err(VfsError::NoSpace);
+            }
+        }
+        if size < current {
+            self.shared.release(current - size);
+        }
+        data.resize(size as usize, 0);
+        Ok(())
+    }
+}
+
+impl Drop for TmpInode {
+    fn drop(&mut self) {
+        self.shared.release(self.content.lock().stored());
+    }
+}
+
+impl Inode for TmpInode {
+    fn metadata(&self) -> Metadata {
+        let content = self.content.lock();
+        let (file_type, size, links) = match &*content {
+            Content::File(data) => (FileType::Regular, data.len() as u64, 1),
+            Content::Directory(entries) => {
+                let subdirectories = entries
+                    .values()
+                    .filter(|entry| matches!(*entry.content.lock(), Content::Directory(_)))
+                    .count();
+                (
+                    FileType::Directory,
+                    entries.len() as u64,
+                    2 + subdirectories as u32,
+                )
+            }
+            Content::Symlink(target) => (FileType::Symlink, target.len() as u64, 1),
+        };
+        Metadata {
+            inode: self.inode,
+            file_type,
+            permissions: self.permissions,
+            size,
+            links,
+        }
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> VfsResult<usize> {
+        let content = self.content.lock();
+        let Content::File(data) = &*content else {
+            return Err(VfsError::Unsupported);
+        };
+        let start = (offset as usize).min(data.len());
+        let length = buffer.len().min(data.len() - start);
+        buffer[..length].copy_from_slice(&data[start..start + length]);
+        Ok(length)
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> VfsResult<usize> {
+        let mut content = self.content.lock();
+        let Content::File(contents) = &mut *content else {
+            return Err(VfsError::Unsupported);
+        };
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let end = offset
+            .checked_add(data.len() as u64)
+            .filter(|end| *end <= isize::MAX as u64)
+            .ok_or(VfsError::FileTooLarge)?;
+        let current = contents.len() as u64;
+        // writes as much as fits under the limit
+        let end = if end > current {
+            current + self.shared.allocate(end - current)
+        } else {
+            end
+        };
+        if end <= offset {
+            return Err(VfsError::NoSpace);
+        }
+        if end > current {
+            contents.resize(end as usize, 0);
+        }
+        let written = (end - offset) as usize;
+        contents[offset as usize..end as usize].copy_from_slice(&data[..written]);
+        Ok(written)
+    }
+
+    fn truncate(&self, size: u64) -> VfsResult<()> {
+        if size > isize::MAX as u64 {
+            return Err(VfsError::FileTooLarge);
+        }
+        let mut content = self.content.lock();
+        match &mut *content {
+            Content::File(data) => self.resize(data, size),
+            _ => Err(VfsError::Unsupported),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> VfsResult<Arc<dyn Inode>> {
+        let content = self.content.lock();
+        let Content::Directory(entries) = &*content else {
+            return Err(VfsError::NotADirectory);
+        };
+        let inode = entries.get(name).ok_or(VfsError::NotFound)?;
+        Ok(inode.clone())
+    }
+
+    fn create(
+        &self,
+        name: &str,
+        file_type: FileType,
+        permissions: u32,
+    ) -> VfsResult<Arc<dyn Inode>> {
+        let content = match file_type {
+            FileType::Regular => Content::File(Vec::new()),
+            FileType::Directory => Content::Directory(BTreeMap::new()),
+            _ => return Err(VfsError::Unsupported),
+        };
+        Ok(self.insert(name, content, permissions)?)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> VfsResult<Arc<dyn Inode>> {
+        Ok(self.insert(name, Content::Symlink(target.to_string()), 0o777)?)
+    }
+
+    fn unlink(&self, name: &str) -> VfsResult<()> {
+        let mut content = self.content.lock();
+        let Content::Directory(entries) = &mut *content else {
+            return Err(VfsError::NotADirectory);
+        };
+        let entry = entries.get(name).ok_or(VfsError::NotFound)?;
+        if matches!(&*entry.content.lock(), Content::Directory(children) if !children.is_empty()) {
+            return Err(VfsError::NotEmpty);
+        }
+        // space is given back once the node is not open anymore
+        entries.remove(name);
+        Ok(())
+    }
+
+    fn read_dir(&self, index: usize) -> VfsResult<Option<DirEntry>> {
+        let content = self.content.lock();
+        let Content::Directory(entries) = &*content else {
+            return Err(VfsError::NotADirectory);
+        };
+        Ok(entries.iter().nth(index).map(|(name, inode)| DirEntry {
+            name: name.clone(),
+            inode: inode.inode,
+            file_type: inode.metadata().file_type,
+        }))
+    }
+
+    fn read_link(&self) -> VfsResult<String> {
+        match &*self.content.lock() {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(VfsError::Unsupported),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use crate::{FileSystem, FileType, TmpFs, VfsError};
+
+    #[test]
+    fn test_size_limit() {
+        let tmpfs = TmpFs::new(10);
+        let file = tmpfs
+            .root()
+            .create("file", FileType::Regular, 0o644)
+            .unwrap();
+        assert_eq!(file.write_at(0, b"0123456789abc"), Ok(10));
+        assert_eq!(file.write_at(10, b"d"), Err(VfsError::NoSpace));
+        assert!(matches!(
+            tmpfs.root().symlink("link", "file"),
+            Err(VfsError::NoSpace)
+        ));
+        assert_eq!(tmpfs.used(), 10);
+
+        file.truncate(4).unwrap();
+        assert_eq!(tmpfs.used(), 4);
+        assert_eq!(file.truncate(11), Err(VfsError::NoSpace));
+        assert_eq!(tmpfs.used(), 4);
+    }
+
+    #[test]
+    fn test_truncate_zero_fills() {
+        let tmpfs = TmpFs::new(u64::MAX);
+        let file = tmpfs
+            .root()
+            .create("file", FileType::Regular, 0o644)
+            .unwrap();
+        file.write_at(0, b"abcdef").unwrap();
+        file.truncate(2).unwrap();
+        file.truncate(5).unwrap();
+        let mut buffer = vec![0xff; 8];
+        assert_eq!(file.read_at(0, &mut buffer), Ok(5));
+        assert_eq!(&buffer[..5], b"ab\0\0\0");
+        assert_eq!(file.metadata().size, 5);
+    }
+
+    #[test]
+    fn test_space_released_with_last_reference() {
+        let tmpfs = TmpFs::new(u64::MAX);
+        let file = tmpfs
+            .root()
+            .create("file", FileType::Regular, 0o644)
+            .unwrap();
+        file.write_at(0, b"data").unwrap();
+        tmpfs.root().unlink("file").unwrap();
+        assert_eq!(tmpfs.used(), 4);
+        drop(file);
+        assert_eq!(tmpfs.used(), 0);
+    }
+
+    #[test]
+    fn test_non_empty_directory() {
+        let tmpfs = TmpFs::new(u64::MAX);
+        let directory = tmpfs
+            .root()
+            .create("directory", FileType::Directory, 0o755)
+            .unwrap();
+        directory.create("file", FileType::Regular, 0o644).unwrap();
+        assert_eq!(tmpfs.root().metadata().links, 3);
+        assert_eq!(tmpfs.root().unlink("directory"), Err(VfsError::NotEmpty));
+        directory.unlink("file").unwrap();
+        tmpfs.root().unlink("directory").unwrap();
+        assert!(tmpfs.root().read_dir(0).unwrap().is_none());
+    }
+}